@@ -0,0 +1,92 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Compare throughput of many concurrent calls with large messages over a
+//! single channel and over a pool of channels.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use grpcio::{
+    pb_de, pb_ser, CallOption, ChannelBuilder, Client, EnvBuilder, Marshaller, Method, MethodType,
+    RpcContext, ServerBuilder, ServiceBuilder, UnarySink,
+};
+use grpcio_proto::testing::messages::{SimpleRequest, SimpleResponse};
+
+const CONCURRENCY: usize = 256;
+const PAYLOAD_SIZE: usize = 64 * 1024;
+const DURATION: Duration = Duration::from_secs(5);
+
+const METHOD_UNARY_CALL: Method<SimpleRequest, SimpleResponse> = Method {
+    ty: MethodType::Unary,
+    name: "/grpc.testing.BenchmarkService/UnaryCall",
+    req_mar: Marshaller {
+        ser: pb_ser,
+        de: pb_de,
+    },
+    resp_mar: Marshaller {
+        ser: pb_ser,
+        de: pb_de,
+    },
+};
+
+/// Run echo calls with `CONCURRENCY` calls in flight and return calls per second.
+fn run(pool_size: usize) -> f64 {
+    let env = Arc::new(EnvBuilder::new().cq_count(4).build());
+    let service = ServiceBuilder::new()
+        .add_unary_handler(
+            &METHOD_UNARY_CALL,
+            |ctx: RpcContext<'_>, req: SimpleRequest, sink: UnarySink<SimpleResponse>| {
+                let mut resp = SimpleResponse::default();
+                resp.mut_payload()
+                    .mut_body()
+                    .extend_from_slice(req.get_payload().get_body());
+                ctx.spawn(sink.success(resp).map(|_| ()));
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+
+    let client_env = Arc::new(EnvBuilder::new().cq_count(4).build());
+    let pool =
+        ChannelBuilder::new(client_env).connect_pool(&format!("127.0.0.1:{}", port), pool_size);
+    let client = Client::with_pool(pool);
+
+    let mut req = SimpleRequest::default();
+    req.mut_payload().set_body(vec![0; PAYLOAD_SIZE]);
+    let timer = Instant::now();
+    let calls = block_on(
+        stream::iter(0..CONCURRENCY)
+            .map(|_| {
+                let (client, req) = (&client, &req);
+                async move {
+                    let mut calls = 0;
+                    while timer.elapsed() < DURATION {
+                        client
+                            .unary_call_async(&METHOD_UNARY_CALL, req, CallOption::default())
+                            .unwrap()
+                            .await
+                            .unwrap();
+                        calls += 1;
+                    }
+                    calls
+                }
+            })
+            .buffer_unordered(CONCURRENCY)
+            .fold(0, |sum, calls| async move { sum + calls }),
+    );
+    calls as f64 / timer.elapsed().as_secs_f64()
+}
+
+fn main() {
+    for &size in &[1, 4] {
+        println!("channels: {}, {:.0} calls/s", size, run(size));
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Compare allocations of decoding messages with and without pooling on a
+//! unary echo loop.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::prelude::*;
+use grpcio::{
+    pb_de, pb_de_pooled, pb_recycle, pb_ser, CallOption, ChannelBuilder, Client, EnvBuilder,
+    Marshaller, MessageReader, Method, MethodType, Result, RpcContext, ServerBuilder,
+    ServiceBuilder, UnarySink,
+};
+use grpcio_proto::testing::messages::{SimpleRequest, SimpleResponse};
+
+/// Counts allocations made by Rust code. Allocations made by gRPC C core
+/// are not affected by pooling and are not counted.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ROUNDS: usize = 10000;
+const PAYLOAD_SIZE: usize = 4096;
+
+fn echo_method(
+    req_de: fn(MessageReader) -> Result<SimpleRequest>,
+    resp_de: fn(MessageReader) -> Result<SimpleResponse>,
+) -> Method<SimpleRequest, SimpleResponse> {
+    Method {
+        ty: MethodType::Unary,
+        name: "/grpc.testing.BenchmarkService/UnaryCall",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: req_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: resp_de,
+        },
+    }
+}
+
+/// Run the echo loop and return the number of allocations per call.
+fn run(pooled: bool) -> usize {
+    let method = if pooled {
+        echo_method(pb_de_pooled, pb_de_pooled)
+    } else {
+        echo_method(pb_de, pb_de)
+    };
+    let env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let service = ServiceBuilder::new()
+        .add_unary_handler(
+            &method,
+            move |ctx: RpcContext<'_>, req: SimpleRequest, sink: UnarySink<SimpleResponse>| {
+                let mut resp = SimpleResponse::default();
+                resp.mut_payload()
+                    .mut_body()
+                    .extend_from_slice(req.get_payload().get_body());
+                if pooled {
+                    pb_recycle(req);
+                }
+                ctx.spawn(sink.success(resp).map(|_| ()));
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let mut req = SimpleRequest::default();
+    req.mut_payload().set_body(vec![0; PAYLOAD_SIZE]);
+    let start = ALLOCATIONS.load(Ordering::Relaxed);
+    let timer = Instant::now();
+    for _ in 0..ROUNDS {
+        let resp = client
+            .unary_call(&method, &req, CallOption::default())
+            .unwrap();
+        assert_eq!(resp.get_payload().get_body().len(), PAYLOAD_SIZE);
+        if pooled {
+            pb_recycle(resp);
+        }
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - start;
+    println!(
+        "pooled: {}, {} calls in {:?}, {} allocations per call",
+        pooled,
+        ROUNDS,
+        timer.elapsed(),
+        allocations / ROUNDS
+    );
+    allocations / ROUNDS
+}
+
+fn main() {
+    let plain = run(false);
+    let pooled = run(true);
+    println!("pooling saves {} allocations per call", plain - pooled);
+}
@@ -0,0 +1,126 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Compare tail latency of quick calls, while slow handlers keep the server
+//! busy, with and without dedicated I/O threads.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use grpcio::{
+    pb_de, pb_ser, CallOption, ChannelBuilder, Client, EnvBuilder, Marshaller, Method, MethodType,
+    RpcContext, ServerBuilder, ServiceBuilder, UnarySink,
+};
+use grpcio_proto::testing::messages::{SimpleRequest, SimpleResponse};
+
+const ROUNDS: usize = 5000;
+const SLOW_CLIENTS: usize = 2;
+const SLOW_HANDLER_COST: Duration = Duration::from_micros(500);
+
+fn method(name: &'static str) -> Method<SimpleRequest, SimpleResponse> {
+    Method {
+        ty: MethodType::Unary,
+        name,
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    }
+}
+
+fn busy_wait(cost: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < cost {}
+}
+
+/// Run quick calls against a busy server and return the sorted latencies.
+fn run(dedicated_io_threads: bool) -> Vec<Duration> {
+    let fast = method("/grpc.testing.BenchmarkService/UnaryCall");
+    let slow = method("/grpc.testing.BenchmarkService/SlowCall");
+    let env = Arc::new(
+        EnvBuilder::new()
+            .cq_count(1)
+            .dedicated_io_threads(dedicated_io_threads)
+            .build(),
+    );
+    let service = ServiceBuilder::new()
+        .add_unary_handler(
+            &fast,
+            |ctx: RpcContext<'_>, _: SimpleRequest, sink: UnarySink<SimpleResponse>| {
+                ctx.spawn(sink.success(SimpleResponse::default()).map(|_| ()));
+            },
+        )
+        .add_unary_handler(
+            &slow,
+            |ctx: RpcContext<'_>, _: SimpleRequest, sink: UnarySink<SimpleResponse>| {
+                busy_wait(SLOW_HANDLER_COST);
+                ctx.spawn(sink.success(SimpleResponse::default()).map(|_| ()));
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env)
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let client_env = Arc::new(EnvBuilder::new().cq_count(2).build());
+    let ch = ChannelBuilder::new(client_env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let slow_clients: Vec<_> = (0..SLOW_CLIENTS)
+        .map(|_| {
+            let (client, stop) = (client.clone(), stop.clone());
+            thread::spawn(move || {
+                let slow = method("/grpc.testing.BenchmarkService/SlowCall");
+                let req = SimpleRequest::default();
+                while !stop.load(Ordering::SeqCst) {
+                    client
+                        .unary_call(&slow, &req, CallOption::default())
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    let req = SimpleRequest::default();
+    let mut latencies = Vec::with_capacity(ROUNDS);
+    for _ in 0..ROUNDS {
+        let timer = Instant::now();
+        client
+            .unary_call(&fast, &req, CallOption::default())
+            .unwrap();
+        latencies.push(timer.elapsed());
+    }
+    stop.store(true, Ordering::SeqCst);
+    for c in slow_clients {
+        c.join().unwrap();
+    }
+    latencies.sort();
+    latencies
+}
+
+fn permille(latencies: &[Duration], p: usize) -> Duration {
+    latencies[(latencies.len() - 1) * p / 1000]
+}
+
+fn main() {
+    for &dedicated in &[false, true] {
+        let latencies = run(dedicated);
+        println!(
+            "dedicated io threads: {}, p50: {:?}, p99: {:?}, p99.9: {:?}",
+            dedicated,
+            permille(&latencies, 500),
+            permille(&latencies, 990),
+            permille(&latencies, 999)
+        );
+    }
+}
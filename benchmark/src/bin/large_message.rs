@@ -0,0 +1,94 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Compare sending large messages that are copied into slices with sending
+//! them as slices directly on a unary echo loop.
+
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use grpcio::{
+    slice_de, slice_ser, CallOption, ChannelBuilder, Client, EnvBuilder, GrpcSlice, Marshaller,
+    MessageReader, Method, MethodType, Result, RpcContext, ServerBuilder, ServiceBuilder,
+    UnarySink,
+};
+
+const ROUNDS: usize = 200;
+const PAYLOAD_SIZE: usize = 3 * 1024 * 1024;
+
+fn vec_ser(t: &Vec<u8>, buf: &mut GrpcSlice) -> Result<()> {
+    *buf = GrpcSlice::from(t.as_slice());
+    Ok(())
+}
+
+fn vec_de(mut reader: MessageReader) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(reader.len());
+    reader.read_to_end(&mut buf).unwrap();
+    Ok(buf)
+}
+
+fn echo_method<T>(
+    ser: fn(&T, &mut GrpcSlice) -> Result<()>,
+    de: fn(MessageReader) -> Result<T>,
+) -> Method<T, T> {
+    Method {
+        ty: MethodType::Unary,
+        name: "/grpc.testing.BenchmarkService/UnaryCall",
+        req_mar: Marshaller { ser, de },
+        resp_mar: Marshaller { ser, de },
+    }
+}
+
+/// Run the echo loop with the payload and return the time spent.
+fn run<T>(method: Method<T, T>, payload: T) -> Duration
+where
+    T: Send + 'static,
+{
+    let env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let service = ServiceBuilder::new()
+        .add_unary_handler(
+            &method,
+            move |ctx: RpcContext<'_>, req: T, sink: UnarySink<T>| {
+                ctx.spawn(sink.success(req).map(|_| ()));
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env)
+        .max_receive_message_len(-1)
+        .max_send_message_len(-1)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let timer = Instant::now();
+    for _ in 0..ROUNDS {
+        client
+            .unary_call(&method, &payload, CallOption::default())
+            .unwrap();
+    }
+    timer.elapsed()
+}
+
+fn main() {
+    let payload = vec![7; PAYLOAD_SIZE];
+    let copied = run(echo_method(vec_ser, vec_de), payload.clone());
+    let sliced = run(echo_method(slice_ser, slice_de), GrpcSlice::from(payload));
+    let mb = (PAYLOAD_SIZE * ROUNDS) as f64 / 1024.0 / 1024.0;
+    println!(
+        "copied: {:?}, {:.2} MiB/s",
+        copied,
+        mb / copied.as_secs_f64()
+    );
+    println!(
+        "sliced: {:?}, {:.2} MiB/s",
+        sliced,
+        mb / sliced.as_secs_f64()
+    );
+}
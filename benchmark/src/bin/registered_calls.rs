@@ -0,0 +1,105 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Compare throughput of small calls across a mix of methods over a channel
+//! with all the methods registered and over one without.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use grpcio::{
+    pb_de, pb_ser, CallOption, ChannelBuilder, Client, EnvBuilder, Marshaller, Method, MethodType,
+    RpcContext, ServerBuilder, ServiceBuilder, UnarySink,
+};
+use grpcio_proto::testing::messages::{SimpleRequest, SimpleResponse};
+
+const CONCURRENCY: usize = 64;
+const DURATION: Duration = Duration::from_secs(5);
+
+const METHOD_NAMES: &[&str] = &[
+    "/grpc.testing.BenchmarkService/UnaryCall0",
+    "/grpc.testing.BenchmarkService/UnaryCall1",
+    "/grpc.testing.BenchmarkService/UnaryCall2",
+    "/grpc.testing.BenchmarkService/UnaryCall3",
+    "/grpc.testing.BenchmarkService/UnaryCall4",
+    "/grpc.testing.BenchmarkService/UnaryCall5",
+    "/grpc.testing.BenchmarkService/UnaryCall6",
+    "/grpc.testing.BenchmarkService/UnaryCall7",
+];
+
+fn method(name: &'static str) -> Method<SimpleRequest, SimpleResponse> {
+    Method {
+        ty: MethodType::Unary,
+        name,
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    }
+}
+
+/// Run empty calls round robin over the methods with `CONCURRENCY` calls in
+/// flight and return calls per second.
+fn run(register: bool) -> f64 {
+    let env = Arc::new(EnvBuilder::new().cq_count(4).build());
+    let methods: Vec<_> = METHOD_NAMES.iter().map(|&name| method(name)).collect();
+    let mut builder = ServiceBuilder::new();
+    for m in &methods {
+        builder = builder.add_unary_handler(
+            m,
+            |ctx: RpcContext<'_>, _: SimpleRequest, sink: UnarySink<SimpleResponse>| {
+                ctx.spawn(sink.success(SimpleResponse::default()).map(|_| ()));
+            },
+        );
+    }
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(builder.build())
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+
+    let client_env = Arc::new(EnvBuilder::new().cq_count(4).build());
+    let ch = ChannelBuilder::new(client_env).connect(&format!("127.0.0.1:{}", port));
+    if register {
+        ch.register_methods(METHOD_NAMES);
+    }
+    let client = Client::new(ch);
+
+    let req = SimpleRequest::default();
+    let timer = Instant::now();
+    let calls = block_on(
+        stream::iter(0..CONCURRENCY)
+            .map(|i| {
+                let (client, req, methods) = (&client, &req, &methods);
+                async move {
+                    let mut calls = 0;
+                    while timer.elapsed() < DURATION {
+                        let m = &methods[(i + calls) % methods.len()];
+                        client
+                            .unary_call_async(m, req, CallOption::default())
+                            .unwrap()
+                            .await
+                            .unwrap();
+                        calls += 1;
+                    }
+                    calls
+                }
+            })
+            .buffer_unordered(CONCURRENCY)
+            .fold(0, |sum, calls| async move { sum + calls }),
+    );
+    calls as f64 / timer.elapsed().as_secs_f64()
+}
+
+fn main() {
+    for &register in &[false, true] {
+        println!("registered: {}, {:.0} calls/s", register, run(register));
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Compare serializing large messages with encoders that grow their buffers
+//! when the initial capacity is the default one and when it matches the size
+//! of the messages.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use grpcio::{serialize_with, serialize_with_capacity, GrpcSlice, Result};
+
+const ROUNDS: usize = 200;
+const CHUNK_SIZE: usize = 1024;
+const CHUNK_COUNT: usize = 1024;
+const MESSAGE_SIZE: usize = CHUNK_SIZE * CHUNK_COUNT;
+
+static REALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Encode the message chunk by chunk like a streaming encoder does, and count
+/// how many times the buffer is reallocated.
+fn encode(msg: &[Vec<u8>], buf: &mut Vec<u8>) -> Result<()> {
+    let mut cap = buf.capacity();
+    for chunk in msg {
+        buf.extend_from_slice(chunk);
+        if buf.capacity() != cap {
+            cap = buf.capacity();
+            REALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+fn default_ser(msg: &Vec<Vec<u8>>, buf: &mut GrpcSlice) -> Result<()> {
+    serialize_with(buf, |v| encode(msg, v))
+}
+
+fn hinted_ser(msg: &Vec<Vec<u8>>, buf: &mut GrpcSlice) -> Result<()> {
+    serialize_with_capacity(buf, MESSAGE_SIZE, |v| encode(msg, v))
+}
+
+/// Serialize the message repeatedly and return the time spent and the number
+/// of reallocations.
+fn run(
+    ser: fn(&Vec<Vec<u8>>, &mut GrpcSlice) -> Result<()>,
+    msg: &Vec<Vec<u8>>,
+) -> (Duration, usize) {
+    REALLOCATIONS.store(0, Ordering::Relaxed);
+    let timer = Instant::now();
+    for _ in 0..ROUNDS {
+        let mut buf = GrpcSlice::default();
+        ser(msg, &mut buf).unwrap();
+        assert_eq!(buf.len(), MESSAGE_SIZE);
+    }
+    (timer.elapsed(), REALLOCATIONS.load(Ordering::Relaxed))
+}
+
+fn main() {
+    let msg = vec![vec![7; CHUNK_SIZE]; CHUNK_COUNT];
+    let (default_time, default_reallocs) = run(default_ser, &msg);
+    let (hinted_time, hinted_reallocs) = run(hinted_ser, &msg);
+    println!(
+        "default capacity: {:?}, {} reallocations per message",
+        default_time,
+        default_reallocs / ROUNDS
+    );
+    println!(
+        "hinted capacity: {:?}, {} reallocations per message",
+        hinted_time,
+        hinted_reallocs / ROUNDS
+    );
+}
@@ -0,0 +1,10 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::env;
+
+fn main() {
+    // Set by grpcio-sys when it supports socket mutators.
+    if env::var("DEP_GRPCIO_SOCKET_MUTATOR").is_ok() {
+        println!("cargo:rustc-cfg=grpcio_socket_mutator");
+    }
+}
@@ -175,12 +175,15 @@ impl<'a> CodeWriter<'a> {
 }
 
 use super::util::{self, fq_grpc, to_snake_case, MethodType};
+use crate::GenOptions;
 
 struct MethodGen<'a> {
     proto: &'a MethodDescriptorProto,
     service_name: String,
     service_path: String,
     root_scope: &'a RootScope<'a>,
+    // Path from the generated module to the parent of message modules.
+    super_path: &'static str,
 }
 
 impl<'a> MethodGen<'a> {
@@ -189,18 +192,21 @@ impl<'a> MethodGen<'a> {
         service_name: String,
         service_path: String,
         root_scope: &'a RootScope<'a>,
+        super_path: &'static str,
     ) -> MethodGen<'a> {
         MethodGen {
             proto,
             service_name,
             service_path,
             root_scope,
+            super_path,
         }
     }
 
     fn input(&self) -> String {
         format!(
-            "super::{}",
+            "{}{}",
+            self.super_path,
             self.root_scope
                 .find_message(self.proto.get_input_type())
                 .rust_fq_name()
@@ -209,7 +215,8 @@ impl<'a> MethodGen<'a> {
 
     fn output(&self) -> String {
         format!(
-            "super::{}",
+            "{}{}",
+            self.super_path,
             self.root_scope
                 .find_message(self.proto.get_output_type())
                 .rust_fq_name()
@@ -256,7 +263,7 @@ impl<'a> MethodGen<'a> {
 
     fn write_definition(&self, w: &mut CodeWriter) {
         let head = format!(
-            "const {}: {}<{}, {}> = {} {{",
+            "pub const {}: {}<{}, {}> = {} {{",
             self.const_method_name(),
             fq_grpc("Method"),
             self.input(),
@@ -299,6 +306,18 @@ impl<'a> MethodGen<'a> {
         )
     }
 
+    fn unary_full(&self, method_name: &str) -> String {
+        format!(
+            "{}_full(&self, req: &{}, opt: {}) -> {}<{}<{}>>",
+            method_name,
+            self.input(),
+            fq_grpc("CallOption"),
+            fq_grpc("Result"),
+            fq_grpc("Response"),
+            self.output()
+        )
+    }
+
     fn unary_async(&self, method_name: &str) -> String {
         format!(
             "{}_async(&self, req: &{}) -> {}<{}<{}>>",
@@ -417,6 +436,14 @@ impl<'a> MethodGen<'a> {
                 });
                 w.write_line("");
 
+                w.pub_fn(&self.unary_full(&method_name), |w| {
+                    w.write_line(&format!(
+                        "self.client.unary_call_full(&{}, req, opt)",
+                        self.const_method_name()
+                    ));
+                });
+                w.write_line("");
+
                 w.pub_fn(&self.unary_async_opt(&method_name), |w| {
                     w.write_line(&format!(
                         "self.client.unary_call_async(&{}, req, opt)",
@@ -544,6 +571,7 @@ impl<'a> ServiceGen<'a> {
         proto: &'a ServiceDescriptorProto,
         file: &FileDescriptorProto,
         root_scope: &'a RootScope,
+        super_path: &'static str,
     ) -> ServiceGen<'a> {
         let service_path = if file.get_package().is_empty() {
             format!("/{}", proto.get_name())
@@ -559,6 +587,7 @@ impl<'a> ServiceGen<'a> {
                     util::to_camel_case(proto.get_name()),
                     service_path.clone(),
                     root_scope,
+                    super_path,
                 )
             })
             .collect();
@@ -584,11 +613,36 @@ impl<'a> ServiceGen<'a> {
 
         w.impl_self_block(&self.client_name(), |w| {
             w.pub_fn("new(channel: ::grpcio::Channel) -> Self", |w| {
+                let methods: Vec<_> = self
+                    .methods
+                    .iter()
+                    .map(|m| format!("{}.name", m.const_method_name()))
+                    .collect();
+                w.write_line(&format!(
+                    "channel.register_methods(&[{}]);",
+                    methods.join(", ")
+                ));
                 w.expr_block(&self.client_name(), |w| {
                     w.field_entry("client", "::grpcio::Client::new(channel)");
                 });
             });
 
+            w.write_line("");
+            w.pub_fn("with_client(client: ::grpcio::Client) -> Self", |w| {
+                w.expr_block(&self.client_name(), |w| {
+                    w.field_entry("client", "client");
+                });
+            });
+
+            w.write_line("");
+            w.pub_fn(
+                "with_default_call_option(mut self, opt: ::grpcio::CallOption) -> Self",
+                |w| {
+                    w.write_line("self.client = self.client.with_default_call_option(opt);");
+                    w.write_line("self");
+                },
+            );
+
             for method in &self.methods {
                 w.write_line("");
                 method.write_client(w);
@@ -650,16 +704,34 @@ impl<'a> ServiceGen<'a> {
     }
 }
 
+fn gen_service_file(
+    service: &ServiceDescriptorProto,
+    file: &FileDescriptorProto,
+    root_scope: &RootScope,
+    name: String,
+) -> compiler_plugin::GenResult {
+    let mut v = Vec::new();
+    {
+        let mut w = CodeWriter::new(&mut v);
+        w.write_generated();
+        w.write_line("");
+        ServiceGen::new(service, file, root_scope, "super::super::").write(&mut w);
+    }
+    compiler_plugin::GenResult { name, content: v }
+}
+
 fn gen_file(
     file: &FileDescriptorProto,
     root_scope: &RootScope,
-) -> Option<compiler_plugin::GenResult> {
+    opts: GenOptions,
+) -> Vec<compiler_plugin::GenResult> {
     if file.get_service().is_empty() {
-        return None;
+        return vec![];
     }
 
     let base = protobuf::descriptorx::proto_path_to_rust_mod(file.get_name());
 
+    let mut results = Vec::new();
     let mut v = Vec::new();
     {
         let mut w = CodeWriter::new(&mut v);
@@ -667,19 +739,40 @@ fn gen_file(
 
         for service in file.get_service() {
             w.write_line("");
-            ServiceGen::new(service, file, root_scope).write(&mut w);
+            if !opts.split_services {
+                ServiceGen::new(service, file, root_scope, "super::").write(&mut w);
+                continue;
+            }
+            let module = to_snake_case(service.get_name());
+            let name = format!("{}_grpc/{}.rs", base, module);
+            w.write_line(format!("pub mod {};", module));
+            w.write_line(format!("pub use self::{}::*;", module));
+            results.push(gen_service_file(service, file, root_scope, name));
         }
     }
 
-    Some(compiler_plugin::GenResult {
-        name: base + "_grpc.rs",
-        content: v,
-    })
+    results.insert(
+        0,
+        compiler_plugin::GenResult {
+            name: base + "_grpc.rs",
+            content: v,
+        },
+    );
+    results
 }
 
 pub fn gen(
     file_descriptors: &[FileDescriptorProto],
     files_to_generate: &[String],
+) -> Vec<compiler_plugin::GenResult> {
+    gen_with_options(file_descriptors, files_to_generate, GenOptions::default())
+}
+
+/// Same as `gen`, but the generated code can be customized by `opts`.
+pub fn gen_with_options(
+    file_descriptors: &[FileDescriptorProto],
+    files_to_generate: &[String],
+    opts: GenOptions,
 ) -> Vec<compiler_plugin::GenResult> {
     let files_map: HashMap<&str, &FileDescriptorProto> =
         file_descriptors.iter().map(|f| (f.get_name(), f)).collect();
@@ -695,12 +788,101 @@ pub fn gen(
             continue;
         }
 
-        results.extend(gen_file(file, &root_scope).into_iter());
+        results.extend(gen_file(file, &root_scope, opts));
     }
 
     results
 }
 
+/// The entry of the `grpc_rust_plugin` protoc plugin.
+///
+/// Options are passed as the parameter of the plugin, see [`GenOptions::parse`].
+///
+/// [`GenOptions::parse`]: ../struct.GenOptions.html#method.parse
 pub fn protoc_gen_grpc_rust_main() {
-    compiler_plugin::plugin_main(gen);
+    compiler_plugin::plugin_main_2(|req| {
+        let opts = GenOptions::parse(req.parameter).unwrap_or_else(|e| panic!("{}", e));
+        gen_with_options(req.file_descriptors, req.files_to_generate, opts)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(name: &str) -> DescriptorProto {
+        let mut msg = DescriptorProto::new();
+        msg.set_name(name.to_owned());
+        msg
+    }
+
+    fn service(name: &str, input: &str, output: &str) -> ServiceDescriptorProto {
+        let mut method = MethodDescriptorProto::new();
+        method.set_name("Call".to_owned());
+        method.set_input_type(input.to_owned());
+        method.set_output_type(output.to_owned());
+        let mut service = ServiceDescriptorProto::new();
+        service.set_name(name.to_owned());
+        service.mut_method().push(method);
+        service
+    }
+
+    #[test]
+    fn test_split_services() {
+        let mut file = FileDescriptorProto::new();
+        file.set_name("multi.proto".to_owned());
+        file.set_package("multi".to_owned());
+        file.mut_message_type().push(message("Req"));
+        file.mut_message_type().push(message("Resp"));
+        file.mut_service()
+            .push(service("FooService", ".multi.Req", ".multi.Resp"));
+        file.mut_service()
+            .push(service("BarService", ".multi.Resp", ".multi.Req"));
+        let files = vec![file];
+        let to_generate = vec!["multi.proto".to_owned()];
+
+        let results = gen(&files, &to_generate);
+        assert_eq!(results.len(), 1);
+        let content = String::from_utf8(results[0].content.clone()).unwrap();
+        assert!(content.contains("super::multi::Req"), "{}", content);
+
+        let opts = GenOptions {
+            split_services: true,
+        };
+        let results = gen_with_options(&files, &to_generate, opts);
+        let names: Vec<_> = results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "multi_grpc.rs",
+                "multi_grpc/foo_service.rs",
+                "multi_grpc/bar_service.rs"
+            ]
+        );
+        let main = String::from_utf8(results[0].content.clone()).unwrap();
+        for module in &["foo_service", "bar_service"] {
+            assert!(main.contains(&format!("pub mod {};", module)), "{}", main);
+            assert!(main.contains(&format!("pub use self::{}::*;", module)));
+        }
+        assert!(!main.contains("METHOD_"), "{}", main);
+        for (result, service) in results[1..].iter().zip(&["FooService", "BarService"]) {
+            let content = String::from_utf8(result.content.clone()).unwrap();
+            // Message types are one more level up from the nested module.
+            assert!(content.contains("super::super::multi::Req"), "{}", content);
+            assert!(content.contains("super::super::multi::Resp"), "{}", content);
+            assert!(content.contains(&format!("pub struct {}Client", service)));
+        }
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert!(!GenOptions::parse("").unwrap().split_services);
+        assert!(GenOptions::parse("split_services").unwrap().split_services);
+        assert!(
+            GenOptions::parse(" split_services, ")
+                .unwrap()
+                .split_services
+        );
+        assert!(GenOptions::parse("split_services,unknown").is_err());
+    }
 }
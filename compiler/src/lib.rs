@@ -6,3 +6,38 @@ pub mod codegen;
 pub mod prost_codegen;
 
 mod util;
+
+/// Options of generating code.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GenOptions {
+    /// Generate each service into a module of its own.
+    ///
+    /// With protobuf, the file generated for `foo.proto` is still `foo_grpc.rs`, but it
+    /// only declares a module for each service, whose code is generated into
+    /// `foo_grpc/{service}.rs`, and re-exports them, so generated items can be referred
+    /// to by the same paths as before. `foo_grpc.rs` has to be declared as a module,
+    /// like `mod foo_grpc;`, so the service files are found next to it.
+    ///
+    /// With prost, each service is generated into `{package}/{service}.rs` of the
+    /// output directory, which is included by the file of the package.
+    pub split_services: bool,
+}
+
+impl GenOptions {
+    /// Parse the options from the parameter of the protoc plugin, like
+    /// `--grpc_out=split_services:out_dir`. Options are separated by commas.
+    pub fn parse(parameter: &str) -> Result<GenOptions, String> {
+        let mut opts = GenOptions::default();
+        for opt in parameter
+            .split(',')
+            .map(str::trim)
+            .filter(|o| !o.is_empty())
+        {
+            match opt {
+                "split_services" => opts.split_services = true,
+                _ => return Err(format!("unknown option {}", opt)),
+            }
+        }
+        Ok(opts)
+    }
+}
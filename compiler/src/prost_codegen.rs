@@ -1,8 +1,10 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::cell::RefCell;
 use std::io::{Error, ErrorKind, Read};
-use std::path::Path;
-use std::{fs, io, process::Command};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{env, fs, io, process::Command};
 
 use derive_new::new;
 use prost::Message;
@@ -10,14 +12,33 @@ use prost_build::{protoc, protoc_include, Config, Method, Service, ServiceGenera
 use prost_types::FileDescriptorSet;
 
 use crate::util::{fq_grpc, to_snake_case, MethodType};
+use crate::GenOptions;
 
 /// Returns the names of all packages compiled.
 pub fn compile_protos<P>(protos: &[P], includes: &[P], out_dir: &str) -> io::Result<Vec<String>>
 where
     P: AsRef<Path>,
 {
+    compile_protos_with_options(protos, includes, out_dir, GenOptions::default())
+}
+
+/// Same as `compile_protos`, but the generated code can be customized by `opts`.
+pub fn compile_protos_with_options<P>(
+    protos: &[P],
+    includes: &[P],
+    out_dir: &str,
+    opts: GenOptions,
+) -> io::Result<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    let service_files = Rc::new(RefCell::new(Vec::new()));
     let mut prost_config = Config::new();
-    prost_config.service_generator(Box::new(Generator));
+    prost_config.service_generator(Box::new(Generator {
+        opts,
+        out_dir: env::current_dir()?.join(out_dir),
+        service_files: service_files.clone(),
+    }));
     prost_config.out_dir(out_dir);
 
     // Create a file descriptor set for the protocol files.
@@ -68,19 +89,68 @@ where
     // actually generate the Rust code.
     prost_config.compile_protos(protos, includes)?;
 
+    for (path, content) in service_files.borrow().iter() {
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)?;
+    }
+
     Ok(packages)
 }
 
-struct Generator;
+struct Generator {
+    opts: GenOptions,
+    // The absolute path of the output directory.
+    out_dir: PathBuf,
+    // Files of split services, which are written after the packages are generated.
+    service_files: Rc<RefCell<Vec<(PathBuf, String)>>>,
+}
 
 impl ServiceGenerator for Generator {
-    fn generate(&mut self, service: Service, buf: &mut String) {
-        generate_methods(&service, buf);
-        generate_client(&service, buf);
-        generate_server(&service, buf);
+    fn generate(&mut self, mut service: Service, buf: &mut String) {
+        if !self.opts.split_services {
+            generate_service(&service, buf);
+            return;
+        }
+        let module = to_snake_case(&service.name);
+        let package = if service.package.is_empty() {
+            "_"
+        } else {
+            &service.package
+        };
+        let path = self.out_dir.join(package).join(format!("{}.rs", module));
+        // The file of the package is usually included from elsewhere, so the path
+        // must be absolute.
+        buf.push_str(&format!(
+            "pub mod {} {{ include!({:?}); }}\npub use self::{}::*;\n",
+            module,
+            path.display().to_string(),
+            module
+        ));
+        for method in &mut service.methods {
+            method.input_type = nested_type(&method.input_type);
+            method.output_type = nested_type(&method.output_type);
+        }
+        let mut content = String::new();
+        generate_service(&service, &mut content);
+        self.service_files.borrow_mut().push((path, content));
     }
 }
 
+// Refer to the type from a module nested in the module of the package.
+fn nested_type(ty: &str) -> String {
+    if ty.starts_with("::") || ty == "()" {
+        ty.to_owned()
+    } else {
+        format!("super::{}", ty)
+    }
+}
+
+fn generate_service(service: &Service, buf: &mut String) {
+    generate_methods(service, buf);
+    generate_client(service, buf);
+    generate_server(service, buf);
+}
+
 fn generate_methods(service: &Service, buf: &mut String) {
     let service_path = if service.package.is_empty() {
         format!("/{}", service.proto_name)
@@ -110,7 +180,7 @@ fn generate_method(service_name: &str, service_path: &str, method: &Method, buf:
         method.output_type
     );
 
-    buf.push_str("const ");
+    buf.push_str("pub const ");
     buf.push_str(&name);
     buf.push_str(": ");
     buf.push_str(&ty);
@@ -169,17 +239,32 @@ fn generate_client(service: &Service, buf: &mut String) {
     buf.push_str("impl ");
     buf.push_str(&client_name);
     buf.push_str(" {\n");
-    generate_ctor(&client_name, buf);
+    generate_ctor(service, &client_name, buf);
     generate_client_methods(service, buf);
     generate_spawn(buf);
     buf.push_str("}\n")
 }
 
-fn generate_ctor(client_name: &str, buf: &mut String) {
+fn generate_ctor(service: &Service, client_name: &str, buf: &mut String) {
     buf.push_str("pub fn new(channel: ::grpcio::Channel) -> Self { ");
+    buf.push_str("channel.register_methods(&[");
+    for method in &service.methods {
+        buf.push_str(&const_method_name(&service.name, method));
+        buf.push_str(".name, ");
+    }
+    buf.push_str("]); ");
     buf.push_str(client_name);
     buf.push_str(" { client: ::grpcio::Client::new(channel) }");
     buf.push_str("}\n");
+
+    buf.push_str("pub fn with_client(client: ::grpcio::Client) -> Self { ");
+    buf.push_str(client_name);
+    buf.push_str(" { client }");
+    buf.push_str("}\n");
+
+    buf.push_str("pub fn with_default_call_option(mut self, opt: ::grpcio::CallOption) -> Self { ");
+    buf.push_str("self.client = self.client.with_default_call_option(opt); self");
+    buf.push_str("}\n");
 }
 
 fn generate_client_methods(service: &Service, buf: &mut String) {
@@ -216,6 +301,7 @@ fn generate_client_method(service_name: &str, method: &Method, buf: &mut String)
                 name,
             )
             .generate(buf);
+            generate_client_full_method(method, name, buf);
             ClientMethod::new(
                 &method.name,
                 true,
@@ -436,6 +522,20 @@ impl<'a> ClientMethod<'a> {
     }
 }
 
+fn generate_client_full_method(method: &Method, data_name: &str, buf: &mut String) {
+    buf.push_str(&format!(
+        "pub fn {}_full(&self, req: &{}, opt: {}) -> {}<{}<{}>> {{ \
+         self.client.unary_call_full(&{}, req, opt) }}\n",
+        method.name,
+        method.input_type,
+        fq_grpc("CallOption"),
+        fq_grpc("Result"),
+        fq_grpc("Response"),
+        method.output_type,
+        data_name,
+    ));
+}
+
 fn generate_spawn(buf: &mut String) {
     buf.push_str(
         "pub fn spawn<F>(&self, f: F) \
@@ -0,0 +1,100 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Discovers the abseil libraries produced by building gRPC, so that the link
+//! directives don't need to be updated whenever the vendored abseil changes.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use walkdir::WalkDir;
+
+/// Returns the name of an abseil library if `file_name` is one.
+fn absl_lib_name<'a>(file_name: &'a str, lib_suffix: &str) -> Option<&'a str> {
+    let name = file_name.strip_suffix(lib_suffix)?;
+    // Static libraries are prefixed by `lib` except on msvc.
+    let name = name.strip_prefix("lib").unwrap_or(name);
+    if name.starts_with("absl_") {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Returns the abseil modules required by a pkg-config file.
+pub fn pc_requires(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|l| {
+            l.strip_prefix("Requires:")
+                .or_else(|| l.strip_prefix("Requires.private:"))
+        })
+        .flat_map(|l| l.split(','))
+        .filter_map(|dep| dep.split_whitespace().next())
+        .filter(|dep| dep.starts_with("absl_"))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+fn visit<'a>(
+    lib: &'a str,
+    requires: &'a HashMap<String, Vec<String>>,
+    visited: &mut HashSet<&'a str>,
+    order: &mut Vec<&'a str>,
+) {
+    if !visited.insert(lib) {
+        return;
+    }
+    if let Some(deps) = requires.get(lib) {
+        for dep in deps.iter().rev() {
+            visit(dep, requires, visited, order);
+        }
+    }
+    order.push(lib);
+}
+
+/// Sorts abseil libraries so that every library comes before its dependencies.
+///
+/// `requires` maps a module to the modules it requires, as listed in the
+/// pkg-config files abseil generates. Modules without libraries, like header
+/// only ones, are followed to their dependencies. Libraries without a
+/// pkg-config file are assumed to be at the top of the dependency graph.
+pub fn sort_absl_libs(libs: &mut Vec<String>, requires: &HashMap<String, Vec<String>>) {
+    libs.sort();
+    libs.dedup();
+    let mut visited = HashSet::new();
+    let mut order = vec![];
+    for lib in libs.iter().rev() {
+        visit(lib, requires, &mut visited, &mut order);
+    }
+    let pos: HashMap<String, usize> = order
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, l)| (l.to_string(), i))
+        .collect();
+    libs.sort_by_key(|l| pos[l]);
+}
+
+/// Finds all abseil libraries under `build_dir` in link order.
+pub fn find_absl_libs(build_dir: &Path, lib_suffix: &str) -> Vec<String> {
+    let mut libs = vec![];
+    let mut requires = HashMap::new();
+    for e in WalkDir::new(build_dir) {
+        let e = e.unwrap();
+        if !e.file_type().is_file() {
+            continue;
+        }
+        let file_name = e.file_name().to_string_lossy();
+        if let Some(name) = absl_lib_name(&file_name, lib_suffix) {
+            libs.push(name.to_owned());
+        } else if let Some(name) = absl_lib_name(&file_name, ".pc") {
+            let content = fs::read_to_string(e.path()).unwrap();
+            let mut deps = pc_requires(&content);
+            deps.sort();
+            requires.insert(name.to_owned(), deps);
+        }
+    }
+    sort_absl_libs(&mut libs, &requires);
+    libs
+}
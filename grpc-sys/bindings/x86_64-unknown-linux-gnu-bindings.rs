@@ -3735,6 +3735,12 @@ extern "C" {
         value_length: *mut usize,
     ) -> *const ::std::os::raw::c_char;
 }
+extern "C" {
+    pub fn grpcwrap_metadata_array_copy(
+        dest: *mut grpc_metadata_array,
+        src: *const grpc_metadata_array,
+    );
+}
 extern "C" {
     pub fn grpcwrap_metadata_array_cleanup(array: *mut grpc_metadata_array);
 }
@@ -3794,6 +3800,11 @@ extern "C" {
         details_length: *mut usize,
     ) -> *const ::std::os::raw::c_char;
 }
+extern "C" {
+    pub fn grpcwrap_batch_context_recv_status_on_client_error_string(
+        ctx: *const grpcwrap_batch_context,
+    ) -> *const ::std::os::raw::c_char;
+}
 extern "C" {
     pub fn grpcwrap_batch_context_recv_status_on_client_trailing_metadata(
         ctx: *const grpcwrap_batch_context,
@@ -3877,6 +3888,22 @@ extern "C" {
         vtable: *const grpc_arg_pointer_vtable,
     );
 }
+extern "C" {
+    pub fn grpcwrap_channel_args_set_socket_mutator(
+        args: *mut grpc_channel_args,
+        index: usize,
+        user_data: *mut ::std::os::raw::c_void,
+        mutate: ::std::option::Option<
+            unsafe extern "C" fn(
+                user_data: *mut ::std::os::raw::c_void,
+                fd: ::std::os::raw::c_int,
+            ) -> ::std::os::raw::c_int,
+        >,
+        destroy: ::std::option::Option<
+            unsafe extern "C" fn(user_data: *mut ::std::os::raw::c_void),
+        >,
+    );
+}
 extern "C" {
     pub fn grpcwrap_channel_args_destroy(args: *mut grpc_channel_args);
 }
@@ -3997,3 +4024,33 @@ extern "C" {
         tag: *mut ::std::os::raw::c_void,
     ) -> grpc_call_error;
 }
+extern "C" {
+    #[doc = " Decode a DER encoded PKCS#12 bundle, and write the certificate chain, leaf"]
+    #[doc = "first, and the private key in PEM to cert_chain and private_key. Returns 1"]
+    #[doc = "on success, and 0 if the bundle is malformed, the passphrase is wrong or"]
+    #[doc = "the bundle has no certificate or key. The slices are only set on success,"]
+    #[doc = "and should be unreffed by the caller."]
+    pub fn grpcwrap_pkcs12_to_pem(
+        der: *const ::std::os::raw::c_char,
+        der_len: usize,
+        passphrase: *const ::std::os::raw::c_char,
+        cert_chain: *mut grpc_slice,
+        private_key: *mut grpc_slice,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    #[doc = " Generate a self signed CA, and a certificate signed by it for the subject"]
+    #[doc = "alternative names san, like \"DNS:localhost,IP:127.0.0.1\". Both are valid"]
+    #[doc = "for valid_secs seconds and use P-256 keys. The PEM encoded CA certificate,"]
+    #[doc = "certificate and its private key are written to ca_cert, cert and"]
+    #[doc = "private_key. Returns 1 on success, and 0 otherwise. The slices are only"]
+    #[doc = "set on success, and should be unreffed by the caller."]
+    pub fn grpcwrap_generate_test_cert(
+        common_name: *const ::std::os::raw::c_char,
+        san: *const ::std::os::raw::c_char,
+        valid_secs: ::std::os::raw::c_long,
+        ca_cert: *mut grpc_slice,
+        cert: *mut grpc_slice,
+        private_key: *mut grpc_slice,
+    ) -> ::std::os::raw::c_int;
+}
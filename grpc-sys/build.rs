@@ -5,12 +5,19 @@ use std::env::VarError;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::{env, fs, io};
 
 use cmake::Config as CmakeConfig;
 use pkg_config::{Config as PkgConfig, Library};
 use walkdir::WalkDir;
 
+mod absl_libs;
+mod grpc_src;
+mod libclang;
+
+use grpc_src::GrpcSource;
+
 const GRPC_VERSION: &str = "1.33.1";
 
 fn probe_library(library: &str, cargo_metadata: bool) -> Library {
@@ -24,29 +31,16 @@ fn probe_library(library: &str, cargo_metadata: bool) -> Library {
     }
 }
 
-fn prepare_grpc() {
-    let modules = vec![
-        "grpc",
-        "grpc/third_party/cares/cares",
-        "grpc/third_party/address_sorting",
-        "grpc/third_party/abseil-cpp",
-        "grpc/third_party/re2",
-    ];
-
-    for module in modules {
-        if is_directory_empty(module).unwrap_or(true) {
-            panic!(
-                "Can't find module {}. You need to run `git submodule \
-                 update --init --recursive` first to build the project.",
-                module
-            );
-        }
+fn prepare_grpc() -> GrpcSource {
+    let external = get_env("GRPCIO_SYS_GRPC_SRC");
+    let source = GrpcSource::new(Path::new("grpc"), external.as_deref());
+    if let Err(e) = source.check() {
+        panic!("{}", e);
     }
-}
-
-fn is_directory_empty<P: AsRef<Path>>(p: P) -> Result<bool, io::Error> {
-    let mut entries = fs::read_dir(p)?;
-    Ok(entries.next().is_none())
+    if external.is_some() {
+        println!("cargo:rerun-if-changed={}", source.path().display());
+    }
+    source
 }
 
 fn trim_start<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
@@ -57,12 +51,12 @@ fn trim_start<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
     }
 }
 
-fn build_grpc(cc: &mut cc::Build, library: &str) {
-    prepare_grpc();
+fn build_grpc(cc: &mut cc::Build, library: &str) -> PathBuf {
+    let source = prepare_grpc();
 
     let target = env::var("TARGET").unwrap();
     let dst = {
-        let mut config = CmakeConfig::new("grpc");
+        let mut config = CmakeConfig::new(source.path());
 
         if get_env("CARGO_CFG_TARGET_OS").map_or(false, |s| s == "macos") {
             config.cxxflag("-stdlib=libc++");
@@ -127,9 +121,13 @@ fn build_grpc(cc: &mut cc::Build, library: &str) {
         if cfg!(feature = "openssl") {
             if cfg!(feature = "openssl-vendored") {
                 config.register_dep("openssl");
+                // ssl_wrap.cc uses OpenSSL directly to decode PKCS#12 bundles.
+                if let Ok(include) = env::var("DEP_OPENSSL_INCLUDE") {
+                    cc.include(include);
+                }
             }
         } else {
-            build_boringssl(&mut config);
+            build_boringssl(&mut config, cc);
         }
         if cfg!(feature = "no-omit-frame-pointer") {
             config
@@ -173,9 +171,15 @@ fn build_grpc(cc: &mut cc::Build, library: &str) {
     let mut libs = HashSet::new();
     collect("gpr", &mut libs);
     collect(library, &mut libs);
+    // The abseil libraries listed in pkg-config files change between versions and
+    // are not in link order, so they are discovered from the build output instead.
+    libs.retain(|l| !l.starts_with("absl_"));
     for l in libs {
         println!("cargo:rustc-link-lib=static={}", l);
     }
+    for l in absl_libs::find_absl_libs(Path::new(&build_dir), lib_suffix) {
+        println!("cargo:rustc-link-lib=static={}", l);
+    }
 
     if cfg!(feature = "secure") {
         if cfg!(feature = "openssl") && !cfg!(feature = "openssl-vendored") {
@@ -192,7 +196,10 @@ fn build_grpc(cc: &mut cc::Build, library: &str) {
         println!("cargo:rustc-link-lib=static=address_sorting");
     }
 
-    cc.include("grpc/include");
+    cc.include(source.include_dir());
+    // socket_mutator.cc uses a header of gRPC Core that is not installed.
+    cc.include(source.path());
+    source.include_dir()
 }
 
 fn figure_ssl_path(build_dir: &str) {
@@ -222,12 +229,14 @@ fn figure_ssl_path(build_dir: &str) {
     println!("cargo:rustc-link-lib=crypto");
 }
 
-fn build_boringssl(config: &mut CmakeConfig) {
+fn build_boringssl(config: &mut CmakeConfig, cc: &mut cc::Build) {
     let boringssl_artifact = boringssl_src::Build::new().build();
     config.define(
         "OPENSSL_ROOT_DIR",
         format!("{}", boringssl_artifact.root_dir().display()),
     );
+    // ssl_wrap.cc uses BoringSSL directly to decode PKCS#12 bundles.
+    cc.include(boringssl_artifact.root_dir().join("include"));
     // To avoid linking system library, set lib path explicitly.
     println!(
         "cargo:rustc-link-search=native={}",
@@ -263,12 +272,60 @@ fn get_env(name: &str) -> Option<String> {
     }
 }
 
+// Returns the name of the C++ standard library to link statically.
+fn static_cxx_stdlib(target: &str) -> Option<&'static str> {
+    if target.contains("msvc") {
+        // The C++ runtime of msvc is linked along with the C runtime.
+        None
+    } else if target.contains("android") {
+        Some("c++_static")
+    } else if target.contains("apple") {
+        // Apple only ships libc++ as a dylib, there is no libc++.a to link.
+        panic!(
+            "static-cxx is not supported on {}, the system does not provide a static libc++",
+            target
+        );
+    } else if target.contains("freebsd") || target.contains("openbsd") {
+        Some("c++")
+    } else {
+        Some("stdc++")
+    }
+}
+
+// Link the C++ standard library statically instead of the shared one cc links by
+// default, which is often missing or incompatible when building static binaries.
+fn link_static_cxx(cc: &mut cc::Build) {
+    let target = env::var("TARGET").unwrap();
+    let stdlib = match static_cxx_stdlib(&target) {
+        Some(stdlib) => stdlib,
+        None => return,
+    };
+    cc.cpp_link_stdlib(None);
+    // The static library usually lives in the private directory of the compiler,
+    // which is not searched by the linker by default.
+    let compiler = cc.get_compiler();
+    let output = Command::new(compiler.path())
+        .arg(format!("-print-file-name=lib{}.a", stdlib))
+        .output();
+    if let Ok(output) = output {
+        let path = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+        // The bare file name is printed if the library is not found.
+        if path.is_absolute() {
+            println!(
+                "cargo:rustc-link-search=native={}",
+                path.parent().unwrap().display()
+            );
+        }
+    }
+    println!("cargo:rustc-link-lib=static={}", stdlib);
+}
+
 // Generate the bindings to grpc C-core.
 // Try to disable the generation of platform-related bindings.
-fn bindgen_grpc(mut config: bindgen::Builder, file_path: &PathBuf) {
+fn bindgen_grpc(mut config: bindgen::Builder, file_path: &PathBuf, include_dir: &Path) {
     // Search header files with API interface
     let mut headers = Vec::new();
-    for result in WalkDir::new(Path::new("./grpc/include")) {
+    for result in WalkDir::new(include_dir) {
         let dent = result.expect("Error happened when search headers");
         if !dent.file_type().is_file() {
             continue;
@@ -294,7 +351,7 @@ fn bindgen_grpc(mut config: bindgen::Builder, file_path: &PathBuf) {
     let cfg = config
         .header("grpc_wrap.cc")
         .clang_arg("-xc++")
-        .clang_arg("-I./grpc/include")
+        .clang_arg(format!("-I{}", include_dir.display()))
         .clang_arg("-std=c++11")
         .rustfmt_bindings(true)
         .impl_debug(true)
@@ -326,31 +383,57 @@ fn bindgen_grpc(mut config: bindgen::Builder, file_path: &PathBuf) {
         .expect("Couldn't write bindings!");
 }
 
+// Checks libclang is available before running bindgen, which otherwise fails with
+// a confusing error deep in the generation.
+fn check_libclang() -> Result<(), String> {
+    let libclang_path = get_env("LIBCLANG_PATH");
+    libclang::find_libclang(libclang_path.as_deref(), &libclang::default_search_roots()).map(|_| ())
+}
+
 // Determine if need to update bindings. Supported platforms do not
 // need to be updated by default unless the UPDATE_BIND is specified.
-// Other platforms use bindgen to generate the bindings every time.
-fn config_binding_path(config: bindgen::Builder) {
+// Other platforms use bindgen to generate the bindings every time, and
+// fall back to a compatible pre-generated binding if libclang is missing
+// unless GRPCIO_SYS_STRICT_BINDGEN is specified.
+fn config_binding_path(config: bindgen::Builder, include_dir: &Path) {
     let file_path: PathBuf;
     let target = env::var("TARGET").unwrap();
-    match target.as_str() {
-        "x86_64-unknown-linux-gnu" | "aarch64-unknown-linux-gnu" => {
-            // Cargo treats nonexistent files changed, so we only emit the rerun-if-changed
-            // directive when we expect the target-specific pre-generated binding file to be
-            // present.
-            println!("cargo:rerun-if-changed=bindings/{}-bindings.rs", &target);
-
-            file_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-                .join("bindings")
-                .join(format!("{}-bindings.rs", &target));
-            if env::var("UPDATE_BIND").map(|s| s == "1").unwrap_or(false) {
-                bindgen_grpc(config, &file_path);
+    let bindings_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("bindings");
+    if libclang::PREBUILT_TARGETS.contains(&target.as_str()) {
+        // Cargo treats nonexistent files changed, so we only emit the rerun-if-changed
+        // directive when we expect the target-specific pre-generated binding file to be
+        // present.
+        println!("cargo:rerun-if-changed=bindings/{}-bindings.rs", &target);
+
+        file_path = bindings_dir.join(format!("{}-bindings.rs", &target));
+        if env::var("UPDATE_BIND").map(|s| s == "1").unwrap_or(false) {
+            if let Err(e) = check_libclang() {
+                panic!("{}", e);
             }
+            bindgen_grpc(config, &file_path, include_dir);
         }
-        _ => {
-            file_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("grpc-bindings.rs");
-            bindgen_grpc(config, &file_path);
+    } else {
+        let strict = get_env("GRPCIO_SYS_STRICT_BINDGEN").map_or(false, |s| s == "1");
+        match check_libclang() {
+            Ok(()) => {
+                file_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("grpc-bindings.rs");
+                bindgen_grpc(config, &file_path, include_dir);
+            }
+            Err(e) => match libclang::compatible_binding(&target) {
+                Some(compatible) if !strict => {
+                    println!(
+                        "cargo:warning=libclang is not found, using the pre-generated \
+                         bindings of {} for {}. Set `LIBCLANG_PATH` to generate bindings \
+                         for the target instead.",
+                        compatible, target
+                    );
+                    println!("cargo:rerun-if-changed=bindings/{}-bindings.rs", compatible);
+                    file_path = bindings_dir.join(format!("{}-bindings.rs", compatible));
+                }
+                _ => panic!("{}", e),
+            },
         }
-    };
+    }
     println!(
         "cargo:rustc-env=BINDING_PATH={}",
         file_path.to_str().unwrap()
@@ -359,6 +442,8 @@ fn config_binding_path(config: bindgen::Builder) {
 
 fn main() {
     println!("cargo:rerun-if-changed=grpc_wrap.cc");
+    println!("cargo:rerun-if-changed=socket_mutator.cc");
+    println!("cargo:rerun-if-changed=ssl_wrap.cc");
     println!("cargo:rerun-if-changed=grpc");
     println!("cargo:rerun-if-env-changed=UPDATE_BIND");
 
@@ -381,23 +466,39 @@ fn main() {
         bind_config = bind_config.clang_arg("-D _WIN32_WINNT=0x600");
     }
 
-    if get_env("GRPCIO_SYS_USE_PKG_CONFIG").map_or(false, |s| s == "1") {
+    let use_pkg_config = get_env("GRPCIO_SYS_USE_PKG_CONFIG").map_or(false, |s| s == "1");
+    let include_dir = if use_pkg_config {
         // Print cargo metadata.
         let lib_core = probe_library(library, true);
         for inc_path in lib_core.include_paths {
             cc.include(inc_path);
         }
+        PathBuf::from("grpc/include")
     } else {
-        build_grpc(&mut cc, library);
-    }
+        build_grpc(&mut cc, library)
+    };
 
     cc.cpp(true);
     if !cfg!(target_env = "msvc") {
         cc.flag("-std=c++11");
     }
+    if cfg!(feature = "static-cxx") || get_env("GRPCIO_SYS_STATIC_CXX").map_or(false, |s| s == "1")
+    {
+        link_static_cxx(&mut cc);
+    }
     cc.file("grpc_wrap.cc");
+    // socket_mutator.cc uses a header of gRPC Core that is not installed, which only
+    // matches the ABI of the bundled gRPC Core. gRPC Core only mutates sockets by file
+    // descriptors on Unix. grpcio checks `DEP_GRPCIO_SOCKET_MUTATOR` to expose it.
+    if !use_pkg_config && env::var("CARGO_CFG_UNIX").is_ok() {
+        cc.file("socket_mutator.cc");
+        println!("cargo:socket_mutator=1");
+    }
+    if cfg!(feature = "secure") {
+        cc.file("ssl_wrap.cc");
+    }
     cc.warnings_into_errors(true);
     cc.compile("libgrpc_wrap.a");
 
-    config_binding_path(bind_config);
+    config_binding_path(bind_config, &include_dir);
 }
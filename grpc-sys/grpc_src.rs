@@ -0,0 +1,76 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Locates the gRPC source tree to build.
+
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+// Submodules that need to be checked out to build the bundled gRPC.
+const SUBMODULES: &[&str] = &[
+    "",
+    "third_party/cares/cares",
+    "third_party/address_sorting",
+    "third_party/abseil-cpp",
+    "third_party/re2",
+];
+
+/// The gRPC source tree used by the build.
+pub struct GrpcSource {
+    path: PathBuf,
+    external: bool,
+}
+
+impl GrpcSource {
+    /// Uses the tree at `external` if specified, otherwise the bundled one at `bundled`.
+    pub fn new(bundled: &Path, external: Option<&str>) -> GrpcSource {
+        match external {
+            Some(path) => GrpcSource {
+                path: PathBuf::from(path),
+                external: true,
+            },
+            None => GrpcSource {
+                path: bundled.to_owned(),
+                external: false,
+            },
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn include_dir(&self) -> PathBuf {
+        self.path.join("include")
+    }
+
+    /// Checks whether the source tree can be built.
+    ///
+    /// An external tree is maintained by the user, so submodules are not checked.
+    pub fn check(&self) -> Result<(), String> {
+        if self.external {
+            if !self.path.join("CMakeLists.txt").is_file() {
+                return Err(format!(
+                    "{} is not a gRPC source tree, check `GRPCIO_SYS_GRPC_SRC`.",
+                    self.path.display()
+                ));
+            }
+            return Ok(());
+        }
+        for module in SUBMODULES {
+            let path = self.path.join(module);
+            if is_directory_empty(&path).unwrap_or(true) {
+                return Err(format!(
+                    "Can't find module {}. You need to run `git submodule \
+                     update --init --recursive` first to build the project.",
+                    path.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_directory_empty<P: AsRef<Path>>(p: P) -> Result<bool, io::Error> {
+    let mut entries = fs::read_dir(p)?;
+    Ok(entries.next().is_none())
+}
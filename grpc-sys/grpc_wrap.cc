@@ -44,6 +44,7 @@
 #include <grpc/support/log.h>
 #include <grpc/support/port_platform.h>
 #include <grpc/support/string_util.h>
+#include <grpc/support/sync.h>
 #include <grpc/support/thd_id.h>
 
 #ifdef GRPC_SYS_SECURE
@@ -87,6 +88,7 @@ typedef struct grpcwrap_batch_context {
     grpc_metadata_array trailing_metadata;
     grpc_status_code status;
     grpc_slice status_details;
+    const char* error_string;
   } recv_status_on_client;
   int recv_close_on_server_cancelled;
 } grpcwrap_batch_context;
@@ -183,6 +185,17 @@ GPR_EXPORT void GPR_CALLTYPE grpcwrap_metadata_array_add(
   array->count++;
 }
 
+/* Copy the entries of src by sharing their slices instead of the contents */
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_metadata_array_copy(
+    grpc_metadata_array* dest, const grpc_metadata_array* src) {
+  grpcwrap_metadata_array_init(dest, src->count);
+  for (size_t i = 0; i < src->count; i++) {
+    dest->metadata[i].key = grpc_slice_ref(src->metadata[i].key);
+    dest->metadata[i].value = grpc_slice_ref(src->metadata[i].value);
+  }
+  dest->count = src->count;
+}
+
 GPR_EXPORT const char* GPR_CALLTYPE grpcwrap_metadata_array_get_key(
     const grpc_metadata_array* array, size_t index, size_t* key_length) {
   GPR_ASSERT(index < array->count);
@@ -258,6 +271,7 @@ grpcwrap_batch_context_destroy(grpcwrap_batch_context* ctx) {
   grpcwrap_metadata_array_destroy_metadata_only(
       &(ctx->recv_status_on_client.trailing_metadata));
   grpc_slice_unref(ctx->recv_status_on_client.status_details);
+  gpr_free((void*)ctx->recv_status_on_client.error_string);
 
   gpr_free(ctx);
 }
@@ -331,6 +345,12 @@ grpcwrap_batch_context_recv_status_on_client_details(
   return (char*)GRPC_SLICE_START_PTR(ctx->recv_status_on_client.status_details);
 }
 
+GPR_EXPORT const char* GPR_CALLTYPE
+grpcwrap_batch_context_recv_status_on_client_error_string(
+    const grpcwrap_batch_context* ctx) {
+  return ctx->recv_status_on_client.error_string;
+}
+
 GPR_EXPORT const grpc_metadata_array* GPR_CALLTYPE
 grpcwrap_batch_context_recv_status_on_client_trailing_metadata(
     const grpcwrap_batch_context* ctx) {
@@ -446,6 +466,17 @@ GPR_EXPORT void GPR_CALLTYPE grpcwrap_channel_args_set_pointer_vtable(
   args->args[index].value.pointer.vtable = vtable;
 }
 
+typedef int (*grpcwrap_mutate_fd_fn)(void* user_data, int fd);
+typedef void (*grpcwrap_destroy_fn)(void* user_data);
+
+/* Sets a socket mutator that calls `mutate` with `user_data` and the file
+   descriptor of every new socket. `destroy` is called with `user_data` once
+   the mutator is not used anymore. Defined in socket_mutator.cc, which is only
+   built on Unix along with the bundled gRPC Core. */
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_channel_args_set_socket_mutator(
+    grpc_channel_args* args, size_t index, void* user_data,
+    grpcwrap_mutate_fd_fn mutate, grpcwrap_destroy_fn destroy);
+
 GPR_EXPORT void GPR_CALLTYPE
 grpcwrap_channel_args_destroy(grpc_channel_args* args) {
   size_t i;
@@ -510,6 +541,8 @@ GPR_EXPORT grpc_call_error GPR_CALLTYPE grpcwrap_call_start_unary(
       &(ctx->recv_status_on_client.status);
   ops[5].data.recv_status_on_client.status_details =
       &(ctx->recv_status_on_client.status_details);
+  ops[5].data.recv_status_on_client.error_string =
+      &(ctx->recv_status_on_client.error_string);
   ops[5].flags = 0;
   ops[5].reserved = nullptr;
 
@@ -550,6 +583,8 @@ GPR_EXPORT grpc_call_error GPR_CALLTYPE grpcwrap_call_start_client_streaming(
       &(ctx->recv_status_on_client.status);
   ops[3].data.recv_status_on_client.status_details =
       &(ctx->recv_status_on_client.status_details);
+  ops[3].data.recv_status_on_client.error_string =
+      &(ctx->recv_status_on_client.error_string);
   ops[3].flags = 0;
   ops[3].reserved = nullptr;
 
@@ -589,6 +624,8 @@ GPR_EXPORT grpc_call_error GPR_CALLTYPE grpcwrap_call_start_server_streaming(
       &(ctx->recv_status_on_client.status);
   ops[3].data.recv_status_on_client.status_details =
       &(ctx->recv_status_on_client.status_details);
+  ops[3].data.recv_status_on_client.error_string =
+      &(ctx->recv_status_on_client.error_string);
   ops[3].flags = 0;
   ops[3].reserved = nullptr;
 
@@ -618,6 +655,8 @@ GPR_EXPORT grpc_call_error GPR_CALLTYPE grpcwrap_call_start_duplex_streaming(
       &(ctx->recv_status_on_client.status);
   ops[1].data.recv_status_on_client.status_details =
       &(ctx->recv_status_on_client.status_details);
+  ops[1].data.recv_status_on_client.error_string =
+      &(ctx->recv_status_on_client.error_string);
   ops[1].flags = 0;
   ops[1].reserved = nullptr;
 
@@ -773,3 +812,29 @@ grpcwrap_server_request_call(grpc_server* server, grpc_completion_queue* cq,
   return grpc_server_request_call(server, &(ctx->call), &(ctx->call_details),
                                   &(ctx->request_metadata), cq, cq, tag);
 }
+
+#ifdef GRPC_SYS_SECURE
+
+/** Decode a DER encoded PKCS#12 bundle, and write the certificate chain, leaf
+    first, and the private key in PEM to cert_chain and private_key. Returns 1
+    on success, and 0 if the bundle is malformed, the passphrase is wrong or
+    the bundle has no certificate or key. The slices are only set on success,
+    and should be unreffed by the caller. Defined in ssl_wrap.cc. */
+GPR_EXPORT int GPR_CALLTYPE grpcwrap_pkcs12_to_pem(const char* der,
+                                                  size_t der_len,
+                                                  const char* passphrase,
+                                                  grpc_slice* cert_chain,
+                                                  grpc_slice* private_key);
+
+/** Generate a self signed CA, and a certificate signed by it for the subject
+    alternative names san, like "DNS:localhost,IP:127.0.0.1". Both are valid
+    for valid_secs seconds and use P-256 keys. The PEM encoded CA certificate,
+    certificate and its private key are written to ca_cert, cert and
+    private_key. Returns 1 on success, and 0 otherwise. The slices are only
+    set on success, and should be unreffed by the caller. Defined in
+    ssl_wrap.cc. */
+GPR_EXPORT int GPR_CALLTYPE grpcwrap_generate_test_cert(
+    const char* common_name, const char* san, long valid_secs,
+    grpc_slice* ca_cert, grpc_slice* cert, grpc_slice* private_key);
+
+#endif
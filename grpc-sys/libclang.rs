@@ -0,0 +1,155 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Checks whether libclang is available before generating bindings.
+//!
+//! bindgen loads libclang at runtime and only reports a missing library deep
+//! inside the generation, so probe the same locations as clang-sys up front.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Targets that have pre-generated bindings in the `bindings` directory.
+pub const PREBUILT_TARGETS: &[&str] = &["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"];
+
+// How deep directories like `/usr/lib` are searched, e.g. `/usr/lib/llvm-10/lib`.
+const SEARCH_DEPTH: usize = 2;
+
+/// Returns the pre-generated binding that can be used for `target`.
+///
+/// Bindings only depend on the architecture and the OS as platform-related types
+/// are not generated, so a gnu or musl linux target matches the one with the same
+/// architecture.
+pub fn compatible_binding(target: &str) -> Option<&'static str> {
+    let arch = target.split('-').next()?;
+    if !target.contains("-linux-gnu") && !target.contains("-linux-musl") {
+        return None;
+    }
+    PREBUILT_TARGETS
+        .iter()
+        .find(|t| t.split('-').next() == Some(arch))
+        .cloned()
+}
+
+/// Checks whether `name` is the file name of a libclang shared library on the host.
+pub fn is_libclang(name: &str) -> bool {
+    if cfg!(windows) {
+        name == "libclang.dll" || name == "clang.dll"
+    } else if cfg!(target_os = "macos") {
+        name.starts_with("libclang") && name.ends_with(".dylib")
+    } else {
+        // Like `libclang.so`, `libclang.so.10` or `libclang-10.so`.
+        name.starts_with("libclang.so") || (name.starts_with("libclang-") && name.ends_with(".so"))
+    }
+}
+
+fn find_in(dir: &Path, depth: usize) -> Option<PathBuf> {
+    let entries = fs::read_dir(dir).ok()?;
+    let mut dirs = vec![];
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            dirs.push(path);
+        } else if entry.file_name().to_str().map_or(false, is_libclang) {
+            return Some(path);
+        }
+    }
+    if depth == 0 {
+        return None;
+    }
+    dirs.sort();
+    dirs.iter().find_map(|d| find_in(d, depth - 1))
+}
+
+/// Finds libclang in `LIBCLANG_PATH` if specified, otherwise in `roots`.
+///
+/// On failure the returned message explains how to make libclang available.
+pub fn find_libclang(libclang_path: Option<&str>, roots: &[PathBuf]) -> Result<PathBuf, String> {
+    if let Some(p) = libclang_path {
+        let path = Path::new(p);
+        let found = if path.is_file() {
+            Some(path.to_owned())
+        } else {
+            find_in(path, 0)
+        };
+        return found.ok_or_else(|| {
+            format!(
+                "`LIBCLANG_PATH` is set to {}, but no libclang shared library is found \
+                 there. Point it at the directory that contains libclang.",
+                p
+            )
+        });
+    }
+    if let Some(path) = roots.iter().find_map(|d| find_in(d, SEARCH_DEPTH)) {
+        return Ok(path);
+    }
+    Err(format!(
+        "Unable to find libclang, which is required to generate bindings for targets \
+         without pre-generated bindings ({}). Install it, for example by `apt install \
+         libclang-dev` on Debian/Ubuntu, `brew install llvm` on macOS or the LLVM \
+         installer from https://releases.llvm.org on Windows, and set `LIBCLANG_PATH` \
+         to the directory that contains it if it's not in a standard location.",
+        PREBUILT_TARGETS.join(", ")
+    ))
+}
+
+// Lists the entries of `dir` whose names start with `prefix`, like `dir/prefix*`.
+fn expand(dir: &str, prefix: &str) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with(prefix))
+        .map(|e| e.path())
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Directories where libclang is usually installed on the host.
+pub fn default_search_roots() -> Vec<PathBuf> {
+    let mut roots = vec![];
+    let flag = if cfg!(windows) {
+        "--bindir"
+    } else {
+        "--libdir"
+    };
+    if let Ok(output) = Command::new("llvm-config").arg(flag).output() {
+        if output.status.success() {
+            let dir = String::from_utf8_lossy(&output.stdout);
+            roots.push(PathBuf::from(dir.trim()));
+        }
+    }
+    let path_var = if cfg!(windows) {
+        "PATH"
+    } else if cfg!(target_os = "macos") {
+        "DYLD_LIBRARY_PATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    };
+    if let Some(paths) = std::env::var_os(path_var) {
+        roots.extend(std::env::split_paths(&paths));
+    }
+    if cfg!(windows) {
+        roots.push(PathBuf::from("C:\\LLVM\\bin"));
+        for dir in expand("C:\\", "Program Files") {
+            roots.push(dir.join("LLVM").join("bin"));
+        }
+    } else {
+        roots.extend(
+            expand("/usr/local/opt", "llvm")
+                .into_iter()
+                .map(|d| d.join("lib")),
+        );
+        roots.push(PathBuf::from("/Library/Developer/CommandLineTools/usr/lib"));
+        roots.push(PathBuf::from(
+            "/Applications/Xcode.app/Contents/Developer/Toolchains/XcodeDefault.xctoolchain/usr/lib",
+        ));
+        roots.extend(expand("/usr", "lib"));
+        roots.extend(expand("/usr/local", "lib"));
+        roots.extend(expand("/usr/local", "llvm"));
+    }
+    roots
+}
@@ -0,0 +1,81 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+/* The socket mutator API of gRPC Core is declared in a header that is not
+   installed with the public ones. It's kept out of grpc_wrap.cc, so the
+   declarations of the header don't end up in the generated bindings. */
+
+#include <grpc/support/port_platform.h>
+
+#include <grpc/support/alloc.h>
+
+#include "src/core/lib/iomgr/socket_mutator.h"
+
+#ifdef GPR_WINDOWS
+#define GPR_EXPORT extern "C" __declspec(dllexport)
+#define GPR_CALLTYPE __cdecl
+#endif
+
+#ifndef GPR_EXPORT
+#define GPR_EXPORT extern "C"
+#endif
+
+#ifndef GPR_CALLTYPE
+#define GPR_CALLTYPE
+#endif
+
+/* Defined in grpc_wrap.cc. */
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_channel_args_set_pointer_vtable(
+    grpc_channel_args* args, size_t index, const char* key, void* value,
+    const grpc_arg_pointer_vtable* vtable);
+
+typedef int (*grpcwrap_mutate_fd_fn)(void* user_data, int fd);
+typedef void (*grpcwrap_destroy_fn)(void* user_data);
+
+typedef struct {
+  /* Must be the first member, gRPC Core only knows about it. */
+  grpc_socket_mutator base;
+  void* user_data;
+  grpcwrap_mutate_fd_fn mutate;
+  grpcwrap_destroy_fn destroy;
+} grpcwrap_socket_mutator;
+
+static bool grpcwrap_socket_mutator_mutate_fd(int fd,
+                                              grpc_socket_mutator* base) {
+  auto* mutator = (grpcwrap_socket_mutator*)base;
+  return mutator->mutate(mutator->user_data, fd) != 0;
+}
+
+static int grpcwrap_socket_mutator_compare(grpc_socket_mutator* a,
+                                           grpc_socket_mutator* b) {
+  return a < b ? -1 : (a > b ? 1 : 0);
+}
+
+static void grpcwrap_socket_mutator_destroy(grpc_socket_mutator* base) {
+  auto* mutator = (grpcwrap_socket_mutator*)base;
+  mutator->destroy(mutator->user_data);
+  gpr_free(mutator);
+}
+
+static const grpc_socket_mutator_vtable grpcwrap_socket_mutator_vtable = {
+    grpcwrap_socket_mutator_mutate_fd, grpcwrap_socket_mutator_compare,
+    grpcwrap_socket_mutator_destroy};
+
+/* Sets a socket mutator that calls `mutate` with `user_data` and the file
+   descriptor of every new socket. `destroy` is called with `user_data` once
+   the mutator is not used anymore. */
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_channel_args_set_socket_mutator(
+    grpc_channel_args* args, size_t index, void* user_data,
+    grpcwrap_mutate_fd_fn mutate, grpcwrap_destroy_fn destroy) {
+  auto* mutator =
+      (grpcwrap_socket_mutator*)gpr_malloc(sizeof(grpcwrap_socket_mutator));
+  grpc_socket_mutator_init(&mutator->base, &grpcwrap_socket_mutator_vtable);
+  mutator->user_data = user_data;
+  mutator->mutate = mutate;
+  mutator->destroy = destroy;
+  grpc_arg arg = grpc_socket_mutator_to_arg(&mutator->base);
+  grpcwrap_channel_args_set_pointer_vtable(args, index, arg.key,
+                                           arg.value.pointer.p,
+                                           arg.value.pointer.vtable);
+  /* The argument holds its own reference. */
+  grpc_socket_mutator_unref(&mutator->base);
+}
@@ -2,6 +2,9 @@
 
 use super::*;
 
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
 use std::time::Duration;
 
 impl gpr_timespec {
@@ -19,3 +22,147 @@ impl From<Duration> for gpr_timespec {
         }
     }
 }
+
+/// Error returned when parsing an unknown compression algorithm or level name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCompressionError {
+    kind: &'static str,
+    name: String,
+}
+
+impl fmt::Display for ParseCompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown compression {}: {:?}", self.kind, self.name)
+    }
+}
+
+impl Error for ParseCompressionError {}
+
+impl grpc_compression_algorithm {
+    /// All the algorithms supported by gRPC Core.
+    pub const ALL: &'static [grpc_compression_algorithm] = &[
+        grpc_compression_algorithm::GRPC_COMPRESS_NONE,
+        grpc_compression_algorithm::GRPC_COMPRESS_DEFLATE,
+        grpc_compression_algorithm::GRPC_COMPRESS_GZIP,
+        grpc_compression_algorithm::GRPC_COMPRESS_STREAM_GZIP,
+    ];
+
+    /// The name of the algorithm used in the `grpc-encoding` header, like `gzip`.
+    ///
+    /// `GRPC_COMPRESS_ALGORITHMS_COUNT` is not an algorithm, so it's named `unknown`.
+    pub fn name(self) -> &'static str {
+        match self {
+            grpc_compression_algorithm::GRPC_COMPRESS_NONE => "identity",
+            grpc_compression_algorithm::GRPC_COMPRESS_DEFLATE => "deflate",
+            grpc_compression_algorithm::GRPC_COMPRESS_GZIP => "gzip",
+            grpc_compression_algorithm::GRPC_COMPRESS_STREAM_GZIP => "stream/gzip",
+            grpc_compression_algorithm::GRPC_COMPRESS_ALGORITHMS_COUNT => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for grpc_compression_algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for grpc_compression_algorithm {
+    type Err = ParseCompressionError;
+
+    /// Parse the name of an algorithm, like `gzip`. Names are case insensitive.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        grpc_compression_algorithm::ALL
+            .iter()
+            .find(|a| a.name().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or_else(|| ParseCompressionError {
+                kind: "algorithm",
+                name: s.to_owned(),
+            })
+    }
+}
+
+impl grpc_compression_level {
+    /// All the compression levels, from the lowest to the highest.
+    pub const ALL: &'static [grpc_compression_level] = &[
+        grpc_compression_level::GRPC_COMPRESS_LEVEL_NONE,
+        grpc_compression_level::GRPC_COMPRESS_LEVEL_LOW,
+        grpc_compression_level::GRPC_COMPRESS_LEVEL_MED,
+        grpc_compression_level::GRPC_COMPRESS_LEVEL_HIGH,
+    ];
+
+    /// The name of the level, like `low`.
+    ///
+    /// `GRPC_COMPRESS_LEVEL_COUNT` is not a level, so it's named `unknown`.
+    pub fn name(self) -> &'static str {
+        match self {
+            grpc_compression_level::GRPC_COMPRESS_LEVEL_NONE => "none",
+            grpc_compression_level::GRPC_COMPRESS_LEVEL_LOW => "low",
+            grpc_compression_level::GRPC_COMPRESS_LEVEL_MED => "medium",
+            grpc_compression_level::GRPC_COMPRESS_LEVEL_HIGH => "high",
+            grpc_compression_level::GRPC_COMPRESS_LEVEL_COUNT => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for grpc_compression_level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for grpc_compression_level {
+    type Err = ParseCompressionError;
+
+    /// Parse the name of a level, like `low`. Names are case insensitive, and
+    /// `med` is accepted for `medium`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("med") {
+            return Ok(grpc_compression_level::GRPC_COMPRESS_LEVEL_MED);
+        }
+        grpc_compression_level::ALL
+            .iter()
+            .find(|l| l.name().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or_else(|| ParseCompressionError {
+                kind: "level",
+                name: s.to_owned(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_compression_algorithm() {
+        for algo in grpc_compression_algorithm::ALL {
+            assert_eq!(algo.to_string().parse(), Ok(*algo));
+        }
+        assert_eq!(
+            "GZIP".parse(),
+            Ok(grpc_compression_algorithm::GRPC_COMPRESS_GZIP)
+        );
+        for invalid in &["", "unknown", "zstd", "gzip "] {
+            let err = invalid.parse::<grpc_compression_algorithm>().unwrap_err();
+            assert!(err.to_string().contains("algorithm"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_parse_compression_level() {
+        for level in grpc_compression_level::ALL {
+            assert_eq!(level.to_string().parse(), Ok(*level));
+        }
+        assert_eq!(
+            "med".parse(),
+            Ok(grpc_compression_level::GRPC_COMPRESS_LEVEL_MED)
+        );
+        for invalid in &["", "unknown", "max"] {
+            let err = invalid.parse::<grpc_compression_level>().unwrap_err();
+            assert!(err.to_string().contains("level"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+/* Helpers that use the SSL library of gRPC Core directly. They are kept out
+   of grpc_wrap.cc, so bindgen doesn't need the SSL headers to generate the
+   bindings. */
+
+#include <grpc/slice.h>
+#include <grpc/support/alloc.h>
+#include <grpc/support/port_platform.h>
+#include <grpc/support/string_util.h>
+
+#include <openssl/bio.h>
+#include <openssl/ec.h>
+#include <openssl/evp.h>
+#include <openssl/objects.h>
+#include <openssl/pem.h>
+#include <openssl/pkcs12.h>
+#include <openssl/x509.h>
+#include <openssl/x509v3.h>
+
+#ifdef GPR_WINDOWS
+#define GPR_EXPORT extern "C" __declspec(dllexport)
+#define GPR_CALLTYPE __cdecl
+#endif
+
+#ifndef GPR_EXPORT
+#define GPR_EXPORT extern "C"
+#endif
+
+#ifndef GPR_CALLTYPE
+#define GPR_CALLTYPE
+#endif
+
+static grpc_slice grpcwrap_bio_to_slice(BIO* bio) {
+  char* data = nullptr;
+  long len = BIO_get_mem_data(bio, &data);
+  return grpc_slice_from_copied_buffer(data, (size_t)len);
+}
+
+/** Decode a DER encoded PKCS#12 bundle, and write the certificate chain, leaf
+    first, and the private key in PEM to cert_chain and private_key. Returns 1
+    on success, and 0 if the bundle is malformed, the passphrase is wrong or
+    the bundle has no certificate or key. The slices are only set on success,
+    and should be unreffed by the caller. */
+GPR_EXPORT int GPR_CALLTYPE grpcwrap_pkcs12_to_pem(const char* der,
+                                                  size_t der_len,
+                                                  const char* passphrase,
+                                                  grpc_slice* cert_chain,
+                                                  grpc_slice* private_key) {
+  const unsigned char* p = (const unsigned char*)der;
+  PKCS12* p12 = d2i_PKCS12(nullptr, &p, (long)der_len);
+  if (p12 == nullptr) {
+    return 0;
+  }
+  EVP_PKEY* pkey = nullptr;
+  X509* cert = nullptr;
+  STACK_OF(X509)* ca = nullptr;
+  int ok = PKCS12_parse(p12, passphrase, &pkey, &cert, &ca);
+  PKCS12_free(p12);
+  BIO* certs = nullptr;
+  BIO* key = nullptr;
+  if (ok) {
+    certs = BIO_new(BIO_s_mem());
+    key = BIO_new(BIO_s_mem());
+    ok = certs != nullptr && key != nullptr && cert != nullptr &&
+         pkey != nullptr && PEM_write_bio_X509(certs, cert) &&
+         PEM_write_bio_PrivateKey(key, pkey, nullptr, nullptr, 0, nullptr,
+                                  nullptr);
+  }
+  for (int i = 0; ok && ca != nullptr && i < (int)sk_X509_num(ca); i++) {
+    ok = PEM_write_bio_X509(certs, sk_X509_value(ca, i));
+  }
+  if (ok) {
+    *cert_chain = grpcwrap_bio_to_slice(certs);
+    *private_key = grpcwrap_bio_to_slice(key);
+  }
+  BIO_free(key);
+  BIO_free(certs);
+  X509_free(cert);
+  EVP_PKEY_free(pkey);
+  sk_X509_pop_free(ca, X509_free);
+  return ok ? 1 : 0;
+}
+
+static EVP_PKEY* grpcwrap_new_test_key() {
+  EVP_PKEY* pkey = nullptr;
+  EVP_PKEY_CTX* ctx = EVP_PKEY_CTX_new_id(EVP_PKEY_EC, nullptr);
+  int ok = ctx != nullptr && EVP_PKEY_keygen_init(ctx) > 0 &&
+           EVP_PKEY_CTX_set_ec_paramgen_curve_nid(ctx, NID_X9_62_prime256v1) >
+               0 &&
+           EVP_PKEY_keygen(ctx, &pkey) > 0;
+  if (!ok) {
+    EVP_PKEY_free(pkey);
+    pkey = nullptr;
+  }
+  EVP_PKEY_CTX_free(ctx);
+  return pkey;
+}
+
+static int grpcwrap_add_test_ext(X509* cert, X509V3_CTX* ctx, int nid,
+                                 const char* value) {
+  /* Older BoringSSL takes a mutable value. */
+  char* v = gpr_strdup(value);
+  X509_EXTENSION* ext = X509V3_EXT_conf_nid(nullptr, ctx, nid, v);
+  gpr_free(v);
+  int ok = ext != nullptr && X509_add_ext(cert, ext, -1);
+  X509_EXTENSION_free(ext);
+  return ok;
+}
+
+/* Create a certificate for pkey signed by issuer_key. The certificate is self
+   signed if issuer is null. */
+static X509* grpcwrap_new_test_cert(EVP_PKEY* pkey, const char* common_name,
+                                    const char* san, X509* issuer,
+                                    EVP_PKEY* issuer_key, long serial,
+                                    long valid_secs) {
+  X509* cert = X509_new();
+  if (cert == nullptr) {
+    return nullptr;
+  }
+  X509_NAME* name = X509_get_subject_name(cert);
+  int ok =
+      X509_set_version(cert, 2) &&
+      ASN1_INTEGER_set(X509_get_serialNumber(cert), serial) &&
+      X509_gmtime_adj(X509_getm_notBefore(cert), -60) &&
+      X509_gmtime_adj(X509_getm_notAfter(cert), valid_secs) &&
+      X509_set_pubkey(cert, pkey) &&
+      X509_NAME_add_entry_by_txt(name, "CN", MBSTRING_ASC,
+                                 (const unsigned char*)common_name, -1, -1,
+                                 0) &&
+      X509_set_issuer_name(
+          cert, issuer != nullptr ? X509_get_subject_name(issuer) : name);
+  X509V3_CTX ctx;
+  X509V3_set_ctx(&ctx, issuer != nullptr ? issuer : cert, cert, nullptr,
+                 nullptr, 0);
+  if (issuer == nullptr) {
+    ok = ok &&
+         grpcwrap_add_test_ext(cert, &ctx, NID_basic_constraints,
+                               "critical,CA:TRUE") &&
+         grpcwrap_add_test_ext(cert, &ctx, NID_key_usage,
+                               "critical,keyCertSign,cRLSign");
+  } else {
+    ok = ok &&
+         grpcwrap_add_test_ext(cert, &ctx, NID_basic_constraints,
+                               "CA:FALSE") &&
+         grpcwrap_add_test_ext(cert, &ctx, NID_key_usage,
+                               "critical,digitalSignature") &&
+         grpcwrap_add_test_ext(cert, &ctx, NID_ext_key_usage,
+                               "serverAuth,clientAuth") &&
+         grpcwrap_add_test_ext(cert, &ctx, NID_subject_alt_name, san);
+  }
+  ok = ok && X509_sign(cert, issuer_key, EVP_sha256()) > 0;
+  if (!ok) {
+    X509_free(cert);
+    return nullptr;
+  }
+  return cert;
+}
+
+/** Generate a self signed CA, and a certificate signed by it for the subject
+    alternative names san, like "DNS:localhost,IP:127.0.0.1". Both are valid
+    for valid_secs seconds and use P-256 keys. The PEM encoded CA certificate,
+    certificate and its private key are written to ca_cert, cert and
+    private_key. Returns 1 on success, and 0 otherwise. The slices are only
+    set on success, and should be unreffed by the caller. */
+GPR_EXPORT int GPR_CALLTYPE grpcwrap_generate_test_cert(
+    const char* common_name, const char* san, long valid_secs,
+    grpc_slice* ca_cert, grpc_slice* cert, grpc_slice* private_key) {
+  EVP_PKEY* ca_key = grpcwrap_new_test_key();
+  EVP_PKEY* key = grpcwrap_new_test_key();
+  X509* ca = nullptr;
+  X509* leaf = nullptr;
+  BIO* ca_bio = BIO_new(BIO_s_mem());
+  BIO* cert_bio = BIO_new(BIO_s_mem());
+  BIO* key_bio = BIO_new(BIO_s_mem());
+  int ok = ca_key != nullptr && key != nullptr && ca_bio != nullptr &&
+           cert_bio != nullptr && key_bio != nullptr;
+  if (ok) {
+    ca = grpcwrap_new_test_cert(ca_key, "grpc-rs test CA", nullptr, nullptr,
+                                ca_key, 1, valid_secs);
+  }
+  if (ca != nullptr) {
+    leaf = grpcwrap_new_test_cert(key, common_name, san, ca, ca_key, 2,
+                                  valid_secs);
+  }
+  ok = leaf != nullptr && PEM_write_bio_X509(ca_bio, ca) &&
+       PEM_write_bio_X509(cert_bio, leaf) &&
+       PEM_write_bio_PrivateKey(key_bio, key, nullptr, nullptr, 0, nullptr,
+                                nullptr);
+  if (ok) {
+    *ca_cert = grpcwrap_bio_to_slice(ca_bio);
+    *cert = grpcwrap_bio_to_slice(cert_bio);
+    *private_key = grpcwrap_bio_to_slice(key_bio);
+  }
+  BIO_free(key_bio);
+  BIO_free(cert_bio);
+  BIO_free(ca_bio);
+  X509_free(leaf);
+  X509_free(ca);
+  EVP_PKEY_free(key);
+  EVP_PKEY_free(ca_key);
+  return ok ? 1 : 0;
+}
@@ -0,0 +1,113 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+#[path = "../absl_libs.rs"]
+mod absl_libs;
+mod common;
+
+use std::collections::HashMap;
+
+use absl_libs::{find_absl_libs, pc_requires, sort_absl_libs};
+use common::Fixture;
+
+#[test]
+fn test_find_absl_libs() {
+    let fixture = Fixture::new(
+        "grpcio-sys-absl",
+        &[
+            "third_party/abseil-cpp/absl/base/libabsl_base.a",
+            "third_party/abseil-cpp/absl/base/libabsl_raw_logging_internal.a",
+            "third_party/abseil-cpp/absl/base/libabsl_log_severity.a",
+            "third_party/abseil-cpp/absl/strings/libabsl_strings.a",
+            "third_party/abseil-cpp/absl/strings/libabsl_str_format_internal.a",
+            "third_party/abseil-cpp/absl/status/libabsl_status.a",
+            "third_party/abseil-cpp/absl/new/libabsl_brand_new.a",
+            "third_party/abseil-cpp/absl/types/libabsl_bad_optional_access.a",
+            "libgrpc.a",
+            "libabsl_base.so",
+            "CMakeFiles/absl_base.dir/base/internal/spinlock.cc.o",
+        ],
+    );
+    let pcs = [
+        ("absl_base", "absl_atomic_hook = 20200923, absl_base_internal = 20200923, absl_config = 20200923, absl_core_headers = 20200923, absl_dynamic_annotations = 20200923, absl_log_severity = 20200923, absl_raw_logging_internal = 20200923, absl_spinlock_wait = 20200923"),
+        ("absl_raw_logging_internal", "absl_atomic_hook = 20200923, absl_config = 20200923, absl_core_headers = 20200923, absl_log_severity = 20200923"),
+        ("absl_log_severity", "absl_core_headers = 20200923"),
+        ("absl_core_headers", "absl_config = 20200923"),
+        ("absl_strings", "absl_base = 20200923, absl_config = 20200923, absl_core_headers = 20200923, absl_raw_logging_internal = 20200923, absl_throw_delegate = 20200923"),
+        ("absl_str_format_internal", "absl_strings = 20200923, absl_config = 20200923, absl_core_headers = 20200923"),
+        ("absl_status", "absl_strings = 20200923, absl_str_format_internal = 20200923, absl_optional = 20200923, absl_raw_logging_internal = 20200923"),
+        // Header only, its dependencies are still followed.
+        ("absl_optional", "absl_bad_optional_access = 20200923, absl_config = 20200923"),
+        ("absl_bad_optional_access", "absl_config = 20200923, absl_raw_logging_internal = 20200923"),
+    ];
+    for (name, requires) in &pcs {
+        fixture.write(
+            &format!("lib/pkgconfig/{}.pc", name),
+            &format!(
+                "Name: {}\nVersion: 20200923\nRequires: {}\nLibs: -l{}\n",
+                name, requires, name
+            ),
+        );
+    }
+    let libs = find_absl_libs(&fixture.0, ".a");
+    assert_eq!(
+        libs,
+        vec![
+            // Libraries without pkg-config files are linked first.
+            "absl_brand_new",
+            "absl_status",
+            "absl_bad_optional_access",
+            "absl_str_format_internal",
+            "absl_strings",
+            "absl_base",
+            "absl_raw_logging_internal",
+            "absl_log_severity",
+        ]
+    );
+}
+
+#[test]
+fn test_find_absl_libs_msvc() {
+    let fixture = Fixture::new(
+        "grpcio-sys-absl-msvc",
+        &[
+            "third_party/abseil-cpp/absl/base/Release/absl_base.lib",
+            "third_party/abseil-cpp/absl/time/Release/absl_time.lib",
+            "Release/grpc.lib",
+        ],
+    );
+    fixture.write(
+        "lib/pkgconfig/absl_time.pc",
+        "Requires: absl_base = 20200923, absl_civil_time = 20200923\n",
+    );
+    let libs = find_absl_libs(&fixture.0, ".lib");
+    assert_eq!(libs, vec!["absl_time", "absl_base"]);
+}
+
+#[test]
+fn test_pc_requires() {
+    let pc = "prefix=/usr\n\
+              Name: absl_time\n\
+              Requires: absl_base = 20200923, absl_civil_time = 20200923\n\
+              Requires.private: absl_int128 = 20200923,openssl\n\
+              Libs: -L${libdir} -labsl_time\n";
+    assert_eq!(
+        pc_requires(pc),
+        vec!["absl_base", "absl_civil_time", "absl_int128"]
+    );
+    assert!(pc_requires("Libs: -labsl_base\n").is_empty());
+}
+
+#[test]
+fn test_sort_absl_libs_cycle() {
+    let mut requires = HashMap::new();
+    requires.insert("absl_a".to_owned(), vec!["absl_b".to_owned()]);
+    requires.insert("absl_b".to_owned(), vec!["absl_a".to_owned()]);
+    let mut libs = vec![
+        "absl_b".to_owned(),
+        "absl_a".to_owned(),
+        "absl_a".to_owned(),
+    ];
+    // Doesn't loop forever on cycles.
+    sort_absl_libs(&mut libs, &requires);
+    assert_eq!(libs, vec!["absl_b", "absl_a"]);
+}
@@ -0,0 +1,36 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A temporary directory for build script tests, removed on drop.
+pub struct Fixture(pub PathBuf);
+
+impl Fixture {
+    /// Create the directory with the given empty files.
+    pub fn new(name: &str, files: &[&str]) -> Fixture {
+        let root = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let fixture = Fixture(root);
+        for f in files {
+            fixture.write(f, "");
+        }
+        fixture
+    }
+
+    /// Write `content` to the file at `path` relative to the directory.
+    pub fn write(&self, path: &str, content: &str) {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,38 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+mod common;
+#[path = "../grpc_src.rs"]
+mod grpc_src;
+
+use std::path::Path;
+
+use common::Fixture;
+use grpc_src::GrpcSource;
+
+#[test]
+fn test_external_source() {
+    // A patched tree without any of the submodules checked out.
+    let fixture = Fixture::new(
+        "grpcio-sys-external-src",
+        &["CMakeLists.txt", "include/grpc/grpc.h"],
+    );
+    let external = fixture.0.to_str().unwrap();
+    let source = GrpcSource::new(Path::new("grpc"), Some(external));
+    assert_eq!(source.path(), fixture.0);
+    assert_eq!(source.include_dir(), fixture.0.join("include"));
+    source.check().unwrap();
+
+    // The same tree fails the submodule check when used as the bundled one.
+    let source = GrpcSource::new(&fixture.0, None);
+    assert_eq!(source.path(), fixture.0);
+    let err = source.check().unwrap_err();
+    assert!(err.contains("git submodule"), "{}", err);
+}
+
+#[test]
+fn test_invalid_external_source() {
+    let fixture = Fixture::new("grpcio-sys-invalid-src", &["README.md"]);
+    let source = GrpcSource::new(Path::new("grpc"), fixture.0.to_str());
+    let err = source.check().unwrap_err();
+    assert!(err.contains("GRPCIO_SYS_GRPC_SRC"), "{}", err);
+}
@@ -0,0 +1,93 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+mod common;
+#[path = "../libclang.rs"]
+mod libclang;
+
+use common::Fixture;
+
+fn libclang_name() -> &'static str {
+    if cfg!(windows) {
+        "libclang.dll"
+    } else if cfg!(target_os = "macos") {
+        "libclang.dylib"
+    } else {
+        "libclang.so.10"
+    }
+}
+
+#[test]
+fn test_missing_libclang() {
+    let fixture = Fixture::new("grpcio-sys-no-libclang", &["lib/libfoo.so", "lib/a/b/c"]);
+    let err = libclang::find_libclang(None, &[fixture.0.clone()]).unwrap_err();
+    assert!(err.contains("LIBCLANG_PATH"), "{}", err);
+    assert!(err.contains("x86_64-unknown-linux-gnu"), "{}", err);
+
+    // A misconfigured `LIBCLANG_PATH` is reported even if libclang can be found elsewhere.
+    let found = Fixture::new(
+        "grpcio-sys-libclang",
+        &[&format!("lib/{}", libclang_name())],
+    );
+    let path = fixture.0.to_str().unwrap();
+    let err = libclang::find_libclang(Some(path), &[found.0.clone()]).unwrap_err();
+    assert!(err.contains(path), "{}", err);
+}
+
+#[test]
+fn test_find_libclang() {
+    let name = libclang_name();
+    let fixture = Fixture::new(
+        "grpcio-sys-find-libclang",
+        &[
+            &format!("llvm/lib/{}", name),
+            &format!("deep/a/b/c/{}", name),
+        ],
+    );
+    let expected = fixture.0.join("llvm").join("lib").join(name);
+    let found = libclang::find_libclang(None, &[fixture.0.join("llvm")]).unwrap();
+    assert_eq!(found, expected);
+    // Roots are only searched two levels deep.
+    assert!(libclang::find_libclang(None, &[fixture.0.join("deep")]).is_err());
+
+    let dir = fixture.0.join("llvm").join("lib");
+    let found = libclang::find_libclang(Some(dir.to_str().unwrap()), &[]).unwrap();
+    assert_eq!(found, expected);
+    let found = libclang::find_libclang(Some(expected.to_str().unwrap()), &[]).unwrap();
+    assert_eq!(found, expected);
+
+    // Only checks it doesn't panic, libclang may not be installed.
+    let _ = libclang::find_libclang(None, &libclang::default_search_roots());
+}
+
+#[test]
+fn test_libclang_name() {
+    assert!(libclang::is_libclang(libclang_name()));
+    assert!(!libclang::is_libclang("libclang-cpp.a"));
+    assert!(!libclang::is_libclang("libfoo.so"));
+}
+
+#[test]
+fn test_compatible_binding() {
+    let cases = [
+        (
+            "x86_64-unknown-linux-musl",
+            Some("x86_64-unknown-linux-gnu"),
+        ),
+        ("aarch64-linux-android", None),
+        (
+            "aarch64-unknown-linux-musl",
+            Some("aarch64-unknown-linux-gnu"),
+        ),
+        ("armv7-unknown-linux-gnueabihf", None),
+        ("x86_64-apple-darwin", None),
+        ("x86_64-pc-windows-msvc", None),
+    ];
+    for (target, expected) in &cases {
+        assert_eq!(
+            libclang::compatible_binding(target),
+            *expected,
+            "{}",
+            target
+        );
+    }
+}
@@ -1,13 +1,27 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::time::Duration;
+
 use grpcio::{
-    ChannelCredentials, ChannelCredentialsBuilder, ServerCredentials, ServerCredentialsBuilder,
+    CallOption, Channel, ChannelCredentials, ChannelCredentialsBuilder, Error, RpcStatusCode,
+    ServerCredentials, ServerCredentialsBuilder,
 };
 
+#[cfg(all(feature = "protobuf-codec", not(feature = "prost-codec")))]
+use crate::health::v1::health::{
+    HealthCheckRequest, HealthCheckResponse_ServingStatus as ServingStatus, HealthClient,
+};
 #[cfg(all(feature = "protobuf-codec", not(feature = "prost-codec")))]
 use crate::testing::messages::{Payload, ResponseParameters};
+#[cfg(all(feature = "protobuf-codec", not(feature = "prost-codec")))]
+const SERVICE_UNKNOWN: ServingStatus = ServingStatus::SERVICE_UNKNOWN;
+
+#[cfg(feature = "prost-codec")]
+use crate::health::v1::{health_check_response::ServingStatus, HealthCheckRequest, HealthClient};
 #[cfg(feature = "prost-codec")]
 use crate::testing::{Payload, ResponseParameters};
+#[cfg(feature = "prost-codec")]
+const SERVICE_UNKNOWN: ServingStatus = ServingStatus::ServiceUnknown;
 
 /// Create a payload with the specified size.
 pub fn new_payload(size: usize) -> Payload {
@@ -36,3 +50,25 @@ pub fn create_test_channel_credentials() -> ChannelCredentials {
         .root_cert(ca.into())
         .build()
 }
+
+/// Check the serving status of `service` with the standard health checking
+/// protocol, which is handy for readiness and liveness probes.
+///
+/// An empty `service` asks for the status of the whole server. The check fails
+/// with `DEADLINE_EXCEEDED` if there is no answer before `timeout`, and
+/// `SERVICE_UNKNOWN` is returned if the service is not known by the server.
+pub fn health_check(
+    channel: &Channel,
+    service: &str,
+    timeout: Duration,
+) -> grpcio::Result<ServingStatus> {
+    let client = HealthClient::new(channel.clone());
+    let mut req = HealthCheckRequest::default();
+    req.set_service(service.to_owned());
+    let opt = CallOption::default().timeout(timeout);
+    match client.check_opt(&req, opt) {
+        Ok(resp) => Ok(resp.get_status()),
+        Err(Error::RpcFailure(s)) if s.status == RpcStatusCode::NOT_FOUND => Ok(SERVICE_UNKNOWN),
+        Err(e) => Err(e),
+    }
+}
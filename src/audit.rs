@@ -0,0 +1,119 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Audit events of server calls.
+
+use std::sync::Arc;
+#[cfg(feature = "json")]
+use std::time::UNIX_EPOCH;
+use std::time::{Duration, SystemTime};
+
+use crate::call::RpcStatusCode;
+
+pub(crate) type AuditHook = Arc<dyn Fn(&AuditEvent) + Send + Sync>;
+
+/// A record of a finished call, reported to the hook registered by
+/// [`ServerBuilder::audit_hook`].
+///
+/// [`ServerBuilder::audit_hook`]: ./struct.ServerBuilder.html#method.audit_hook
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    pub(crate) method: String,
+    pub(crate) peer: String,
+    pub(crate) identity: Vec<String>,
+    pub(crate) status: RpcStatusCode,
+    pub(crate) start: SystemTime,
+    pub(crate) latency: Duration,
+}
+
+impl AuditEvent {
+    /// The full method name like `/helloworld.Greeter/SayHello`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// The address of the client, like `ipv4:127.0.0.1:50051`.
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    /// The authenticated identity of the client, which is the values of the peer
+    /// identity property of the auth context, like the subject alternative names of
+    /// the client certificate.
+    ///
+    /// It's empty if the client is not authenticated.
+    pub fn identity(&self) -> &[String] {
+        &self.identity
+    }
+
+    /// The status the call finishes with. Calls that finish without a status, like
+    /// being cancelled by the client, are reported as `CANCELLED`.
+    pub fn status(&self) -> RpcStatusCode {
+        self.status
+    }
+
+    /// The time the call is dispatched to the handler, or rejected without one.
+    pub fn start(&self) -> SystemTime {
+        self.start
+    }
+
+    /// The time from the call is dispatched or rejected to the time the call is
+    /// released.
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Format the event as a single line JSON object, which is handy for writing
+    /// audit logs.
+    ///
+    /// The fields are `method`, `peer`, `identity`, `code`, `start_ms`, which is
+    /// milliseconds since the Unix epoch, and `latency_us`.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        let code: i32 = self.status.into();
+        let start_ms = self
+            .start
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        serde_json::json!({
+            "method": self.method,
+            "peer": self.peer,
+            "identity": self.identity,
+            "code": code,
+            "start_ms": start_ms,
+            "latency_us": self.latency.as_micros() as u64,
+        })
+        .to_string()
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_to_json() {
+        let event = AuditEvent {
+            method: "/a.B/C".to_owned(),
+            peer: "ipv4:127.0.0.1:1234".to_owned(),
+            identity: vec!["x\"y".to_owned(), "z\n".to_owned()],
+            status: RpcStatusCode::NOT_FOUND,
+            start: UNIX_EPOCH + Duration::from_millis(1500),
+            latency: Duration::from_micros(42),
+        };
+        let json = event.to_json();
+        assert!(!json.contains('\n'), "{}", json);
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "method": "/a.B/C",
+                "peer": "ipv4:127.0.0.1:1234",
+                "identity": ["x\"y", "z\n"],
+                "code": 5,
+                "start_ms": 1500,
+                "latency_us": 42,
+            })
+        );
+    }
+}
@@ -1,8 +1,9 @@
 //! API for authenticating peer
 //! Based on https://grpc.github.io/grpc/core/md_doc_server_side_auth.html
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::marker::PhantomData;
+use std::mem;
 use std::ptr::NonNull;
 
 use crate::grpc_sys::{
@@ -62,10 +63,67 @@ impl AuthContext {
             let iter = grpc_sys::grpc_auth_context_peer_identity(self.ctx.as_ref());
             AuthPropertyIter {
                 iter,
+                _name: None,
                 _lifetime: PhantomData,
             }
         }
     }
+
+    /// `AuthContext[name]`
+    ///
+    /// There may be several properties with the same name. Names containing a nul
+    /// byte never match any property.
+    pub fn find_properties(&self, name: &str) -> AuthPropertyIter {
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            Err(_) => {
+                return AuthPropertyIter {
+                    // An iterator without context yields nothing.
+                    iter: unsafe { mem::zeroed() },
+                    _name: None,
+                    _lifetime: PhantomData,
+                };
+            }
+        };
+        unsafe {
+            let iter = grpc_sys::grpc_auth_context_find_properties_by_name(
+                self.ctx.as_ref(),
+                name.as_ptr(),
+            );
+            AuthPropertyIter {
+                iter,
+                // The iterator refers to the name instead of copying it.
+                _name: Some(name),
+                _lifetime: PhantomData,
+            }
+        }
+    }
+
+    fn find_property_str(&self, name: &[u8]) -> Option<&str> {
+        let name = CStr::from_bytes_with_nul(name).unwrap().to_str().unwrap();
+        self.find_properties(name)
+            .next()
+            .and_then(|p| p.value_str().ok())
+    }
+
+    /// The type of transport security, e.g. `ssl` for TLS.
+    pub fn transport_security_type(&self) -> Option<&str> {
+        self.find_property_str(grpc_sys::GRPC_TRANSPORT_SECURITY_TYPE_PROPERTY_NAME)
+    }
+
+    /// The security level negotiated by the transport, e.g.
+    /// `TSI_PRIVACY_AND_INTEGRITY` for TLS.
+    pub fn security_level(&self) -> Option<&str> {
+        self.find_property_str(grpc_sys::GRPC_TRANSPORT_SECURITY_LEVEL_PROPERTY_NAME)
+    }
+
+    /// Whether the TLS session is resumed from a previous one.
+    ///
+    /// Returns `None` if the transport is not TLS.
+    pub fn ssl_session_reused(&self) -> Option<bool> {
+        self.find_property_str(grpc_sys::GRPC_SSL_SESSION_REUSED_PROPERTY)
+            .map(|v| v == "true")
+    }
 }
 
 impl<'a> IntoIterator for &'a AuthContext {
@@ -79,6 +137,7 @@ impl<'a> IntoIterator for &'a AuthContext {
             let iter = grpc_sys::grpc_auth_context_property_iterator(self.ctx.as_ref());
             AuthPropertyIter {
                 iter,
+                _name: None,
                 _lifetime: PhantomData,
             }
         }
@@ -93,6 +152,7 @@ impl Drop for AuthContext {
 
 pub struct AuthPropertyIter<'a> {
     iter: grpc_auth_property_iterator,
+    _name: Option<CString>,
     _lifetime: PhantomData<&'a grpc_auth_property_iterator>,
 }
 
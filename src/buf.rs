@@ -1,12 +1,15 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use grpcio_sys::*;
+use std::alloc::{self, GlobalAlloc, Layout};
 use std::cell::UnsafeCell;
 use std::ffi::{c_void, CStr, CString};
 use std::fmt::{self, Debug, Formatter};
 use std::io::{self, BufRead, Read};
 use std::mem::{self, ManuallyDrop, MaybeUninit};
 
+use crate::codec::SliceAllocator;
+
 /// Copied from grpc-sys/grpc/include/grpc/impl/codegen/slice.h. Unfortunately bindgen doesn't
 /// generate it automatically.
 const INLINED_SIZE: usize = mem::size_of::<libc::size_t>() + mem::size_of::<*mut u8>() - 1
@@ -71,11 +74,15 @@ impl GrpcSlice {
 
     /// Reallocates current slice with given capacity.
     ///
-    /// The length of returned slice is the exact same as given cap.
+    /// The length of returned slice is the exact same as given cap. Slices that
+    /// can't be inlined are allocated by the allocator set by [`set_slice_allocator`]
+    /// if there is one.
     ///
     /// ## Safety
     ///
     /// Caller is expected to initialize all available bytes to guarantee safety of this slice.
+    ///
+    /// [`set_slice_allocator`]: ./fn.set_slice_allocator.html
     pub unsafe fn realloc(&mut self, cap: usize) -> &mut [MaybeUninit<u8>] {
         if cap <= INLINED_SIZE {
             // Only inlined slice can be reused safely.
@@ -88,13 +95,36 @@ impl GrpcSlice {
                 cap,
             )
         } else {
-            *self = GrpcSlice(grpcio_sys::grpc_slice_malloc_large(cap));
+            *self = match crate::codec::slice_allocator() {
+                Some(a) => GrpcSlice::alloc_with(a, cap),
+                None => GrpcSlice(grpcio_sys::grpc_slice_malloc_large(cap)),
+            };
             let start = self.0.data.refcounted.bytes;
             let len = self.0.data.refcounted.length;
             std::slice::from_raw_parts_mut(start as *mut MaybeUninit<u8>, len)
         }
     }
 
+    /// Allocates an uninitialized slice of `cap` bytes by `a`.
+    unsafe fn alloc_with(a: SliceAllocator, cap: usize) -> GrpcSlice {
+        let layout = Layout::from_size_align(cap, 1).unwrap();
+        let ptr = a.alloc(layout);
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        let buf = Box::into_raw(Box::new(AllocatedBuf {
+            allocator: a,
+            ptr,
+            layout,
+        }));
+        GrpcSlice(grpcio_sys::grpc_slice_new_with_user_data(
+            ptr as _,
+            cap,
+            Some(drop_allocated_buf),
+            buf as _,
+        ))
+    }
+
     pub fn as_mut_ptr(&mut self) -> *mut grpc_slice {
         &mut self.0
     }
@@ -152,10 +182,29 @@ unsafe extern "C" fn drop_vec(ptr: *mut c_void, len: usize) {
     Vec::from_raw_parts(ptr as *mut u8, len, len);
 }
 
+unsafe extern "C" fn drop_boxed_vec(v: *mut c_void) {
+    Box::from_raw(v as *mut Vec<u8>);
+}
+
+/// A buffer allocated by a custom slice allocator.
+///
+/// The allocator is kept so that the buffer can still be freed by it after
+/// another allocator is set.
+struct AllocatedBuf {
+    allocator: SliceAllocator,
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+unsafe extern "C" fn drop_allocated_buf(v: *mut c_void) {
+    let buf = Box::from_raw(v as *mut AllocatedBuf);
+    buf.allocator.dealloc(buf.ptr, buf.layout);
+}
+
 impl From<Vec<u8>> for GrpcSlice {
     /// Converts a `Vec<u8>` into `GrpcSlice`.
     ///
-    /// If v can't fit inline, there will be allocations.
+    /// The data is never copied, the vector is owned by the slice instead.
     #[inline]
     fn from(mut v: Vec<u8>) -> GrpcSlice {
         if v.is_empty() {
@@ -170,10 +219,15 @@ impl From<Vec<u8>> for GrpcSlice {
             return GrpcSlice(slice);
         }
 
+        // The capacity is needed to free the vector, so keep the vector itself.
+        let (ptr, len) = (v.as_mut_ptr(), v.len());
+        let v = Box::into_raw(Box::new(v));
         unsafe {
-            GrpcSlice(grpcio_sys::grpc_slice_from_copied_buffer(
-                v.as_mut_ptr() as _,
-                v.len(),
+            GrpcSlice(grpcio_sys::grpc_slice_new_with_user_data(
+                ptr as _,
+                len,
+                Some(drop_boxed_vec),
+                v as _,
             ))
         }
     }
@@ -304,6 +358,10 @@ pub struct GrpcByteBufferReader {
     offset: usize,
     /// How many bytes pending for reading.
     remain: usize,
+    /// The algorithm the message was compressed with when it was received.
+    compression: grpc_compression_algorithm,
+    /// Whether the data is still compressed with `compression`.
+    compressed: bool,
 }
 
 impl GrpcByteBufferReader {
@@ -314,6 +372,7 @@ impl GrpcByteBufferReader {
         let mut reader = MaybeUninit::uninit();
         let mut s = MaybeUninit::uninit();
         unsafe {
+            let compression = (*buf.0).data.raw.compression;
             let code = grpc_byte_buffer_reader_init(reader.as_mut_ptr(), buf.0);
             assert_eq!(code, 1);
             if 0 == grpc_byte_buffer_reader_next(reader.as_mut_ptr(), s.as_mut_ptr()) {
@@ -328,10 +387,34 @@ impl GrpcByteBufferReader {
                 slice: ManuallyDrop::new(GrpcSlice(s.assume_init())),
                 offset: 0,
                 remain,
+                compression,
+                compressed: false,
             }
         }
     }
 
+    /// Creates a reader for a received `GrpcByteBuffer` without decompressing it.
+    ///
+    /// gRPC Core decompresses a buffer as a whole when it's read, so the compressed
+    /// data is read instead, and the library decompresses it within the receive limit.
+    pub(crate) fn new_compressed(buf: GrpcByteBuffer) -> GrpcByteBufferReader {
+        unsafe {
+            let raw = &(*buf.0).data.raw;
+            let compression = raw.compression;
+            if compression == grpc_compression_algorithm::GRPC_COMPRESS_NONE {
+                return GrpcByteBufferReader::new(buf);
+            }
+            // The slices are referenced by the new buffer, so nothing is copied.
+            let plain =
+                grpc_raw_byte_buffer_create(raw.slice_buffer.slices, raw.slice_buffer.count);
+            drop(buf);
+            let mut reader = GrpcByteBufferReader::new(GrpcByteBuffer(plain));
+            reader.compression = compression;
+            reader.compressed = true;
+            reader
+        }
+    }
+
     /// Get the next slice from reader.
     fn load_next_slice(&mut self) {
         unsafe {
@@ -343,6 +426,32 @@ impl GrpcByteBufferReader {
         self.offset = 0;
     }
 
+    /// Take all the data that has not been read as a single slice.
+    ///
+    /// No data is copied if the data is in one slice already, which is usually
+    /// the case for small messages. Otherwise all slices are copied into a new
+    /// one.
+    pub fn into_slice(mut self) -> GrpcSlice {
+        if self.remain == 0 {
+            return GrpcSlice::default();
+        }
+        if self.offset + self.remain <= self.slice.len() {
+            return GrpcSlice(unsafe {
+                grpc_slice_sub(self.slice.0, self.offset, self.offset + self.remain)
+            });
+        }
+        let mut slice = GrpcSlice::default();
+        unsafe {
+            let bytes = slice.realloc(self.remain);
+            let mut dest = &mut *(bytes as *mut [MaybeUninit<u8>] as *mut [u8]);
+            while !dest.is_empty() {
+                let read = self.read(dest).unwrap();
+                dest = &mut mem::take(&mut dest)[read..];
+            }
+        }
+        slice
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.remain
@@ -352,6 +461,19 @@ impl GrpcByteBufferReader {
     pub fn is_empty(&self) -> bool {
         self.remain == 0
     }
+
+    /// Get the algorithm the message was compressed with when it was received,
+    /// which gRPC Core takes from the `grpc-encoding` header.
+    pub fn compression(&self) -> grpc_compression_algorithm {
+        self.compression
+    }
+
+    /// Whether the data is still compressed, see [`compression`].
+    ///
+    /// [`compression`]: #method.compression
+    pub(crate) fn is_compressed(&self) -> bool {
+        self.compressed
+    }
 }
 
 impl Read for GrpcByteBufferReader {
@@ -565,10 +687,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_into_slice() {
+        for len in 0..=1024 {
+            for n_slice in 1..=4 {
+                let source = vec![len as u8; len];
+                let expect = vec![len as u8; len * n_slice];
+                let reader = new_message_reader(source.clone(), n_slice);
+                assert_eq!(reader.into_slice().as_slice(), expect.as_slice());
+
+                let mut reader = new_message_reader(source.clone(), n_slice);
+                reader.consume(len / 2);
+                assert_eq!(
+                    reader.into_slice().as_slice(),
+                    &expect[len / 2..],
+                    "len: {}, nslice: {}",
+                    len,
+                    n_slice
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_converter() {
         let a = vec![1, 2, 3, 0];
         assert_eq!(GrpcSlice::from(a.clone()).as_slice(), a.as_slice());
+        let mut b = Vec::with_capacity(4096);
+        b.extend_from_slice(&[7; 1024]);
+        let ptr = b.as_ptr();
+        let s = GrpcSlice::from(b);
+        assert_eq!(s.as_slice(), &[7; 1024][..]);
+        assert_eq!(s.as_slice().as_ptr(), ptr);
         assert_eq!(GrpcSlice::from(a.as_slice()).as_slice(), a.as_slice());
 
         let s = "abcd".to_owned();
@@ -1,26 +1,124 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::any::{Any, TypeId};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::mem;
 use std::pin::Pin;
+use std::process;
 use std::ptr;
+use std::str;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::grpc_sys;
+use futures::future;
 use futures::ready;
-use futures::sink::Sink;
+use futures::sink::{Sink, SinkExt};
 use futures::stream::Stream;
 use futures::task::{Context, Poll};
 use parking_lot::Mutex;
 use std::future::Future;
 
-use super::{ShareCall, ShareCallHolder, SinkBase, WriteFlags};
+use super::server::NO_PAYLOAD;
+use super::{IdleTimer, ShareCall, ShareCallHolder, SinkBase, WriteFlags};
+use crate::auth_context::AuthContext;
 use crate::buf::GrpcSlice;
-use crate::call::{check_run, Call, MessageReader, Method};
-use crate::channel::Channel;
-use crate::codec::{DeserializeFn, SerializeFn};
+use crate::call::{check_run, Call, MessageReader, Method, RpcStatus, RpcStatusCode};
+use crate::channel::{Channel, CompressionAlgorithms};
+use crate::codec::{DeserializeFn, MessageCompressor, SerializeFn, MESSAGE_ENCODING_KEY};
 use crate::error::{Error, Result};
-use crate::metadata::Metadata;
-use crate::task::{BatchFuture, BatchType};
+use crate::metadata::{Metadata, MetadataBuilder};
+use crate::task::{BatchFuture, BatchResult, BatchType, Delay};
+
+/// The header used to carry census context.
+///
+/// Headers prefixed with `grpc-` are reserved for gRPC itself, so an
+/// application-level binary header is used instead.
+pub(crate) const CENSUS_CONTEXT_KEY: &str = "x-census-context-bin";
+
+/// The header used to carry call IDs.
+pub(crate) const CALL_ID_KEY: &str = "x-call-id";
+
+/// The header used to mark probes of methods, see [`Client::probe_method`].
+///
+/// [`Client::probe_method`]: ../../struct.Client.html#method.probe_method
+pub(crate) const PROBE_KEY: &str = "x-method-probe";
+
+/// Generate a random 128-bit ID in hex.
+///
+/// Every `RandomState` is seeded with different random keys, so IDs are unique
+/// across processes as well as across calls.
+pub(crate) fn new_call_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    let mut id = String::with_capacity(32);
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        (seq, nanos, process::id()).hash(&mut hasher);
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id
+}
+
+/// Errors that are only reported by a failed TLS handshake.
+const SECURITY_MISMATCH_ERRORS: &[&str] = &[
+    "Handshake read failed",
+    "Handshake write failed",
+    "Handshake failed",
+    "Ssl handshake failed",
+    "SSL_ERROR_",
+    "WRONG_VERSION_NUMBER",
+];
+
+/// Errors reported when a TLS server closes the connection of a plaintext
+/// client, as it can't parse the HTTP/2 preface as a TLS handshake.
+const PLAINTEXT_MISMATCH_ERRORS: &[&str] =
+    &["Socket closed", "Connection reset by peer", "Broken pipe"];
+
+/// Add a hint to the failure status if it may be caused by connecting to a
+/// server with mismatched transport security.
+///
+/// gRPC core only reports such failures as generic `UNAVAILABLE` errors,
+/// which are hard to figure out without a hint. Secure channels only get the
+/// hint for failed TLS handshakes. Insecure channels get it when the server
+/// closes the connection during the handshake, which is what a TLS server
+/// does, so refused connections are not taken as a mismatch.
+pub(crate) fn explain_security_mismatch(secure: bool, mut status: RpcStatus) -> RpcStatus {
+    if status.status != RpcStatusCode::UNAVAILABLE {
+        return status;
+    }
+    let (errors, hint) = if secure {
+        (
+            SECURITY_MISMATCH_ERRORS,
+            "a secure channel can't connect to a plaintext server",
+        )
+    } else {
+        (
+            PLAINTEXT_MISMATCH_ERRORS,
+            "an insecure channel can't connect to a TLS server",
+        )
+    };
+    let is_mismatch_error = |s: &Option<String>| {
+        s.as_ref()
+            .map_or(false, |s| errors.iter().any(|e| s.contains(e)))
+    };
+    if !is_mismatch_error(&status.details) && !is_mismatch_error(&status.debug_error_string) {
+        return status;
+    }
+    status.details = Some(format!(
+        "{} (possible security mismatch: {})",
+        status.details.as_deref().unwrap_or_default(),
+        hint
+    ));
+    status
+}
 
 /// Update the flag bit in res.
 #[inline]
@@ -36,16 +134,26 @@ pub fn change_flag(res: &mut u32, flag: u32, set: bool) {
 #[derive(Clone, Default)]
 pub struct CallOption {
     timeout: Option<Duration>,
-    write_flags: WriteFlags,
+    write_flags: Option<WriteFlags>,
     call_flags: u32,
+    // Bits of `call_flags` that are set explicitly, either on or off.
+    call_flags_mask: u32,
     headers: Option<Metadata>,
+    census_context: Option<Vec<u8>>,
+    binary_contexts: Vec<(String, Vec<u8>)>,
+    compression: Option<CompressionAlgorithms>,
+    call_id: Option<String>,
+    message_compressor: Option<Arc<dyn MessageCompressor>>,
+    max_receive_message_len: Option<i32>,
+    max_send_message_len: Option<i32>,
+    idle_timeout: Option<Duration>,
+    extensions: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
 }
 
 impl CallOption {
     /// Signal that the call is idempotent.
     pub fn idempotent(mut self, is_idempotent: bool) -> CallOption {
-        change_flag(
-            &mut self.call_flags,
+        self.set_call_flag(
             grpc_sys::GRPC_INITIAL_METADATA_IDEMPOTENT_REQUEST,
             is_idempotent,
         );
@@ -54,8 +162,7 @@ impl CallOption {
 
     /// Signal that the call should not return UNAVAILABLE before it has started.
     pub fn wait_for_ready(mut self, wait_for_ready: bool) -> CallOption {
-        change_flag(
-            &mut self.call_flags,
+        self.set_call_flag(
             grpc_sys::GRPC_INITIAL_METADATA_WAIT_FOR_READY,
             wait_for_ready,
         );
@@ -64,17 +171,13 @@ impl CallOption {
 
     /// Signal that the call is cacheable. gRPC is free to use GET verb.
     pub fn cacheable(mut self, cacheable: bool) -> CallOption {
-        change_flag(
-            &mut self.call_flags,
-            grpc_sys::GRPC_INITIAL_METADATA_CACHEABLE_REQUEST,
-            cacheable,
-        );
+        self.set_call_flag(grpc_sys::GRPC_INITIAL_METADATA_CACHEABLE_REQUEST, cacheable);
         self
     }
 
     /// Set write flags.
     pub fn write_flags(mut self, write_flags: WriteFlags) -> CallOption {
-        self.write_flags = write_flags;
+        self.write_flags = Some(write_flags);
         self
     }
 
@@ -99,6 +202,327 @@ impl CallOption {
     pub fn get_headers(&self) -> Option<&Metadata> {
         self.headers.as_ref()
     }
+
+    /// Attach an opaque census context to the call.
+    ///
+    /// The context is carried to the server in the `x-census-context-bin` header as is,
+    /// and can be read by [`RpcContext::census_context`].
+    ///
+    /// [`RpcContext::census_context`]: ./struct.RpcContext.html#method.census_context
+    pub fn census_context(mut self, ctx: &[u8]) -> CallOption {
+        self.census_context = Some(ctx.to_vec());
+        self
+    }
+
+    /// Attach a binary context blob named `name` to the call.
+    ///
+    /// It's meant for passing opaque context, like the one of a service mesh, along
+    /// with the call. The blob is carried in the `{name}-bin` header and can be read
+    /// by [`RpcContext::binary_context`]. gRPC Core encodes binary headers in base64
+    /// on the wire and decodes them on receiving, so the blob can contain any bytes.
+    /// A blob of the same name replaces the previous one.
+    ///
+    /// An error is returned if `name` is not a valid metadata key, ends with `-bin`,
+    /// or starts with the reserved `grpc-` prefix. Names are case insensitive.
+    ///
+    /// [`RpcContext::binary_context`]: ./struct.RpcContext.html#method.binary_context
+    pub fn binary_context(mut self, name: &str, value: &[u8]) -> Result<CallOption> {
+        let key = crate::metadata::binary_context_key(name)?;
+        self.binary_contexts.retain(|(k, _)| *k != key);
+        self.binary_contexts.push((key, value.to_vec()));
+        Ok(self)
+    }
+
+    /// Get the binary context blob named `name` attached by [`binary_context`].
+    ///
+    /// [`binary_context`]: #method.binary_context
+    pub fn get_binary_context(&self, name: &str) -> Option<&[u8]> {
+        let key = crate::metadata::binary_context_key(name).ok()?;
+        self.binary_contexts
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Set the algorithm used to compress the messages of the call.
+    ///
+    /// It overrides the default compression algorithm of the channel.
+    pub fn compression_algorithm(mut self, algo: CompressionAlgorithms) -> CallOption {
+        self.compression = Some(algo);
+        self
+    }
+
+    /// Set the ID of the call.
+    ///
+    /// The ID is sent in the `x-call-id` header, and can be read by
+    /// [`RpcContext::call_id`] on server side, so logs of both sides can be
+    /// correlated. [`Client::with_call_ids`] generates unique IDs for calls
+    /// automatically.
+    ///
+    /// [`RpcContext::call_id`]: ./struct.RpcContext.html#method.call_id
+    /// [`Client::with_call_ids`]: ./struct.Client.html#method.with_call_ids
+    pub fn call_id<S: Into<String>>(mut self, id: S) -> CallOption {
+        self.call_id = Some(id.into());
+        self
+    }
+
+    /// Get the ID of the call.
+    pub fn get_call_id(&self) -> Option<&str> {
+        self.call_id.as_deref()
+    }
+
+    /// Compress the messages of the call with a compressor in the codec layer.
+    ///
+    /// The server has to register the same compressor, otherwise the call fails
+    /// with `UNIMPLEMENTED`. The compressor is not negotiated with the server, so
+    /// it's up to the caller to only use compressors the server is known to
+    /// support. Responses are compressed by the server with the same compressor.
+    /// See [`MessageCompressor`] for more details.
+    ///
+    /// [`MessageCompressor`]: ./trait.MessageCompressor.html
+    pub fn message_compressor(mut self, c: Arc<dyn MessageCompressor>) -> CallOption {
+        self.message_compressor = Some(c);
+        self
+    }
+
+    /// Set the maximum length in bytes of messages the call can receive.
+    ///
+    /// It overrides [`ChannelBuilder::max_receive_message_len`] for the call, so a
+    /// call known to receive large messages doesn't need the limit to be raised
+    /// for the whole channel. Receiving a larger message fails the call with
+    /// `RESOURCE_EXHAUSTED`. `-1` means unlimited.
+    ///
+    /// The limit can't be raised beyond [`ChannelBuilder::receive_message_len_ceiling`],
+    /// which gRPC Core checks before buffering the message.
+    ///
+    /// [`ChannelBuilder::max_receive_message_len`]: ./struct.ChannelBuilder.html#method.max_receive_message_len
+    /// [`ChannelBuilder::receive_message_len_ceiling`]: ./struct.ChannelBuilder.html#method.receive_message_len_ceiling
+    pub fn max_receive_message_len(mut self, len: i32) -> CallOption {
+        self.max_receive_message_len = Some(len);
+        self
+    }
+
+    /// Get the receive limit set by [`max_receive_message_len`].
+    ///
+    /// [`max_receive_message_len`]: #method.max_receive_message_len
+    pub fn get_max_receive_message_len(&self) -> Option<i32> {
+        self.max_receive_message_len
+    }
+
+    /// Set the maximum length in bytes of messages the call can send.
+    ///
+    /// It overrides [`ChannelBuilder::max_send_message_len`] for the call. Sending a
+    /// larger message fails with [`Error::RpcFailure`] of `RESOURCE_EXHAUSTED` without
+    /// sending it. `-1` means unlimited.
+    ///
+    /// The limit can't be raised beyond [`ChannelBuilder::send_message_len_ceiling`],
+    /// which gRPC Core checks as well.
+    ///
+    /// [`ChannelBuilder::max_send_message_len`]: ./struct.ChannelBuilder.html#method.max_send_message_len
+    /// [`ChannelBuilder::send_message_len_ceiling`]: ./struct.ChannelBuilder.html#method.send_message_len_ceiling
+    /// [`Error::RpcFailure`]: ./enum.Error.html#variant.RpcFailure
+    pub fn max_send_message_len(mut self, len: i32) -> CallOption {
+        self.max_send_message_len = Some(len);
+        self
+    }
+
+    /// Get the send limit set by [`max_send_message_len`].
+    ///
+    /// [`max_send_message_len`]: #method.max_send_message_len
+    pub fn get_max_send_message_len(&self) -> Option<i32> {
+        self.max_send_message_len
+    }
+
+    /// Set the idle timeout of streaming calls.
+    ///
+    /// Unlike [`timeout`], which limits the whole call, the idle timeout limits the
+    /// time between messages. It starts over whenever a message is sent or received,
+    /// and once it's exceeded the call is cancelled with `DEADLINE_EXCEEDED`, which
+    /// catches peers that hang in the middle of long-lived streams. It's checked
+    /// while the receiver of the call is polled. Unary calls ignore it.
+    ///
+    /// [`timeout`]: #method.timeout
+    pub fn idle_timeout(mut self, timeout: Duration) -> CallOption {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Get the idle timeout set by [`idle_timeout`].
+    ///
+    /// [`idle_timeout`]: #method.idle_timeout
+    pub fn get_idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    fn set_call_flag(&mut self, flag: u32, set: bool) {
+        change_flag(&mut self.call_flags, flag, set);
+        self.call_flags_mask |= flag;
+    }
+
+    /// Fill the settings that are not set explicitly by the option with `base`.
+    ///
+    /// Headers, binary contexts and extensions of `base` are added unless the option
+    /// has its own ones with the same keys. Call flags like [`idempotent`] are inherited
+    /// one by one, so turning a flag off explicitly overrides `base` too. Call IDs are
+    /// never inherited as they identify single calls.
+    ///
+    /// [`idempotent`]: #method.idempotent
+    pub(crate) fn inherit(mut self, base: &CallOption) -> CallOption {
+        if self.timeout.is_none() {
+            self.timeout = base.timeout;
+        }
+        if self.write_flags.is_none() {
+            self.write_flags = base.write_flags;
+        }
+        self.call_flags |= base.call_flags & !self.call_flags_mask;
+        self.call_flags_mask |= base.call_flags_mask;
+        if self.census_context.is_none() {
+            self.census_context = base.census_context.clone();
+        }
+        for (key, value) in &base.binary_contexts {
+            if self.binary_contexts.iter().all(|(k, _)| k != key) {
+                self.binary_contexts.push((key.clone(), value.clone()));
+            }
+        }
+        if self.compression.is_none() {
+            self.compression = base.compression;
+        }
+        if self.message_compressor.is_none() {
+            self.message_compressor = base.message_compressor.clone();
+        }
+        if self.max_receive_message_len.is_none() {
+            self.max_receive_message_len = base.max_receive_message_len;
+        }
+        if self.max_send_message_len.is_none() {
+            self.max_send_message_len = base.max_send_message_len;
+        }
+        if self.idle_timeout.is_none() {
+            self.idle_timeout = base.idle_timeout;
+        }
+        if let Some(base_headers) = &base.headers {
+            self.headers = Some(match &self.headers {
+                Some(own) => base_headers.merge(own),
+                None => base_headers.clone(),
+            });
+        }
+        for (id, value) in &base.extensions {
+            self.extensions.entry(*id).or_insert_with(|| value.clone());
+        }
+        self
+    }
+
+    /// Attach a value to the call, which can be read by [`extension`] later.
+    ///
+    /// Values are keyed by their types, a value of the same type replaces the previous
+    /// one. They are never sent to the server, but are useful for passing data to the
+    /// interceptors of the client, see [`Client::with_interceptor`].
+    ///
+    /// [`extension`]: #method.extension
+    /// [`Client::with_interceptor`]: ./struct.Client.html#method.with_interceptor
+    pub fn with_extension<T: Any + Send + Sync>(mut self, value: T) -> CallOption {
+        self.extensions.insert(TypeId::of::<T>(), Arc::new(value));
+        self
+    }
+
+    /// Get the value of type `T` attached by [`with_extension`].
+    ///
+    /// [`with_extension`]: #method.with_extension
+    pub fn extension<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref())
+    }
+
+    fn attach_extra_headers(&mut self) {
+        let mut extra = MetadataBuilder::new();
+        if let Some(ctx) = self.census_context.take() {
+            extra.add_metadata(CENSUS_CONTEXT_KEY, &ctx).unwrap();
+        }
+        for (key, value) in mem::take(&mut self.binary_contexts) {
+            extra.add_metadata(&key, &value).unwrap();
+        }
+        if let Some(id) = &self.call_id {
+            extra.add_metadata(CALL_ID_KEY, id.as_bytes()).unwrap();
+        }
+        if let Some(c) = &self.message_compressor {
+            extra
+                .add_metadata(MESSAGE_ENCODING_KEY, c.name().as_bytes())
+                .unwrap();
+        }
+        if let Some(algo) = self.compression.take() {
+            let name = unsafe {
+                let mut name = ptr::null();
+                assert_eq!(
+                    grpc_sys::grpc_compression_algorithm_name(algo, &mut name),
+                    1,
+                    "invalid compression algorithm {:?}",
+                    algo
+                );
+                CStr::from_ptr(name)
+            };
+            let key = &grpc_sys::GRPC_COMPRESSION_REQUEST_ALGORITHM_MD_KEY;
+            let key = str::from_utf8(&key[..key.len() - 1]).unwrap();
+            extra.add_metadata(key, name.to_bytes()).unwrap();
+        }
+        self.merge_headers(extra.build());
+    }
+
+    /// Add `extra` to the headers, replacing the headers of the same keys.
+    fn merge_headers(&mut self, extra: Metadata) {
+        if extra.is_empty() {
+            return;
+        }
+        self.headers = Some(match &self.headers {
+            Some(headers) => headers.merge(&extra),
+            None => extra,
+        });
+    }
+}
+
+/// Settings shared by many calls, like authentication or tenant headers.
+///
+/// Headers are validated and built once when the context is created, calls made
+/// with the context reuse them instead of building them again. The context itself
+/// is cheap to clone and can be shared among threads.
+#[derive(Clone)]
+pub struct ClientContext {
+    opt: Arc<CallOption>,
+}
+
+impl ClientContext {
+    /// Create a context from the options shared by calls.
+    ///
+    /// Call IDs are dropped as they identify single calls.
+    pub fn new(mut opt: CallOption) -> ClientContext {
+        opt.call_id = None;
+        ClientContext { opt: Arc::new(opt) }
+    }
+
+    /// Create a context that only sends the headers.
+    pub fn with_headers(headers: Metadata) -> ClientContext {
+        ClientContext::new(CallOption::default().headers(headers))
+    }
+
+    /// Get the headers sent by calls made with the context.
+    pub fn headers(&self) -> Option<&Metadata> {
+        self.opt.get_headers()
+    }
+
+    /// Get the options of a call made with the context.
+    pub fn call_option(&self) -> CallOption {
+        (*self.opt).clone()
+    }
+
+    /// Apply the context to the options of a call.
+    ///
+    /// Settings given explicitly by `opt` take precedence, they are merged the same
+    /// way as [`Client::with_default_call_option`]. Headers are only rebuilt if `opt`
+    /// has its own ones.
+    ///
+    /// [`Client::with_default_call_option`]: ./struct.Client.html#method.with_default_call_option
+    pub fn apply(&self, opt: CallOption) -> CallOption {
+        opt.inherit(&self.opt)
+    }
 }
 
 impl Call {
@@ -108,15 +532,20 @@ impl Call {
         req: &Req,
         mut opt: CallOption,
     ) -> Result<ClientUnaryReceiver<Resp>> {
-        let call = channel.create_call(method, &opt)?;
+        let mut call = channel.create_call(method, &opt)?;
+        opt.attach_extra_headers();
+        call.call_id = opt.call_id.take();
+        call.compressor = opt.message_compressor.take();
         let mut payload = GrpcSlice::default();
-        (method.req_ser())(req, &mut payload);
+        (method.req_ser())(req, &mut payload)?;
+        call.compress(&mut payload)?;
+        call.check_send_len(payload.len())?;
         let cq_f = check_run(BatchType::CheckRead, |ctx, tag| unsafe {
             grpc_sys::grpcwrap_call_start_unary(
                 call.call,
                 ctx,
                 payload.as_mut_ptr(),
-                opt.write_flags.flags,
+                opt.write_flags.map_or(0, |f| f.flags),
                 opt.headers
                     .as_mut()
                     .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
@@ -124,7 +553,12 @@ impl Call {
                 tag,
             )
         });
-        Ok(ClientUnaryReceiver::new(call, cq_f, method.resp_de()))
+        Ok(ClientUnaryReceiver::new(
+            call,
+            cq_f,
+            method.resp_de(),
+            channel.is_secure(),
+        ))
     }
 
     pub fn client_streaming<Req, Resp>(
@@ -132,7 +566,10 @@ impl Call {
         method: &Method<Req, Resp>,
         mut opt: CallOption,
     ) -> Result<(ClientCStreamSender<Req>, ClientCStreamReceiver<Resp>)> {
-        let call = channel.create_call(method, &opt)?;
+        let mut call = channel.create_call(method, &opt)?;
+        opt.attach_extra_headers();
+        call.call_id = opt.call_id.take();
+        call.compressor = opt.message_compressor.take();
         let cq_f = check_run(BatchType::CheckRead, |ctx, tag| unsafe {
             grpc_sys::grpcwrap_call_start_client_streaming(
                 call.call,
@@ -145,7 +582,10 @@ impl Call {
             )
         });
 
-        let share_call = Arc::new(Mutex::new(ShareCall::new(call, cq_f)));
+        let mut share_call = ShareCall::new(call, cq_f);
+        share_call.channel_secure = Some(channel.is_secure());
+        share_call.idle = opt.idle_timeout.map(IdleTimer::new);
+        let share_call = Arc::new(Mutex::new(share_call));
         let sink = ClientCStreamSender::new(share_call.clone(), method.req_ser());
         let recv = ClientCStreamReceiver {
             call: share_call,
@@ -155,21 +595,57 @@ impl Call {
         Ok((sink, recv))
     }
 
+    pub fn probe<Req, Resp>(
+        channel: &Channel,
+        method: &Method<Req, Resp>,
+        mut opt: CallOption,
+    ) -> Result<impl Future<Output = Result<bool>>> {
+        let mut probe = MetadataBuilder::with_capacity(1);
+        probe.add_metadata(PROBE_KEY, b"1").unwrap();
+        opt.merge_headers(probe.build());
+        let (mut sender, receiver) = Call::client_streaming(channel, method, opt)?;
+        Ok(async move {
+            // The server may have finished the call already, the result is decided
+            // by the status only.
+            let _ = sender.close().await;
+            match receiver.await {
+                Ok(_) => Ok(true),
+                Err(Error::RpcFailure(status)) => match status.status {
+                    RpcStatusCode::UNIMPLEMENTED => Ok(false),
+                    // Unary and server streaming methods reject the call for lack of
+                    // a request before calling the handler.
+                    RpcStatusCode::INTERNAL if status.details.as_deref() == Some(NO_PAYLOAD) => {
+                        Ok(true)
+                    }
+                    // Other failures may come from anywhere, like the handler, an
+                    // interceptor or the transport, so they are returned as is.
+                    _ => Err(Error::RpcFailure(status)),
+                },
+                Err(e) => Err(e),
+            }
+        })
+    }
+
     pub fn server_streaming<Req, Resp>(
         channel: &Channel,
         method: &Method<Req, Resp>,
         req: &Req,
         mut opt: CallOption,
     ) -> Result<ClientSStreamReceiver<Resp>> {
-        let call = channel.create_call(method, &opt)?;
+        let mut call = channel.create_call(method, &opt)?;
+        opt.attach_extra_headers();
+        call.call_id = opt.call_id.take();
+        call.compressor = opt.message_compressor.take();
         let mut payload = GrpcSlice::default();
-        (method.req_ser())(req, &mut payload);
+        (method.req_ser())(req, &mut payload)?;
+        call.compress(&mut payload)?;
+        call.check_send_len(payload.len())?;
         let cq_f = check_run(BatchType::Finish, |ctx, tag| unsafe {
             grpc_sys::grpcwrap_call_start_server_streaming(
                 call.call,
                 ctx,
                 payload.as_mut_ptr(),
-                opt.write_flags.flags,
+                opt.write_flags.map_or(0, |f| f.flags),
                 opt.headers
                     .as_mut()
                     .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
@@ -178,12 +654,15 @@ impl Call {
             )
         });
 
-        // TODO: handle header
-        check_run(BatchType::Finish, |ctx, tag| unsafe {
+        let headers_f = check_run(BatchType::Finish, |ctx, tag| unsafe {
             grpc_sys::grpcwrap_call_recv_initial_metadata(call.call, ctx, tag)
         });
 
-        Ok(ClientSStreamReceiver::new(call, cq_f, method.resp_de()))
+        let mut share_call = ShareCall::new(call, cq_f);
+        share_call.headers_f = Some(headers_f);
+        share_call.channel_secure = Some(channel.is_secure());
+        share_call.idle = opt.idle_timeout.map(IdleTimer::new);
+        Ok(ClientSStreamReceiver::new(share_call, method.resp_de()))
     }
 
     pub fn duplex_streaming<Req, Resp>(
@@ -191,7 +670,10 @@ impl Call {
         method: &Method<Req, Resp>,
         mut opt: CallOption,
     ) -> Result<(ClientDuplexSender<Req>, ClientDuplexReceiver<Resp>)> {
-        let call = channel.create_call(method, &opt)?;
+        let mut call = channel.create_call(method, &opt)?;
+        opt.attach_extra_headers();
+        call.call_id = opt.call_id.take();
+        call.compressor = opt.message_compressor.take();
         let cq_f = check_run(BatchType::Finish, |ctx, tag| unsafe {
             grpc_sys::grpcwrap_call_start_duplex_streaming(
                 call.call,
@@ -204,12 +686,15 @@ impl Call {
             )
         });
 
-        // TODO: handle header.
-        check_run(BatchType::Finish, |ctx, tag| unsafe {
+        let headers_f = check_run(BatchType::Finish, |ctx, tag| unsafe {
             grpc_sys::grpcwrap_call_recv_initial_metadata(call.call, ctx, tag)
         });
 
-        let share_call = Arc::new(Mutex::new(ShareCall::new(call, cq_f)));
+        let mut share_call = ShareCall::new(call, cq_f);
+        share_call.headers_f = Some(headers_f);
+        share_call.channel_secure = Some(channel.is_secure());
+        share_call.idle = opt.idle_timeout.map(IdleTimer::new);
+        let share_call = Arc::new(Mutex::new(share_call));
         let sink = ClientDuplexSender::new(share_call.clone(), method.req_ser());
         let recv = ClientDuplexReceiver::new(share_call, method.resp_de());
         Ok((sink, recv))
@@ -224,26 +709,98 @@ pub struct ClientUnaryReceiver<T> {
     call: Call,
     resp_f: BatchFuture,
     resp_de: DeserializeFn<T>,
+    channel_secure: bool,
 }
 
 impl<T> ClientUnaryReceiver<T> {
-    fn new(call: Call, resp_f: BatchFuture, resp_de: DeserializeFn<T>) -> ClientUnaryReceiver<T> {
+    fn new(
+        call: Call,
+        resp_f: BatchFuture,
+        resp_de: DeserializeFn<T>,
+        channel_secure: bool,
+    ) -> ClientUnaryReceiver<T> {
         ClientUnaryReceiver {
             call,
             resp_f,
             resp_de,
+            channel_secure,
         }
     }
 
     /// Cancel the call.
+    ///
+    /// The call finishes with `CANCELLED` locally, and the server is notified
+    /// that the call is cancelled.
     #[inline]
     pub fn cancel(&mut self) {
         self.call.cancel()
     }
 
+    /// Cancel the call with the given status.
+    ///
+    /// The call finishes with `status` locally, so the reason of cancellation
+    /// is visible to everything waiting on the call. Only the code can reach the
+    /// server, mapped to an HTTP/2 error code, as HTTP/2 can't carry a message
+    /// when resetting a stream.
+    #[inline]
+    pub fn cancel_with_status(&mut self, status: &RpcStatus) {
+        self.call.cancel_with_status(status)
+    }
+
+    /// Get the authentication context of the server side.
+    ///
+    /// It's only available after the connection is established, and will
+    /// return None if the channel is not secure.
+    pub fn auth_context(&self) -> Option<AuthContext> {
+        self.call.auth_context()
+    }
+
+    /// Get the ID sent with the call, see [`CallOption::call_id`].
+    ///
+    /// [`CallOption::call_id`]: ./struct.CallOption.html#method.call_id
+    pub fn call_id(&self) -> Option<String> {
+        self.call.call_id.clone()
+    }
+
+    /// Get the absolute deadline of the call, see [`CallOption::timeout`].
+    ///
+    /// It's the time the call is created plus the timeout, measured by the system
+    /// clock like gRPC Core does, so it can be compared with `SystemTime::now()` but
+    /// is affected by clock adjustments. Returns `None` if the call has no timeout.
+    ///
+    /// [`CallOption::timeout`]: ./struct.CallOption.html#method.timeout
+    pub fn deadline_instant(&self) -> Option<SystemTime> {
+        self.call.deadline()
+    }
+
     #[inline]
     pub fn resp_de(&self, reader: MessageReader) -> Result<T> {
-        (self.resp_de)(reader)
+        (self.resp_de)(self.call.decompress(reader)?)
+    }
+
+    /// Get the full response, including the headers and trailers sent by
+    /// the server, instead of the message only.
+    pub fn full(mut self) -> impl Future<Output = Result<Response<T>>> {
+        future::poll_fn(move |cx| {
+            let data = ready!(self.poll_result(cx)?);
+            let message = self.resp_de(data.message_reader.unwrap())?;
+            let empty = || MetadataBuilder::new().build();
+            Poll::Ready(Ok(Response {
+                headers: data.initial_metadata.unwrap_or_else(empty),
+                message,
+                trailers: data.trailing_metadata.unwrap_or_else(empty),
+            }))
+        })
+    }
+
+    fn poll_result(&mut self, cx: &mut Context) -> Poll<Result<BatchResult>> {
+        match ready!(Pin::new(&mut self.resp_f).poll(cx)) {
+            Err(Error::RpcFailure(status)) => {
+                let status = explain_security_mismatch(self.channel_secure, status);
+                Poll::Ready(Err(Error::RpcFailure(status)))
+            }
+            res => Poll::Ready(res),
+        }
     }
 }
 
@@ -251,12 +808,46 @@ impl<T> Future for ClientUnaryReceiver<T> {
     type Output = Result<T>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>> {
-        let data = ready!(Pin::new(&mut self.resp_f).poll(cx)?);
-        let t = self.resp_de(data.unwrap())?;
+        let data = ready!(self.poll_result(cx)?);
+        let t = self.resp_de(data.message_reader.unwrap())?;
         Poll::Ready(Ok(t))
     }
 }
 
+/// A unary response with the metadata sent along with it.
+pub struct Response<T> {
+    headers: Metadata,
+    message: T,
+    trailers: Metadata,
+}
+
+impl<T> Response<T> {
+    /// Get the response headers (initial metadata).
+    pub fn headers(&self) -> &Metadata {
+        &self.headers
+    }
+
+    /// Get the response message.
+    pub fn message(&self) -> &T {
+        &self.message
+    }
+
+    /// Get the trailing metadata.
+    pub fn trailers(&self) -> &Metadata {
+        &self.trailers
+    }
+
+    /// Consume the response and return the message.
+    pub fn into_message(self) -> T {
+        self.message
+    }
+
+    /// Consume the response and return the headers, message and trailers.
+    pub fn into_parts(self) -> (Metadata, T, Metadata) {
+        (self.headers, self.message, self.trailers)
+    }
+}
+
 /// A receiver for client streaming call.
 ///
 /// If the corresponding sink has dropped or cancelled, this will poll a
@@ -278,8 +869,54 @@ impl<T> ClientCStreamReceiver<T> {
         lock.call.cancel()
     }
 
+    /// Cancel the call with the given status.
+    ///
+    /// See [`ClientUnaryReceiver::cancel_with_status`] for details.
+    ///
+    /// [`ClientUnaryReceiver::cancel_with_status`]: ./struct.ClientUnaryReceiver.html#method.cancel_with_status
+    pub fn cancel_with_status(&mut self, status: &RpcStatus) {
+        let lock = self.call.lock();
+        lock.call.cancel_with_status(status)
+    }
+
+    /// Get the authentication context of the server side.
+    ///
+    /// See [`ClientUnaryReceiver::auth_context`] for details.
+    ///
+    /// [`ClientUnaryReceiver::auth_context`]: ./struct.ClientUnaryReceiver.html#method.auth_context
+    pub fn auth_context(&self) -> Option<AuthContext> {
+        self.call.lock().call.auth_context()
+    }
+
+    /// Get the ID sent with the call.
+    ///
+    /// See [`ClientUnaryReceiver::call_id`] for details.
+    ///
+    /// [`ClientUnaryReceiver::call_id`]: ./struct.ClientUnaryReceiver.html#method.call_id
+    pub fn call_id(&self) -> Option<String> {
+        self.call.lock().call.call_id.clone()
+    }
+
+    /// Get the absolute deadline of the call.
+    ///
+    /// See [`ClientUnaryReceiver::deadline_instant`] for details.
+    ///
+    /// [`ClientUnaryReceiver::deadline_instant`]: ./struct.ClientUnaryReceiver.html#method.deadline_instant
+    pub fn deadline_instant(&self) -> Option<SystemTime> {
+        self.call.lock().call.deadline()
+    }
+
+    /// Get the trailing metadata sent by the server.
+    ///
+    /// It's only available after the call is finished successfully, and will
+    /// return None if the server doesn't send any trailers.
+    pub fn trailers(&self) -> Option<Metadata> {
+        self.call.lock().trailers.clone()
+    }
+
     #[inline]
     pub fn resp_de(&self, reader: MessageReader) -> Result<T> {
+        let reader = self.call.lock().call.decompress(reader)?;
         (self.resp_de)(reader)
     }
 }
@@ -300,9 +937,10 @@ impl<T> Future for ClientCStreamReceiver<T> {
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>> {
         let data = {
             let mut call = self.call.lock();
+            call.poll_idle(cx);
             ready!(call.poll_finish(cx)?)
         };
-        let t = (self.resp_de)(data.unwrap())?;
+        let t = self.resp_de(data.unwrap())?;
         self.finished = true;
         Poll::Ready(Ok(t))
     }
@@ -322,9 +960,11 @@ pub struct StreamingCallSink<Req> {
 
 impl<Req> StreamingCallSink<Req> {
     fn new(call: Arc<Mutex<ShareCall>>, req_ser: SerializeFn<Req>) -> StreamingCallSink<Req> {
+        let mut sink_base = SinkBase::new(false);
+        sink_base.max_message_len = call.lock().call.max_send_len;
         StreamingCallSink {
             call,
-            sink_base: SinkBase::new(false),
+            sink_base,
             close_f: None,
             req_ser,
         }
@@ -343,10 +983,33 @@ impl<Req> StreamingCallSink<Req> {
         self.sink_base.enhance_buffer_strategy = flag;
     }
 
+    /// Get the number of bytes accepted by the sink but not sent out yet.
+    ///
+    /// It includes the message buffered by [`enhance_batch`] and the message being
+    /// sent, which is held back by gRPC C Core until HTTP/2 flow control allows it
+    /// to be written, so it grows when the peer reads slowly. Producers can use it
+    /// to throttle themselves. The value is only updated when the sink is polled.
+    ///
+    /// [`enhance_batch`]: #method.enhance_batch
+    pub fn buffered_len(&self) -> usize {
+        self.sink_base.buffered_len()
+    }
+
+    /// Cancel the call.
     pub fn cancel(&mut self) {
         let call = self.call.lock();
         call.call.cancel()
     }
+
+    /// Cancel the call with the given status.
+    ///
+    /// See [`ClientUnaryReceiver::cancel_with_status`] for details.
+    ///
+    /// [`ClientUnaryReceiver::cancel_with_status`]: ./struct.ClientUnaryReceiver.html#method.cancel_with_status
+    pub fn cancel_with_status(&mut self, status: &RpcStatus) {
+        let call = self.call.lock();
+        call.call.cancel_with_status(status)
+    }
 }
 
 impl<P> Drop for StreamingCallSink<P> {
@@ -374,6 +1037,7 @@ impl<Req> Sink<(Req, WriteFlags)> for StreamingCallSink<Req> {
         {
             let mut call = self.call.lock();
             call.check_alive()?;
+            call.touch();
         }
         let t = &mut *self;
         Pin::new(&mut t.sink_base).start_send(&mut t.call, &msg, flags, t.req_ser)
@@ -444,11 +1108,16 @@ impl<H: ShareCallHolder + Unpin, T> ResponseStreamImpl<H, T> {
         self.call.call(|c| c.call.cancel())
     }
 
+    fn cancel_with_status(&mut self, status: &RpcStatus) {
+        self.call.call(|c| c.call.cancel_with_status(status))
+    }
+
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<T>>> {
         if !self.finished {
             let t = &mut *self;
             let finished = &mut t.finished;
             let _ = t.call.call(|c| {
+                c.poll_idle(cx);
                 let res = c.poll_finish(cx);
                 *finished = c.finished;
                 res
@@ -459,7 +1128,7 @@ impl<H: ShareCallHolder + Unpin, T> ResponseStreamImpl<H, T> {
         loop {
             if !self.read_done {
                 if let Some(msg_f) = &mut self.msg_f {
-                    bytes = ready!(Pin::new(msg_f).poll(cx)?);
+                    bytes = ready!(Pin::new(msg_f).poll(cx)?).message_reader;
                     if bytes.is_none() {
                         self.read_done = true;
                     }
@@ -478,6 +1147,10 @@ impl<H: ShareCallHolder + Unpin, T> ResponseStreamImpl<H, T> {
             let msg_f = self.call.call(|c| c.call.start_recv_message())?;
             self.msg_f = Some(msg_f);
             if let Some(data) = bytes {
+                let data = self.call.call(|c| {
+                    c.touch();
+                    c.call.decompress(data)
+                })?;
                 let msg = (self.resp_de)(data)?;
                 return Poll::Ready(Some(Ok(msg)));
             }
@@ -497,23 +1170,88 @@ impl<H: ShareCallHolder + Unpin, T> ResponseStreamImpl<H, T> {
 #[must_use = "if unused the ClientSStreamReceiver may immediately cancel the RPC"]
 pub struct ClientSStreamReceiver<Resp> {
     imp: ResponseStreamImpl<ShareCall, Resp>,
+    total_deadline: Option<Delay>,
 }
 
 impl<Resp> ClientSStreamReceiver<Resp> {
-    fn new(
-        call: Call,
-        finish_f: BatchFuture,
-        de: DeserializeFn<Resp>,
-    ) -> ClientSStreamReceiver<Resp> {
-        let share_call = ShareCall::new(call, finish_f);
+    fn new(share_call: ShareCall, de: DeserializeFn<Resp>) -> ClientSStreamReceiver<Resp> {
         ClientSStreamReceiver {
             imp: ResponseStreamImpl::new(share_call, de),
+            total_deadline: None,
         }
     }
 
+    /// Bound the time to consume the whole stream.
+    ///
+    /// If the stream is not finished within `timeout`, the call is cancelled and
+    /// the stream ends with a [`RpcFailure`] error with the `DEADLINE_EXCEEDED`
+    /// status. Unlike [`CallOption::timeout`], the time is counted from now instead
+    /// of from the call is created, and it's only enforced while the stream is
+    /// polled.
+    ///
+    /// [`RpcFailure`]: ./enum.Error.html#variant.RpcFailure
+    /// [`CallOption::timeout`]: ./struct.CallOption.html#method.timeout
+    pub fn total_deadline(mut self, timeout: Duration) -> ClientSStreamReceiver<Resp> {
+        self.total_deadline = Some(Delay::new(Instant::now() + timeout));
+        self
+    }
+
+    /// Cancel the call.
     pub fn cancel(&mut self) {
         self.imp.cancel()
     }
+
+    /// Cancel the call with the given status.
+    ///
+    /// See [`ClientUnaryReceiver::cancel_with_status`] for details.
+    ///
+    /// [`ClientUnaryReceiver::cancel_with_status`]: ./struct.ClientUnaryReceiver.html#method.cancel_with_status
+    pub fn cancel_with_status(&mut self, status: &RpcStatus) {
+        self.imp.cancel_with_status(status)
+    }
+
+    /// Get the authentication context of the server side.
+    ///
+    /// See [`ClientUnaryReceiver::auth_context`] for details.
+    ///
+    /// [`ClientUnaryReceiver::auth_context`]: ./struct.ClientUnaryReceiver.html#method.auth_context
+    pub fn auth_context(&self) -> Option<AuthContext> {
+        self.imp.call.call.auth_context()
+    }
+
+    /// Get the ID sent with the call.
+    ///
+    /// See [`ClientUnaryReceiver::call_id`] for details.
+    ///
+    /// [`ClientUnaryReceiver::call_id`]: ./struct.ClientUnaryReceiver.html#method.call_id
+    pub fn call_id(&self) -> Option<String> {
+        self.imp.call.call.call_id.clone()
+    }
+
+    /// Get the absolute deadline of the call.
+    ///
+    /// See [`ClientUnaryReceiver::deadline_instant`] for details.
+    ///
+    /// [`ClientUnaryReceiver::deadline_instant`]: ./struct.ClientUnaryReceiver.html#method.deadline_instant
+    pub fn deadline_instant(&self) -> Option<SystemTime> {
+        self.imp.call.call.deadline()
+    }
+
+    /// Get the response headers (initial metadata) sent by the server.
+    ///
+    /// The returned future resolves once the headers are received, which
+    /// may happen before any message arrives.
+    pub fn headers(&mut self) -> impl Future<Output = Result<Metadata>> + '_ {
+        future::poll_fn(move |cx| self.imp.call.poll_headers(cx))
+    }
+
+    /// Get the trailing metadata sent by the server.
+    ///
+    /// It's only available after the call is finished successfully, and will
+    /// return None if the server doesn't send any trailers.
+    pub fn trailers(&self) -> Option<Metadata> {
+        self.imp.call.trailers.clone()
+    }
 }
 
 impl<Resp> Stream for ClientSStreamReceiver<Resp> {
@@ -521,6 +1259,15 @@ impl<Resp> Stream for ClientSStreamReceiver<Resp> {
 
     #[inline]
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Some(deadline) = &mut self.total_deadline {
+            if Pin::new(deadline).poll(cx).is_ready() {
+                self.total_deadline = None;
+                if !self.imp.finished {
+                    let status = RpcStatus::deadline_exceeded("Stream deadline exceeded");
+                    self.imp.cancel_with_status(&status);
+                }
+            }
+        }
         Pin::new(&mut self.imp).poll(cx)
     }
 }
@@ -544,9 +1291,62 @@ impl<Resp> ClientDuplexReceiver<Resp> {
         }
     }
 
+    /// Cancel the call.
     pub fn cancel(&mut self) {
         self.imp.cancel()
     }
+
+    /// Cancel the call with the given status.
+    ///
+    /// See [`ClientUnaryReceiver::cancel_with_status`] for details.
+    ///
+    /// [`ClientUnaryReceiver::cancel_with_status`]: ./struct.ClientUnaryReceiver.html#method.cancel_with_status
+    pub fn cancel_with_status(&mut self, status: &RpcStatus) {
+        self.imp.cancel_with_status(status)
+    }
+
+    /// Get the authentication context of the server side.
+    ///
+    /// See [`ClientUnaryReceiver::auth_context`] for details.
+    ///
+    /// [`ClientUnaryReceiver::auth_context`]: ./struct.ClientUnaryReceiver.html#method.auth_context
+    pub fn auth_context(&self) -> Option<AuthContext> {
+        self.imp.call.lock().call.auth_context()
+    }
+
+    /// Get the ID sent with the call.
+    ///
+    /// See [`ClientUnaryReceiver::call_id`] for details.
+    ///
+    /// [`ClientUnaryReceiver::call_id`]: ./struct.ClientUnaryReceiver.html#method.call_id
+    pub fn call_id(&self) -> Option<String> {
+        self.imp.call.lock().call.call_id.clone()
+    }
+
+    /// Get the absolute deadline of the call.
+    ///
+    /// See [`ClientUnaryReceiver::deadline_instant`] for details.
+    ///
+    /// [`ClientUnaryReceiver::deadline_instant`]: ./struct.ClientUnaryReceiver.html#method.deadline_instant
+    pub fn deadline_instant(&self) -> Option<SystemTime> {
+        self.imp.call.lock().call.deadline()
+    }
+
+    /// Get the response headers (initial metadata) sent by the server.
+    ///
+    /// The returned future resolves once the headers are received, which
+    /// may happen before any message arrives.
+    pub fn headers(&mut self) -> impl Future<Output = Result<Metadata>> + '_ {
+        future::poll_fn(move |cx| self.imp.call.lock().poll_headers(cx))
+    }
+
+    /// Get the trailing metadata sent by the server.
+    ///
+    /// It's only available after the call is finished successfully, and will
+    /// return None if the server doesn't send any trailers.
+    pub fn trailers(&self) -> Option<Metadata> {
+        self.imp.call.lock().trailers.clone()
+    }
 }
 
 impl<Resp> Drop for ClientDuplexReceiver<Resp> {
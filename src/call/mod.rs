@@ -3,28 +3,35 @@
 pub mod client;
 pub mod server;
 
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
 use std::fmt::{self, Debug, Display};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::{ptr, slice};
+use std::time::{Duration, Instant, SystemTime};
+use std::{error, io, ptr, result, slice};
 
 use crate::cq::CompletionQueue;
 use crate::grpc_sys::{self, grpc_call, grpc_call_error, grpcwrap_batch_context};
 use futures::future::Future;
 use futures::ready;
-use futures::task::{Context, Poll};
+use futures::task::{Context, Poll, Waker};
 use libc::c_void;
 use parking_lot::Mutex;
 
+use crate::auth_context::AuthContext;
 use crate::buf::{GrpcByteBuffer, GrpcByteBufferReader, GrpcSlice};
-use crate::codec::{DeserializeFn, Marshaller, SerializeFn};
+use crate::codec::{self, DeserializeFn, Marshaller, MessageCompressor, SerializeFn};
 use crate::error::{Error, Result};
 use crate::grpc_sys::grpc_status_code::*;
-use crate::task::{self, BatchFuture, BatchType, CallTag};
+use crate::metadata::{Metadata, MetadataBuilder};
+use crate::metrics::CallRecorder;
+use crate::task::{self, BatchFuture, BatchType, CallTag, Delay};
 
 /// An gRPC status code structure.
 /// This type contains constants for all gRPC status codes.
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub struct RpcStatusCode(i32);
 
 impl From<i32> for RpcStatusCode {
@@ -39,6 +46,39 @@ impl Into<i32> for RpcStatusCode {
     }
 }
 
+impl RpcStatusCode {
+    /// Whether the code is one of the status codes defined by gRPC.
+    ///
+    /// Codes received from peers are preserved as is, so newer or custom codes
+    /// are still accessible via `Into<i32>`.
+    pub fn is_known(self) -> bool {
+        GRPC_STATUS_OK <= self.0 && self.0 <= GRPC_STATUS_UNAUTHENTICATED
+    }
+
+    /// Get the HTTP status code the gRPC status code maps to.
+    ///
+    /// It follows the mapping defined in `google/rpc/code.proto`, which is what
+    /// HTTP gateways usually use. Unknown codes are mapped to 500.
+    pub fn to_http_status(self) -> u16 {
+        match self {
+            RpcStatusCode::OK => 200,
+            RpcStatusCode::CANCELLED => 499,
+            RpcStatusCode::INVALID_ARGUMENT
+            | RpcStatusCode::FAILED_PRECONDITION
+            | RpcStatusCode::OUT_OF_RANGE => 400,
+            RpcStatusCode::UNAUTHENTICATED => 401,
+            RpcStatusCode::PERMISSION_DENIED => 403,
+            RpcStatusCode::NOT_FOUND => 404,
+            RpcStatusCode::ALREADY_EXISTS | RpcStatusCode::ABORTED => 409,
+            RpcStatusCode::RESOURCE_EXHAUSTED => 429,
+            RpcStatusCode::UNIMPLEMENTED => 501,
+            RpcStatusCode::UNAVAILABLE => 503,
+            RpcStatusCode::DEADLINE_EXCEEDED => 504,
+            _ => 500,
+        }
+    }
+}
+
 impl Display for RpcStatusCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         Debug::fmt(self, f)
@@ -126,6 +166,17 @@ pub struct Method<Req, Resp> {
     pub resp_mar: Marshaller<Resp>,
 }
 
+impl<Req, Resp> Clone for Method<Req, Resp> {
+    fn clone(&self) -> Method<Req, Resp> {
+        Method {
+            ty: self.ty,
+            name: self.name,
+            req_mar: self.req_mar.clone(),
+            resp_mar: self.resp_mar.clone(),
+        }
+    }
+}
+
 impl<Req, Resp> Method<Req, Resp> {
     /// Get the request serializer.
     #[inline]
@@ -153,13 +204,32 @@ impl<Req, Resp> Method<Req, Resp> {
 }
 
 /// RPC result returned from the server.
-#[derive(Debug, Clone)]
+///
+/// It can't be built by a struct literal, use [`RpcStatus::new`] or the
+/// constructors of each status code like [`RpcStatus::unavailable`] instead.
+///
+/// [`RpcStatus::new`]: #method.new
+/// [`RpcStatus::unavailable`]: #method.unavailable
+#[derive(Clone)]
 pub struct RpcStatus {
     /// gRPC status code. `Ok` indicates success, all other values indicate an error.
     pub status: RpcStatusCode,
 
     /// Optional detail string.
     pub details: Option<String>,
+
+    debug_error_string: Option<String>,
+}
+
+// The debug error string is left out to keep the format of failures, it's
+// verbose and can be read by `debug_error_string` when needed.
+impl Debug for RpcStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("RpcStatus")
+            .field("status", &self.status)
+            .field("details", &self.details)
+            .finish()
+    }
 }
 
 impl Display for RpcStatus {
@@ -174,6 +244,7 @@ impl RpcStatus {
         RpcStatus {
             status: code.into(),
             details,
+            debug_error_string: None,
         }
     }
 
@@ -181,6 +252,130 @@ impl RpcStatus {
     pub fn ok() -> RpcStatus {
         RpcStatus::new(RpcStatusCode::OK, None)
     }
+
+    /// Get the raw status code, which may be out of the range of codes known
+    /// to this library.
+    pub fn code(&self) -> i32 {
+        self.status.into()
+    }
+
+    /// Get the full error string of gRPC Core, if any.
+    ///
+    /// It's only available for failures received by client, which gRPC Core
+    /// describes in detail including the underlying cause, like the error of
+    /// the connection and where it's raised. It's for diagnostics only, the format
+    /// is unspecified and may change between versions of gRPC Core.
+    pub fn debug_error_string(&self) -> Option<&str> {
+        self.debug_error_string.as_deref()
+    }
+}
+
+macro_rules! status_constructors {
+    ($($(#[$attr:meta])* $name:ident => $code:ident,)+) => {
+        impl RpcStatus {
+            $(
+                $(#[$attr])*
+                pub fn $name<S: Into<String>>(details: S) -> RpcStatus {
+                    RpcStatus::new(RpcStatusCode::$code, Some(details.into()))
+                }
+            )+
+        }
+    };
+}
+
+status_constructors! {
+    /// Create a new [`RpcStatus`] of `CANCELLED` with the details.
+    cancelled => CANCELLED,
+    /// Create a new [`RpcStatus`] of `UNKNOWN` with the details.
+    unknown => UNKNOWN,
+    /// Create a new [`RpcStatus`] of `INVALID_ARGUMENT` with the details.
+    invalid_argument => INVALID_ARGUMENT,
+    /// Create a new [`RpcStatus`] of `DEADLINE_EXCEEDED` with the details.
+    deadline_exceeded => DEADLINE_EXCEEDED,
+    /// Create a new [`RpcStatus`] of `NOT_FOUND` with the details.
+    not_found => NOT_FOUND,
+    /// Create a new [`RpcStatus`] of `ALREADY_EXISTS` with the details.
+    already_exists => ALREADY_EXISTS,
+    /// Create a new [`RpcStatus`] of `PERMISSION_DENIED` with the details.
+    permission_denied => PERMISSION_DENIED,
+    /// Create a new [`RpcStatus`] of `RESOURCE_EXHAUSTED` with the details.
+    resource_exhausted => RESOURCE_EXHAUSTED,
+    /// Create a new [`RpcStatus`] of `FAILED_PRECONDITION` with the details.
+    failed_precondition => FAILED_PRECONDITION,
+    /// Create a new [`RpcStatus`] of `ABORTED` with the details.
+    aborted => ABORTED,
+    /// Create a new [`RpcStatus`] of `OUT_OF_RANGE` with the details.
+    out_of_range => OUT_OF_RANGE,
+    /// Create a new [`RpcStatus`] of `UNIMPLEMENTED` with the details.
+    unimplemented => UNIMPLEMENTED,
+    /// Create a new [`RpcStatus`] of `INTERNAL` with the details.
+    internal => INTERNAL,
+    /// Create a new [`RpcStatus`] of `UNAVAILABLE` with the details.
+    unavailable => UNAVAILABLE,
+    /// Create a new [`RpcStatus`] of `DATA_LOSS` with the details.
+    data_loss => DATA_LOSS,
+    /// Create a new [`RpcStatus`] of `UNAUTHENTICATED` with the details.
+    unauthenticated => UNAUTHENTICATED,
+}
+
+impl error::Error for RpcStatus {}
+
+impl From<io::Error> for RpcStatus {
+    /// Convert an I/O error to a status.
+    ///
+    /// Error kinds that have obvious counterparts, like `NotFound`, are mapped to
+    /// them, others are mapped to `INTERNAL`. The error is used as the details.
+    fn from(e: io::Error) -> RpcStatus {
+        let code = match e.kind() {
+            io::ErrorKind::NotFound => RpcStatusCode::NOT_FOUND,
+            io::ErrorKind::PermissionDenied => RpcStatusCode::PERMISSION_DENIED,
+            io::ErrorKind::AlreadyExists => RpcStatusCode::ALREADY_EXISTS,
+            io::ErrorKind::InvalidInput => RpcStatusCode::INVALID_ARGUMENT,
+            io::ErrorKind::TimedOut => RpcStatusCode::DEADLINE_EXCEEDED,
+            _ => RpcStatusCode::INTERNAL,
+        };
+        RpcStatus::new(code, Some(e.to_string()))
+    }
+}
+
+impl From<Error> for RpcStatus {
+    /// Convert an error of this library to a status.
+    ///
+    /// A failed call returns its status as is, so handlers can pass on the
+    /// failures of outbound calls. Other errors are mapped to `INTERNAL`.
+    fn from(e: Error) -> RpcStatus {
+        match e {
+            Error::RpcFailure(status) => status,
+            e => RpcStatus::new(RpcStatusCode::INTERNAL, Some(e.to_string())),
+        }
+    }
+}
+
+/// Map errors of any type into [`RpcStatus`].
+///
+/// It's implemented for all results whose errors implement `Display`, like
+/// `std::io::Error` or `anyhow::Error`, so errors can be propagated by `?` in
+/// handlers returning `Result<T, RpcStatus>` with explicit status codes.
+///
+/// ```ignore
+/// let user = db.find_user(&id).status(RpcStatusCode::NOT_FOUND)?;
+/// ```
+pub trait StatusResultExt<T> {
+    /// Map the error to a status of the code, the error is used as the details.
+    fn status(self, code: RpcStatusCode) -> result::Result<T, RpcStatus>;
+
+    /// Map the error to a status of `INTERNAL`.
+    fn internal(self) -> result::Result<T, RpcStatus>;
+}
+
+impl<T, E: Display> StatusResultExt<T> for result::Result<T, E> {
+    fn status(self, code: RpcStatusCode) -> result::Result<T, RpcStatus> {
+        self.map_err(|e| RpcStatus::new(code, Some(e.to_string())))
+    }
+
+    fn internal(self) -> result::Result<T, RpcStatus> {
+        self.status(RpcStatusCode::INTERNAL)
+    }
 }
 
 pub type MessageReader = GrpcByteBufferReader;
@@ -230,13 +425,42 @@ impl BatchContext {
             }
         };
 
-        RpcStatus::new(status, details)
+        let debug_error_string = unsafe {
+            let ptr = grpc_sys::grpcwrap_batch_context_recv_status_on_client_error_string(self.ctx);
+            if ptr.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+            }
+        };
+
+        RpcStatus {
+            debug_error_string,
+            ..RpcStatus::new(status, details)
+        }
+    }
+
+    /// Get a copy of the received initial metadata, if any.
+    pub fn initial_metadata(&self) -> Option<Metadata> {
+        unsafe {
+            let ptr = grpc_sys::grpcwrap_batch_context_recv_initial_metadata(self.ctx);
+            copy_metadata(ptr)
+        }
+    }
+
+    /// Get a copy of the received trailing metadata, if any.
+    pub fn trailing_metadata(&self) -> Option<Metadata> {
+        unsafe {
+            let ptr =
+                grpc_sys::grpcwrap_batch_context_recv_status_on_client_trailing_metadata(self.ctx);
+            copy_metadata(ptr)
+        }
     }
 
     /// Fetch the response bytes of the rpc call.
     pub fn recv_message(&mut self) -> Option<MessageReader> {
         let buf = self.take_recv_message()?;
-        Some(GrpcByteBufferReader::new(buf))
+        Some(GrpcByteBufferReader::new_compressed(buf))
     }
 }
 
@@ -255,6 +479,15 @@ fn box_batch_tag(tag: CallTag) -> (*mut grpcwrap_batch_context, *mut c_void) {
     )
 }
 
+unsafe fn copy_metadata(ptr: *const grpc_sys::grpc_metadata_array) -> Option<Metadata> {
+    let metadata = &*(ptr as *const Metadata);
+    if metadata.is_empty() {
+        None
+    } else {
+        Some(metadata.clone())
+    }
+}
+
 /// A helper function that runs the batch call and checks the result.
 fn check_run<F>(bt: BatchType, f: F) -> BatchFuture
 where
@@ -280,6 +513,22 @@ where
 pub struct Call {
     pub call: *mut grpc_call,
     pub cq: CompletionQueue,
+    registry: Option<Arc<CallRegistry>>,
+    // Whether the call is counted as in flight on `cq`.
+    counted: bool,
+    // The ID sent with the call, only set on client side.
+    call_id: Option<String>,
+    // Compressor of the messages in the codec layer.
+    compressor: Option<Arc<dyn MessageCompressor>>,
+    // Message length limits checked by the library, only set on client side.
+    max_send_len: Option<usize>,
+    max_receive_len: Option<usize>,
+    // Limit of messages after being decompressed by `compressor`.
+    max_decompressed_len: Option<usize>,
+    // The absolute deadline of the call, only set on client side.
+    deadline: Option<SystemTime>,
+    // Records the call to server metrics, only set on server side.
+    recorder: Option<CallRecorder>,
 }
 
 unsafe impl Send for Call {}
@@ -287,7 +536,141 @@ unsafe impl Send for Call {}
 impl Call {
     pub unsafe fn from_raw(call: *mut grpc_sys::grpc_call, cq: CompletionQueue) -> Call {
         assert!(!call.is_null());
-        Call { call, cq }
+        Call {
+            call,
+            cq,
+            registry: None,
+            counted: false,
+            call_id: None,
+            compressor: None,
+            max_send_len: None,
+            max_receive_len: None,
+            max_decompressed_len: None,
+            deadline: None,
+            recorder: None,
+        }
+    }
+
+    /// Set the absolute deadline of the call, which is only tracked on client side.
+    pub fn set_deadline(&mut self, deadline: Option<SystemTime>) {
+        self.deadline = deadline;
+    }
+
+    /// Get the absolute deadline of the call set by [`set_deadline`].
+    ///
+    /// [`set_deadline`]: #method.set_deadline
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+
+    /// Record the call to server metrics when it's released.
+    pub(crate) fn set_recorder(&mut self, recorder: CallRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Compress the serialized message if the call uses a message compressor.
+    fn compress(&self, buf: &mut GrpcSlice) -> Result<()> {
+        match &self.compressor {
+            Some(c) => codec::compress_message(c.as_ref(), buf),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the length of a message to be sent against the send limit of the call.
+    fn check_send_len(&self, len: usize) -> Result<()> {
+        match self.max_send_len {
+            Some(max) if len > max => Err(Error::RpcFailure(message_too_large(len, max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decompress the received message if the call uses a message compressor.
+    ///
+    /// The call is canceled if the message exceeds the receive limit of the call,
+    /// before or after decompression.
+    fn decompress(&self, reader: MessageReader) -> Result<MessageReader> {
+        if let Some(max) = self.max_receive_len {
+            if reader.len() > max {
+                let status = RpcStatus::new(
+                    RpcStatusCode::RESOURCE_EXHAUSTED,
+                    Some(format!(
+                        "Received message larger than max ({} vs. {})",
+                        reader.len(),
+                        max
+                    )),
+                );
+                self.cancel_with_status(&status);
+                return Err(Error::RpcFailure(status));
+            }
+        }
+        let reader = if reader.is_compressed() {
+            // Left compressed by gRPC Core, which happens when per-message decompression
+            // is disabled.
+            self.cancel_on_failure(codec::inflate_message(reader, self.max_decompressed_len))?
+        } else {
+            reader
+        };
+        let c = match &self.compressor {
+            Some(c) => c,
+            None => return Ok(reader),
+        };
+        let res = codec::decompress_message(c.as_ref(), reader, self.max_decompressed_len);
+        self.cancel_on_failure(res)
+    }
+
+    // Cancel the call with the status if decompressing fails with one.
+    fn cancel_on_failure(&self, res: Result<MessageReader>) -> Result<MessageReader> {
+        if let Err(Error::RpcFailure(status)) = &res {
+            self.cancel_with_status(status);
+        }
+        res
+    }
+
+    /// Set the message length limits checked by the library, `None` means unlimited.
+    pub fn set_message_limits(
+        &mut self,
+        max_send_len: Option<usize>,
+        max_receive_len: Option<usize>,
+    ) {
+        self.max_send_len = max_send_len;
+        self.max_receive_len = max_receive_len;
+        // Like gRPC, the receive limit applies to decompressed messages too.
+        self.max_decompressed_len = max_receive_len;
+    }
+
+    /// Count the call as in flight on its completion queue until it's dropped.
+    pub fn count_in_flight(&mut self) {
+        if !self.counted {
+            self.cq.add_call();
+            self.counted = true;
+        }
+    }
+
+    /// Get the authentication context of the call.
+    ///
+    /// Returns `None` if the call is not secured.
+    pub fn auth_context(&self) -> Option<AuthContext> {
+        unsafe { AuthContext::from_call_ptr(self.call) }
+    }
+
+    /// Track the call in the registry until it's dropped.
+    pub fn register(&mut self, registry: Arc<CallRegistry>) {
+        registry.calls.lock().insert(self.call as usize);
+        self.registry = Some(registry);
+    }
+
+    /// Send the initial metadata asynchronously.
+    pub fn start_send_initial_metadata(&mut self, metadata: &mut Metadata) -> Result<BatchFuture> {
+        let _cq_ref = self.cq.borrow()?;
+        let f = check_run(BatchType::Finish, |ctx, tag| unsafe {
+            grpc_sys::grpcwrap_call_send_initial_metadata(
+                self.call,
+                ctx,
+                metadata as *mut _ as _,
+                tag,
+            )
+        });
+        Ok(f)
     }
 
     /// Send a message asynchronously.
@@ -338,6 +721,7 @@ impl Call {
         let f = check_run(BatchType::Finish, |ctx, tag| unsafe {
             grpc_sys::grpcwrap_call_start_serverside(self.call, ctx, tag)
         });
+        self.count_in_flight();
         Ok(f)
     }
 
@@ -345,11 +729,15 @@ impl Call {
     pub fn start_send_status_from_server(
         &mut self,
         status: &RpcStatus,
+        trailers: &mut Option<Metadata>,
         send_empty_metadata: bool,
         payload: &mut Option<GrpcSlice>,
         write_flags: u32,
     ) -> Result<BatchFuture> {
         let _cq_ref = self.cq.borrow()?;
+        if let Some(r) = &mut self.recorder {
+            r.set_status(status.status);
+        }
         let send_empty_metadata = if send_empty_metadata { 1 } else { 0 };
         let f = check_run(BatchType::Finish, |ctx, tag| unsafe {
             let details_ptr = status
@@ -367,7 +755,9 @@ impl Call {
                 status.status.into(),
                 details_ptr,
                 details_len,
-                ptr::null_mut(),
+                trailers
+                    .as_mut()
+                    .map_or_else(ptr::null_mut, |t| t as *mut _ as _),
                 send_empty_metadata,
                 payload_p,
                 write_flags,
@@ -378,13 +768,16 @@ impl Call {
     }
 
     /// Abort an rpc call before handler is called.
-    pub fn abort(self, status: &RpcStatus) {
+    pub fn abort(mut self, status: &RpcStatus) {
         match self.cq.borrow() {
             // Queue is shutdown, ignore.
             Err(Error::QueueShutdown) => return,
             Err(e) => panic!("unexpected error when aborting call: {:?}", e),
             _ => {}
         }
+        if let Some(r) = &mut self.recorder {
+            r.set_status(status.status);
+        }
         let call_ptr = self.call;
         let tag = CallTag::abort(self);
         let (batch_ptr, tag_ptr) = box_batch_tag(tag);
@@ -428,14 +821,103 @@ impl Call {
             grpc_sys::grpc_call_cancel(self.call, ptr::null_mut());
         }
     }
+
+    /// Cancel the rpc call with the given status.
+    fn cancel_with_status(&self, status: &RpcStatus) {
+        match self.cq.borrow() {
+            // Queue is shutdown, ignore.
+            Err(Error::QueueShutdown) => return,
+            Err(e) => panic!("unexpected error when canceling call: {:?}", e),
+            _ => {}
+        }
+        let details = status.details.as_ref().map_or_else(CString::default, |s| {
+            CString::new(s.as_str()).unwrap_or_default()
+        });
+        unsafe {
+            grpc_sys::grpc_call_cancel_with_status(
+                self.call,
+                status.status.into(),
+                details.as_ptr(),
+                ptr::null_mut(),
+            );
+        }
+    }
 }
 
 impl Drop for Call {
     fn drop(&mut self) {
+        // Unregister before releasing the call so the registry never sees
+        // a dangling pointer.
+        if let Some(registry) = self.registry.take() {
+            registry.unregister(self.call);
+        }
+        if self.counted {
+            self.cq.remove_call();
+        }
         unsafe { grpc_sys::grpc_call_unref(self.call) }
     }
 }
 
+/// A set of calls that are still alive, so they can be canceled in bulk.
+#[derive(Default)]
+pub struct CallRegistry {
+    calls: Mutex<HashSet<usize>>,
+    draining: AtomicBool,
+    // Wakers of the tasks waiting for all calls to finish.
+    drain_wakers: Mutex<Vec<Waker>>,
+}
+
+impl CallRegistry {
+    fn unregister(&self, call: *mut grpc_call) {
+        let mut calls = self.calls.lock();
+        calls.remove(&(call as usize));
+        if calls.is_empty() {
+            for waker in self.drain_wakers.lock().drain(..) {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Get the number of registered calls.
+    pub fn count(&self) -> usize {
+        self.calls.lock().len()
+    }
+
+    /// Refuse new calls from now on.
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Poll whether all registered calls are finished.
+    pub fn poll_drained(&self, cx: &mut Context) -> Poll<()> {
+        let calls = self.calls.lock();
+        if calls.is_empty() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.drain_wakers.lock();
+        // Keep one waker per task, repeated polls must not grow the list.
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    /// Cancel all registered calls.
+    ///
+    /// # Safety
+    ///
+    /// The completion queues of the registered calls must not be shutdown.
+    pub unsafe fn cancel_all(&self) {
+        for call in self.calls.lock().iter() {
+            grpc_sys::grpc_call_cancel(*call as *mut grpc_call, ptr::null_mut());
+        }
+    }
+}
+
 /// A share object for client streaming and duplex streaming call.
 ///
 /// In both cases, receiver and sender can be polled in the same time,
@@ -446,6 +928,13 @@ struct ShareCall {
     close_f: BatchFuture,
     finished: bool,
     status: Option<RpcStatus>,
+    headers_f: Option<BatchFuture>,
+    headers: Option<Metadata>,
+    trailers: Option<Metadata>,
+    // Whether the channel is secure, only set for client calls.
+    channel_secure: Option<bool>,
+    // Only set for client streaming calls with an idle timeout.
+    idle: Option<IdleTimer>,
 }
 
 impl ShareCall {
@@ -455,20 +944,68 @@ impl ShareCall {
             close_f,
             finished: false,
             status: None,
+            headers_f: None,
+            headers: None,
+            trailers: None,
+            channel_secure: None,
+            idle: None,
+        }
+    }
+
+    /// Mark the call as active, so the idle timeout starts over.
+    fn touch(&mut self) {
+        if let Some(idle) = &mut self.idle {
+            idle.reset();
+        }
+    }
+
+    /// Cancel the call with `DEADLINE_EXCEEDED` if it has been idle for longer
+    /// than the idle timeout.
+    fn poll_idle(&mut self, cx: &mut Context) {
+        if self.finished {
+            return;
+        }
+        if let Some(idle) = &mut self.idle {
+            if idle.poll_expired(cx) {
+                self.idle = None;
+                let status = RpcStatus::deadline_exceeded("Idle timeout exceeded");
+                self.call.cancel_with_status(&status);
+            }
         }
     }
 
+    /// Poll the initial metadata sent by the remote side.
+    ///
+    /// Empty metadata is returned if it's not received separately.
+    fn poll_headers(&mut self, cx: &mut Context) -> Poll<Result<Metadata>> {
+        if let Some(f) = &mut self.headers_f {
+            let result = ready!(Pin::new(f).poll(cx)?);
+            self.headers = result.initial_metadata;
+            self.headers_f.take();
+        }
+        let headers = match &self.headers {
+            Some(headers) => headers.clone(),
+            None => MetadataBuilder::new().build(),
+        };
+        Poll::Ready(Ok(headers))
+    }
+
     /// Poll if the call is still alive.
     ///
     /// If the call is still running, will register a notification for its completion.
     fn poll_finish(&mut self, cx: &mut Context) -> Poll<Result<Option<MessageReader>>> {
         let res = match Pin::new(&mut self.close_f).poll(cx) {
-            Poll::Ready(Ok(reader)) => {
+            Poll::Ready(Ok(result)) => {
                 self.status = Some(RpcStatus::ok());
-                Poll::Ready(Ok(reader))
+                self.trailers = result.trailing_metadata;
+                Poll::Ready(Ok(result.message_reader))
             }
             Poll::Pending => return Poll::Pending,
             Poll::Ready(Err(Error::RpcFailure(status))) => {
+                let status = match self.channel_secure {
+                    Some(secure) => client::explain_security_mismatch(secure, status),
+                    None => status,
+                };
                 self.status = Some(status.clone());
                 Poll::Ready(Err(Error::RpcFailure(status)))
             }
@@ -490,6 +1027,45 @@ impl ShareCall {
     }
 }
 
+/// Expires once no activity is seen for `timeout`, like a call for its idle
+/// timeout or a stream sink for its heartbeat.
+struct IdleTimer {
+    timeout: Duration,
+    last_active: Instant,
+    // Rearmed lazily instead of on every message, so timers don't pile up.
+    timer: Delay,
+}
+
+impl IdleTimer {
+    fn new(timeout: Duration) -> IdleTimer {
+        let now = Instant::now();
+        IdleTimer {
+            timeout,
+            last_active: now,
+            timer: Delay::new(now + timeout),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.last_active = Instant::now();
+    }
+
+    // Checks whether the timeout is exceeded. Otherwise the task is woken up
+    // when it may be.
+    fn poll_expired(&mut self, cx: &mut Context) -> bool {
+        loop {
+            if Pin::new(&mut self.timer).poll(cx).is_pending() {
+                return false;
+            }
+            let due = self.last_active + self.timeout;
+            if due <= Instant::now() {
+                return true;
+            }
+            self.timer = Delay::new(due);
+        }
+    }
+}
+
 /// A helper trait that allows executing function on the internal `ShareCall` struct.
 trait ShareCallHolder {
     fn call<R, F: FnOnce(&mut ShareCall) -> R>(&mut self, f: F) -> R;
@@ -513,6 +1089,10 @@ struct StreamingBase {
     close_f: Option<BatchFuture>,
     msg_f: Option<BatchFuture>,
     read_done: bool,
+    // Whether reading is done because the call is broken.
+    read_failed: bool,
+    // Whether to start receiving the next message before it's asked for.
+    read_ahead: bool,
 }
 
 impl StreamingBase {
@@ -521,6 +1101,8 @@ impl StreamingBase {
             close_f,
             msg_f: None,
             read_done: false,
+            read_failed: false,
+            read_ahead: true,
         }
     }
 
@@ -546,9 +1128,15 @@ impl StreamingBase {
         let mut bytes = None;
         if !self.read_done {
             if let Some(msg_f) = &mut self.msg_f {
-                bytes = ready!(Pin::new(msg_f).poll(cx)?);
+                let res = ready!(Pin::new(msg_f).poll(cx)?);
+                bytes = res.message_reader;
                 if bytes.is_none() {
                     self.read_done = true;
+                    if res.read_failed {
+                        // The stream is broken instead of being closed by the peer.
+                        self.read_failed = true;
+                        return Poll::Ready(Some(Err(Error::RemoteStopped)));
+                    }
                 }
             }
         }
@@ -562,6 +1150,10 @@ impl StreamingBase {
 
         // so msg_f must be either stale or not initialized yet.
         self.msg_f.take();
+        if bytes.is_some() && !self.read_ahead {
+            // The next message will be received when it's polled.
+            return Poll::Ready(bytes.map(Ok));
+        }
         let msg_f = call.call(|c| c.call.start_recv_message())?;
         self.msg_f = Some(msg_f);
         if bytes.is_none() {
@@ -621,6 +1213,17 @@ impl WriteFlags {
     }
 }
 
+/// The status reported when a message exceeds the send limit.
+fn message_too_large(len: usize, max: usize) -> RpcStatus {
+    RpcStatus::new(
+        RpcStatusCode::RESOURCE_EXHAUSTED,
+        Some(format!(
+            "Sent message larger than max ({} vs. {})",
+            len, max
+        )),
+    )
+}
+
 /// A helper struct for constructing Sink object for batch requests.
 struct SinkBase {
     // Batch job to be executed in `poll_ready`.
@@ -636,6 +1239,10 @@ struct SinkBase {
     // Used to records whether a message in which `buffer_hint` is false exists.
     // Note: only used in enhanced buffer strategy.
     last_buf_hint: bool,
+    // Messages larger than the limit are rejected before being sent.
+    max_message_len: Option<usize>,
+    // Length of the message being sent by `batch_f`.
+    sending_len: usize,
 }
 
 impl SinkBase {
@@ -647,7 +1254,31 @@ impl SinkBase {
             last_buf_hint: true,
             send_metadata,
             enhance_buffer_strategy: false,
+            max_message_len: None,
+            sending_len: 0,
+        }
+    }
+
+    // Bytes that are accepted by the sink but not yet sent out.
+    fn buffered_len(&self) -> usize {
+        let buffered = if self.buf_flags.is_some() {
+            self.buffer.len()
+        } else {
+            0
+        };
+        self.sending_len + buffered
+    }
+
+    // Discard the serialized message in buffer if it exceeds the limit.
+    fn check_message_len(&mut self) -> Result<()> {
+        if let Some(max) = self.max_message_len {
+            let len = self.buffer.len();
+            if len > max {
+                self.buffer = GrpcSlice::default();
+                return Err(Error::RpcFailure(message_too_large(len, max)));
+            }
         }
+        Ok(())
     }
 
     fn start_send<T, C: ShareCallHolder>(
@@ -660,7 +1291,9 @@ impl SinkBase {
         // temporary fix: buffer hint with send meta will not send out any metadata.
         // note: only the first message can enter this code block.
         if self.send_metadata {
-            ser(t, &mut self.buffer);
+            ser(t, &mut self.buffer)?;
+            call.call(|c| c.call.compress(&mut self.buffer))?;
+            self.check_message_len()?;
             self.buf_flags = Some(flags);
             self.start_send_buffer_message(false, call)?;
             self.send_metadata = false;
@@ -673,7 +1306,9 @@ impl SinkBase {
             self.start_send_buffer_message(true, call)?;
         }
 
-        ser(t, &mut self.buffer);
+        ser(t, &mut self.buffer)?;
+        call.call(|c| c.call.compress(&mut self.buffer))?;
+        self.check_message_len()?;
         let hint = flags.get_buffer_hint();
         self.last_buf_hint &= hint;
         self.buf_flags = Some(flags);
@@ -686,6 +1321,27 @@ impl SinkBase {
         Ok(())
     }
 
+    // Send the message without buffer hint, regardless of the batch strategy.
+    //
+    // Messages buffered by the enhanced batch strategy should be flushed first.
+    fn start_send_now<T, C: ShareCallHolder>(
+        &mut self,
+        call: &mut C,
+        t: &T,
+        flags: WriteFlags,
+        ser: SerializeFn<T>,
+    ) -> Result<()> {
+        assert!(self.buf_flags.is_none());
+        ser(t, &mut self.buffer)?;
+        call.call(|c| c.call.compress(&mut self.buffer))?;
+        self.check_message_len()?;
+        self.buf_flags = Some(flags);
+        self.start_send_buffer_message(false, call)?;
+        self.send_metadata = false;
+        self.last_buf_hint = true;
+        Ok(())
+    }
+
     #[inline]
     fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<()>> {
         match &mut self.batch_f {
@@ -695,6 +1351,7 @@ impl SinkBase {
             }
         }
         self.batch_f.take();
+        self.sending_len = 0;
         Poll::Ready(Ok(()))
     }
 
@@ -715,6 +1372,19 @@ impl SinkBase {
         Poll::Ready(Ok(()))
     }
 
+    fn start_send_headers<C: ShareCallHolder>(
+        &mut self,
+        call: &mut C,
+        mut headers: Metadata,
+    ) -> Result<()> {
+        assert!(self.send_metadata, "headers have already been sent");
+        assert!(self.batch_f.is_none());
+        let f = call.call(|c| c.call.start_send_initial_metadata(&mut headers))?;
+        self.batch_f = Some(f);
+        self.send_metadata = false;
+        Ok(())
+    }
+
     #[inline]
     fn start_send_buffer_message<C: ShareCallHolder>(
         &mut self,
@@ -726,6 +1396,7 @@ impl SinkBase {
 
         let mut flags = self.buf_flags.clone().unwrap();
         flags = flags.buffer_hint(buffer_hint);
+        self.sending_len = self.buffer.len();
         let write_f = call.call(|c| {
             c.call
                 .start_send_message(&mut self.buffer, flags.flags, self.send_metadata)
@@ -738,3 +1409,39 @@ impl SinkBase {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_http_status() {
+        let table = [
+            (RpcStatusCode::OK, 200),
+            (RpcStatusCode::CANCELLED, 499),
+            (RpcStatusCode::UNKNOWN, 500),
+            (RpcStatusCode::INVALID_ARGUMENT, 400),
+            (RpcStatusCode::DEADLINE_EXCEEDED, 504),
+            (RpcStatusCode::NOT_FOUND, 404),
+            (RpcStatusCode::ALREADY_EXISTS, 409),
+            (RpcStatusCode::PERMISSION_DENIED, 403),
+            (RpcStatusCode::RESOURCE_EXHAUSTED, 429),
+            (RpcStatusCode::FAILED_PRECONDITION, 400),
+            (RpcStatusCode::ABORTED, 409),
+            (RpcStatusCode::OUT_OF_RANGE, 400),
+            (RpcStatusCode::UNIMPLEMENTED, 501),
+            (RpcStatusCode::INTERNAL, 500),
+            (RpcStatusCode::UNAVAILABLE, 503),
+            (RpcStatusCode::DATA_LOSS, 500),
+            (RpcStatusCode::UNAUTHENTICATED, 401),
+        ];
+        for code in GRPC_STATUS_OK..=GRPC_STATUS_UNAUTHENTICATED {
+            let (_, http) = table
+                .iter()
+                .find(|(c, _)| *c == RpcStatusCode::from(code))
+                .unwrap();
+            assert_eq!(RpcStatusCode::from(code).to_http_status(), *http);
+        }
+        assert_eq!(RpcStatusCode::from(42).to_http_status(), 500);
+    }
+}
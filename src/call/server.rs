@@ -3,31 +3,42 @@
 use std::ffi::CStr;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::{result, slice};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{result, slice, str};
 
 use crate::grpc_sys::{
     self, gpr_clock_type, gpr_timespec, grpc_call_error, grpcwrap_request_call_context,
 };
-use futures::future::Future;
+use futures::future::{self, Either, Future};
+use futures::io::{AsyncRead, AsyncReadExt};
 use futures::ready;
-use futures::sink::Sink;
-use futures::stream::Stream;
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{FusedStream, Stream};
 use futures::task::{Context, Poll};
 use parking_lot::Mutex;
 
-use super::{RpcStatus, ShareCall, ShareCallHolder, WriteFlags};
+use super::{message_too_large, IdleTimer, RpcStatus, ShareCall, ShareCallHolder, WriteFlags};
+use crate::audit::AuditEvent;
 use crate::auth_context::AuthContext;
 use crate::buf::GrpcSlice;
+use crate::call::client::{CALL_ID_KEY, CENSUS_CONTEXT_KEY, PROBE_KEY};
 use crate::call::{
     BatchContext, Call, MessageReader, MethodType, RpcStatusCode, SinkBase, StreamingBase,
 };
-use crate::codec::{DeserializeFn, SerializeFn};
+use crate::channel::CompressionAlgorithms;
+use crate::codec::{DeserializeFn, MessageCompressor, SerializeFn, MESSAGE_ENCODING_KEY};
 use crate::cq::CompletionQueue;
 use crate::error::{Error, Result};
 use crate::metadata::Metadata;
+use crate::metrics::{CallObservers, CallRecorder};
 use crate::server::{BoxHandler, RequestCallContext};
-use crate::task::{BatchFuture, CallTag, Executor, Kicker};
+use crate::task::{BatchFuture, CallTag, Delay, Executor, Kicker};
 
+/// The deadline of a call.
+///
+/// It's cheap to copy, so it can be moved into the futures spawned by a handler to
+/// check the time left, for example before producing each message of a stream.
+#[derive(Clone, Copy)]
 pub struct Deadline {
     spec: gpr_timespec,
 }
@@ -48,6 +59,65 @@ impl Deadline {
             grpc_sys::gpr_time_cmp(now, self.spec) >= 0
         }
     }
+
+    /// Get the time left before the deadline is exceeded.
+    ///
+    /// Returns `None` if the call has no deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        unsafe {
+            let inf = grpc_sys::gpr_inf_future(gpr_clock_type::GPR_CLOCK_REALTIME);
+            if grpc_sys::gpr_time_cmp(self.spec, inf) >= 0 {
+                return None;
+            }
+            let now = grpc_sys::gpr_now(gpr_clock_type::GPR_CLOCK_REALTIME);
+            if grpc_sys::gpr_time_cmp(now, self.spec) >= 0 {
+                return Some(Duration::from_secs(0));
+            }
+            let left = grpc_sys::gpr_time_sub(self.spec, now);
+            Some(Duration::new(left.tv_sec as u64, left.tv_nsec as u32))
+        }
+    }
+
+    /// Get the absolute time when the deadline is exceeded.
+    ///
+    /// gRPC Core tracks deadlines by the system clock, so the returned time can be
+    /// compared with `SystemTime::now()`, but it's affected by clock adjustments. The
+    /// client sends the timeout rather than the deadline, so it's the time the call
+    /// arrives plus the timeout. Returns `None` if the call has no deadline.
+    pub fn instant(&self) -> Option<SystemTime> {
+        unsafe {
+            let inf = grpc_sys::gpr_inf_future(gpr_clock_type::GPR_CLOCK_REALTIME);
+            if grpc_sys::gpr_time_cmp(self.spec, inf) >= 0 {
+                return None;
+            }
+        }
+        let since_epoch = Duration::new(self.spec.tv_sec as u64, self.spec.tv_nsec as u32);
+        Some(UNIX_EPOCH + since_epoch)
+    }
+}
+
+/// Limits of the messages of a call that are checked by the library.
+#[derive(Clone, Copy, Default)]
+pub struct MessageLimits {
+    pub max_send_len: Option<usize>,
+    pub max_decompressed_receive_len: Option<usize>,
+}
+
+impl MessageLimits {
+    fn check_received(&self, msg: &MessageReader) -> result::Result<(), RpcStatus> {
+        match self.max_decompressed_receive_len {
+            // Compressed messages are checked while they are decompressed.
+            Some(max) if !msg.is_compressed() && msg.len() > max => Err(RpcStatus::new(
+                RpcStatusCode::RESOURCE_EXHAUSTED,
+                Some(format!(
+                    "Received message larger than max after decompression ({} vs. {})",
+                    msg.len(),
+                    max
+                )),
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 /// Context for accepting a request.
@@ -74,17 +144,44 @@ impl RequestContext {
         cq: &CompletionQueue,
         rc: &mut RequestCallContext,
     ) -> result::Result<(), Self> {
+        let observers = rc.observers(self.method());
+        if !rc.accept_peer(|| self.peer()) {
+            execute_rejected(
+                self,
+                cq.clone(),
+                RpcStatus::new(
+                    RpcStatusCode::PERMISSION_DENIED,
+                    Some("peer is not allowed".to_owned()),
+                ),
+                &observers,
+            );
+            return Ok(());
+        }
+        let compressor = match self.message_compressor(rc) {
+            Ok(c) => c,
+            Err(status) => {
+                execute_rejected(self, cq.clone(), status, &observers);
+                return Ok(());
+            }
+        };
+        let limits = rc.message_limits();
         let handler = unsafe { rc.get_handler(self.method()) };
+        if handler.is_some() && rc.answers_probes() && self.is_probe() {
+            // Reply without calling the handler, so probes have no side effects.
+            execute_rejected(self, cq.clone(), RpcStatus::ok(), &observers);
+            return Ok(());
+        }
         match handler {
             Some(handler) => match handler.method_type() {
                 MethodType::Unary | MethodType::ServerStreaming => Err(self),
                 _ => {
-                    execute(self, cq, None, handler);
+                    execute(self, cq, None, handler, limits, compressor, observers);
                     Ok(())
                 }
             },
             None => {
-                execute_unimplemented(self, cq.clone());
+                let status = rc.unimplemented_status(self.method());
+                execute_rejected(self, cq.clone(), status, &observers);
                 Ok(())
             }
         }
@@ -179,6 +276,67 @@ impl RequestContext {
             AuthContext::from_call_ptr(call)
         }
     }
+
+    /// Create a recorder that reports the call to the observers when it's released.
+    ///
+    /// Returns `None` if there is nothing to report to.
+    fn recorder(&self, observers: &CallObservers, start: Instant) -> Option<CallRecorder> {
+        if observers.is_empty() {
+            return None;
+        }
+        let audit = observers.audit.as_ref().map(|hook| {
+            let identity = self
+                .auth_context()
+                .map(|ctx| {
+                    ctx.peer_identity()
+                        .into_iter()
+                        .map(|p| String::from_utf8_lossy(p.value()).into_owned())
+                        .collect()
+                })
+                .unwrap_or_default();
+            let event = AuditEvent {
+                method: String::from_utf8_lossy(self.method()).into_owned(),
+                peer: self.peer(),
+                identity,
+                status: RpcStatusCode::OK,
+                start: SystemTime::now() - start.elapsed(),
+                latency: Duration::default(),
+            };
+            (hook.clone(), event)
+        });
+        Some(CallRecorder::new(observers.metrics.clone(), audit, start))
+    }
+
+    /// Get the call that is reported to the observers when it's released.
+    fn observed_call(&self, cq: CompletionQueue, observers: &CallObservers) -> Call {
+        let mut call = self.call(cq);
+        if let Some(recorder) = self.recorder(observers, Instant::now()) {
+            call.set_recorder(recorder);
+        }
+        call
+    }
+
+    /// Whether the call only checks the existence of the method.
+    fn is_probe(&self) -> bool {
+        self.metadata().iter().any(|(key, _)| key == PROBE_KEY)
+    }
+
+    /// Get the message compressor the call asks for.
+    ///
+    /// It fails if the compressor is not registered to the server.
+    fn message_compressor(
+        &self,
+        rc: &RequestCallContext,
+    ) -> result::Result<Option<Arc<dyn MessageCompressor>>, RpcStatus> {
+        match self
+            .metadata()
+            .iter()
+            .find(|(key, _)| *key == MESSAGE_ENCODING_KEY)
+        {
+            Some((_, name)) => rc.message_compressor(name).map(Some),
+            None => Ok(None),
+        }
+    }
 }
 
 impl Drop for RequestContext {
@@ -225,54 +383,153 @@ impl UnaryRequestContext {
         cq: &CompletionQueue,
         reader: Option<MessageReader>,
     ) {
+        let observers = rc.observers(self.request.method());
+        let compressor = match self.request.message_compressor(rc) {
+            Ok(c) => c,
+            Err(status) => return execute_rejected(self.request, cq.clone(), status, &observers),
+        };
+        let limits = rc.message_limits();
         let handler = unsafe { rc.get_handler(self.request.method()).unwrap() };
-        if reader.is_some() {
-            return execute(self.request, cq, reader, handler);
+        if let Some(reader) = reader {
+            if let Err(status) = limits.check_received(&reader) {
+                return self
+                    .request
+                    .observed_call(cq.clone(), &observers)
+                    .abort(&status);
+            }
+            return execute(
+                self.request,
+                cq,
+                Some(reader),
+                handler,
+                limits,
+                compressor,
+                observers,
+            );
         }
 
-        let status = RpcStatus::new(RpcStatusCode::INTERNAL, Some("No payload".to_owned()));
-        self.request.call(cq.clone()).abort(&status)
+        let status = RpcStatus::new(RpcStatusCode::INTERNAL, Some(NO_PAYLOAD.to_owned()));
+        self.request
+            .observed_call(cq.clone(), &observers)
+            .abort(&status)
     }
 }
 
+/// The details of the status failing unary and server streaming calls that end
+/// without a request message.
+pub(crate) const NO_PAYLOAD: &str = "No payload";
+
 /// A stream for client a streaming call and a duplex streaming call.
 ///
+/// The stream ends with `None` when the client half-closes it cleanly, and with
+/// [`Error::RemoteStopped`] when the call is cancelled or the connection is broken,
+/// see [`RequestStream::is_half_closed`]. In the latter case nothing can be sent to
+/// the client anymore, the handler should abort its work and drop the sink.
+///
+/// The stream yields `None` after the terminal item. If the handler finishes the
+/// call by the sink before the client half-closes, reading the stream fails with
+/// [`Error::RpcFinished`] instead.
+///
 /// The corresponding RPC will be canceled if the stream did not
 /// finish before dropping.
+///
+/// [`Error::RemoteStopped`]: ./enum.Error.html#variant.RemoteStopped
+/// [`Error::RpcFinished`]: ./enum.Error.html#variant.RpcFinished
+/// [`RequestStream::is_half_closed`]: ./struct.RequestStream.html#method.is_half_closed
 #[must_use = "if unused the RequestStream may immediately cancel the RPC"]
 pub struct RequestStream<T> {
     call: Arc<Mutex<ShareCall>>,
     base: StreamingBase,
     de: DeserializeFn<T>,
+    limits: MessageLimits,
 }
 
 impl<T> RequestStream<T> {
-    fn new(call: Arc<Mutex<ShareCall>>, de: DeserializeFn<T>) -> RequestStream<T> {
+    fn new(
+        call: Arc<Mutex<ShareCall>>,
+        de: DeserializeFn<T>,
+        limits: MessageLimits,
+    ) -> RequestStream<T> {
         RequestStream {
             call,
             base: StreamingBase::new(None),
             de,
+            limits,
         }
     }
+
+    /// Set whether to receive the next message before the handler asks for it.
+    ///
+    /// By default the stream always keeps one receive in flight, so a message can be
+    /// buffered while the handler is busy. When `read_ahead` is disabled, a message is
+    /// only received when the stream is polled, and HTTP/2 flow control stops the client
+    /// from sending more than the stream window in the meantime. Use
+    /// [`ChannelBuilder::stream_initial_window_size`] and disable
+    /// [`ChannelBuilder::http2_bdp_probe`] on the server channel args to bound how many
+    /// bytes are buffered per stream.
+    ///
+    /// [`ChannelBuilder::stream_initial_window_size`]: ./struct.ChannelBuilder.html#method.stream_initial_window_size
+    /// [`ChannelBuilder::http2_bdp_probe`]: ./struct.ChannelBuilder.html#method.http2_bdp_probe
+    pub fn read_ahead(&mut self, read_ahead: bool) {
+        self.base.read_ahead = read_ahead;
+    }
+
+    /// Whether the client has half-closed the stream.
+    ///
+    /// It becomes true once the stream yields `None`, which only happens when the
+    /// client finishes sending cleanly. If the call is cancelled or broken, the
+    /// stream yields [`Error::RemoteStopped`] instead, so handlers can tell a
+    /// complete request stream from a partial one.
+    ///
+    /// [`Error::RemoteStopped`]: ./enum.Error.html#variant.RemoteStopped
+    pub fn is_half_closed(&self) -> bool {
+        self.base.read_done && !self.base.read_failed
+    }
 }
 
 impl<T> Stream for RequestStream<T> {
     type Item = Result<T>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<T>>> {
+        let t = &mut *self;
+        if t.base.read_done {
+            return Poll::Ready(None);
+        }
         {
-            let mut call = self.call.lock();
-            call.check_alive()?;
+            let mut call = t.call.lock();
+            if let Err(e) = call.check_alive() {
+                // The half-close from the client can't be received anymore.
+                t.base.read_done = true;
+                t.base.read_failed = true;
+                if call.finished {
+                    // Finished by the handler.
+                    return Poll::Ready(Some(Err(e)));
+                }
+                // The call is gone before the client half-closes the stream.
+                return Poll::Ready(Some(Err(Error::RemoteStopped)));
+            }
         }
 
-        let t = &mut *self;
         match ready!(t.base.poll(cx, &mut t.call, false)?) {
             None => Poll::Ready(None),
-            Some(data) => Poll::Ready(Some((t.de)(data))),
+            Some(data) => {
+                if let Err(status) = t.limits.check_received(&data) {
+                    t.call.lock().call.cancel_with_status(&status);
+                    return Poll::Ready(Some(Err(Error::RpcFailure(status))));
+                }
+                let data = t.call.lock().call.decompress(data)?;
+                Poll::Ready(Some((t.de)(data)))
+            }
         }
     }
 }
 
+impl<T> FusedStream for RequestStream<T> {
+    fn is_terminated(&self) -> bool {
+        self.base.read_done
+    }
+}
+
 impl<T> Drop for RequestStream<T> {
     /// The corresponding RPC will be canceled if the stream did not
     /// finish before dropping.
@@ -315,18 +572,35 @@ macro_rules! impl_unary_sink {
         pub struct $t<T> {
             call: Option<$holder>,
             write_flags: u32,
+            headers: Option<Metadata>,
+            trailers: Option<Metadata>,
             ser: SerializeFn<T>,
+            max_message_len: Option<usize>,
         }
 
         impl<T> $t<T> {
-            fn new(call: $holder, ser: SerializeFn<T>) -> $t<T> {
+            fn new(call: $holder, ser: SerializeFn<T>, max_message_len: Option<usize>) -> $t<T> {
                 $t {
                     call: Some(call),
                     write_flags: 0,
+                    headers: None,
+                    trailers: None,
                     ser,
+                    max_message_len,
                 }
             }
 
+            /// Set the response headers (initial metadata) that will be sent before
+            /// the response.
+            pub fn set_headers(&mut self, headers: Metadata) {
+                self.headers = Some(headers);
+            }
+
+            /// Set the trailing metadata that will be sent along with the status.
+            pub fn set_trailers(&mut self, trailers: Metadata) {
+                self.trailers = Some(trailers);
+            }
+
             pub fn success(self, t: T) -> $rt {
                 self.complete(RpcStatus::ok(), Some(t))
             }
@@ -335,22 +609,60 @@ macro_rules! impl_unary_sink {
                 self.complete(status, None)
             }
 
-            fn complete(mut self, status: RpcStatus, t: Option<T>) -> $rt {
-                let mut data = t.as_ref().map(|t| {
-                    let mut buf = GrpcSlice::default();
-                    (self.ser)(t, &mut buf);
-                    buf
-                });
+            fn complete(mut self, mut status: RpcStatus, t: Option<T>) -> $rt {
+                let mut ser_err = None;
+                let mut data = match t {
+                    Some(t) => {
+                        let mut buf = GrpcSlice::default();
+                        let res = match (self.ser)(&t, &mut buf) {
+                            Ok(()) => self.call.as_mut().unwrap().call(|c| c.call.compress(&mut buf)),
+                            Err(e) => Err(e),
+                        };
+                        match res {
+                            Ok(()) if self.max_message_len.map_or(false, |max| buf.len() > max) => {
+                                status = message_too_large(buf.len(), self.max_message_len.unwrap());
+                                ser_err = Some(Error::RpcFailure(status.clone()));
+                                None
+                            }
+                            Ok(()) => Some(buf),
+                            Err(e) => {
+                                // Don't leave the client waiting, report the failure
+                                // as an internal error instead.
+                                status = RpcStatus::new(
+                                    RpcStatusCode::INTERNAL,
+                                    Some(format!("Failed to serialize response message: {:?}", e)),
+                                );
+                                ser_err = Some(e);
+                                None
+                            }
+                        }
+                    }
+                    None => None,
+                };
 
                 let write_flags = self.write_flags;
+                let headers = self.headers.take();
+                let trailers = &mut self.trailers;
                 let res = self.call.as_mut().unwrap().call(|c| {
-                    c.call
-                        .start_send_status_from_server(&status, true, &mut data, write_flags)
+                    let send_empty_metadata = match headers {
+                        Some(mut headers) => {
+                            c.call.start_send_initial_metadata(&mut headers)?;
+                            false
+                        }
+                        None => true,
+                    };
+                    c.call.start_send_status_from_server(
+                        &status,
+                        trailers,
+                        send_empty_metadata,
+                        &mut data,
+                        write_flags,
+                    )
                 });
 
-                let (cq_f, err) = match res {
-                    Ok(f) => (Some(f), None),
-                    Err(e) => (None, Some(e)),
+                let (cq_f, err) = match (res, ser_err) {
+                    (Ok(f), None) => (Some(f), None),
+                    (Ok(_), Some(e)) | (Err(e), _) => (None, Some(e)),
                 };
 
                 $rt {
@@ -398,6 +710,35 @@ impl_unary_sink!(
     Arc<Mutex<ShareCall>>
 );
 
+// A message sent by a stream sink when it's idle for too long.
+struct Heartbeat<T> {
+    msg: T,
+    timer: IdleTimer,
+}
+
+impl<T> Heartbeat<T> {
+    fn new(interval: Duration, msg: T) -> Heartbeat<T> {
+        Heartbeat {
+            msg,
+            timer: IdleTimer::new(interval),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.timer.reset();
+    }
+
+    // Checks whether a heartbeat should be sent now, in which case it's treated
+    // as sent. Otherwise the task is woken up when it's due.
+    fn poll_due(&mut self, cx: &mut Context) -> bool {
+        if !self.timer.poll_expired(cx) {
+            return false;
+        }
+        self.timer.reset();
+        true
+    }
+}
+
 // A macro helper to implement server side streaming sink.
 macro_rules! impl_stream_sink {
     ($(#[$attr:meta])* $t:ident, $ft:ident, $holder:ty) => {
@@ -407,21 +748,28 @@ macro_rules! impl_stream_sink {
             base: SinkBase,
             flush_f: Option<BatchFuture>,
             status: RpcStatus,
+            trailers: Option<Metadata>,
             flushed: bool,
             closed: bool,
             ser: SerializeFn<T>,
+            // Boxed so the sink is still `Unpin` when `T` is not.
+            heartbeat: Option<Box<Heartbeat<T>>>,
         }
 
         impl<T> $t<T> {
-            fn new(call: $holder, ser: SerializeFn<T>) -> $t<T> {
+            fn new(call: $holder, ser: SerializeFn<T>, max_message_len: Option<usize>) -> $t<T> {
+                let mut base = SinkBase::new(true);
+                base.max_message_len = max_message_len;
                 $t {
                     call: Some(call),
-                    base: SinkBase::new(true),
+                    base,
                     flush_f: None,
                     status: RpcStatus::ok(),
+                    trailers: None,
                     flushed: false,
                     closed: false,
                     ser,
+                    heartbeat: None,
                 }
             }
 
@@ -438,17 +786,82 @@ macro_rules! impl_stream_sink {
                 self.base.enhance_buffer_strategy = flag;
             }
 
+            /// Get the number of bytes accepted by the sink but not sent out yet.
+            ///
+            /// It includes the message buffered by [`enhance_batch`] and the message being
+            /// sent, which is held back by gRPC C Core until HTTP/2 flow control allows it
+            /// to be written, so it grows when the peer reads slowly. Producers can use it
+            /// to throttle themselves. The value is only updated when the sink is polled.
+            ///
+            /// [`enhance_batch`]: #method.enhance_batch
+            pub fn buffered_len(&self) -> usize {
+                self.base.buffered_len()
+            }
+
             pub fn set_status(&mut self, status: RpcStatus) {
                 assert!(self.flush_f.is_none());
                 self.status = status;
             }
 
+            /// Send `msg` as a heartbeat whenever no message is sent for `interval`.
+            ///
+            /// Proxies may close streams that stay idle for a while, which transport
+            /// keepalive pings don't prevent as they are not forwarded. Heartbeats are
+            /// sent when the sink is flushed with nothing else to send, like when it's
+            /// driven by `send_all` and the source stream is pending. So a handler that
+            /// awaits other futures between sends needs to keep flushing the sink
+            /// meanwhile. Receivers should be able to tell heartbeats from other
+            /// messages, for example by making them empty.
+            pub fn set_heartbeat(&mut self, interval: Duration, msg: T) {
+                self.heartbeat = Some(Box::new(Heartbeat::new(interval, msg)));
+            }
+
+            /// Send the response headers (initial metadata) before any message.
+            ///
+            /// The headers are sent out immediately instead of along with the first
+            /// message. It can only be called once and before any message is sent.
+            pub fn send_headers(&mut self, headers: Metadata) -> Result<()> {
+                assert!(self.flush_f.is_none());
+                self.base.start_send_headers(self.call.as_mut().unwrap(), headers)
+            }
+
+            /// Set the trailing metadata that will be sent along with the status.
+            ///
+            /// It must be called before the sink is closed or failed.
+            pub fn set_trailers(&mut self, trailers: Metadata) {
+                assert!(self.flush_f.is_none());
+                self.trailers = Some(trailers);
+            }
+
+            /// Send a message and wait until it's handed to gRPC Core to be sent out
+            /// immediately.
+            ///
+            /// Unlike sending through the `Sink` API, the message is never held back: it's
+            /// sent without `buffer_hint` even if `flags` has it or the enhanced batch
+            /// strategy is enabled, and pending messages are sent before it. It's useful
+            /// for acknowledging a request early before doing slow work.
+            pub async fn send_now(&mut self, msg: T, flags: WriteFlags) -> Result<()> {
+                future::poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await?;
+                let t = &mut *self;
+                t.base.start_send_now(t.call.as_mut().unwrap(), &msg, flags, t.ser)?;
+                if let Some(h) = &mut t.heartbeat {
+                    h.reset();
+                }
+                future::poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await
+            }
+
             pub fn fail(mut self, status: RpcStatus) -> $ft {
                 assert!(self.flush_f.is_none());
                 let send_metadata = self.base.send_metadata;
+                let trailers = &mut self.trailers;
                 let res = self.call.as_mut().unwrap().call(|c| {
-                    c.call
-                        .start_send_status_from_server(&status, send_metadata, &mut None, 0)
+                    c.call.start_send_status_from_server(
+                        &status,
+                        trailers,
+                        send_metadata,
+                        &mut None,
+                        0,
+                    )
                 });
 
                 let (fail_f, err) = match res {
@@ -493,16 +906,28 @@ macro_rules! impl_stream_sink {
             #[inline]
             fn start_send(mut self: Pin<&mut Self>, (msg, flags): (T, WriteFlags)) -> Result<()> {
                 let t = &mut *self;
+                if let Some(h) = &mut t.heartbeat {
+                    h.reset();
+                }
                 t.base.start_send(t.call.as_mut().unwrap(), &msg, flags, t.ser)
             }
 
             #[inline]
             fn poll_flush(mut self: Pin<&mut Self>,  cx: &mut Context) -> Poll<Result<()>> {
-                if let Poll::Ready(_) = self.call.as_mut().unwrap().call(|c| c.poll_finish(cx))? {
-                    return Poll::Ready(Err(Error::RemoteStopped));
+                loop {
+                    if let Poll::Ready(_) = self.call.as_mut().unwrap().call(|c| c.poll_finish(cx))? {
+                        return Poll::Ready(Err(Error::RemoteStopped));
+                    }
+                    let t = &mut *self;
+                    ready!(Pin::new(&mut t.base).poll_flush(cx, t.call.as_mut().unwrap())?);
+                    match &mut t.heartbeat {
+                        Some(h) if h.poll_due(cx) => {
+                            let flags = WriteFlags::default();
+                            t.base.start_send(t.call.as_mut().unwrap(), &h.msg, flags, t.ser)?;
+                        }
+                        _ => return Poll::Ready(Ok(())),
+                    }
                 }
-                let t = &mut *self;
-                Pin::new(&mut t.base).poll_flush(cx, t.call.as_mut().unwrap())
             }
 
             fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
@@ -512,9 +937,15 @@ macro_rules! impl_stream_sink {
                     let send_metadata = self.base.send_metadata;
                     let t = &mut *self;
                     let status = &t.status;
+                    let trailers = &mut t.trailers;
                     let flush_f = t.call.as_mut().unwrap().call(|c| {
-                        c.call
-                            .start_send_status_from_server(status, send_metadata, &mut None, 0)
+                        c.call.start_send_status_from_server(
+                            status,
+                            trailers,
+                            send_metadata,
+                            &mut None,
+                            0,
+                        )
                     })?;
                     t.flush_f = Some(flush_f);
                 }
@@ -576,6 +1007,62 @@ impl_stream_sink!(
     ServerStreamingSinkFailure,
     ShareCall
 );
+impl<T> ServerStreamingSink<T> {
+    /// Stream all the data of `reader` to the client and close the sink.
+    ///
+    /// Data is read in chunks of `chunk_size` bytes, except the last one, and every
+    /// chunk is turned into a message by `f`. A chunk is read only after the previous
+    /// one is accepted by the sink, so a slow client slows down reading instead of
+    /// having the whole data buffered. A blocking reader like `std::fs::File` can be
+    /// wrapped by [`AllowStdIo`], but it blocks the thread of the completion queue when
+    /// reading.
+    ///
+    /// If reading fails, the call fails with `INTERNAL` and the status is returned.
+    ///
+    /// # Panics
+    ///
+    /// If `chunk_size` is 0.
+    ///
+    /// [`AllowStdIo`]: https://docs.rs/futures/0.3/futures/io/struct.AllowStdIo.html
+    pub async fn send_reader<R, F>(
+        mut self,
+        mut reader: R,
+        chunk_size: usize,
+        mut f: F,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        F: FnMut(Vec<u8>) -> T,
+    {
+        assert!(chunk_size > 0);
+        loop {
+            let mut chunk = vec![0; chunk_size];
+            let mut filled = 0;
+            while filled < chunk_size {
+                match reader.read(&mut chunk[filled..]).await {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        let status = RpcStatus::new(
+                            RpcStatusCode::INTERNAL,
+                            Some(format!("failed to read: {}", e)),
+                        );
+                        self.fail(status.clone()).await?;
+                        return Err(Error::RpcFailure(status));
+                    }
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            chunk.truncate(filled);
+            self.feed((f(chunk), WriteFlags::default())).await?;
+        }
+        self.close().await
+    }
+}
+
 impl_stream_sink!(
     /// A sink for duplex streaming call.
     ///
@@ -594,14 +1081,31 @@ pub struct RpcContext<'a> {
     ctx: RequestContext,
     executor: Executor<'a>,
     deadline: Deadline,
+    limits: MessageLimits,
+    compressor: Option<Arc<dyn MessageCompressor>>,
+    observers: CallObservers,
+    start: Instant,
+    incoming_compression: CompressionAlgorithms,
 }
 
 impl<'a> RpcContext<'a> {
-    fn new(ctx: RequestContext, cq: &CompletionQueue) -> RpcContext<'_> {
+    fn new(
+        ctx: RequestContext,
+        cq: &CompletionQueue,
+        limits: MessageLimits,
+        compressor: Option<Arc<dyn MessageCompressor>>,
+        observers: CallObservers,
+        incoming_compression: CompressionAlgorithms,
+    ) -> RpcContext<'_> {
         RpcContext {
             deadline: ctx.deadline(),
             ctx,
             executor: Executor::new(cq),
+            limits,
+            compressor,
+            observers,
+            start: Instant::now(),
+            incoming_compression,
         }
     }
 
@@ -611,7 +1115,19 @@ impl<'a> RpcContext<'a> {
     }
 
     pub(crate) fn call(&self) -> Call {
-        self.ctx.call(self.executor.cq().clone())
+        let mut call = self.ctx.call(self.executor.cq().clone());
+        call.compressor = self.compressor.clone();
+        call.max_decompressed_len = self.limits.max_decompressed_receive_len;
+        call
+    }
+
+    // The call owned by the sinks, which sends the status and is reported to observers.
+    fn handler_call(&self) -> Call {
+        let mut call = self.call();
+        if let Some(recorder) = self.ctx.recorder(&self.observers, self.start) {
+            call.set_recorder(recorder);
+        }
+        call
     }
 
     pub fn method(&self) -> &[u8] {
@@ -626,11 +1142,73 @@ impl<'a> RpcContext<'a> {
         &self.deadline
     }
 
+    /// Get the time left before the deadline of the call is exceeded.
+    ///
+    /// Returns `None` if the call has no deadline. Streaming handlers can use it to
+    /// stop producing messages that will never be delivered. As the context can't be
+    /// moved into spawned futures, copy [`deadline`] instead and check
+    /// [`Deadline::remaining`] there.
+    ///
+    /// [`deadline`]: #method.deadline
+    /// [`Deadline::remaining`]: ./struct.Deadline.html#method.remaining
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline.remaining()
+    }
+
+    /// Get the absolute deadline of the call.
+    ///
+    /// Unlike [`time_remaining`], it doesn't change over time, which is handy for
+    /// scheduling. See [`Deadline::instant`] for the clock semantics.
+    ///
+    /// [`time_remaining`]: #method.time_remaining
+    /// [`Deadline::instant`]: ./struct.Deadline.html#method.instant
+    pub fn deadline_instant(&self) -> Option<SystemTime> {
+        self.deadline.instant()
+    }
+
     /// Get the initial metadata sent by client.
     pub fn request_headers(&self) -> &Metadata {
         self.ctx.metadata()
     }
 
+    /// Get the census context attached by client, if any.
+    ///
+    /// See [`CallOption::census_context`] for more details.
+    ///
+    /// [`CallOption::census_context`]: ./struct.CallOption.html#method.census_context
+    pub fn census_context(&self) -> Option<&[u8]> {
+        self.request_headers()
+            .iter()
+            .find(|(key, _)| *key == CENSUS_CONTEXT_KEY)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the binary context blob named `name` attached by client, if any.
+    ///
+    /// See [`CallOption::binary_context`] for more details. `None` is also returned
+    /// if `name` is invalid.
+    ///
+    /// [`CallOption::binary_context`]: ./struct.CallOption.html#method.binary_context
+    pub fn binary_context(&self, name: &str) -> Option<&[u8]> {
+        let key = crate::metadata::binary_context_key(name).ok()?;
+        self.request_headers()
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the ID of the call sent by client, if any.
+    ///
+    /// See [`CallOption::call_id`] for more details.
+    ///
+    /// [`CallOption::call_id`]: ./struct.CallOption.html#method.call_id
+    pub fn call_id(&self) -> Option<&str> {
+        self.request_headers()
+            .iter()
+            .find(|(key, _)| *key == CALL_ID_KEY)
+            .and_then(|(_, value)| str::from_utf8(value).ok())
+    }
+
     pub fn peer(&self) -> String {
         self.ctx.peer()
     }
@@ -642,6 +1220,27 @@ impl<'a> RpcContext<'a> {
         self.ctx.auth_context()
     }
 
+    /// Get the algorithm the client compressed the request message with, as
+    /// announced by the `grpc-encoding` header.
+    ///
+    /// gRPC Core keeps the header to itself, so the algorithm is taken from the
+    /// received request, which is only possible when the library decompresses it
+    /// instead of gRPC Core, that is when
+    /// [`ServerBuilder::max_decompressed_message_len`] is set. It's also only known
+    /// for unary and server streaming calls, whose request is received before the
+    /// handler is called. Returns `None` if the request is not compressed, gRPC Core
+    /// has decompressed it, or the call streams its requests. Messages compressed by
+    /// a [`MessageCompressor`] are not reported here.
+    ///
+    /// [`ServerBuilder::max_decompressed_message_len`]: ./struct.ServerBuilder.html#method.max_decompressed_message_len
+    /// [`MessageCompressor`]: ./trait.MessageCompressor.html
+    pub fn incoming_compression(&self) -> Option<CompressionAlgorithms> {
+        match self.incoming_compression {
+            CompressionAlgorithms::GRPC_COMPRESS_NONE => None,
+            algo => Some(algo),
+        }
+    }
+
     /// Spawn the future into current gRPC poll thread.
     ///
     /// This can reduce a lot of context switching, but please make
@@ -652,6 +1251,34 @@ impl<'a> RpcContext<'a> {
     {
         self.executor.spawn(f, self.kicker())
     }
+
+    /// Spawn the future into current gRPC poll thread, and cancel it once the
+    /// deadline of the call is exceeded.
+    ///
+    /// When the deadline is exceeded, the call is finished with `DEADLINE_EXCEEDED`
+    /// and the future is dropped along with the sink it owns. It's a safety net for
+    /// handlers that may run longer than the client is willing to wait.
+    pub fn spawn_with_deadline<F>(&self, f: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let left = match self.deadline.remaining() {
+            Some(left) => left,
+            None => return self.spawn(f),
+        };
+        let call = self.call();
+        let timeout = Delay::new(Instant::now() + left);
+        self.spawn(async move {
+            if let Either::Right((_, f)) = future::select(Box::pin(f), timeout).await {
+                let status = RpcStatus::new(
+                    RpcStatusCode::DEADLINE_EXCEEDED,
+                    Some("Deadline Exceeded".to_owned()),
+                );
+                call.cancel_with_status(&status);
+                drop(f);
+            }
+        })
+    }
 }
 
 // Following four helper functions are used to create a callback closure.
@@ -676,9 +1303,9 @@ pub fn execute_unary<P, Q, F>(
 ) where
     F: FnMut(RpcContext<'_>, P, UnarySink<Q>),
 {
-    let mut call = ctx.call();
+    let mut call = ctx.handler_call();
     let close_f = accept_call!(call);
-    let request = match de(payload) {
+    let request = match call.decompress(payload).and_then(de) {
         Ok(f) => f,
         Err(e) => {
             let status = RpcStatus::new(
@@ -689,7 +1316,7 @@ pub fn execute_unary<P, Q, F>(
             return;
         }
     };
-    let sink = UnarySink::new(ShareCall::new(call, close_f), ser);
+    let sink = UnarySink::new(ShareCall::new(call, close_f), ser, ctx.limits.max_send_len);
     f(ctx, request, sink)
 }
 
@@ -702,12 +1329,12 @@ pub fn execute_client_streaming<P, Q, F>(
 ) where
     F: FnMut(RpcContext<'_>, RequestStream<P>, ClientStreamingSink<Q>),
 {
-    let mut call = ctx.call();
+    let mut call = ctx.handler_call();
     let close_f = accept_call!(call);
     let call = Arc::new(Mutex::new(ShareCall::new(call, close_f)));
 
-    let req_s = RequestStream::new(call.clone(), de);
-    let sink = ClientStreamingSink::new(call, ser);
+    let req_s = RequestStream::new(call.clone(), de, ctx.limits);
+    let sink = ClientStreamingSink::new(call, ser, ctx.limits.max_send_len);
     f(ctx, req_s, sink)
 }
 
@@ -721,10 +1348,10 @@ pub fn execute_server_streaming<P, Q, F>(
 ) where
     F: FnMut(RpcContext<'_>, P, ServerStreamingSink<Q>),
 {
-    let mut call = ctx.call();
+    let mut call = ctx.handler_call();
     let close_f = accept_call!(call);
 
-    let request = match de(payload) {
+    let request = match call.decompress(payload).and_then(de) {
         Ok(t) => t,
         Err(e) => {
             let status = RpcStatus::new(
@@ -736,7 +1363,8 @@ pub fn execute_server_streaming<P, Q, F>(
         }
     };
 
-    let sink = ServerStreamingSink::new(ShareCall::new(call, close_f), ser);
+    let sink =
+        ServerStreamingSink::new(ShareCall::new(call, close_f), ser, ctx.limits.max_send_len);
     f(ctx, request, sink)
 }
 
@@ -749,22 +1377,27 @@ pub fn execute_duplex_streaming<P, Q, F>(
 ) where
     F: FnMut(RpcContext<'_>, RequestStream<P>, DuplexSink<Q>),
 {
-    let mut call = ctx.call();
+    let mut call = ctx.handler_call();
     let close_f = accept_call!(call);
     let call = Arc::new(Mutex::new(ShareCall::new(call, close_f)));
 
-    let req_s = RequestStream::new(call.clone(), de);
-    let sink = DuplexSink::new(call, ser);
+    let req_s = RequestStream::new(call.clone(), de, ctx.limits);
+    let sink = DuplexSink::new(call, ser, ctx.limits.max_send_len);
     f(ctx, req_s, sink)
 }
 
-// A helper function used to handle all undefined rpc calls.
-pub fn execute_unimplemented(ctx: RequestContext, cq: CompletionQueue) {
+// Finish the call with the status without calling any handler.
+fn execute_rejected(
+    ctx: RequestContext,
+    cq: CompletionQueue,
+    status: RpcStatus,
+    observers: &CallObservers,
+) {
     // Suppress needless-pass-by-value.
     let ctx = ctx;
-    let mut call = ctx.call(cq);
+    let mut call = ctx.observed_call(cq, observers);
     accept_call!(call);
-    call.abort(&RpcStatus::new(RpcStatusCode::UNIMPLEMENTED, None))
+    call.abort(&status)
 }
 
 // Helper function to call handler.
@@ -775,7 +1408,14 @@ fn execute(
     cq: &CompletionQueue,
     payload: Option<MessageReader>,
     f: &mut BoxHandler,
+    limits: MessageLimits,
+    compressor: Option<Arc<dyn MessageCompressor>>,
+    observers: CallObservers,
 ) {
-    let rpc_ctx = RpcContext::new(ctx, cq);
+    let incoming_compression = payload.as_ref().map_or(
+        CompressionAlgorithms::GRPC_COMPRESS_NONE,
+        MessageReader::compression,
+    );
+    let rpc_ctx = RpcContext::new(ctx, cq, limits, compressor, observers, incoming_compression);
     f.handle(rpc_ctx, payload)
 }
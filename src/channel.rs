@@ -1,31 +1,60 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::borrow::Cow;
-use std::collections::hash_map::Entry;
+use std::collections::hash_map::{Entry, RandomState};
 use std::collections::HashMap;
+#[cfg(feature = "json")]
+use std::collections::HashSet;
 use std::ffi::{CStr, CString};
+use std::hash::{BuildHasher, Hasher};
+#[cfg(grpcio_socket_mutator)]
+use std::io;
+use std::net::SocketAddr;
+#[cfg(grpcio_socket_mutator)]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "json")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use std::{cmp, i32, ptr};
+#[cfg(feature = "json")]
+use std::sync::Weak;
+use std::time::{Duration, Instant, SystemTime};
+use std::{cmp, i32, ptr, slice};
+
+use futures::future::{self, Either};
+use futures::Future;
+#[cfg(feature = "json")]
+use futures::{
+    channel::mpsc,
+    task::{Context, Poll},
+    Stream,
+};
+use parking_lot::RwLock;
 
 use crate::grpc_sys::{
-    self, gpr_timespec, grpc_arg_pointer_vtable, grpc_channel, grpc_channel_args,
+    self, gpr_timespec, grpc_arg, grpc_arg_pointer_vtable, grpc_arg_type, grpc_channel,
+    grpc_channel_args,
 };
-use libc::{self, c_char, c_int};
+use libc::{self, c_char, c_int, c_void};
 
-use crate::call::{Call, Method};
+use crate::call::{Call, CallRegistry, Method, RpcStatus, RpcStatusCode};
+#[cfg(feature = "json")]
+use crate::channelz;
 use crate::cq::CompletionQueue;
-use crate::env::Environment;
-use crate::error::Result;
-use crate::task::Kicker;
+use crate::env::{CqPickPolicy, Environment};
+use crate::error::{Error, Result};
+use crate::task::{CallTag, Delay, Executor, Kicker};
 use crate::CallOption;
 use crate::ResourceQuota;
 
 pub use crate::grpc_sys::{
     grpc_compression_algorithm as CompressionAlgorithms,
     grpc_compression_level as CompressionLevel, grpc_connectivity_state as ConnectivityState,
+    ParseCompressionError,
 };
 
+const OPT_CHANNEL_LABEL: &[u8] = b"grpc-rs.channel_label\0";
+
 /// Ref: http://www.grpc.io/docs/guides/wire.html#user-agents
 fn format_user_agent_string(agent: &str) -> CString {
     let version = env!("CARGO_PKG_VERSION");
@@ -38,7 +67,7 @@ fn format_user_agent_string(agent: &str) -> CString {
     CString::new(val).unwrap()
 }
 
-fn dur_to_ms(dur: Duration) -> i32 {
+pub(crate) fn dur_to_ms(dur: Duration) -> i32 {
     let millis = dur.as_secs() * 1000 + dur.subsec_nanos() as u64 / 1_000_000;
     cmp::min(i32::MAX as u64, millis) as i32
 }
@@ -47,6 +76,33 @@ enum Options {
     Integer(i32),
     String(CString),
     Pointer(ResourceQuota, *const grpc_arg_pointer_vtable),
+    #[cfg(grpcio_socket_mutator)]
+    SocketMutator(Arc<SocketMutator>),
+}
+
+#[cfg(grpcio_socket_mutator)]
+type SocketMutator = dyn Fn(c_int) -> io::Result<()> + Send + Sync;
+
+#[cfg(grpcio_socket_mutator)]
+unsafe extern "C" fn mutate_socket(user_data: *mut c_void, fd: c_int) -> c_int {
+    let f = &*(user_data as *const Arc<SocketMutator>);
+    // Unwinding into gRPC Core is undefined behavior.
+    match panic::catch_unwind(AssertUnwindSafe(|| f(fd))) {
+        Ok(Ok(())) => 1,
+        Ok(Err(e)) => {
+            warn!("failed to mutate socket {}: {}", fd, e);
+            0
+        }
+        Err(_) => {
+            warn!("socket mutator panicked on socket {}", fd);
+            0
+        }
+    }
+}
+
+#[cfg(grpcio_socket_mutator)]
+unsafe extern "C" fn drop_socket_mutator(user_data: *mut c_void) {
+    Box::from_raw(user_data as *mut Arc<SocketMutator>);
 }
 
 /// The optimization target for a [`Channel`].
@@ -66,10 +122,67 @@ pub enum LbPolicy {
     RoundRobin,
 }
 
+// Get the `host:port` part of a target like `dns:///host:port`.
+fn target_authority(target: &str) -> &str {
+    match target.strip_prefix("dns:") {
+        // Skip the authority of the DNS server if any.
+        Some(t) => match t.strip_prefix("//") {
+            Some(t) => t.find('/').map_or(t, |i| &t[i + 1..]),
+            None => t,
+        },
+        None => target,
+    }
+}
+
+// Get the host part of a target like `dns:///host:port`.
+fn target_host(target: &str) -> &str {
+    let target = target_authority(target);
+    if let Some(t) = target.strip_prefix('[') {
+        return t.split(']').next().unwrap();
+    }
+    match target.rfind(':') {
+        // A bare IPv6 address.
+        Some(i) if target[..i].contains(':') => target,
+        Some(i) => &target[..i],
+        None => target,
+    }
+}
+
+fn check_resolved_addrs(addrs: &[SocketAddr]) {
+    assert!(!addrs.is_empty(), "no address is given");
+    assert!(
+        addrs.iter().all(|a| a.is_ipv4() == addrs[0].is_ipv4()),
+        "IPv4 and IPv6 addresses can't be mixed: {:?}",
+        addrs
+    );
+}
+
+fn is_no_proxy(host: &str, no_proxy: &[String]) -> bool {
+    let host = host.to_ascii_lowercase();
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim().trim_start_matches('.').to_ascii_lowercase();
+        !entry.is_empty()
+            && (host == entry
+                || (host.ends_with(&entry) && host[..host.len() - entry.len()].ends_with('.')))
+    })
+}
+
 /// [`Channel`] factory in order to configure the properties.
 pub struct ChannelBuilder {
     env: Arc<Environment>,
     options: HashMap<Cow<'static, [u8]>, Options>,
+    // Addresses that override the target given on connect.
+    resolved_addrs: Vec<SocketAddr>,
+    max_subchannels: Option<usize>,
+    label: Option<String>,
+    no_proxy: Vec<String>,
+    limits: MessageLenLimits,
+    // The largest receive limit calls can raise theirs to.
+    receive_message_len_ceiling: Option<i32>,
+    // The largest send limit calls can raise theirs to.
+    send_message_len_ceiling: Option<i32>,
+    // Addresses of host names that are resolved without DNS.
+    static_dns: HashMap<String, Vec<SocketAddr>>,
 }
 
 impl ChannelBuilder {
@@ -78,10 +191,196 @@ impl ChannelBuilder {
         ChannelBuilder {
             env,
             options: HashMap::new(),
+            resolved_addrs: Vec::new(),
+            max_subchannels: None,
+            label: None,
+            no_proxy: Vec::new(),
+            limits: MessageLenLimits::UNLIMITED,
+            receive_message_len_ceiling: None,
+            send_message_len_ceiling: None,
+            static_dns: HashMap::new(),
         }
     }
 
+    /// Tag the channel with a human readable label, to tell which logical channel is
+    /// which when debugging.
+    ///
+    /// The label can be retrieved by [`Channel::label`]. It's also set as the channel
+    /// arg `grpc-rs.channel_label`, so it's kept along with the other args by gRPC Core.
+    ///
+    /// [`Channel::label`]: ./struct.Channel.html#method.label
+    pub fn channel_label<S: Into<String>>(mut self, label: S) -> ChannelBuilder {
+        let label = label.into();
+        self.options.insert(
+            Cow::Borrowed(OPT_CHANNEL_LABEL),
+            Options::String(CString::new(label.clone()).unwrap()),
+        );
+        self.label = Some(label);
+        self
+    }
+
+    /// Connect to the given addresses directly instead of resolving the target.
+    ///
+    /// No DNS lookup is made, the target given on connect is only used as the
+    /// default authority unless [`default_authority`] is set. It's useful in tests
+    /// and for services found by custom discovery.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `addrs` is empty, or mixes IPv4 and IPv6 addresses,
+    /// which gRPC Core can't resolve in a single target.
+    ///
+    /// [`default_authority`]: #method.default_authority
+    pub fn resolved_addresses(mut self, addrs: &[SocketAddr]) -> ChannelBuilder {
+        check_resolved_addrs(addrs);
+        self.resolved_addrs = addrs.to_vec();
+        self
+    }
+
+    /// Resolve the host `name` to the given addresses without DNS, like an entry
+    /// of `/etc/hosts` that only applies to this builder.
+    ///
+    /// When the host of the target given on connect matches `name` case
+    /// insensitively, the channel connects to `addrs` as if they were given by
+    /// [`resolved_addresses`], so the port of the target is ignored and the target
+    /// is used as the default authority. Other targets are resolved as usual.
+    /// It's useful for tests and air-gapped environments.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `addrs` is empty, or mixes IPv4 and IPv6 addresses.
+    ///
+    /// [`resolved_addresses`]: #method.resolved_addresses
+    pub fn add_static_dns(mut self, name: &str, addrs: &[SocketAddr]) -> ChannelBuilder {
+        check_resolved_addrs(addrs);
+        self.static_dns
+            .insert(name.to_ascii_lowercase(), addrs.to_vec());
+        self
+    }
+
+    /// Set the maximum number of subchannels, that is backend connections, the
+    /// channel maintains.
+    ///
+    /// gRPC Core connects to every resolved address when the `round_robin` policy
+    /// is used, which can be too many for a large fleet of backends. When the cap
+    /// is lower than the number of addresses given by [`resolved_addresses`],
+    /// [`add_static_dns`] or an `ipv4:` or `ipv6:` target, a random run of `max`
+    /// addresses is picked, so calls are only balanced over them, while different
+    /// channels are spread over the whole fleet.
+    ///
+    /// The run is picked once when connecting, so it's not rebalanced when backends
+    /// come and go. gRPC Core has no load balancing policy that caps subchannels,
+    /// so the addresses it resolves itself, for example by DNS for the default
+    /// `dns:` target, can't be capped. Such targets are connected without the cap,
+    /// and a warning is logged. Resolve the addresses with [`resolved_addresses`]
+    /// instead to cap them.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `max` is 0.
+    ///
+    /// [`resolved_addresses`]: #method.resolved_addresses
+    /// [`add_static_dns`]: #method.add_static_dns
+    pub fn max_subchannels(mut self, max: usize) -> ChannelBuilder {
+        assert!(max > 0);
+        self.max_subchannels = Some(max);
+        self
+    }
+
+    // Build the target of the addresses resolved for `target`, capped by `max_subchannels`.
+    fn resolved_target(&self, target: &str) -> Option<String> {
+        let addrs = if !self.resolved_addrs.is_empty() {
+            &self.resolved_addrs
+        } else {
+            self.static_dns
+                .get(&target_host(target).to_ascii_lowercase())?
+        };
+        let scheme = if addrs[0].is_ipv4() { "ipv4" } else { "ipv6" };
+        let addrs: Vec<_> = addrs.iter().map(SocketAddr::to_string).collect();
+        Some(format!("{}:{}", scheme, self.cap_addrs(&addrs).join(",")))
+    }
+
+    // Cap the address list of an `ipv4:` or `ipv6:` target by `max_subchannels`.
+    // Other targets are resolved by gRPC Core, and left as is.
+    fn capped_target(&self, target: &str) -> String {
+        let list = ["ipv4:", "ipv6:"]
+            .iter()
+            .find_map(|scheme| Some((*scheme, target.strip_prefix(scheme)?)));
+        let (scheme, list) = match list {
+            Some(l) => l,
+            None => {
+                warn!(
+                    "max_subchannels can't cap the addresses resolved by gRPC Core for {}",
+                    target
+                );
+                return target.to_owned();
+            }
+        };
+        let addrs: Vec<_> = list.split(',').map(str::to_owned).collect();
+        format!("{}{}", scheme, self.cap_addrs(&addrs).join(","))
+    }
+
+    // Pick a random run of at most `max_subchannels` addresses.
+    fn cap_addrs<'a>(&self, addrs: &'a [String]) -> Vec<&'a str> {
+        let count = self
+            .max_subchannels
+            .map_or(addrs.len(), |m| cmp::min(m, addrs.len()));
+        let start = if count < addrs.len() {
+            RandomState::new().build_hasher().finish() as usize % addrs.len()
+        } else {
+            0
+        };
+        (0..count)
+            .map(|i| addrs[(start + i) % addrs.len()].as_str())
+            .collect()
+    }
+
+    /// Connect through the given HTTP proxy with the `CONNECT` method.
+    ///
+    /// `proxy` is in the format of `http://[user:password@]host:port`. It takes
+    /// precedence over the proxy set by the environment variables `grpc_proxy`,
+    /// `https_proxy` and `http_proxy`.
+    pub fn http_proxy(mut self, proxy: &str) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_HTTP_PROXY),
+            Options::String(CString::new(proxy).unwrap()),
+        );
+        self
+    }
+
+    /// Set whether to connect through HTTP proxies at all. It's enabled by default.
+    pub fn enable_http_proxy(mut self, enable: bool) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_ENABLE_HTTP_PROXY),
+            Options::Integer(enable as i32),
+        );
+        self
+    }
+
+    /// Connect to the given hosts directly instead of through the HTTP proxy.
+    ///
+    /// A host matches if it's the same as or a subdomain of an entry, like the
+    /// `no_proxy` environment variable, which is still respected by gRPC Core.
+    pub fn no_proxy<I, S>(mut self, hosts: I) -> ChannelBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.no_proxy = hosts.into_iter().map(Into::into).collect();
+        self
+    }
+
     /// Set default authority to pass if none specified on call construction.
+    ///
+    /// The authority is sent as the `:authority` pseudo header of every call, and
+    /// is the name servers and proxies use to route and validate calls. By default
+    /// it's derived from the target, for example `host:port` for `dns` targets. For
+    /// `unix:` targets, gRPC Core uses `localhost`, which servers validating the
+    /// authority may reject, so it should be set explicitly in that case. It also
+    /// applies to targets of custom schemes like those built by
+    /// [`resolved_addresses`].
+    ///
+    /// [`resolved_addresses`]: #method.resolved_addresses
     pub fn default_authority<S: Into<Vec<u8>>>(mut self, authority: S) -> ChannelBuilder {
         let authority = CString::new(authority).unwrap();
         self.options.insert(
@@ -102,6 +401,29 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set a callback to tune every new socket before it's used.
+    ///
+    /// The callback is invoked with the file descriptor of each socket the channel
+    /// connects, so socket options that gRPC Core doesn't expose, like `SO_MARK` or
+    /// `TCP_CONGESTION`, can be set by `setsockopt`. When the arguments are used by a
+    /// server, it's also invoked with listening and accepted sockets. Returning an
+    /// error or panicking fails the socket, the error is logged.
+    ///
+    /// It's only supported on Unix, where gRPC Core works with file descriptors,
+    /// and when gRPC Core is built by grpcio-sys instead of linked by pkg-config,
+    /// as it relies on a header of gRPC Core that is not installed.
+    #[cfg(grpcio_socket_mutator)]
+    pub fn socket_mutator<F>(mut self, f: F) -> ChannelBuilder
+    where
+        F: Fn(std::os::unix::io::RawFd) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_SOCKET_MUTATOR),
+            Options::SocketMutator(Arc::new(f)),
+        );
+        self
+    }
+
     /// Set maximum number of concurrent incoming streams to allow on a HTTP/2 connection.
     pub fn max_concurrent_stream(mut self, num: i32) -> ChannelBuilder {
         self.options.insert(
@@ -112,6 +434,12 @@ impl ChannelBuilder {
     }
 
     /// Set maximum message length that the channel can receive. `-1` means unlimited.
+    ///
+    /// Defaults to 4MB. Calls can lower it by [`CallOption::max_receive_message_len`],
+    /// and raise it up to [`receive_message_len_ceiling`].
+    ///
+    /// [`CallOption::max_receive_message_len`]: ./struct.CallOption.html#method.max_receive_message_len
+    /// [`receive_message_len_ceiling`]: #method.receive_message_len_ceiling
     pub fn max_receive_message_len(mut self, len: i32) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(grpcio_sys::GRPC_ARG_MAX_RECEIVE_MESSAGE_LENGTH),
@@ -120,7 +448,32 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set the largest length that calls can raise their receive limit to by
+    /// [`CallOption::max_receive_message_len`]. `-1` means unlimited.
+    ///
+    /// gRPC Core rejects a message as soon as its length prefix exceeds the limit of
+    /// the channel, before buffering it, and can't raise the limit for a single call.
+    /// So the limit of gRPC Core is set to the ceiling instead, while calls that don't
+    /// raise their limit are still held to [`max_receive_message_len`] once a message
+    /// is received. A peer can make such a call buffer a message up to the ceiling
+    /// before it's rejected, so keep it as low as the largest call needs.
+    ///
+    /// Defaults to [`max_receive_message_len`], so calls can't raise their limit.
+    ///
+    /// [`CallOption::max_receive_message_len`]: ./struct.CallOption.html#method.max_receive_message_len
+    /// [`max_receive_message_len`]: #method.max_receive_message_len
+    pub fn receive_message_len_ceiling(mut self, len: i32) -> ChannelBuilder {
+        self.receive_message_len_ceiling = Some(len);
+        self
+    }
+
     /// Set maximum message length that the channel can send. `-1` means unlimited.
+    ///
+    /// Defaults to unlimited. Calls can lower it by [`CallOption::max_send_message_len`],
+    /// and raise it up to [`send_message_len_ceiling`].
+    ///
+    /// [`CallOption::max_send_message_len`]: ./struct.CallOption.html#method.max_send_message_len
+    /// [`send_message_len_ceiling`]: #method.send_message_len_ceiling
     pub fn max_send_message_len(mut self, len: i32) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(grpcio_sys::GRPC_ARG_MAX_SEND_MESSAGE_LENGTH),
@@ -129,6 +482,22 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set the largest length that calls can raise their send limit to by
+    /// [`CallOption::max_send_message_len`]. `-1` means unlimited.
+    ///
+    /// gRPC Core can't raise the limit for a single call, so the limit of gRPC Core
+    /// is set to the ceiling instead, while calls that don't raise their limit are
+    /// still held to [`max_send_message_len`] before a message is sent.
+    ///
+    /// Defaults to [`max_send_message_len`], so calls can't raise their limit.
+    ///
+    /// [`CallOption::max_send_message_len`]: ./struct.CallOption.html#method.max_send_message_len
+    /// [`max_send_message_len`]: #method.max_send_message_len
+    pub fn send_message_len_ceiling(mut self, len: i32) -> ChannelBuilder {
+        self.send_message_len_ceiling = Some(len);
+        self
+    }
+
     /// Set maximum time between subsequent connection attempts.
     pub fn max_reconnect_backoff(mut self, backoff: Duration) -> ChannelBuilder {
         self.options.insert(
@@ -138,6 +507,39 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set the duration a channel can stay without any outstanding call before it
+    /// enters idle state.
+    ///
+    /// An idle channel releases all its connections. Connections are re-established
+    /// transparently when the next RPC is issued on the channel, which introduces an
+    /// extra connect latency for that RPC. gRPC C Core does not accept values smaller
+    /// than 1 second, smaller values will be clamped.
+    pub fn max_channel_idle(mut self, idle: Duration) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_CLIENT_IDLE_TIMEOUT_MS),
+            Options::Integer(dur_to_ms(idle)),
+        );
+        self
+    }
+
+    /// Set the minimum time between subsequent connection attempts.
+    ///
+    /// gRPC Core 1.33 has no separate connect timeout: a connection attempt lasts
+    /// until the later of this value and the time the next attempt is scheduled by
+    /// backoff. It defaults to 20 seconds, so lowering it along with
+    /// [`initial_reconnect_backoff`] also makes a hanging connect attempt fail fast,
+    /// and the channel retry or fail over to other addresses sooner. Values smaller
+    /// than 100ms are clamped.
+    ///
+    /// [`initial_reconnect_backoff`]: #method.initial_reconnect_backoff
+    pub fn min_reconnect_backoff(mut self, backoff: Duration) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_MIN_RECONNECT_BACKOFF_MS),
+            Options::Integer(dur_to_ms(backoff)),
+        );
+        self
+    }
+
     /// Set time between the first and second connection attempts.
     pub fn initial_reconnect_backoff(mut self, backoff: Duration) -> ChannelBuilder {
         self.options.insert(
@@ -147,6 +549,27 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set the minimum time between two DNS resolutions of the target.
+    ///
+    /// The channel re-resolves the target when connections fail, a resolution
+    /// within the period since the last one is delayed until the period ends, so
+    /// the result of a resolution is effectively cached for the period. It only
+    /// applies to the `dns` resolver. gRPC Core defaults it to 30 seconds.
+    ///
+    /// There is no client side setting to re-resolve periodically while connections
+    /// are healthy, as gRPC Core provides no way to trigger a resolution. Use
+    /// [`ServerBuilder::max_connection_age`] on the servers instead, which makes
+    /// clients reconnect and re-resolve as connections are aged out.
+    ///
+    /// [`ServerBuilder::max_connection_age`]: ./struct.ServerBuilder.html#method.max_connection_age
+    pub fn dns_min_resolution_period(mut self, period: Duration) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_DNS_MIN_TIME_BETWEEN_RESOLUTIONS_MS),
+            Options::Integer(dur_to_ms(period)),
+        );
+        self
+    }
+
     /// Set initial sequence number for HTTP/2 transports.
     pub fn https_initial_seq_number(mut self, number: i32) -> ChannelBuilder {
         self.options.insert(
@@ -177,6 +600,28 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set primary user agent without appending the `grpc-rust/<version>` token like
+    /// [`primary_user_agent`] does.
+    ///
+    /// It's meant for servers and proxies that match on the user agent of clients.
+    /// Note that a fully custom user agent is not supported: gRPC Core always
+    /// appends its own `grpc-c/<version> (<platform>; chttp2)` token and replaces any
+    /// `user-agent` metadata set by calls, so the user-agent metadata received by
+    /// servers is `agent` followed by a space and that token.
+    ///
+    /// Setting it replaces the value set by [`primary_user_agent`], and vice versa.
+    /// Servers that infer client capabilities or work around bugs of particular
+    /// versions from the user agent can no longer recognize the client as grpc-rust.
+    ///
+    /// [`primary_user_agent`]: #method.primary_user_agent
+    pub fn primary_user_agent_without_version(mut self, agent: &str) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_PRIMARY_USER_AGENT_STRING),
+            Options::String(CString::new(agent).unwrap()),
+        );
+        self
+    }
+
     /// Set whether to allow the use of `SO_REUSEPORT` if available. Defaults to `true`.
     pub fn reuse_port(mut self, reuse: bool) -> ChannelBuilder {
         let opt = if reuse { 1 } else { 0 };
@@ -236,7 +681,64 @@ impl ChannelBuilder {
         self
     }
 
+    /// How much memory to use for the HPACK decoder table, in bytes.
+    ///
+    /// The value is advertised to the peer as `SETTINGS_HEADER_TABLE_SIZE`.
+    /// Min 0, defaults to 4096 as specified by HTTP/2.
+    pub fn http2_hpack_table_size_decoder(mut self, size: i32) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_HTTP2_HPACK_TABLE_SIZE_DECODER),
+            Options::Integer(size),
+        );
+        self
+    }
+
+    /// How much memory to use for the HPACK encoder table, in bytes.
+    ///
+    /// The actual table size is the smaller one of this value and the size
+    /// advertised by the peer. Min 0, defaults to 4096 as specified by HTTP/2.
+    pub fn http2_hpack_table_size_encoder(mut self, size: i32) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_HTTP2_HPACK_TABLE_SIZE_ENCODER),
+            Options::Integer(size),
+        );
+        self
+    }
+
+    /// Set the value of the `:scheme` pseudo-header sent with requests, which
+    /// is either `http` or `https`.
+    ///
+    /// gRPC Core always speaks HTTP/2 with prior knowledge on insecure channels,
+    /// and negotiates `h2` via ALPN on secure channels; there is no HTTP/1.1
+    /// upgrade to fall back to. Insecure channels send `http` by default and
+    /// secure ones `https`, which some gateways that terminate TLS in front of
+    /// the client check. It maps to `grpc.http2_scheme`.
+    pub fn http2_scheme(mut self, scheme: &str) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_HTTP2_SCHEME),
+            Options::String(CString::new(scheme).unwrap()),
+        );
+        self
+    }
+
+    /// Set whether to offer the true binary metadata extension of gRPC, which
+    /// sends binary headers without base64 encoding when the peer supports it.
+    ///
+    /// The extension is negotiated by a non-standard HTTP/2 setting, which some
+    /// intermediaries reject or mangle, so it can be disabled for interoperability.
+    /// It maps to `grpc.http2.true_binary` and is enabled by default.
+    pub fn http2_true_binary(mut self, enable: bool) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_HTTP2_ENABLE_TRUE_BINARY),
+            Options::Integer(enable as i32),
+        );
+        self
+    }
+
     /// Set whether to enable BDP probing.
+    ///
+    /// BDP probing sends HTTP/2 pings along with data, which some intermediaries
+    /// treat as abusive, so it may need to be disabled for interoperability.
     pub fn http2_bdp_probe(mut self, enable: bool) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(grpcio_sys::GRPC_ARG_HTTP2_BDP_PROBE),
@@ -247,6 +749,9 @@ impl ChannelBuilder {
 
     /// Minimum time between sending successive ping frames without receiving any
     /// data frame.
+    ///
+    /// Clients should keep it no shorter than the server's
+    /// `http2_min_recv_ping_interval_without_data`, or the pings count as strikes.
     pub fn http2_min_sent_ping_interval_without_data(
         mut self,
         interval: Duration,
@@ -260,6 +765,11 @@ impl ChannelBuilder {
 
     /// Minimum allowed time between receiving successive ping frames without
     /// sending any data frame.
+    ///
+    /// Pings received more often count as strikes, see
+    /// [`http2_max_ping_strikes`](#method.http2_max_ping_strikes). Unless
+    /// `keepalive_permit_without_calls` is set, a server only allows one ping
+    /// every two hours when there are no calls.
     pub fn http2_min_recv_ping_interval_without_data(
         mut self,
         interval: Duration,
@@ -294,6 +804,9 @@ impl ChannelBuilder {
     }
 
     /// Set default compression algorithm for the channel.
+    ///
+    /// Algorithms can be parsed from their names, like `"gzip".parse()`, which is
+    /// handy for configurations. `CompressionAlgorithms::ALL` lists the supported ones.
     pub fn default_compression_algorithm(mut self, algo: CompressionAlgorithms) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(grpcio_sys::GRPC_COMPRESSION_CHANNEL_DEFAULT_ALGORITHM),
@@ -303,6 +816,8 @@ impl ChannelBuilder {
     }
 
     /// Set default compression level for the channel.
+    ///
+    /// Levels can be parsed from their names, like `"low".parse()`.
     pub fn default_compression_level(mut self, level: CompressionLevel) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(grpcio_sys::GRPC_COMPRESSION_CHANNEL_DEFAULT_LEVEL),
@@ -370,6 +885,69 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set the default service config of the channel in JSON, which is used unless the
+    /// resolver returns one.
+    ///
+    /// It's how retries are configured: `retryPolicy` of `methodConfig` specifies which
+    /// calls are retried, and `retryThrottling` specifies a token bucket shared by all
+    /// calls of the channel to avoid retry storms. Every failed attempt takes a token and
+    /// every success adds `tokenRatio` tokens back, retries stop once half of `maxTokens`
+    /// are taken. For example:
+    ///
+    /// ```json
+    /// {
+    ///     "methodConfig": [{
+    ///         "name": [{"service": "helloworld.Greeter"}],
+    ///         "retryPolicy": {
+    ///             "maxAttempts": 5,
+    ///             "initialBackoff": "0.1s",
+    ///             "maxBackoff": "1s",
+    ///             "backoffMultiplier": 2,
+    ///             "retryableStatusCodes": ["UNAVAILABLE"]
+    ///         }
+    ///     }],
+    ///     "retryThrottling": {"maxTokens": 10, "tokenRatio": 0.1}
+    /// }
+    /// ```
+    ///
+    /// Retried calls may have been processed by the server already, like calls cut off
+    /// by a GOAWAY, so non-idempotent methods should be sent at most once. A config
+    /// naming the method overrides the one of its service, so leaving `retryPolicy` out
+    /// of it disables retries for the method only:
+    ///
+    /// ```json
+    /// {
+    ///     "name": [{"service": "helloworld.Greeter", "method": "SayHello"}]
+    /// }
+    /// ```
+    ///
+    /// Calls of such methods fail with the first error, leaving the decision to the
+    /// caller.
+    ///
+    /// The config is validated by gRPC Core when the channel is created, an invalid config
+    /// makes all calls fail with `UNAVAILABLE`.
+    pub fn service_config(mut self, config: &str) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_SERVICE_CONFIG),
+            Options::String(CString::new(config).unwrap()),
+        );
+        self
+    }
+
+    /// Set whether to retry failed calls as the retry policy in the service config
+    /// specifies. It's enabled by default.
+    ///
+    /// When disabled, every call is sent only once and fails with the first error,
+    /// no matter what the service config says. Note that calls that never leave the
+    /// client can still be retried transparently by gRPC Core.
+    pub fn enable_retries(mut self, enable: bool) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_ENABLE_RETRIES),
+            Options::Integer(enable as i32),
+        );
+        self
+    }
+
     /// Set a raw integer configuration.
     ///
     /// This method is only for bench usage, users should use the encapsulated API instead.
@@ -420,29 +998,153 @@ impl ChannelBuilder {
                         vtable,
                     )
                 },
+                #[cfg(grpcio_socket_mutator)]
+                Options::SocketMutator(ref f) => unsafe {
+                    let user_data = Box::into_raw(Box::new(f.clone()));
+                    grpc_sys::grpcwrap_channel_args_set_socket_mutator(
+                        args,
+                        i,
+                        user_data as _,
+                        Some(mutate_socket),
+                        Some(drop_socket_mutator),
+                    )
+                },
             }
         }
         ChannelArgs { args }
     }
 
+    // Get the target to create the channel with.
+    fn prepare_target(&mut self, addr: &str) -> CString {
+        if !self.no_proxy.is_empty() && is_no_proxy(target_host(addr), &self.no_proxy) {
+            self.options.insert(
+                Cow::Borrowed(grpcio_sys::GRPC_ARG_ENABLE_HTTP_PROXY),
+                Options::Integer(0),
+            );
+        }
+        match self.resolved_target(addr) {
+            Some(target) => {
+                if let Entry::Vacant(e) = self
+                    .options
+                    .entry(Cow::Borrowed(grpcio_sys::GRPC_ARG_DEFAULT_AUTHORITY))
+                {
+                    let authority = target_authority(addr);
+                    e.insert(Options::String(CString::new(authority).unwrap()));
+                }
+                CString::new(target).unwrap()
+            }
+            None if self.max_subchannels.is_some() => {
+                CString::new(self.capped_target(addr)).unwrap()
+            }
+            None => CString::new(addr).unwrap(),
+        }
+    }
+
+    fn message_len_limit(&self, key: &'static [u8], default: i32) -> i32 {
+        match self.options.get(&Cow::Borrowed(key)) {
+            Some(Options::Integer(len)) => *len,
+            _ => default,
+        }
+    }
+
+    // Take the default message length limits of calls from the options. The limits
+    // of gRPC Core are only raised to the ceilings, so they are still enforced unless
+    // calls are allowed to raise theirs.
     fn prepare_connect_args(&mut self) -> ChannelArgs {
         if let Entry::Vacant(e) = self.options.entry(Cow::Borrowed(
             grpcio_sys::GRPC_ARG_PRIMARY_USER_AGENT_STRING,
         )) {
             e.insert(Options::String(format_user_agent_string("")));
         }
+        self.limits = MessageLenLimits {
+            receive: self.message_len_limit(
+                grpcio_sys::GRPC_ARG_MAX_RECEIVE_MESSAGE_LENGTH,
+                grpcio_sys::GRPC_DEFAULT_MAX_RECV_MESSAGE_LENGTH as i32,
+            ),
+            send: self.message_len_limit(
+                grpcio_sys::GRPC_ARG_MAX_SEND_MESSAGE_LENGTH,
+                grpcio_sys::GRPC_DEFAULT_MAX_SEND_MESSAGE_LENGTH,
+            ),
+        };
+        let raise = |limit: i32, ceiling: Option<i32>| match ceiling {
+            Some(ceiling) if ceiling < 0 => -1,
+            Some(ceiling) if limit >= 0 => ceiling.max(limit),
+            _ => limit,
+        };
+        let receive = raise(self.limits.receive, self.receive_message_len_ceiling);
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_MAX_RECEIVE_MESSAGE_LENGTH),
+            Options::Integer(receive),
+        );
+        let send = raise(self.limits.send, self.send_message_len_ceiling);
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_MAX_SEND_MESSAGE_LENGTH),
+            Options::Integer(send),
+        );
         self.build_args()
     }
 
     /// Build an insecure [`Channel`] that connects to a specific address.
     pub fn connect(mut self, addr: &str) -> Channel {
+        let addr = self.prepare_target(addr);
         let args = self.prepare_connect_args();
-        let addr = CString::new(addr).unwrap();
         let addr_ptr = addr.as_ptr();
         let channel =
             unsafe { grpc_sys::grpc_insecure_channel_create(addr_ptr, args.args, ptr::null_mut()) };
 
-        unsafe { Channel::new(self.env.pick_cq(), self.env, channel) }
+        unsafe {
+            Channel::with_security(
+                self.env.pick_cq(),
+                self.env,
+                channel,
+                false,
+                self.label,
+                self.limits,
+            )
+        }
+    }
+
+    /// Build a [`ChannelPool`] of `size` insecure channels to the address.
+    ///
+    /// Every channel of the pool keeps connections of its own instead of sharing
+    /// them with other channels, which is done by enabling
+    /// `GRPC_ARG_USE_LOCAL_SUBCHANNEL_POOL`.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `size` is 0.
+    pub fn connect_pool(self, addr: &str, size: usize) -> ChannelPool {
+        self.build_pool(addr, size, false, |addr, args| unsafe {
+            grpc_sys::grpc_insecure_channel_create(addr.as_ptr(), args.args, ptr::null_mut())
+        })
+    }
+
+    // Build a pool of `size` channels, each created by `create` from the target and
+    // the arguments.
+    fn build_pool<F>(mut self, addr: &str, size: usize, secure: bool, create: F) -> ChannelPool
+    where
+        F: Fn(&CString, &ChannelArgs) -> *mut grpc_channel,
+    {
+        assert!(size > 0);
+        self.options.insert(
+            Cow::Borrowed(grpcio_sys::GRPC_ARG_USE_LOCAL_SUBCHANNEL_POOL),
+            Options::Integer(1),
+        );
+        let addr = self.prepare_target(addr);
+        let args = self.prepare_connect_args();
+        let channels = (0..size)
+            .map(|_| unsafe {
+                Channel::with_security(
+                    self.env.pick_cq(),
+                    self.env.clone(),
+                    create(&addr, &args),
+                    secure,
+                    self.label.clone(),
+                    self.limits,
+                )
+            })
+            .collect();
+        ChannelPool::new(channels)
     }
 
     /// Build an insecure [`Channel`] taking over an established connection from
@@ -464,7 +1166,14 @@ impl ChannelBuilder {
         let target_ptr = target.as_ptr();
         let channel = grpc_sys::grpc_insecure_channel_create_from_fd(target_ptr, fd, args.args);
 
-        Channel::new(self.env.pick_cq(), self.env, channel)
+        Channel::with_security(
+            self.env.pick_cq(),
+            self.env,
+            channel,
+            false,
+            self.label,
+            self.limits,
+        )
     }
 }
 
@@ -478,7 +1187,7 @@ mod secure_channel {
 
     use crate::ChannelCredentials;
 
-    use super::{Channel, ChannelBuilder, Options};
+    use super::{Channel, ChannelBuilder, ChannelPool, Options};
 
     const OPT_SSL_TARGET_NAME_OVERRIDE: &[u8] = b"grpc.ssl_target_name_override\0";
 
@@ -499,8 +1208,8 @@ mod secure_channel {
 
         /// Build a secure [`Channel`] that connects to a specific address.
         pub fn secure_connect(mut self, addr: &str, mut creds: ChannelCredentials) -> Channel {
+            let addr = self.prepare_target(addr);
             let args = self.prepare_connect_args();
-            let addr = CString::new(addr).unwrap();
             let addr_ptr = addr.as_ptr();
             let channel = unsafe {
                 grpc_sys::grpc_secure_channel_create(
@@ -511,7 +1220,35 @@ mod secure_channel {
                 )
             };
 
-            unsafe { Channel::new(self.env.pick_cq(), self.env, channel) }
+            unsafe {
+                Channel::with_security(
+                    self.env.pick_cq(),
+                    self.env,
+                    channel,
+                    true,
+                    self.label,
+                    self.limits,
+                )
+            }
+        }
+
+        /// Same as [`connect_pool`], but the channels are secure.
+        ///
+        /// [`connect_pool`]: #method.connect_pool
+        pub fn secure_connect_pool(
+            self,
+            addr: &str,
+            size: usize,
+            creds: ChannelCredentials,
+        ) -> ChannelPool {
+            self.build_pool(addr, size, true, |addr, args| unsafe {
+                grpc_sys::grpc_secure_channel_create(
+                    creds.as_raw(),
+                    addr.as_ptr(),
+                    args.args,
+                    ptr::null_mut(),
+                )
+            })
         }
     }
 }
@@ -524,6 +1261,106 @@ impl ChannelArgs {
     pub fn as_ptr(&self) -> *const grpc_channel_args {
         self.args
     }
+
+    fn entries(&self) -> &[grpc_arg] {
+        unsafe {
+            let args = &*self.args;
+            if args.num_args == 0 {
+                &[]
+            } else {
+                slice::from_raw_parts(args.args, args.num_args)
+            }
+        }
+    }
+
+    /// Get the value of an integer argument, `key` should end with `\0`.
+    pub(crate) fn get_integer(&self, key: &[u8]) -> Option<i32> {
+        self.entries().iter().find_map(|arg| {
+            let k = unsafe { CStr::from_ptr(arg.key) }.to_bytes_with_nul();
+            match arg.type_ {
+                grpc_arg_type::GRPC_ARG_INTEGER if k == key => Some(unsafe { arg.value.integer }),
+                _ => None,
+            }
+        })
+    }
+
+    /// Get the value set by
+    /// [`ChannelBuilder::http2_max_pings_without_data`](./struct.ChannelBuilder.html#method.http2_max_pings_without_data).
+    pub fn http2_max_pings_without_data(&self) -> Option<i32> {
+        self.get_integer(grpc_sys::GRPC_ARG_HTTP2_MAX_PINGS_WITHOUT_DATA)
+    }
+
+    /// Get the value set by
+    /// [`ChannelBuilder::http2_max_ping_strikes`](./struct.ChannelBuilder.html#method.http2_max_ping_strikes).
+    pub fn http2_max_ping_strikes(&self) -> Option<i32> {
+        self.get_integer(grpc_sys::GRPC_ARG_HTTP2_MAX_PING_STRIKES)
+    }
+
+    /// Get the value set by
+    /// [`ChannelBuilder::http2_min_sent_ping_interval_without_data`](./struct.ChannelBuilder.html#method.http2_min_sent_ping_interval_without_data).
+    pub fn http2_min_sent_ping_interval_without_data(&self) -> Option<Duration> {
+        self.get_integer(grpc_sys::GRPC_ARG_HTTP2_MIN_SENT_PING_INTERVAL_WITHOUT_DATA_MS)
+            .map(|ms| Duration::from_millis(ms.max(0) as u64))
+    }
+
+    /// Get the value set by
+    /// [`ChannelBuilder::http2_min_recv_ping_interval_without_data`](./struct.ChannelBuilder.html#method.http2_min_recv_ping_interval_without_data).
+    ///
+    /// A client talking to a server built with these args should not send pings
+    /// more often than this, or it may be closed with a "too many pings" GOAWAY.
+    pub fn http2_min_recv_ping_interval_without_data(&self) -> Option<Duration> {
+        self.get_integer(grpc_sys::GRPC_ARG_HTTP2_MIN_RECV_PING_INTERVAL_WITHOUT_DATA_MS)
+            .map(|ms| Duration::from_millis(ms.max(0) as u64))
+    }
+
+    /// Build args with the given integer args, followed by the args of `base` that
+    /// are not overridden.
+    pub(crate) fn with_integers(
+        base: Option<&ChannelArgs>,
+        integers: &[(&'static [u8], i32)],
+    ) -> ChannelArgs {
+        let base_args = base.map_or(&[][..], ChannelArgs::entries);
+        let inherited: Vec<_> = base_args
+            .iter()
+            .filter(|arg| {
+                let key = unsafe { CStr::from_ptr(arg.key) }.to_bytes_with_nul();
+                integers.iter().all(|(k, _)| *k != key)
+            })
+            .collect();
+        unsafe {
+            let args = grpc_sys::grpcwrap_channel_args_create(integers.len() + inherited.len());
+            for (i, (key, val)) in integers.iter().enumerate() {
+                grpc_sys::grpcwrap_channel_args_set_integer(args, i, key.as_ptr() as _, *val)
+            }
+            for (i, arg) in inherited.into_iter().enumerate() {
+                let i = i + integers.len();
+                match arg.type_ {
+                    grpc_arg_type::GRPC_ARG_INTEGER => grpc_sys::grpcwrap_channel_args_set_integer(
+                        args,
+                        i,
+                        arg.key,
+                        arg.value.integer,
+                    ),
+                    grpc_arg_type::GRPC_ARG_STRING => grpc_sys::grpcwrap_channel_args_set_string(
+                        args,
+                        i,
+                        arg.key,
+                        arg.value.string,
+                    ),
+                    grpc_arg_type::GRPC_ARG_POINTER => {
+                        grpc_sys::grpcwrap_channel_args_set_pointer_vtable(
+                            args,
+                            i,
+                            arg.key,
+                            arg.value.pointer.p,
+                            arg.value.pointer.vtable,
+                        )
+                    }
+                }
+            }
+            ChannelArgs { args }
+        }
+    }
 }
 
 impl Drop for ChannelArgs {
@@ -532,11 +1369,40 @@ impl Drop for ChannelArgs {
     }
 }
 
+// Default message length limits of calls, `-1` means unlimited.
+//
+// They are checked by the library on top of the limits of gRPC Core, so calls can
+// override them.
+#[derive(Clone, Copy)]
+struct MessageLenLimits {
+    receive: i32,
+    send: i32,
+}
+
+impl MessageLenLimits {
+    // Used by channels that are not built by `ChannelBuilder`, of which the limits
+    // are still checked by gRPC Core.
+    const UNLIMITED: MessageLenLimits = MessageLenLimits {
+        receive: -1,
+        send: -1,
+    };
+}
+
 struct ChannelInner {
-    _env: Arc<Environment>,
+    env: Arc<Environment>,
     channel: *mut grpc_channel,
+    calls: Arc<CallRegistry>,
+    secure: bool,
+    label: Option<String>,
+    limits: MessageLenLimits,
+    // Handles returned by `grpc_channel_register_call`, which are owned by the channel.
+    registered: RwLock<HashMap<&'static str, *mut c_void>>,
 }
 
+// `grpc_channel` is thread-safe.
+unsafe impl Send for ChannelInner {}
+unsafe impl Sync for ChannelInner {}
+
 impl ChannelInner {
     // If try_to_connect is true, the channel will try to establish a connection, potentially
     // changing the state.
@@ -554,6 +1420,43 @@ impl Drop for ChannelInner {
     }
 }
 
+/// The result of [`Channel::drain`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrainResult {
+    /// All calls are finished.
+    Drained,
+    /// The deadline is hit with the given number of calls still in flight.
+    DeadlineExceeded(usize),
+}
+
+/// An event of a connection of a channel, see [`Channel::connection_events`].
+#[cfg(feature = "json")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The subchannel to the address becomes ready to send calls.
+    Connected(String),
+    /// The subchannel to the address is no longer ready, for example because the
+    /// connection is closed by the server.
+    Disconnected(String),
+}
+
+/// A stream of [`ConnectionEvent`]s, see [`Channel::connection_events`].
+///
+/// The channel stops being watched once it's dropped.
+#[cfg(feature = "json")]
+pub struct ConnectionEvents {
+    rx: mpsc::UnboundedReceiver<ConnectionEvent>,
+}
+
+#[cfg(feature = "json")]
+impl Stream for ConnectionEvents {
+    type Item = ConnectionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<ConnectionEvent>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
 /// A gRPC channel.
 ///
 /// Channels are an abstraction of long-lived connections to remote servers. More client objects
@@ -582,19 +1485,219 @@ impl Channel {
         cq: CompletionQueue,
         env: Arc<Environment>,
         channel: *mut grpc_channel,
+    ) -> Channel {
+        Channel::with_security(cq, env, channel, false, None, MessageLenLimits::UNLIMITED)
+    }
+
+    unsafe fn with_security(
+        cq: CompletionQueue,
+        env: Arc<Environment>,
+        channel: *mut grpc_channel,
+        secure: bool,
+        label: Option<String>,
+        limits: MessageLenLimits,
     ) -> Channel {
         Channel {
-            inner: Arc::new(ChannelInner { _env: env, channel }),
+            inner: Arc::new(ChannelInner {
+                env,
+                channel,
+                calls: Arc::default(),
+                secure,
+                label,
+                limits,
+                registered: RwLock::default(),
+            }),
             cq,
         }
     }
 
+    /// Get the label set by [`ChannelBuilder::channel_label`].
+    ///
+    /// [`ChannelBuilder::channel_label`]: ./struct.ChannelBuilder.html#method.channel_label
+    pub fn label(&self) -> Option<&str> {
+        self.inner.label.as_deref()
+    }
+
+    /// Get the target the channel was created for.
+    pub fn target(&self) -> String {
+        unsafe {
+            let p = grpc_sys::grpc_channel_get_target(self.inner.channel);
+            let target = CStr::from_ptr(p).to_string_lossy().into_owned();
+            grpc_sys::gpr_free(p as _);
+            target
+        }
+    }
+
+    /// Get the keepalive statistics of the connections of the channel.
+    ///
+    /// The statistics are reported by channelz of gRPC Core, which needs to be
+    /// enabled by `grpc.enable_channelz`, as it is by default. Channels are looked
+    /// up by their targets, and connections are shared by channels to the same
+    /// target unless [`ChannelBuilder::connect_pool`] is used, so connections of
+    /// such channels are all returned.
+    ///
+    /// gRPC Core doesn't report when a ping was sent or acknowledged. The number of
+    /// pings sent along with the time a message was last received on the
+    /// connection is the closest thing: pings keep being sent on an idle
+    /// connection that's alive, while a connection that's dropped by the network
+    /// stops counting.
+    ///
+    /// [`ChannelBuilder::connect_pool`]: ./struct.ChannelBuilder.html#method.connect_pool
+    #[cfg(feature = "json")]
+    pub fn keepalive_stats(&self) -> Vec<KeepaliveStats> {
+        let mut stats = Vec::new();
+        for subchannel in self.subchannels() {
+            let socket_refs = subchannel["socketRef"]
+                .as_array()
+                .map_or(&[][..], Vec::as_slice);
+            for socket_ref in socket_refs {
+                let socket = channelz::as_i64(&socket_ref["socketId"]).and_then(channelz::socket);
+                if let Some(socket) = socket {
+                    stats.push(KeepaliveStats::from_socket(&socket));
+                }
+            }
+        }
+        stats
+    }
+
+    /// Get the channelz subchannels of the channels to the same target.
+    #[cfg(feature = "json")]
+    fn subchannels(&self) -> Vec<serde_json::Value> {
+        let target = self.target();
+        let mut subchannels = Vec::new();
+        for channel in channelz::top_channels() {
+            if channel["data"]["target"].as_str() != Some(&target) {
+                continue;
+            }
+            let subchannel_refs = channel["subchannelRef"]
+                .as_array()
+                .map_or(&[][..], Vec::as_slice);
+            subchannels.extend(subchannel_refs.iter().filter_map(|subchannel_ref| {
+                channelz::as_i64(&subchannel_ref["subchannelId"]).and_then(channelz::subchannel)
+            }));
+        }
+        subchannels
+    }
+
+    /// Get the addresses of the subchannels that are ready.
+    #[cfg(feature = "json")]
+    fn ready_addresses(&self) -> HashSet<String> {
+        self.subchannels()
+            .iter()
+            .filter_map(|subchannel| {
+                let data = &subchannel["data"];
+                if data["state"]["state"].as_str() != Some("READY") {
+                    return None;
+                }
+                data["target"].as_str().map(ToOwned::to_owned)
+            })
+            .collect()
+    }
+
+    /// Check if the channel is created with secure credentials.
+    pub(crate) fn is_secure(&self) -> bool {
+        self.inner.secure
+    }
+
     // If try_to_connect is true, the channel will try to establish a connection, potentially
     // changing the state.
     pub fn check_connectivity_state(&self, try_to_connect: bool) -> ConnectivityState {
         self.inner.check_connectivity_state(try_to_connect)
     }
 
+    /// Wait for the connectivity state of the channel to change from `last_observed`.
+    ///
+    /// The returned future resolves to `false` if the state doesn't change before
+    /// `timeout` elapses.
+    pub fn wait_for_state_change(
+        &self,
+        last_observed: ConnectivityState,
+        timeout: Duration,
+    ) -> impl Future<Output = bool> {
+        self.watch_state_change(last_observed, gpr_timespec::from(timeout))
+    }
+
+    fn watch_state_change(
+        &self,
+        last_observed: ConnectivityState,
+        deadline: gpr_timespec,
+    ) -> impl Future<Output = bool> {
+        let (cq_f, prom) = CallTag::action_pair();
+        let tag = Box::into_raw(Box::new(prom));
+        let should_wait = match self.cq.borrow() {
+            Ok(cq_ref) => {
+                unsafe {
+                    grpc_sys::grpc_channel_watch_connectivity_state(
+                        self.inner.channel,
+                        last_observed,
+                        deadline,
+                        cq_ref.as_ptr(),
+                        tag as *mut _,
+                    )
+                }
+                true
+            }
+            Err(_) => {
+                // The queue is shutdown, so the tag will never be resolved.
+                unsafe { Box::from_raw(tag) };
+                false
+            }
+        };
+        async move { should_wait && cq_f.await.unwrap_or(false) }
+    }
+
+    /// Watch the subchannels of the channel getting connected or disconnected.
+    ///
+    /// Subchannels are looked up by channelz of gRPC Core like
+    /// [`keepalive_stats`](#method.keepalive_stats), and checked whenever the
+    /// connectivity state of the channel changes. Subchannels of other channels to
+    /// the same target are also reported, and changes that don't affect the state
+    /// of the channel, like one of several connections dropped by round robin, may
+    /// be reported late. Watching stops once the stream or all clones of the
+    /// channel are dropped.
+    #[cfg(feature = "json")]
+    pub fn connection_events(&self) -> ConnectionEvents {
+        let (tx, rx) = mpsc::unbounded();
+        let kicker = match self.create_kicker() {
+            Ok(kicker) => kicker,
+            // The queue is shutdown, the channel can't be used anymore.
+            Err(_) => return ConnectionEvents { rx },
+        };
+        let inner = Arc::downgrade(&self.inner);
+        let cq = self.cq.clone();
+        let watch = async move {
+            let mut connected = HashSet::new();
+            loop {
+                let channel = match Weak::upgrade(&inner) {
+                    Some(inner) => Channel {
+                        inner,
+                        cq: cq.clone(),
+                    },
+                    None => return,
+                };
+                let state = channel.check_connectivity_state(false);
+                let ready = channel.ready_addresses();
+                for addr in connected.difference(&ready) {
+                    let _ = tx.unbounded_send(ConnectionEvent::Disconnected(addr.clone()));
+                }
+                for addr in ready.difference(&connected) {
+                    let _ = tx.unbounded_send(ConnectionEvent::Connected(addr.clone()));
+                }
+                connected = ready;
+                if state == ConnectivityState::GRPC_CHANNEL_SHUTDOWN || tx.is_closed() {
+                    return;
+                }
+                // Don't keep the channel alive while waiting, the watch is resolved
+                // when the channel is destroyed.
+                let changed = channel.watch_state_change(state, gpr_timespec::inf_future());
+                drop(channel);
+                changed.await;
+            }
+        };
+        Executor::new(&self.cq).spawn(watch, kicker);
+        ConnectionEvents { rx }
+    }
+
     /// Create a Kicker.
     pub(crate) fn create_kicker(&self) -> Result<Kicker> {
         let cq_ref = self.cq.borrow()?;
@@ -625,7 +1728,19 @@ impl Channel {
         method: &Method<Req, Resp>,
         opt: &CallOption,
     ) -> Result<Call> {
-        let cq_ref = self.cq.borrow()?;
+        if self.inner.calls.is_draining() {
+            return Err(Error::RpcFailure(RpcStatus::new(
+                RpcStatusCode::UNAVAILABLE,
+                Some("Channel is draining".to_owned()),
+            )));
+        }
+        let cq = match self.inner.env.cq_pick_policy() {
+            CqPickPolicy::RoundRobin => self.cq.clone(),
+            CqPickPolicy::LeastLoaded => self.inner.env.pick_cq(),
+        };
+        let cq_ref = cq.borrow()?;
+        // gRPC Core turns the timeout into a deadline the same way.
+        let deadline = opt.get_timeout().map(|t| SystemTime::now() + t);
         let raw_call = unsafe {
             let ch = self.inner.channel;
             let cq = cq_ref.as_ptr();
@@ -634,23 +1749,208 @@ impl Channel {
             let timeout = opt
                 .get_timeout()
                 .map_or_else(gpr_timespec::inf_future, gpr_timespec::from);
-            grpc_sys::grpcwrap_channel_create_call(
-                ch,
-                ptr::null_mut(),
-                0,
-                cq,
-                method_ptr as *const _,
-                method_len,
-                ptr::null(),
-                0,
-                timeout,
-            )
+            match self.inner.registered.read().get(method.name) {
+                Some(&handle) => grpc_sys::grpc_channel_create_registered_call(
+                    ch,
+                    ptr::null_mut(),
+                    0,
+                    cq,
+                    handle,
+                    timeout,
+                    ptr::null_mut(),
+                ),
+                None => grpc_sys::grpcwrap_channel_create_call(
+                    ch,
+                    ptr::null_mut(),
+                    0,
+                    cq,
+                    method_ptr as *const _,
+                    method_len,
+                    ptr::null(),
+                    0,
+                    timeout,
+                ),
+            }
         };
 
-        unsafe { Ok(Call::from_raw(raw_call, self.cq.clone())) }
+        let mut call = unsafe { Call::from_raw(raw_call, cq.clone()) };
+        call.register(self.inner.calls.clone());
+        call.count_in_flight();
+        call.set_deadline(deadline);
+        let limit = |len: i32| if len >= 0 { Some(len as usize) } else { None };
+        let limits = &self.inner.limits;
+        call.set_message_limits(
+            limit(opt.get_max_send_message_len().unwrap_or(limits.send)),
+            limit(opt.get_max_receive_message_len().unwrap_or(limits.receive)),
+        );
+        Ok(call)
+    }
+
+    /// Register methods to the channel, so calls to them are created from
+    /// registered handles instead of parsing and interning the method path every
+    /// time.
+    ///
+    /// Registering all methods of a service once when creating a client amortizes
+    /// the cost, which is what the generated clients do. Methods registered
+    /// already are skipped. The handles are shared by the clones of the channel
+    /// and released with it.
+    pub fn register_methods(&self, methods: &[&'static str]) {
+        {
+            let registered = self.inner.registered.read();
+            if methods.iter().all(|m| registered.contains_key(m)) {
+                return;
+            }
+        }
+        let mut registered = self.inner.registered.write();
+        for &method in methods {
+            if let Entry::Vacant(e) = registered.entry(method) {
+                let name = CString::new(method).unwrap();
+                let handle = unsafe {
+                    grpc_sys::grpc_channel_register_call(
+                        self.inner.channel,
+                        name.as_ptr(),
+                        ptr::null(),
+                        ptr::null_mut(),
+                    )
+                };
+                e.insert(handle);
+            }
+        }
+    }
+
+    /// Check whether the method is registered by [`register_methods`].
+    ///
+    /// [`register_methods`]: #method.register_methods
+    pub fn is_registered(&self, method: &str) -> bool {
+        self.inner.registered.read().contains_key(method)
+    }
+
+    /// Cancel all in-flight calls created by the channel and its clones.
+    ///
+    /// Every canceled call will fail with `CANCELLED`. Calls started after
+    /// this function returns are not affected, and the channel can still be
+    /// used as usual.
+    pub fn cancel_all(&self) {
+        let _cq_ref = match self.cq.borrow() {
+            // Queue is shutdown, all calls are finished already.
+            Err(Error::QueueShutdown) => return,
+            Err(e) => panic!("unexpected error when canceling calls: {:?}", e),
+            Ok(r) => r,
+        };
+        unsafe { self.inner.calls.cancel_all() }
+    }
+
+    /// Stop starting new calls and wait for all in-flight calls to finish.
+    ///
+    /// It's the graceful counterpart of [`cancel_all`]. Once it's called, starting
+    /// a call on the channel or any of its clones fails with `UNAVAILABLE`. A call is
+    /// finished when all of its senders, receivers and futures are dropped. Calls are
+    /// not canceled when the deadline is hit, use [`cancel_all`] to do so.
+    ///
+    /// [`cancel_all`]: #method.cancel_all
+    pub fn drain(&self, deadline: Instant) -> impl Future<Output = DrainResult> {
+        self.inner.calls.start_draining();
+        let calls = self.inner.calls.clone();
+        let timeout = Delay::new(deadline);
+        async move {
+            let drained = future::poll_fn(|cx| calls.poll_drained(cx));
+            futures::pin_mut!(drained);
+            match future::select(drained, timeout).await {
+                Either::Left(_) => DrainResult::Drained,
+                Either::Right(_) => match calls.count() {
+                    0 => DrainResult::Drained,
+                    n => DrainResult::DeadlineExceeded(n),
+                },
+            }
+        }
     }
 
     pub(crate) fn cq(&self) -> &CompletionQueue {
         &self.cq
     }
 }
+
+/// Keepalive statistics of a connection, see [`Channel::keepalive_stats`].
+///
+/// [`Channel::keepalive_stats`]: ./struct.Channel.html#method.keepalive_stats
+#[cfg(feature = "json")]
+#[derive(Clone, Debug, Default)]
+pub struct KeepaliveStats {
+    /// The remote address of the connection, like `ipv4:127.0.0.1:50051`.
+    pub remote: String,
+    /// The number of keepalive pings sent on the connection.
+    pub keepalives_sent: u64,
+    /// The time a message was last sent on the connection.
+    pub last_message_sent: Option<SystemTime>,
+    /// The time a message was last received on the connection.
+    pub last_message_received: Option<SystemTime>,
+}
+
+#[cfg(feature = "json")]
+impl KeepaliveStats {
+    fn from_socket(socket: &serde_json::Value) -> KeepaliveStats {
+        let data = &socket["data"];
+        KeepaliveStats {
+            remote: socket["ref"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_owned(),
+            keepalives_sent: channelz::as_i64(&data["keepAlivesSent"]).unwrap_or(0) as u64,
+            last_message_sent: channelz::as_timestamp(&data["lastMessageSentTimestamp"]),
+            last_message_received: channelz::as_timestamp(&data["lastMessageReceivedTimestamp"]),
+        }
+    }
+}
+
+/// A set of channels to the same target, which calls are spread over in turn.
+///
+/// All calls of a channel to a backend are multiplexed on a single HTTP/2
+/// connection, so under heavy load they are limited by the connection, for
+/// example by TCP head-of-line blocking and by the number of concurrent streams
+/// the server allows. A pool opens a connection for each of its channels.
+///
+/// Use [`ChannelBuilder::connect_pool`] to build a pool, and [`Client::with_pool`]
+/// to make calls over it.
+///
+/// [`ChannelBuilder::connect_pool`]: ./struct.ChannelBuilder.html#method.connect_pool
+/// [`Client::with_pool`]: ./struct.Client.html#method.with_pool
+#[derive(Clone)]
+pub struct ChannelPool {
+    channels: Arc<[Channel]>,
+    idx: Arc<AtomicUsize>,
+}
+
+impl ChannelPool {
+    /// Create a pool of the given channels.
+    ///
+    /// Channels to the same target share their connections unless they are built
+    /// with different channel args, which is taken care of by
+    /// [`ChannelBuilder::connect_pool`].
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `channels` is empty.
+    ///
+    /// [`ChannelBuilder::connect_pool`]: ./struct.ChannelBuilder.html#method.connect_pool
+    pub fn new(channels: Vec<Channel>) -> ChannelPool {
+        assert!(!channels.is_empty(), "no channel is given");
+        ChannelPool {
+            channels: channels.into(),
+            idx: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Pick the next channel in turn.
+    pub fn pick(&self) -> &Channel {
+        if self.channels.len() == 1 {
+            return &self.channels[0];
+        }
+        let idx = self.idx.fetch_add(1, Ordering::Relaxed);
+        &self.channels[idx % self.channels.len()]
+    }
+
+    /// Get all the channels of the pool.
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+}
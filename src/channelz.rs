@@ -0,0 +1,138 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Helpers to query channelz of gRPC Core, which reports entities in JSON.
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use crate::grpc_sys;
+
+/// 64-bit integers are written as strings in JSON by protobuf.
+pub fn as_i64(v: &Value) -> Option<i64> {
+    match v {
+        Value::String(s) => s.parse().ok(),
+        v => v.as_i64(),
+    }
+}
+
+/// Timestamps are written in RFC 3339 by protobuf, like
+/// `2020-01-02T03:04:05.678Z`.
+pub fn as_timestamp(v: &Value) -> Option<SystemTime> {
+    parse_timestamp(v.as_str()?)
+}
+
+// Days since 1970-01-01 of the civil date.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = (s.get(..10)?, s.get(11..)?);
+    if s.as_bytes()[10] != b'T' {
+        return None;
+    }
+    let num = |s: &str| s.parse::<i64>().ok();
+    let (y, m, d) = (
+        num(&date[..4])?,
+        num(date.get(5..7)?)?,
+        num(date.get(8..)?)?,
+    );
+    let (hh, mm) = (num(time.get(..2)?)?, num(time.get(3..5)?)?);
+    let (ss, nanos) = match time.get(6..)?.find('.') {
+        Some(pos) => {
+            let frac = &time[7 + pos..];
+            let nanos = num(frac)? * 10i64.pow(9u32.checked_sub(frac.len() as u32)?);
+            (num(&time[6..6 + pos])?, nanos)
+        }
+        None => (num(&time[6..])?, 0),
+    };
+    let secs = days_from_civil(y, m, d) * 86400 + hh * 3600 + mm * 60 + ss;
+    if secs < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::new(secs as u64, nanos as u32))
+}
+
+// Take the JSON string returned by channelz.
+unsafe fn take_json(p: *mut c_char) -> Option<Value> {
+    if p.is_null() {
+        return None;
+    }
+    let json = serde_json::from_slice(CStr::from_ptr(p).to_bytes()).ok();
+    grpc_sys::gpr_free(p as _);
+    json
+}
+
+/// Get all the top channels.
+pub fn top_channels() -> Vec<Value> {
+    let mut channels = Vec::new();
+    let mut start = 0;
+    loop {
+        let page = match unsafe { take_json(grpc_sys::grpc_channelz_get_top_channels(start)) } {
+            Some(page) => page,
+            None => return channels,
+        };
+        let end = page["end"].as_bool().unwrap_or(false);
+        if let Value::Object(mut fields) = page {
+            if let Some(Value::Array(values)) = fields.remove("channel") {
+                channels.extend(values);
+            }
+        }
+        let last = channels.last().and_then(|c| as_i64(&c["ref"]["channelId"]));
+        match last {
+            Some(id) if !end => start = id as isize + 1,
+            _ => return channels,
+        }
+    }
+}
+
+pub fn subchannel(id: i64) -> Option<Value> {
+    unsafe { take_json(grpc_sys::grpc_channelz_get_subchannel(id as isize)) }
+}
+
+pub fn socket(id: i64) -> Option<Value> {
+    unsafe { take_json(grpc_sys::grpc_channelz_get_socket(id as isize)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_i64() {
+        let json: Value =
+            serde_json::from_str(r#"{"a": "12", "b": -3, "c": "x", "d": 1.5}"#).unwrap();
+        assert_eq!(as_i64(&json["a"]), Some(12));
+        assert_eq!(as_i64(&json["b"]), Some(-3));
+        assert_eq!(as_i64(&json["c"]), None);
+        assert_eq!(as_i64(&json["d"]), None);
+        assert_eq!(as_i64(&json["missing"]), None);
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let ts = |s: &str| as_timestamp(&Value::String(s.to_owned()));
+        assert_eq!(ts("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            ts("2020-02-29T12:34:56.5Z"),
+            Some(UNIX_EPOCH + Duration::new(1_582_979_696, 500_000_000))
+        );
+        assert_eq!(
+            ts("2001-09-09T01:46:40.000000001Z"),
+            Some(UNIX_EPOCH + Duration::new(1_000_000_000, 1))
+        );
+        assert_eq!(ts("2020-02-29 12:34:56Z"), None);
+        assert_eq!(ts("2020-02-29T12:34:56"), None);
+        assert_eq!(ts("1969-12-31T23:59:59Z"), None);
+    }
+}
@@ -1,30 +1,175 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use crate::call::client::{
-    CallOption, ClientCStreamReceiver, ClientCStreamSender, ClientDuplexReceiver,
-    ClientDuplexSender, ClientSStreamReceiver, ClientUnaryReceiver,
+    new_call_id, CallOption, ClientCStreamReceiver, ClientCStreamSender, ClientDuplexReceiver,
+    ClientDuplexSender, ClientSStreamReceiver, ClientUnaryReceiver, Response,
 };
+use crate::call::server::RpcContext;
 use crate::call::{Call, Method};
-use crate::channel::Channel;
+use crate::channel::{Channel, ChannelPool};
 use crate::error::Result;
+use crate::metadata::{Metadata, MetadataBuilder};
 use crate::task::Executor;
 use crate::task::Kicker;
 use futures::executor::block_on;
 use futures::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Settings inherited from an inbound call, which are applied to all
+/// outbound calls.
+#[derive(Clone)]
+struct Propagation {
+    deadline: Option<Instant>,
+    headers: Metadata,
+}
+
+impl Propagation {
+    fn apply(&self, mut opt: CallOption) -> CallOption {
+        if let Some(deadline) = self.deadline {
+            let left = deadline
+                .checked_duration_since(Instant::now())
+                .unwrap_or_else(|| Duration::from_secs(0));
+            if opt.get_timeout().map_or(true, |t| t > left) {
+                opt = opt.timeout(left);
+            }
+        }
+        if !self.headers.is_empty() {
+            // Headers set explicitly on the call take precedence.
+            let headers = match opt.get_headers() {
+                Some(own) => self.headers.merge(own),
+                None => self.headers.clone(),
+            };
+            opt = opt.headers(headers);
+        }
+        opt
+    }
+}
+
+type Interceptor = Arc<dyn Fn(&str, CallOption) -> CallOption + Send + Sync>;
 
 /// A generic client for making RPC calls.
 #[derive(Clone)]
 pub struct Client {
-    channel: Channel,
+    channels: ChannelPool,
     // Used to kick its completion queue.
     kicker: Kicker,
+    default_option: Option<Arc<CallOption>>,
+    propagation: Option<Propagation>,
+    interceptor: Option<Interceptor>,
+    call_ids: bool,
 }
 
 impl Client {
     /// Initialize a new [`Client`].
     pub fn new(channel: Channel) -> Client {
-        let kicker = channel.create_kicker().unwrap();
-        Client { channel, kicker }
+        Client::with_pool(ChannelPool::new(vec![channel]))
+    }
+
+    /// Initialize a new [`Client`] that spreads calls over the channels of the
+    /// pool in turn.
+    ///
+    /// Streaming calls stay on the channel they are started on. Futures spawned by
+    /// [`spawn`] run on the completion queue of the first channel.
+    ///
+    /// [`spawn`]: #method.spawn
+    pub fn with_pool(channels: ChannelPool) -> Client {
+        let kicker = channels.channels()[0].create_kicker().unwrap();
+        Client {
+            channels,
+            kicker,
+            default_option: None,
+            propagation: None,
+            interceptor: None,
+            call_ids: false,
+        }
+    }
+
+    /// Set the base options of all calls made by the client.
+    ///
+    /// Options of every call are merged with `opt`, settings that are given
+    /// explicitly to a call take precedence. Headers are merged by key, and call
+    /// flags like [`wait_for_ready`] set by `opt` apply to all calls.
+    ///
+    /// [`wait_for_ready`]: ./struct.CallOption.html#method.wait_for_ready
+    pub fn with_default_call_option(mut self, opt: CallOption) -> Client {
+        self.default_option = Some(Arc::new(opt));
+        self
+    }
+
+    /// Intercept the options of every call made by the client.
+    ///
+    /// `f` is given the full name of the method and the options of the call, and returns
+    /// the options to make the call with. It can be used to implement cross-cutting
+    /// concerns like authentication or tracing, and can read the values attached by
+    /// [`CallOption::with_extension`] to decide what to do per call. It's invoked after
+    /// the settings of [`propagate_from`] are applied, and replaces the previous
+    /// interceptor. Generated clients can be built from the client by their
+    /// `with_client` constructors.
+    ///
+    /// [`CallOption::with_extension`]: ./struct.CallOption.html#method.with_extension
+    /// [`propagate_from`]: #method.propagate_from
+    pub fn with_interceptor<F>(mut self, f: F) -> Client
+    where
+        F: Fn(&str, CallOption) -> CallOption + Send + Sync + 'static,
+    {
+        self.interceptor = Some(Arc::new(f));
+        self
+    }
+
+    /// Generate a unique ID for every call made by the client.
+    ///
+    /// IDs are random 128-bit numbers in hex, and are sent the same way as
+    /// [`CallOption::call_id`], so they can be read by `call_id` of both the
+    /// receivers and [`RpcContext`] to correlate logs. Calls that set their own
+    /// IDs are left untouched.
+    ///
+    /// [`CallOption::call_id`]: ./struct.CallOption.html#method.call_id
+    /// [`RpcContext`]: ./struct.RpcContext.html
+    pub fn with_call_ids(mut self) -> Client {
+        self.call_ids = true;
+        self
+    }
+
+    /// Propagate the deadline and the given request headers of an inbound call
+    /// to all calls made by the client.
+    ///
+    /// The timeout of every outbound call is capped by the time left of the
+    /// inbound call, so a chain of services never waits longer than the original
+    /// caller does. Headers in `header_keys`, for example tracing ids, are copied
+    /// to outbound calls unless the call sets them explicitly.
+    pub fn propagate_from(mut self, ctx: &RpcContext<'_>, header_keys: &[&str]) -> Client {
+        let deadline = ctx.deadline().remaining().map(|d| Instant::now() + d);
+        let mut headers = MetadataBuilder::new();
+        for (key, value) in ctx.request_headers() {
+            if header_keys.iter().any(|k| k.eq_ignore_ascii_case(key)) {
+                headers.add_metadata(key, value).unwrap();
+            }
+        }
+        let headers = headers.build();
+        self.propagation = Some(Propagation { deadline, headers });
+        self
+    }
+
+    #[inline]
+    fn prepare(&self, method: &str, opt: CallOption) -> CallOption {
+        let opt = match &self.default_option {
+            Some(base) => opt.inherit(base),
+            None => opt,
+        };
+        let opt = match &self.propagation {
+            Some(p) => p.apply(opt),
+            None => opt,
+        };
+        let opt = if self.call_ids && opt.get_call_id().is_none() {
+            opt.call_id(new_call_id())
+        } else {
+            opt
+        };
+        match &self.interceptor {
+            Some(f) => f(method, opt),
+            None => opt,
+        }
     }
 
     /// Create a synchronized unary RPC call.
@@ -40,6 +185,17 @@ impl Client {
         block_on(self.unary_call_async(method, req, opt)?)
     }
 
+    /// Create a synchronized unary RPC call and get the full response, including
+    /// the headers and trailers sent by the server.
+    pub fn unary_call_full<Req, Resp>(
+        &self,
+        method: &Method<Req, Resp>,
+        req: &Req,
+        opt: CallOption,
+    ) -> Result<Response<Resp>> {
+        block_on(self.unary_call_async(method, req, opt)?.full())
+    }
+
     /// Create an asynchronized unary RPC call.
     pub fn unary_call_async<Req, Resp>(
         &self,
@@ -47,7 +203,12 @@ impl Client {
         req: &Req,
         opt: CallOption,
     ) -> Result<ClientUnaryReceiver<Resp>> {
-        Call::unary_async(&self.channel, method, req, opt)
+        Call::unary_async(
+            self.channels.pick(),
+            method,
+            req,
+            self.prepare(method.name, opt),
+        )
     }
 
     /// Create an asynchronized client streaming call.
@@ -58,7 +219,7 @@ impl Client {
         method: &Method<Req, Resp>,
         opt: CallOption,
     ) -> Result<(ClientCStreamSender<Req>, ClientCStreamReceiver<Resp>)> {
-        Call::client_streaming(&self.channel, method, opt)
+        Call::client_streaming(self.channels.pick(), method, self.prepare(method.name, opt))
     }
 
     /// Create an asynchronized server streaming call.
@@ -70,7 +231,12 @@ impl Client {
         req: &Req,
         opt: CallOption,
     ) -> Result<ClientSStreamReceiver<Resp>> {
-        Call::server_streaming(&self.channel, method, req, opt)
+        Call::server_streaming(
+            self.channels.pick(),
+            method,
+            req,
+            self.prepare(method.name, opt),
+        )
     }
 
     /// Create an asynchronized duplex streaming call.
@@ -83,7 +249,44 @@ impl Client {
         method: &Method<Req, Resp>,
         opt: CallOption,
     ) -> Result<(ClientDuplexSender<Req>, ClientDuplexReceiver<Resp>)> {
-        Call::duplex_streaming(&self.channel, method, opt)
+        Call::duplex_streaming(self.channels.pick(), method, self.prepare(method.name, opt))
+    }
+
+    /// Check whether the method is implemented by the server.
+    ///
+    /// A call without any message is made to the method. The method is missing if
+    /// the server responds `UNIMPLEMENTED`, and present if it responds `OK`, or
+    /// fails a unary or server streaming method for lack of a request message.
+    /// Other failures, like `UNAVAILABLE` or `PERMISSION_DENIED`, are returned as
+    /// is, as they don't tell whether the method exists.
+    ///
+    /// Servers built by this library reply to probes without calling the handler
+    /// unless [`ServerBuilder::answer_probes`] is disabled. Other servers handle the
+    /// probe like any other call: the handler of a client streaming or duplex
+    /// streaming method is called with an empty request stream, and whatever side
+    /// effects it has happen.
+    ///
+    /// It uses futures::executor::block_on to wait for the futures. It's recommended to use
+    /// the asynchronous version.
+    ///
+    /// [`ServerBuilder::answer_probes`]: ./struct.ServerBuilder.html#method.answer_probes
+    pub fn probe_method<Req, Resp>(
+        &self,
+        method: &Method<Req, Resp>,
+        opt: CallOption,
+    ) -> Result<bool> {
+        block_on(self.probe_method_async(method, opt)?)
+    }
+
+    /// Same as [`probe_method`], but doesn't block.
+    ///
+    /// [`probe_method`]: #method.probe_method
+    pub fn probe_method_async<Req, Resp>(
+        &self,
+        method: &Method<Req, Resp>,
+        opt: CallOption,
+    ) -> Result<impl Future<Output = Result<bool>>> {
+        Call::probe(self.channels.pick(), method, self.prepare(method.name, opt))
     }
 
     /// Spawn the future into current gRPC poll thread.
@@ -95,6 +298,6 @@ impl Client {
         F: Future<Output = ()> + Send + 'static,
     {
         let kicker = self.kicker.clone();
-        Executor::new(self.channel.cq()).spawn(f, kicker)
+        Executor::new(self.channels.channels()[0].cq()).spawn(f, kicker)
     }
 }
@@ -1,11 +1,19 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
-use crate::buf::GrpcSlice;
-use crate::call::MessageReader;
-use crate::error::Result;
+use std::alloc::GlobalAlloc;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use parking_lot::RwLock;
+
+use crate::buf::{GrpcByteBuffer, GrpcSlice};
+use crate::call::{MessageReader, RpcStatus, RpcStatusCode};
+use crate::error::{Error, Result};
+use crate::grpc_sys::grpc_compression_algorithm;
 
 pub type DeserializeFn<T> = fn(MessageReader) -> Result<T>;
-pub type SerializeFn<T> = fn(&T, &mut GrpcSlice);
+pub type SerializeFn<T> = fn(&T, &mut GrpcSlice) -> Result<()>;
 
 /// Defines how to serialize and deserialize between the specialized type and byte slice.
 pub struct Marshaller<T> {
@@ -25,6 +33,332 @@ pub struct Marshaller<T> {
     pub de: DeserializeFn<T>,
 }
 
+impl<T> Clone for Marshaller<T> {
+    fn clone(&self) -> Marshaller<T> {
+        Marshaller {
+            ser: self.ser,
+            de: self.de,
+        }
+    }
+}
+
+/// The default initial capacity of buffers used by [`serialize_with`].
+///
+/// [`serialize_with`]: ./fn.serialize_with.html
+pub const DEFAULT_SERIALIZE_CAPACITY: usize = 128;
+
+static SERIALIZE_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_SERIALIZE_CAPACITY);
+
+/// Set the initial capacity of buffers used by [`serialize_with`] globally.
+///
+/// Serializers that don't know the size of messages in advance grow their
+/// buffers by reallocation, setting the capacity close to the common message
+/// size avoids the reallocations.
+///
+/// [`serialize_with`]: ./fn.serialize_with.html
+pub fn set_serialize_capacity(cap: usize) {
+    SERIALIZE_CAPACITY.store(cap, Ordering::Relaxed);
+}
+
+/// Serialize a message with `f` into a buffer of the global initial capacity.
+///
+/// It's a helper for custom marshallers whose encoders write to growable
+/// buffers, see [`set_serialize_capacity`]. Protobuf and prost messages compute
+/// their exact sizes before serialization, so they don't need it.
+///
+/// [`set_serialize_capacity`]: ./fn.set_serialize_capacity.html
+pub fn serialize_with<F>(buf: &mut GrpcSlice, f: F) -> Result<()>
+where
+    F: FnOnce(&mut Vec<u8>) -> Result<()>,
+{
+    serialize_with_capacity(buf, SERIALIZE_CAPACITY.load(Ordering::Relaxed), f)
+}
+
+/// Same as [`serialize_with`], but uses `cap` as the initial capacity.
+///
+/// It can be used to give a hint for a method whose messages are known to be
+/// large.
+///
+/// [`serialize_with`]: ./fn.serialize_with.html
+pub fn serialize_with_capacity<F>(buf: &mut GrpcSlice, cap: usize, f: F) -> Result<()>
+where
+    F: FnOnce(&mut Vec<u8>) -> Result<()>,
+{
+    let mut v = Vec::with_capacity(cap);
+    f(&mut v)?;
+    // Converting a vector doesn't copy the data.
+    *buf = GrpcSlice::from(v);
+    Ok(())
+}
+
+pub(crate) type SliceAllocator = &'static (dyn GlobalAlloc + Sync);
+
+static SLICE_ALLOCATOR: RwLock<Option<SliceAllocator>> = parking_lot::const_rwlock(None);
+
+/// Set the allocator of the slices that messages are serialized into globally,
+/// `None` restores the default one of gRPC core.
+///
+/// The allocator is shared by all channels and servers of the process, so it's
+/// meant to be set once at startup, like a logger.
+///
+/// It applies to the buffers allocated by the codec layer, which are the slices
+/// protobuf and prost messages are serialized into, see [`GrpcSlice::realloc`],
+/// and the slices large received messages are gathered into before being
+/// decompressed by a [`MessageCompressor`]. Small messages are still inlined in
+/// slices and allocations inside gRPC core are not affected. Buffers allocated
+/// before the change are freed by the allocator that allocated them.
+///
+/// [`GrpcSlice::realloc`]: ./struct.GrpcSlice.html#method.realloc
+/// [`MessageCompressor`]: ./trait.MessageCompressor.html
+pub fn set_slice_allocator(allocator: Option<&'static (dyn GlobalAlloc + Sync)>) {
+    *SLICE_ALLOCATOR.write() = allocator;
+}
+
+pub(crate) fn slice_allocator() -> Option<SliceAllocator> {
+    *SLICE_ALLOCATOR.read()
+}
+
+/// The header carrying the name of the [`MessageCompressor`] used by a call.
+///
+/// It can't be `grpc-encoding`, gRPC Core removes the header from the received
+/// metadata and treats the algorithms it doesn't know as no compression.
+///
+/// [`MessageCompressor`]: ./trait.MessageCompressor.html
+pub(crate) const MESSAGE_ENCODING_KEY: &str = "x-message-encoding";
+
+/// A compressor that compresses messages in the codec layer.
+///
+/// Unlike the compression algorithms of gRPC Core, which compress messages in the
+/// transport and are negotiated by `grpc-encoding` and `grpc-accept-encoding`
+/// headers, a message compressor compresses serialized messages before they are
+/// handed to gRPC Core, so any algorithm like zstd can be used. Peers that don't
+/// know the compressor can't read the messages, so it should only be used when
+/// both sides register it.
+///
+/// There is no negotiation like `grpc-accept-encoding`: a client compresses the
+/// requests before it hears anything from the server, so the caller has to make
+/// sure the server supports the compressor, for example by deploying both sides
+/// together or by falling back to no compression on `UNIMPLEMENTED`.
+///
+/// A client enables it by [`CallOption::message_compressor`], which sends its name
+/// in the `x-message-encoding` header, as gRPC Core keeps `grpc-encoding` to itself.
+/// A server registers it by
+/// [`ServerBuilder::register_message_compressor`], and then decompresses requests
+/// and compresses responses of calls asking for it. Calls asking for unknown
+/// compressors are rejected with `UNIMPLEMENTED`, the details of the status list
+/// the registered names.
+///
+/// [`CallOption::message_compressor`]: ./struct.CallOption.html#method.message_compressor
+/// [`ServerBuilder::register_message_compressor`]: ./struct.ServerBuilder.html#method.register_message_compressor
+pub trait MessageCompressor: Send + Sync {
+    /// The name of the compressor, which should be unique.
+    fn name(&self) -> &str;
+
+    /// Compress a serialized message.
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Decompress a compressed message into `out`.
+    ///
+    /// Writing to `out` fails once the message exceeds the receive limit of the call,
+    /// so a small message can't expand to exhaust the memory. Decompress in a
+    /// streaming way, like `zstd::stream::copy_decode`, instead of into a buffer
+    /// first to benefit from it.
+    fn decompress(&self, data: &[u8], out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Compress the serialized message in place.
+pub(crate) fn compress_message(c: &dyn MessageCompressor, buf: &mut GrpcSlice) -> Result<()> {
+    let compressed = c
+        .compress(buf.as_slice())
+        .map_err(|e| Error::Codec(Box::new(e)))?;
+    *buf = GrpcSlice::from(compressed);
+    Ok(())
+}
+
+/// A buffer that refuses to grow beyond its limit.
+struct LimitedBuf {
+    buf: Vec<u8>,
+    max: usize,
+    exceeded: bool,
+}
+
+impl Write for LimitedBuf {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.len() > self.max - self.buf.len() {
+            self.exceeded = true;
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "decompressed message too large",
+            ));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decompress the received message, which fails with `RESOURCE_EXHAUSTED` once
+/// it exceeds `max_len`.
+pub(crate) fn decompress_message(
+    c: &dyn MessageCompressor,
+    reader: MessageReader,
+    max_len: Option<usize>,
+) -> Result<MessageReader> {
+    decompress_limited(reader, max_len, |data, out| c.decompress(data, out))
+}
+
+/// Decompress the received message that gRPC Core left compressed with one of the
+/// algorithms built into gRPC, which fails with `RESOURCE_EXHAUSTED` once it
+/// exceeds `max_len`.
+pub(crate) fn inflate_message(
+    reader: MessageReader,
+    max_len: Option<usize>,
+) -> Result<MessageReader> {
+    let algorithm = reader.compression();
+    decompress_limited(reader, max_len, |data, out| {
+        match algorithm {
+            grpc_compression_algorithm::GRPC_COMPRESS_GZIP => {
+                io::copy(&mut GzDecoder::new(data), out)?;
+            }
+            // The deflate encoding of gRPC is zlib.
+            grpc_compression_algorithm::GRPC_COMPRESS_DEFLATE => {
+                io::copy(&mut ZlibDecoder::new(data), out)?;
+            }
+            a => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported compression algorithm {:?}", a),
+                ))
+            }
+        }
+        Ok(())
+    })
+}
+
+fn decompress_limited<F>(
+    reader: MessageReader,
+    max_len: Option<usize>,
+    f: F,
+) -> Result<MessageReader>
+where
+    F: FnOnce(&[u8], &mut dyn Write) -> io::Result<()>,
+{
+    let compressed = reader.into_slice();
+    let mut out = LimitedBuf {
+        buf: Vec::new(),
+        max: max_len.unwrap_or(usize::MAX),
+        exceeded: false,
+    };
+    let res = f(compressed.as_slice(), &mut out);
+    if out.exceeded {
+        return Err(Error::RpcFailure(RpcStatus::new(
+            RpcStatusCode::RESOURCE_EXHAUSTED,
+            Some(format!(
+                "Received message larger than max after decompression (over {} bytes)",
+                out.max
+            )),
+        )));
+    }
+    res.map_err(|e| Error::Codec(Box::new(e)))?;
+    let slice = GrpcSlice::from(out.buf);
+    Ok(MessageReader::new(GrpcByteBuffer::from(&slice)))
+}
+
+/// A thread local pool of decoded messages that can be reused.
+///
+/// Messages are moved in and out of the pool, so a message can never be
+/// shared by different calls.
+mod pool {
+    use std::any::{Any, TypeId};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    /// The max number of messages cached for each type in a thread.
+    pub const POOL_CAPACITY: usize = 64;
+
+    thread_local! {
+        // Every value is a `Vec<T>` where `T` is the type of the key.
+        static POOL: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+    }
+
+    pub fn take<T: Any>() -> Option<T> {
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            let msgs = pool.get_mut(&TypeId::of::<T>())?;
+            msgs.downcast_mut::<Vec<T>>().unwrap().pop()
+        })
+    }
+
+    pub fn put<T: Any>(msg: T) {
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            let msgs = pool
+                .entry(TypeId::of::<T>())
+                .or_insert_with(|| Box::new(Vec::<T>::new()));
+            let msgs = msgs.downcast_mut::<Vec<T>>().unwrap();
+            if msgs.len() < POOL_CAPACITY {
+                msgs.push(msg);
+            }
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_pool() {
+            assert_eq!(take::<String>(), None);
+            put(String::with_capacity(16));
+            put(vec![1u8]);
+            assert_eq!(take::<Vec<u8>>(), Some(vec![1]));
+            assert_eq!(take::<Vec<u8>>(), None);
+            assert!(take::<String>().unwrap().capacity() >= 16);
+
+            for _ in 0..POOL_CAPACITY + 1 {
+                put(0u32);
+            }
+            let mut count = 0;
+            while take::<u32>().is_some() {
+                count += 1;
+            }
+            assert_eq!(count, POOL_CAPACITY);
+
+            // Pools are not shared between threads.
+            put(1u64);
+            std::thread::spawn(|| assert_eq!(take::<u64>(), None))
+                .join()
+                .unwrap();
+            assert_eq!(take::<u64>(), Some(1));
+        }
+    }
+}
+
+/// A codec for messages that are raw bytes.
+///
+/// Sending a message only increases the reference count of the slice, so large
+/// payloads can be sent without being copied.
+pub mod slice_codec {
+    use super::MessageReader;
+    use crate::buf::GrpcSlice;
+    use crate::error::Result;
+
+    #[inline]
+    pub fn ser(t: &GrpcSlice, buf: &mut GrpcSlice) -> Result<()> {
+        *buf = t.clone();
+        Ok(())
+    }
+
+    /// Data is only copied if the message is received in multiple slices.
+    #[inline]
+    pub fn de(reader: MessageReader) -> Result<GrpcSlice> {
+        Ok(reader.into_slice())
+    }
+}
+
 #[cfg(feature = "protobuf-codec")]
 pub mod pb_codec {
     use protobuf::{CodedInputStream, CodedOutputStream, Message};
@@ -34,14 +368,18 @@ pub mod pb_codec {
     use crate::error::Result;
 
     #[inline]
-    pub fn ser<T: Message>(t: &T, buf: &mut GrpcSlice) {
+    pub fn ser<T: Message>(t: &T, buf: &mut GrpcSlice) -> Result<()> {
+        // Missing required fields should be reported instead of sending a
+        // malformed message.
+        t.check_initialized()?;
         let cap = t.compute_size();
         unsafe {
             let bytes = buf.realloc(cap as usize);
             let raw_bytes = &mut *(bytes as *mut [std::mem::MaybeUninit<u8>] as *mut [u8]);
             let mut s = CodedOutputStream::bytes(raw_bytes);
-            t.write_to_with_cached_sizes(&mut s).unwrap();
+            t.write_to_with_cached_sizes(&mut s)?;
         }
+        Ok(())
     }
 
     #[inline]
@@ -51,6 +389,24 @@ pub mod pb_codec {
         m.merge_from(&mut s)?;
         Ok(m)
     }
+
+    /// Same as `de`, but reuses a message given back by `recycle` in the
+    /// current thread if there is any, which saves allocations.
+    #[inline]
+    pub fn de_pooled<T: Message>(mut reader: MessageReader) -> Result<T> {
+        let mut s = CodedInputStream::from_buffered_reader(&mut reader);
+        let mut m = super::pool::take().unwrap_or_else(T::new);
+        m.merge_from(&mut s)?;
+        Ok(m)
+    }
+
+    /// Give a message back so that it can be reused by `de_pooled`.
+    ///
+    /// The message is cleared, but its allocated buffers are kept.
+    pub fn recycle<T: Message>(mut m: T) {
+        m.clear();
+        super::pool::put(m);
+    }
 }
 
 #[cfg(feature = "prost-codec")]
@@ -62,15 +418,15 @@ pub mod pr_codec {
     use crate::error::Result;
 
     #[inline]
-    pub fn ser<M: Message>(msg: &M, buf: &mut GrpcSlice) {
+    pub fn ser<M: Message>(msg: &M, buf: &mut GrpcSlice) -> Result<()> {
         let size = msg.encoded_len();
         unsafe {
             let bytes = buf.realloc(size);
             let mut b = &mut *(bytes as *mut [std::mem::MaybeUninit<u8>] as *mut [u8]);
-            msg.encode(&mut b)
-                .expect("Writing message to buffer failed");
+            msg.encode(&mut b)?;
             debug_assert!(b.is_empty());
         }
+        Ok(())
     }
 
     #[inline]
@@ -79,4 +435,48 @@ pub mod pr_codec {
         reader.advance(0);
         M::decode(reader).map_err(Into::into)
     }
+
+    /// Same as `de`, but reuses a message given back by `recycle` in the
+    /// current thread if there is any, which saves allocations.
+    #[inline]
+    pub fn de_pooled<M: Message + Default + 'static>(mut reader: MessageReader) -> Result<M> {
+        use bytes::buf::Buf;
+        reader.advance(0);
+        let mut m: M = super::pool::take().unwrap_or_default();
+        m.merge(reader)?;
+        Ok(m)
+    }
+
+    /// Give a message back so that it can be reused by `de_pooled`.
+    ///
+    /// The message is cleared, but its allocated buffers are kept.
+    pub fn recycle<M: Message + 'static>(mut m: M) {
+        m.clear();
+        super::pool::put(m);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_with_capacity() {
+        let mut buf = GrpcSlice::default();
+        serialize_with_capacity(&mut buf, 4096, |v| {
+            assert!(v.capacity() >= 4096);
+            v.extend_from_slice(&[1; 100]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(buf.as_slice(), &[1; 100][..]);
+
+        serialize_with(&mut buf, |v| {
+            assert!(v.capacity() >= DEFAULT_SERIALIZE_CAPACITY);
+            v.push(2);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(buf.as_slice(), &[2]);
+    }
 }
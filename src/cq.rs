@@ -3,9 +3,10 @@
 use std::cell::UnsafeCell;
 use std::collections::VecDeque;
 use std::ptr;
-use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread::{self, ThreadId};
+use std::time::Duration;
 
 use crate::error::{Error, Result};
 use crate::grpc_sys::{self, gpr_clock_type, grpc_completion_queue};
@@ -22,6 +23,8 @@ pub struct CompletionQueueHandle {
     // be shutdown; When `ref_cnt` > 0, completion queue can accept requests
     // and should not be shutdown.
     ref_cnt: AtomicIsize,
+    // The number of calls that are in flight on the queue.
+    calls: AtomicUsize,
 }
 
 unsafe impl Sync for CompletionQueueHandle {}
@@ -32,6 +35,7 @@ impl CompletionQueueHandle {
         CompletionQueueHandle {
             cq: unsafe { grpc_sys::grpc_completion_queue_create_for_next(ptr::null_mut()) },
             ref_cnt: AtomicIsize::new(1),
+            calls: AtomicUsize::new(0),
         }
     }
 
@@ -135,7 +139,9 @@ impl<'a> Drop for CompletionQueueRef<'a> {
 /// 2. If not, the future will be wrapped as a call tag and pushed into
 ///    completion queue and finally popped at the call to `grpc_completion_queue_next`.
 pub struct WorkQueue {
-    id: ThreadId,
+    // None if the queue is polled by threads owned by users, in which case all
+    // works are deferred by kicking the completion queue.
+    id: Option<ThreadId>,
     pending_work: UnsafeCell<VecDeque<UnfinishedWork>>,
 }
 
@@ -147,17 +153,25 @@ const QUEUE_CAPACITY: usize = 4096;
 impl WorkQueue {
     pub fn new() -> WorkQueue {
         WorkQueue {
-            id: std::thread::current().id(),
+            id: Some(std::thread::current().id()),
             pending_work: UnsafeCell::new(VecDeque::with_capacity(QUEUE_CAPACITY)),
         }
     }
 
+    /// Create a queue that is not bound to any thread, no work can be pushed into it.
+    pub fn unbound() -> WorkQueue {
+        WorkQueue {
+            id: None,
+            pending_work: UnsafeCell::new(VecDeque::new()),
+        }
+    }
+
     /// Pushes an unfinished work into the inner queue.
     ///
     /// If the method is not called from the same thread where it's created,
     /// the work will returned and no work is pushed.
     pub fn push_work(&self, work: UnfinishedWork) -> Option<UnfinishedWork> {
-        if self.id == thread::current().id() {
+        if self.id == Some(thread::current().id()) {
             unsafe { &mut *self.pending_work.get() }.push_back(work);
             None
         } else {
@@ -197,11 +211,32 @@ impl CompletionQueue {
         }
     }
 
+    /// Same as `next`, but returns a `GRPC_QUEUE_TIMEOUT` event if no event is
+    /// available within `timeout`.
+    pub fn next_timeout(&self, timeout: Duration) -> Event {
+        unsafe {
+            grpc_sys::grpc_completion_queue_next(self.handle.cq, timeout.into(), ptr::null_mut())
+        }
+    }
+
     pub fn borrow(&self) -> Result<CompletionQueueRef<'_>> {
         self.handle.add_ref()?;
         Ok(CompletionQueueRef { queue: self })
     }
 
+    /// Get the number of calls that are in flight on the queue.
+    pub fn call_count(&self) -> usize {
+        self.handle.calls.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_call(&self) {
+        self.handle.calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn remove_call(&self) {
+        self.handle.calls.fetch_sub(1, Ordering::Relaxed);
+    }
+
     /// Begin destruction of a completion queue.
     ///
     /// Once all possible events are drained then `next()` will start to produce
@@ -210,7 +245,22 @@ impl CompletionQueue {
         self.handle.shutdown()
     }
 
+    /// Get the id of the polling thread.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the queue is polled by threads owned by users,
+    /// which have no single id. Use [`polling_thread_id`] to handle both cases.
+    ///
+    /// [`polling_thread_id`]: #method.polling_thread_id
     pub fn worker_id(&self) -> ThreadId {
+        self.polling_thread_id()
+            .expect("completion queue is polled by threads owned by users")
+    }
+
+    /// Get the id of the polling thread, or `None` if it's polled by threads owned
+    /// by users.
+    pub fn polling_thread_id(&self) -> Option<ThreadId> {
         self.worker.id
     }
 }
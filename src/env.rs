@@ -4,42 +4,117 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread::{Builder as ThreadBuilder, JoinHandle};
+use std::time::Duration;
 
 use crate::grpc_sys;
 
-use crate::cq::{CompletionQueue, CompletionQueueHandle, EventType, WorkQueue};
+use crate::cq::{CompletionQueue, CompletionQueueHandle, Event, EventType, WorkQueue};
 use crate::task::CallTag;
 
+// Handle an event of the queue, returns false if the queue is shut down.
+fn handle_event(cq: &CompletionQueue, e: Event) -> bool {
+    match e.type_ {
+        EventType::GRPC_QUEUE_SHUTDOWN => return false,
+        EventType::GRPC_QUEUE_TIMEOUT => return true,
+        EventType::GRPC_OP_COMPLETE => {}
+    }
+
+    let tag: Box<CallTag> = unsafe { Box::from_raw(e.tag as _) };
+
+    tag.resolve(cq, e.success != 0);
+    while let Some(work) = unsafe { cq.worker.pop_work() } {
+        work.finish();
+    }
+    true
+}
+
 // event loop
 fn poll_queue(tx: mpsc::Sender<CompletionQueue>) {
     let cq = Arc::new(CompletionQueueHandle::new());
     let worker_info = Arc::new(WorkQueue::new());
     let cq = CompletionQueue::new(cq, worker_info);
     tx.send(cq.clone()).expect("send back completion queue");
+    // timeout should not happen in theory.
+    while handle_event(&cq, cq.next()) {}
+}
+
+// An event pulled by an I/O thread, which is only resolved by the completion
+// thread of the queue.
+struct ForwardedEvent(Event);
+
+unsafe impl Send for ForwardedEvent {}
+
+// event loop of a completion thread when I/O is polled by a dedicated thread.
+fn complete_queue(tx: mpsc::Sender<CompletionQueue>, rx: mpsc::Receiver<ForwardedEvent>) {
+    let cq = Arc::new(CompletionQueueHandle::new());
+    let worker_info = Arc::new(WorkQueue::new());
+    let cq = CompletionQueue::new(cq, worker_info);
+    tx.send(cq.clone()).expect("send back completion queue");
+    for ForwardedEvent(e) in rx {
+        if !handle_event(&cq, e) {
+            return;
+        }
+    }
+}
+
+// event loop of a dedicated I/O thread, it only pulls events and hands them
+// over to the completion thread, so polling is never delayed by handlers.
+fn forward_queue(cq: CompletionQueue, tx: mpsc::Sender<ForwardedEvent>) {
     loop {
         let e = cq.next();
-        match e.type_ {
-            EventType::GRPC_QUEUE_SHUTDOWN => break,
-            // timeout should not happen in theory.
-            EventType::GRPC_QUEUE_TIMEOUT => continue,
-            EventType::GRPC_OP_COMPLETE => {}
+        let shutdown = match e.type_ {
+            EventType::GRPC_QUEUE_SHUTDOWN => true,
+            _ => false,
+        };
+        if tx.send(ForwardedEvent(e)).is_err() || shutdown {
+            return;
         }
+    }
+}
 
-        let tag: Box<CallTag> = unsafe { Box::from_raw(e.tag as _) };
+/// A poller of a completion queue, which is driven by a thread owned by users.
+///
+/// It's created by [`EnvBuilder::build_with_pollers`]. Every poller must be kept
+/// polling until the queue is shut down, which happens when the [`Environment`] is
+/// dropped, otherwise calls on the queue never make progress.
+pub struct CqPoller {
+    cq: CompletionQueue,
+}
 
-        tag.resolve(&cq, e.success != 0);
-        while let Some(work) = unsafe { cq.worker.pop_work() } {
-            work.finish();
-        }
+impl CqPoller {
+    /// Wait for at most `timeout` and handle at most one event of the queue.
+    ///
+    /// Returns false if the queue is shut down and there is nothing left to poll.
+    pub fn poll_once(&self, timeout: Duration) -> bool {
+        handle_event(&self.cq, self.cq.next_timeout(timeout))
     }
+
+    /// Poll the queue until it's shut down.
+    pub fn run(self) {
+        while handle_event(&self.cq, self.cq.next()) {}
+    }
+}
+
+/// Strategy of picking completion queues for new channels and calls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CqPickPolicy {
+    /// Channels take completion queues in turn, and all calls of a channel use the
+    /// completion queue of the channel.
+    RoundRobin,
+    /// Every new client call uses the completion queue with the fewest calls in
+    /// flight, so a busy channel doesn't overload a single polling thread.
+    LeastLoaded,
 }
 
 /// [`Environment`] factory in order to configure the properties.
 pub struct EnvBuilder {
     cq_count: usize,
+    cq_pick_policy: CqPickPolicy,
     name_prefix: Option<String>,
-    after_start: Option<Arc<dyn Fn() + Send + Sync>>,
+    stack_size: Option<usize>,
+    after_start: Option<Arc<dyn Fn(usize) + Send + Sync>>,
     before_stop: Option<Arc<dyn Fn() + Send + Sync>>,
+    dedicated_io_threads: bool,
 }
 
 impl EnvBuilder {
@@ -47,9 +122,12 @@ impl EnvBuilder {
     pub fn new() -> EnvBuilder {
         EnvBuilder {
             cq_count: unsafe { grpc_sys::gpr_cpu_num_cores() as usize },
+            cq_pick_policy: CqPickPolicy::RoundRobin,
             name_prefix: None,
+            stack_size: None,
             after_start: None,
             before_stop: None,
+            dedicated_io_threads: false,
         }
     }
 
@@ -65,14 +143,51 @@ impl EnvBuilder {
         self
     }
 
+    /// Set how completion queues are picked for new channels and calls.
+    ///
+    /// [`CqPickPolicy::RoundRobin`] is used by default. Calls accepted by servers are
+    /// not affected, they are always handled by the completion queue assigned to the
+    /// connection by gRPC Core.
+    pub fn cq_pick_policy(mut self, policy: CqPickPolicy) -> EnvBuilder {
+        self.cq_pick_policy = policy;
+        self
+    }
+
     /// Set the thread name prefix of each polling thread.
     pub fn name_prefix<S: Into<String>>(mut self, prefix: S) -> EnvBuilder {
         self.name_prefix = Some(prefix.into());
         self
     }
 
+    /// Set the stack size, in bytes, of each polling thread.
+    ///
+    /// Handlers and futures spawned by `RpcContext::spawn` run on polling threads,
+    /// so deeply recursive handlers may need a larger stack. If not set, the default
+    /// stack size of `std::thread` is used, which is 2 MiB at the time of writing
+    /// and can be overridden by the `RUST_MIN_STACK` environment variable. Setting a
+    /// small value is risky as a stack overflow aborts the whole process.
+    pub fn stack_size(mut self, size: usize) -> EnvBuilder {
+        self.stack_size = Some(size);
+        self
+    }
+
     /// Execute function `f` after each thread is started but before it starts doing work.
     pub fn after_start<F: Fn() + Send + Sync + 'static>(mut self, f: F) -> EnvBuilder {
+        self.after_start = Some(Arc::new(move |_| f()));
+        self
+    }
+
+    /// Same as `after_start`, but `f` is also given the index of the polling thread,
+    /// which ranges from 0 to `cq_count - 1` and is unique among the threads of the
+    /// environment. It replaces the function set by `after_start`.
+    ///
+    /// The index can be used to pin each polling thread to a dedicated core or NUMA
+    /// node, for example by calling `libc::sched_setaffinity` with a CPU set chosen
+    /// by the index, as the hook runs on the polling thread itself.
+    pub fn after_start_with_index<F: Fn(usize) + Send + Sync + 'static>(
+        mut self,
+        f: F,
+    ) -> EnvBuilder {
         self.after_start = Some(Arc::new(f));
         self
     }
@@ -83,6 +198,69 @@ impl EnvBuilder {
         self
     }
 
+    /// Whether to poll I/O on dedicated threads, separate from the threads that
+    /// handle completions.
+    ///
+    /// By default, each polling thread both drives I/O of its completion queue and
+    /// runs the handlers and futures resolved by the queue. While a handler is
+    /// running, no I/O of the queue makes progress, so a slow handler delays every
+    /// other call on the queue, which shows up as tail latency.
+    ///
+    /// If enabled, an extra I/O thread is spawned for each completion queue. It
+    /// only pulls events from the queue and hands them over to the completion
+    /// thread, so reading and writing sockets goes on while handlers are busy. The
+    /// cost is a cross-thread hop and wakeup for every event, which adds a few
+    /// microseconds to each completion and twice as many threads. It's usually
+    /// only worth it when handlers do noticeable work on the polling threads; for
+    /// quick handlers, the default is faster.
+    ///
+    /// `after_start`, `after_start_with_index` and `before_stop` only apply to the
+    /// completion threads, as handlers never run on I/O threads. I/O threads share
+    /// the stack size and are named `{prefix}-io-{index}`.
+    pub fn dedicated_io_threads(mut self, enable: bool) -> EnvBuilder {
+        self.dedicated_io_threads = enable;
+        self
+    }
+
+    fn thread_builder(&self, name: impl FnOnce(&str) -> String) -> ThreadBuilder {
+        let mut builder = ThreadBuilder::new();
+        if let Some(ref prefix) = self.name_prefix {
+            builder = builder.name(name(prefix));
+        }
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+        builder
+    }
+
+    /// Same as `build`, but no polling thread is spawned. Instead, a poller is returned
+    /// for each completion queue, and users are responsible for driving them on their
+    /// own threads by [`CqPoller::run`] or [`CqPoller::poll_once`].
+    ///
+    /// Thread related settings like `name_prefix`, `stack_size`, `after_start`,
+    /// `before_stop` and `dedicated_io_threads` are ignored. As the polling threads
+    /// are unknown to the library, futures spawned on the queues are always woken up
+    /// by kicking the queue instead of being polled in place, which is a bit slower.
+    pub fn build_with_pollers(self) -> (Environment, Vec<CqPoller>) {
+        unsafe {
+            grpc_sys::grpc_init();
+        }
+        let cqs: Vec<_> = (0..self.cq_count)
+            .map(|_| {
+                let handle = Arc::new(CompletionQueueHandle::new());
+                CompletionQueue::new(handle, Arc::new(WorkQueue::unbound()))
+            })
+            .collect();
+        let pollers = cqs.iter().map(|cq| CqPoller { cq: cq.clone() }).collect();
+        let env = Environment {
+            cqs,
+            idx: AtomicUsize::new(0),
+            cq_pick_policy: self.cq_pick_policy,
+            _handles: Vec::new(),
+        };
+        (env, pollers)
+    }
+
     /// Finalize the [`EnvBuilder`], build the [`Environment`] and initialize the gRPC library.
     pub fn build(self) -> Environment {
         unsafe {
@@ -91,20 +269,32 @@ impl EnvBuilder {
         let mut cqs = Vec::with_capacity(self.cq_count);
         let mut handles = Vec::with_capacity(self.cq_count);
         let (tx, rx) = mpsc::channel();
+        let mut io_threads = Vec::new();
         for i in 0..self.cq_count {
-            let tx_i = tx.clone();
-            let mut builder = ThreadBuilder::new();
-            if let Some(ref prefix) = self.name_prefix {
-                builder = builder.name(format!("{}-{}", prefix, i));
-            }
+            let mut tx_i = tx.clone();
+            let io_rx = if self.dedicated_io_threads {
+                // The I/O thread must forward events to the thread owning the queue,
+                // so the queue is sent back on a channel of its own.
+                let (cq_tx, cq_rx) = mpsc::channel();
+                let (io_tx, io_rx) = mpsc::channel();
+                tx_i = cq_tx;
+                io_threads.push((cq_rx, io_tx));
+                Some(io_rx)
+            } else {
+                None
+            };
+            let builder = self.thread_builder(|prefix| format!("{}-{}", prefix, i));
             let after_start = self.after_start.clone();
             let before_stop = self.before_stop.clone();
             let handle = builder
                 .spawn(move || {
                     if let Some(f) = after_start {
-                        f();
+                        f(i);
+                    }
+                    match io_rx {
+                        Some(io_rx) => complete_queue(tx_i, io_rx),
+                        None => poll_queue(tx_i),
                     }
-                    poll_queue(tx_i);
                     if let Some(f) = before_stop {
                         f();
                     }
@@ -112,13 +302,25 @@ impl EnvBuilder {
                 .unwrap();
             handles.push(handle);
         }
-        for _ in 0..self.cq_count {
-            cqs.push(rx.recv().unwrap());
+        if io_threads.is_empty() {
+            for _ in 0..self.cq_count {
+                cqs.push(rx.recv().unwrap());
+            }
+        }
+        for (i, (cq_rx, io_tx)) in io_threads.into_iter().enumerate() {
+            let cq = cq_rx.recv().unwrap();
+            cqs.push(cq.clone());
+            let handle = self
+                .thread_builder(|prefix| format!("{}-io-{}", prefix, i))
+                .spawn(move || forward_queue(cq, io_tx))
+                .unwrap();
+            handles.push(handle);
         }
 
         Environment {
             cqs,
             idx: AtomicUsize::new(0),
+            cq_pick_policy: self.cq_pick_policy,
             _handles: handles,
         }
     }
@@ -128,6 +330,7 @@ impl EnvBuilder {
 pub struct Environment {
     cqs: Vec<CompletionQueue>,
     idx: AtomicUsize,
+    cq_pick_policy: CqPickPolicy,
     _handles: Vec<JoinHandle<()>>,
 }
 
@@ -152,10 +355,23 @@ impl Environment {
         self.cqs.as_slice()
     }
 
-    /// Pick an arbitrary completion queue.
+    /// Pick a completion queue according to the [`CqPickPolicy`].
     pub fn pick_cq(&self) -> CompletionQueue {
         let idx = self.idx.fetch_add(1, Ordering::Relaxed);
-        self.cqs[idx % self.cqs.len()].clone()
+        let len = self.cqs.len();
+        match self.cq_pick_policy {
+            CqPickPolicy::RoundRobin => self.cqs[idx % len].clone(),
+            // Start from a rotating position, so ties are broken in turn.
+            CqPickPolicy::LeastLoaded => (0..len)
+                .map(|i| &self.cqs[(idx + i) % len])
+                .min_by_key(|cq| cq.call_count())
+                .unwrap()
+                .clone(),
+        }
+    }
+
+    pub(crate) fn cq_pick_policy(&self) -> CqPickPolicy {
+        self.cq_pick_policy
     }
 }
 
@@ -199,4 +415,39 @@ mod tests {
             handle.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_dedicated_io_threads() {
+        let mut env = EnvBuilder::new()
+            .cq_count(2)
+            .dedicated_io_threads(true)
+            .build();
+        assert_eq!(env.completion_queues().len(), 2);
+        assert_eq!(env._handles.len(), 4);
+        for cq in env.completion_queues() {
+            cq.shutdown();
+        }
+        for handle in env._handles.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_dedicated_io_threads_handle_events() {
+        use crate::{ChannelBuilder, Client};
+        use futures::channel::oneshot;
+
+        let env = Arc::new(EnvBuilder::new().dedicated_io_threads(true).build());
+        let ch = ChannelBuilder::new(env).connect("127.0.0.1:0");
+        let client = Client::new(ch);
+        let (tx, rx) = oneshot::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        client.spawn(async move {
+            done_tx.send(rx.await.unwrap()).unwrap();
+        });
+        // Waking the future from outside kicks the queue, the event is pulled by
+        // the I/O thread and the future is polled by the completion thread.
+        tx.send(42).unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(3)).unwrap(), 42);
+    }
 }
@@ -6,7 +6,7 @@ use crate::call::RpcStatus;
 use crate::grpc_sys::grpc_call_error;
 
 #[cfg(feature = "prost-codec")]
-use prost::DecodeError;
+use prost::{DecodeError, EncodeError};
 #[cfg(feature = "protobuf-codec")]
 use protobuf::ProtobufError;
 
@@ -33,12 +33,16 @@ pub enum Error {
     GoogleAuthenticationFailed,
     /// Invalid format of metadata.
     InvalidMetadata(String),
+    /// Failed to load credentials.
+    InvalidCredentials(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::RpcFailure(RpcStatus { status, details }) => match details {
+            Error::RpcFailure(RpcStatus {
+                status, details, ..
+            }) => match details {
                 Some(details) => write!(fmt, "RpcFailure: {} {}", status, details),
                 None => write!(fmt, "RpcFailure: {}", status),
             },
@@ -47,6 +51,22 @@ impl fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// Get the full error string of gRPC Core of a failed RPC, if any.
+    ///
+    /// See [`RpcStatus::debug_error_string`] for more details.
+    ///
+    /// [`RpcStatus::debug_error_string`]: ./struct.RpcStatus.html#method.debug_error_string
+    pub fn debug_error_string(&self) -> Option<&str> {
+        match self {
+            Error::RpcFailure(status) | Error::RpcFinished(Some(status)) => {
+                status.debug_error_string()
+            }
+            _ => None,
+        }
+    }
+}
+
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
@@ -70,6 +90,13 @@ impl From<DecodeError> for Error {
     }
 }
 
+#[cfg(feature = "prost-codec")]
+impl From<EncodeError> for Error {
+    fn from(e: EncodeError) -> Error {
+        Error::Codec(Box::new(e))
+    }
+}
+
 /// Type alias to use this library's [`Error`] type in a `Result`.
 pub type Result<T> = result::Result<T, Error>;
 
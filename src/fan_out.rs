@@ -0,0 +1,122 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Instant;
+
+use futures::future::{self, Either};
+use futures::stream::{self, Stream, StreamExt};
+
+use crate::call::client::CallOption;
+use crate::call::{Method, RpcStatus, RpcStatusCode};
+use crate::client::Client;
+use crate::error::{Error, Result};
+
+/// Issue unary calls to many backends concurrently and gather the results.
+///
+/// Each item of `calls` is a client and the request to send with it. At most
+/// `concurrency` calls are in flight at the same time, the rest are issued as
+/// soon as earlier ones complete.
+///
+/// The timeout of `opt`, if any, is the deadline of the whole fan-out rather
+/// than of each call: calls issued later only get the time left, and calls that
+/// can't be issued before the deadline fail with `DEADLINE_EXCEEDED`.
+///
+/// Results are yielded in completion order together with the index of the
+/// request in `calls`. A failed call doesn't affect the others.
+///
+/// # Panics
+///
+/// This function will panic if `concurrency` is 0.
+pub fn unary_fan_out<'a, Req, Resp, I>(
+    method: &'a Method<Req, Resp>,
+    calls: I,
+    opt: CallOption,
+    concurrency: usize,
+) -> impl Stream<Item = (usize, Result<Resp>)> + 'a
+where
+    I: IntoIterator<Item = (Client, Req)>,
+    I::IntoIter: 'a,
+    Resp: 'a,
+{
+    assert!(concurrency > 0);
+    let deadline = opt.get_timeout().map(|t| Instant::now() + t);
+    stream::iter(calls.into_iter().enumerate())
+        .map(move |(i, (client, req))| {
+            let mut opt = opt.clone();
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    let status = RpcStatus::new(RpcStatusCode::DEADLINE_EXCEEDED, None);
+                    return Either::Left(future::ready((i, Err(Error::RpcFailure(status)))));
+                }
+                opt = opt.timeout(deadline - now);
+            }
+            let f = client.unary_call_async(method, &req, opt);
+            Either::Right(async move {
+                match f {
+                    Ok(f) => (i, f.await),
+                    Err(e) => (i, Err(e)),
+                }
+            })
+        })
+        .buffer_unordered(concurrency)
+}
+
+/// Issue the same unary request to many backends concurrently and return the
+/// first successful response.
+///
+/// It's a hedging helper for reducing tail latency, so the request should be
+/// idempotent. Once a call succeeds, all the other calls are cancelled and their
+/// responses are discarded. If all calls fail, the error of the last failed call
+/// is returned. Dropping the returned future cancels all the calls in flight.
+///
+/// # Panics
+///
+/// This function will panic if `clients` is empty.
+pub fn unary_hedge<Req, Resp, I>(
+    method: &Method<Req, Resp>,
+    clients: I,
+    req: &Req,
+    opt: CallOption,
+) -> impl Future<Output = Result<Resp>>
+where
+    I: IntoIterator<Item = Client>,
+{
+    let mut calls = vec![];
+    let mut last_err = None;
+    for client in clients {
+        match client.unary_call_async(method, req, opt.clone()) {
+            Ok(f) => calls.push(f),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    assert!(
+        !calls.is_empty() || last_err.is_some(),
+        "no backend to hedge"
+    );
+    future::poll_fn(move |cx| {
+        let mut i = 0;
+        while i < calls.len() {
+            match Pin::new(&mut calls[i]).poll(cx) {
+                Poll::Pending => i += 1,
+                Poll::Ready(Ok(resp)) => {
+                    calls.swap_remove(i);
+                    for mut call in calls.drain(..) {
+                        call.cancel();
+                    }
+                    return Poll::Ready(Ok(resp));
+                }
+                Poll::Ready(Err(e)) => {
+                    calls.swap_remove(i);
+                    last_err = Some(e);
+                }
+            }
+        }
+        if calls.is_empty() {
+            return Poll::Ready(Err(last_err.take().unwrap()));
+        }
+        Poll::Pending
+    })
+}
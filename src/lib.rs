@@ -9,11 +9,30 @@ framework that puts mobile and HTTP/2 first. grpcio is built on [gRPC Core] and
 [gRPC]: https://grpc.io/
 [gRPC Core]: https://github.com/grpc/grpc
 [futures-rs]: https://github.com/rust-lang/futures-rs
+[tower]: https://github.com/tower-rs/tower
+[serde_json]: https://github.com/serde-rs/json
+[`Channel::keepalive_stats`]: ./struct.Channel.html#method.keepalive_stats
+[`Channel::connection_events`]: ./struct.Channel.html#method.connection_events
+[`AuditEvent::to_json`]: ./struct.AuditEvent.html#method.to_json
+[`Metadata`]: ./struct.Metadata.html
+[`http::HeaderMap`]: https://docs.rs/http/0.2/http/header/struct.HeaderMap.html
+[rust-protobuf]: https://github.com/stepancheg/rust-protobuf
+[prost]: https://github.com/danburkert/prost
 
 ## Optional features
 
+- **`protobuf-codec`** *(enabled by default)* - Enables the codec for messages generated by
+  [rust-protobuf].
+- **`prost-codec`** - Enables the codec for messages generated by [prost].
 - **`secure`** *(enabled by default)* - Enables support for TLS encryption and some authentication
   mechanisms.
+- **`testing`** - Enables [`TestCert`] for generating certificates in tests, and [`MockServer`]
+  for stubbing services in client tests. Implies `secure`.
+- **`tower`** - Enables adapting calls and handlers to [tower] services.
+- **`json`** - Enables [`Channel::keepalive_stats`] and [`Channel::connection_events`], which
+  parse the channelz JSON of gRPC Core with [serde_json], and [`AuditEvent::to_json`].
+- **`http-header`** - Enables conversions between [`Metadata`] and [`http::HeaderMap`], values of
+  binary keys are base64 encoded.
 
 */
 
@@ -26,55 +45,85 @@ use grpcio_sys as grpc_sys;
 #[macro_use]
 extern crate log;
 
+mod audit;
 mod auth_context;
 mod buf;
 mod call;
 mod channel;
+#[cfg(feature = "json")]
+mod channelz;
 mod client;
 mod codec;
 mod cq;
 mod env;
 mod error;
+mod fan_out;
 mod log_util;
 mod metadata;
+mod metrics;
+#[cfg(feature = "testing")]
+mod mock;
 mod quota;
 #[cfg(feature = "secure")]
 mod security;
 mod server;
 mod task;
+#[cfg(feature = "tower")]
+mod tower;
 
 pub use crate::buf::GrpcSlice;
 pub use crate::call::client::{
-    CallOption, ClientCStreamReceiver, ClientCStreamSender, ClientDuplexReceiver,
-    ClientDuplexSender, ClientSStreamReceiver, ClientUnaryReceiver, StreamingCallSink,
+    CallOption, ClientCStreamReceiver, ClientCStreamSender, ClientContext, ClientDuplexReceiver,
+    ClientDuplexSender, ClientSStreamReceiver, ClientUnaryReceiver, Response, StreamingCallSink,
 };
 pub use crate::call::server::{
     ClientStreamingSink, ClientStreamingSinkResult, Deadline, DuplexSink, DuplexSinkFailure,
     RequestStream, RpcContext, ServerStreamingSink, ServerStreamingSinkFailure, UnarySink,
     UnarySinkResult,
 };
-pub use crate::call::{MessageReader, Method, MethodType, RpcStatus, RpcStatusCode, WriteFlags};
+pub use crate::call::{
+    MessageReader, Method, MethodType, RpcStatus, RpcStatusCode, StatusResultExt, WriteFlags,
+};
 pub use crate::channel::{
-    Channel, ChannelBuilder, CompressionAlgorithms, CompressionLevel, ConnectivityState, LbPolicy,
-    OptTarget,
+    Channel, ChannelBuilder, ChannelPool, CompressionAlgorithms, CompressionLevel,
+    ConnectivityState, DrainResult, LbPolicy, OptTarget, ParseCompressionError,
 };
+#[cfg(feature = "json")]
+pub use crate::channel::{ConnectionEvent, ConnectionEvents, KeepaliveStats};
 pub use crate::client::Client;
 
 #[cfg(feature = "protobuf-codec")]
-pub use crate::codec::pb_codec::{de as pb_de, ser as pb_ser};
+pub use crate::codec::pb_codec::{
+    de as pb_de, de_pooled as pb_de_pooled, recycle as pb_recycle, ser as pb_ser,
+};
 #[cfg(feature = "prost-codec")]
-pub use crate::codec::pr_codec::{de as pr_de, ser as pr_ser};
+pub use crate::codec::pr_codec::{
+    de as pr_de, de_pooled as pr_de_pooled, recycle as pr_recycle, ser as pr_ser,
+};
+pub use crate::codec::slice_codec::{de as slice_de, ser as slice_ser};
 
+pub use crate::audit::AuditEvent;
 pub use crate::auth_context::{AuthContext, AuthProperty, AuthPropertyIter};
-pub use crate::codec::Marshaller;
-pub use crate::env::{EnvBuilder, Environment};
+pub use crate::codec::{
+    serialize_with, serialize_with_capacity, set_serialize_capacity, set_slice_allocator,
+    Marshaller, MessageCompressor, DEFAULT_SERIALIZE_CAPACITY,
+};
+pub use crate::env::{CqPickPolicy, CqPoller, EnvBuilder, Environment};
 pub use crate::error::{Error, Result};
-pub use crate::log_util::redirect_log;
+pub use crate::fan_out::{unary_fan_out, unary_hedge};
+pub use crate::log_util::{redirect_log, set_tracer_enabled, trace_http2_frames};
 pub use crate::metadata::{Metadata, MetadataBuilder, MetadataIter};
+pub use crate::metrics::{LatencyHistogram, MethodMetrics, ServerMetrics};
+#[cfg(feature = "testing")]
+pub use crate::mock::{MockServer, MockServerBuilder};
 pub use crate::quota::ResourceQuota;
+#[cfg(feature = "testing")]
+pub use crate::security::TestCert;
 #[cfg(feature = "secure")]
 pub use crate::security::{
-    CertificateRequestType, ChannelCredentials, ChannelCredentialsBuilder, ServerCredentials,
-    ServerCredentialsBuilder, ServerCredentialsFetcher,
+    CertificateRequestType, ChannelCredentials, ChannelCredentialsBuilder, Pkcs12Identity,
+    ServerCredentials, ServerCredentialsBuilder, ServerCredentialsFetcher,
 };
 pub use crate::server::{Server, ServerBuilder, Service, ServiceBuilder, ShutdownFuture};
+#[cfg(feature = "tower")]
+pub use crate::tower::UnaryService;
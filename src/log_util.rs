@@ -1,8 +1,9 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 
 use crate::grpc_sys::{self, gpr_log_func_args, gpr_log_severity};
+use libc::c_int;
 use log::{self, Level, LevelFilter, Record};
 
 #[inline]
@@ -55,3 +56,28 @@ pub fn redirect_log() {
         grpc_sys::gpr_set_log_function(Some(delegate));
     }
 }
+
+/// Enable or disable a tracer of gRPC Core by name, for example `api` or `http`.
+///
+/// It's the same as listing the tracer in the `GRPC_TRACE` environment variable, but
+/// can be changed at runtime. Traces are logged at info level. Returns false if the
+/// tracer is unknown.
+pub fn set_tracer_enabled(name: &str, enabled: bool) -> bool {
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    unsafe { grpc_sys::grpc_tracer_set_enabled(name.as_ptr(), enabled as c_int) != 0 }
+}
+
+/// Log the HTTP/2 frames and headers sent and received by all transports via `log`.
+///
+/// It enables the `http` tracer of gRPC Core and redirects the log of gRPC Core, so
+/// the max level of `log` should be at least `Info` before calling it. It's verbose
+/// and slows down every call, so it should only be enabled while debugging.
+pub fn trace_http2_frames(enabled: bool) {
+    if enabled {
+        redirect_log();
+    }
+    set_tracer_enabled("http", enabled);
+}
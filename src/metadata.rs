@@ -3,7 +3,7 @@
 use crate::grpc_sys::{self, grpc_metadata, grpc_metadata_array};
 use std::borrow::Cow;
 use std::mem::ManuallyDrop;
-use std::{mem, slice, str};
+use std::{cmp, mem, slice, str};
 
 use crate::error::{Error, Result};
 
@@ -48,6 +48,39 @@ fn normalize_key(key: &str, binary: bool) -> Result<Cow<'_, str>> {
     Ok(key)
 }
 
+/// Get the key of the header carrying the binary context named `name`.
+///
+/// The name follows the rules of non-binary keys, and names starting with `grpc-`
+/// are reserved.
+pub(crate) fn binary_context_key(name: &str) -> Result<String> {
+    let name = normalize_key(name, false)?;
+    if name.starts_with("grpc-") {
+        return Err(Error::InvalidMetadata(format!(
+            "binary context name {:?} is reserved",
+            name
+        )));
+    }
+    Ok(format!("{}-bin", name))
+}
+
+const ENCODING_VERSION: u8 = 0;
+
+fn read_bytes<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(Error::InvalidMetadata(
+            "encoded metadata is truncated".to_owned(),
+        ));
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(bytes)
+}
+
+fn read_u32(buf: &mut &[u8]) -> Result<u32> {
+    let bytes = read_bytes(buf, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
 /// Builder for immutable Metadata.
 pub struct MetadataBuilder {
     arr: Metadata,
@@ -86,7 +119,7 @@ impl MetadataBuilder {
         self.add_metadata(&key, value.as_bytes())
     }
 
-    fn add_metadata(&mut self, key: &str, value: &[u8]) -> Result<&mut MetadataBuilder> {
+    pub(crate) fn add_metadata(&mut self, key: &str, value: &[u8]) -> Result<&mut MetadataBuilder> {
         unsafe {
             grpc_sys::grpcwrap_metadata_array_add(
                 &mut self.arr.0,
@@ -186,6 +219,91 @@ impl Metadata {
         }
     }
 
+    /// Merge `overrides` into the entries, entries of a key in `overrides` replace
+    /// all the entries of the key.
+    pub(crate) fn merge(&self, overrides: &Metadata) -> Metadata {
+        let mut builder = MetadataBuilder::with_capacity(self.len() + overrides.len());
+        for (key, value) in self {
+            if overrides.iter().all(|(k, _)| k != key) {
+                builder.add_metadata(key, value).unwrap();
+            }
+        }
+        for (key, value) in overrides {
+            builder.add_metadata(key, value).unwrap();
+        }
+        builder.build()
+    }
+
+    /// Encode the entries into a portable byte format, so they can be stored and
+    /// restored by [`decode`] later, for example when requests are queued.
+    ///
+    /// The format starts with a version byte, followed by the number of entries
+    /// and then each key and value, all prefixed by their lengths as big-endian
+    /// `u32`. Values of binary keys are kept as raw bytes, and the order of
+    /// entries, including multiple values of a key, is preserved.
+    ///
+    /// [`decode`]: #method.decode
+    pub fn encode(&self) -> Vec<u8> {
+        let size = self
+            .iter()
+            .fold(5, |size, (k, v)| size + 8 + k.len() + v.len());
+        let mut buf = Vec::with_capacity(size);
+        buf.push(ENCODING_VERSION);
+        buf.extend_from_slice(&(self.len() as u32).to_be_bytes());
+        for (key, value) in self {
+            buf.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            buf.extend_from_slice(key.as_bytes());
+            buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            buf.extend_from_slice(value);
+        }
+        buf
+    }
+
+    /// Decode the entries encoded by [`encode`].
+    ///
+    /// Entries are validated the same way as they are added by [`MetadataBuilder`],
+    /// so `-bin` keys accept any value while others only accept printable ASCII.
+    ///
+    /// [`encode`]: #method.encode
+    /// [`MetadataBuilder`]: ./struct.MetadataBuilder.html
+    pub fn decode(mut buf: &[u8]) -> Result<Metadata> {
+        match read_bytes(&mut buf, 1)? {
+            [ENCODING_VERSION] => {}
+            [v] => {
+                return Err(Error::InvalidMetadata(format!(
+                    "unsupported encoding version {}",
+                    v
+                )))
+            }
+            _ => unreachable!(),
+        }
+        let count = read_u32(&mut buf)? as usize;
+        // Don't trust the count for allocation, every entry takes at least 8 bytes.
+        let mut builder = MetadataBuilder::with_capacity(cmp::min(count, buf.len() / 8));
+        for _ in 0..count {
+            let len = read_u32(&mut buf)? as usize;
+            let key = str::from_utf8(read_bytes(&mut buf, len)?)
+                .map_err(|_| Error::InvalidMetadata("key is not utf8".to_owned()))?;
+            let len = read_u32(&mut buf)? as usize;
+            let value = read_bytes(&mut buf, len)?;
+            if key.ends_with("-bin") {
+                builder.add_bytes(key, value)?;
+            } else {
+                let value = str::from_utf8(value).map_err(|_| {
+                    Error::InvalidMetadata(format!("value of {} is not ascii", key))
+                })?;
+                builder.add_str(key, value)?;
+            }
+        }
+        if !buf.is_empty() {
+            return Err(Error::InvalidMetadata(format!(
+                "{} trailing bytes after metadata",
+                buf.len()
+            )));
+        }
+        Ok(builder.build())
+    }
+
     /// Decomposes a Metadata array into its raw components.
     ///
     /// Returns the raw pointer to the underlying data, the length of the vector (in elements),
@@ -217,13 +335,14 @@ impl Metadata {
 }
 
 impl Clone for Metadata {
+    /// Entries are shared with the original instead of being copied, so cloning is
+    /// cheap even if values are large.
     fn clone(&self) -> Metadata {
-        let mut builder = MetadataBuilder::with_capacity(self.len());
-        for (k, v) in self.iter() {
-            // use `add_metadata` to skip validation.
-            builder.add_metadata(k, v).unwrap();
+        unsafe {
+            let mut arr = mem::MaybeUninit::uninit();
+            grpc_sys::grpcwrap_metadata_array_copy(arr.as_mut_ptr(), &self.0);
+            Metadata(arr.assume_init())
         }
-        builder.build()
     }
 }
 
@@ -236,6 +355,8 @@ impl Drop for Metadata {
 }
 
 unsafe impl Send for Metadata {}
+// Entries can't be modified through a shared reference.
+unsafe impl Sync for Metadata {}
 
 /// Immutable metadata iterator
 ///
@@ -274,6 +395,87 @@ impl<'a> IntoIterator for &'a Metadata {
     }
 }
 
+#[cfg(feature = "http-header")]
+mod http_header {
+    use std::convert::TryFrom;
+
+    use http::header::{HeaderMap, HeaderName, HeaderValue};
+
+    use super::{Metadata, MetadataBuilder};
+    use crate::error::{Error, Result};
+
+    impl From<&Metadata> for HeaderMap {
+        /// Values of binary keys are base64 encoded as they are on the wire.
+        fn from(metadata: &Metadata) -> HeaderMap {
+            let mut map = HeaderMap::with_capacity(metadata.len());
+            for (key, value) in metadata {
+                let name = match HeaderName::from_bytes(key.as_bytes()) {
+                    Ok(name) => name,
+                    // gRPC Core only accepts valid header names, so it should not happen.
+                    Err(_) => continue,
+                };
+                let value = if key.ends_with("-bin") {
+                    HeaderValue::from_str(&base64::encode_config(value, base64::STANDARD_NO_PAD))
+                } else {
+                    HeaderValue::from_bytes(value)
+                };
+                if let Ok(value) = value {
+                    map.append(name, value);
+                }
+            }
+            map
+        }
+    }
+
+    impl From<Metadata> for HeaderMap {
+        fn from(metadata: Metadata) -> HeaderMap {
+            HeaderMap::from(&metadata)
+        }
+    }
+
+    impl TryFrom<&HeaderMap> for MetadataBuilder {
+        type Error = Error;
+
+        /// Values of binary keys are expected to be base64 encoded, both padded and
+        /// unpadded values are accepted.
+        fn try_from(map: &HeaderMap) -> Result<MetadataBuilder> {
+            let mut builder = MetadataBuilder::with_capacity(map.len());
+            for (name, value) in map {
+                let key = name.as_str();
+                if key.ends_with("-bin") {
+                    let encoded = value.as_bytes();
+                    let end = encoded
+                        .iter()
+                        .rposition(|b| *b != b'=')
+                        .map_or(0, |pos| pos + 1);
+                    let decoded = base64::decode_config(&encoded[..end], base64::STANDARD_NO_PAD)
+                        .map_err(|e| {
+                        Error::InvalidMetadata(format!(
+                            "value of {} is not valid base64: {}",
+                            key, e
+                        ))
+                    })?;
+                    builder.add_bytes(key, &decoded)?;
+                } else {
+                    let value = value.to_str().map_err(|_| {
+                        Error::InvalidMetadata(format!("value of {} is not ascii", key))
+                    })?;
+                    builder.add_str(key, value)?;
+                }
+            }
+            Ok(builder)
+        }
+    }
+
+    impl TryFrom<HeaderMap> for MetadataBuilder {
+        type Error = Error;
+
+        fn try_from(map: HeaderMap) -> Result<MetadataBuilder> {
+            MetadataBuilder::try_from(&map)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -349,4 +551,111 @@ mod tests {
         assert!(empty_metadata.is_empty());
         assert_eq!(empty_metadata.len(), 0);
     }
+
+    #[test]
+    fn test_merge() {
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("a", "1").unwrap();
+        builder.add_str("b", "2").unwrap();
+        builder.add_str("b", "3").unwrap();
+        let base = builder.build();
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("b", "4").unwrap();
+        builder.add_str("c", "5").unwrap();
+        let overrides = builder.build();
+        let merged = base.merge(&overrides);
+        let entries: Vec<_> = merged.iter().collect();
+        assert_eq!(
+            entries,
+            [("a", &b"1"[..]), ("b", &b"4"[..]), ("c", &b"5"[..])]
+        );
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("x-trace-id", "abc").unwrap();
+        builder.add_bytes("x-token-bin", &[0, 1, 254, 255]).unwrap();
+        builder.add_str("x-tag", "a").unwrap();
+        builder.add_str("x-tag", "b").unwrap();
+        builder.add_bytes("x-empty-bin", b"").unwrap();
+        let metadata = builder.build();
+        let buf = metadata.encode();
+        let decoded = Metadata::decode(&buf).unwrap();
+        let entries: Vec<_> = decoded.iter().collect();
+        assert_eq!(entries, metadata.iter().collect::<Vec<_>>());
+        assert_eq!(
+            entries,
+            vec![
+                ("x-trace-id", &b"abc"[..]),
+                ("x-token-bin", &[0, 1, 254, 255][..]),
+                ("x-tag", &b"a"[..]),
+                ("x-tag", &b"b"[..]),
+                ("x-empty-bin", &b""[..]),
+            ]
+        );
+
+        let empty = MetadataBuilder::new().build();
+        assert!(Metadata::decode(&empty.encode()).unwrap().is_empty());
+
+        // Truncated, trailing garbage and unknown versions are rejected.
+        for i in 0..buf.len() {
+            assert!(Metadata::decode(&buf[..i]).is_err());
+        }
+        let mut longer = buf.clone();
+        longer.push(0);
+        assert!(Metadata::decode(&longer).is_err());
+        let mut versioned = buf.clone();
+        versioned[0] = 1;
+        assert!(Metadata::decode(&versioned).is_err());
+        // Values are validated like they are added by the builder.
+        let mut builder = MetadataBuilder::new();
+        builder.add_metadata("x-tag", b"\n").unwrap();
+        assert!(Metadata::decode(&builder.build().encode()).is_err());
+    }
+
+    #[cfg(feature = "http-header")]
+    #[test]
+    fn test_http_header_map() {
+        use http::header::{HeaderMap, HeaderValue};
+        use std::convert::TryFrom;
+
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("x-trace-id", "abc").unwrap();
+        builder.add_bytes("x-token-bin", &[0, 1, 254, 255]).unwrap();
+        let map = HeaderMap::from(builder.build());
+        assert_eq!(map.get("x-trace-id").unwrap(), "abc");
+        assert_eq!(map.get("x-token-bin").unwrap(), "AAH+/w");
+
+        let metadata = MetadataBuilder::try_from(&map).unwrap().build();
+        let entries: Vec<_> = metadata.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("x-trace-id", &b"abc"[..]),
+                ("x-token-bin", &[0, 1, 254, 255][..])
+            ]
+        );
+
+        // Padded values are accepted too.
+        let mut map = HeaderMap::new();
+        map.insert("x-token-bin", HeaderValue::from_static("AAH+/w=="));
+        let metadata = MetadataBuilder::try_from(map).unwrap().build();
+        assert_eq!(
+            metadata.get(0),
+            Some(("x-token-bin", &[0, 1, 254, 255][..]))
+        );
+
+        let mut map = HeaderMap::new();
+        map.insert("x-token-bin", HeaderValue::from_static("not base64!"));
+        assert!(MetadataBuilder::try_from(map).is_err());
+
+        // Valid in HTTP but not in gRPC.
+        let mut map = HeaderMap::new();
+        map.insert("x~key", HeaderValue::from_static("value"));
+        assert!(matches!(
+            MetadataBuilder::try_from(map),
+            Err(Error::InvalidMetadata(_))
+        ));
+    }
 }
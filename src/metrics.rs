@@ -0,0 +1,313 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A built-in collector of basic server side metrics.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::audit::{AuditEvent, AuditHook};
+use crate::call::RpcStatusCode;
+
+/// Upper bounds of the latency buckets in milliseconds, the last bucket is unbounded.
+const LATENCY_BOUNDS_MS: [u64; 13] = [
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000,
+];
+
+const LATENCY_BUCKETS: usize = LATENCY_BOUNDS_MS.len() + 1;
+
+/// The number of status codes defined by gRPC, from `OK` to `UNAUTHENTICATED`.
+const KNOWN_CODES: usize = 17;
+
+/// A histogram of call latencies.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyHistogram {
+    count: u64,
+    sum: Duration,
+    max: Duration,
+    buckets: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    /// The number of observed calls.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The total latency of observed calls.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// The largest observed latency.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// The average latency, or `None` if no call is observed.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            (self.sum.as_nanos() / u128::from(self.count)) as u64,
+        ))
+    }
+
+    /// Get the number of calls in each bucket along with the exclusive upper bound of
+    /// the bucket. The bound of the last bucket is `None`, which means unbounded.
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        let bounds = LATENCY_BOUNDS_MS
+            .iter()
+            .map(|b| Some(Duration::from_millis(*b)))
+            .chain(Some(None));
+        bounds
+            .enumerate()
+            .map(|(i, b)| (b, self.buckets.get(i).cloned().unwrap_or(0)))
+            .collect()
+    }
+}
+
+/// Metrics of a method.
+#[derive(Clone, Debug, Default)]
+pub struct MethodMetrics {
+    calls: u64,
+    errors: HashMap<RpcStatusCode, u64>,
+    latency: LatencyHistogram,
+}
+
+impl MethodMetrics {
+    /// The number of finished calls.
+    pub fn calls(&self) -> u64 {
+        self.calls
+    }
+
+    /// The number of calls finished with a status other than `OK`.
+    pub fn errors(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// The number of calls finished with `code`.
+    pub fn errors_of(&self, code: RpcStatusCode) -> u64 {
+        self.errors.get(&code).cloned().unwrap_or(0)
+    }
+
+    /// The number of failed calls by status code.
+    pub fn errors_by_code(&self) -> &HashMap<RpcStatusCode, u64> {
+        &self.errors
+    }
+
+    /// The latency histogram of all calls, including failed ones.
+    pub fn latency(&self) -> &LatencyHistogram {
+        &self.latency
+    }
+}
+
+/// Collects per-method call counts, error counts by status code and latencies
+/// of a server.
+///
+/// Register it by [`ServerBuilder::metrics`] and take a [`snapshot`] when it's
+/// scraped. A call is recorded when it finishes, including calls rejected before
+/// reaching the handler, like probes or calls from denied peers. Its latency is
+/// measured from the time the call is dispatched or rejected to the time the call
+/// is released after the status is sent. Calls that finish without a status, like
+/// being dropped by the handler or cancelled by the client, are recorded as
+/// `CANCELLED`, and status codes not defined by gRPC as `UNKNOWN`. Calls to methods
+/// that are not registered are not recorded.
+///
+/// [`ServerBuilder::metrics`]: ./struct.ServerBuilder.html#method.metrics
+/// [`snapshot`]: #method.snapshot
+#[derive(Default)]
+pub struct ServerMetrics {
+    // Only locked when a server is built or a snapshot is taken, calls update
+    // the counters of their methods directly.
+    methods: Mutex<HashMap<&'static str, Arc<MethodCounters>>>,
+}
+
+impl ServerMetrics {
+    /// Create an empty collector.
+    pub fn new() -> ServerMetrics {
+        ServerMetrics::default()
+    }
+
+    /// Get the metrics of all the methods that have finished calls, keyed by the full
+    /// method name like `/helloworld.Greeter/SayHello`.
+    pub fn snapshot(&self) -> HashMap<String, MethodMetrics> {
+        self.methods
+            .lock()
+            .iter()
+            .map(|(method, counters)| (method.to_string(), counters.snapshot()))
+            .filter(|(_, m)| m.calls > 0)
+            .collect()
+    }
+
+    /// Get the counters of a method, which are shared by all servers using the
+    /// collector.
+    pub(crate) fn register(&self, method: &'static str) -> Arc<MethodCounters> {
+        self.methods.lock().entry(method).or_default().clone()
+    }
+}
+
+/// Counters of the finished calls of a method.
+#[derive(Default)]
+pub(crate) struct MethodCounters {
+    // Calls finished with a status code, indexed by the code.
+    codes: [AtomicU64; KNOWN_CODES],
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS],
+    latency_sum_ns: AtomicU64,
+    latency_max_ns: AtomicU64,
+}
+
+impl MethodCounters {
+    fn record(&self, code: RpcStatusCode, latency: Duration) {
+        let code = if code.is_known() {
+            code
+        } else {
+            RpcStatusCode::UNKNOWN
+        };
+        let code: i32 = code.into();
+        self.codes[code as usize].fetch_add(1, Ordering::Relaxed);
+        let ms = latency.as_millis();
+        let pos = LATENCY_BOUNDS_MS
+            .iter()
+            .position(|b| ms < u128::from(*b))
+            .unwrap_or(LATENCY_BOUNDS_MS.len());
+        self.latency_buckets[pos].fetch_add(1, Ordering::Relaxed);
+        let ns = latency.as_nanos() as u64;
+        self.latency_sum_ns.fetch_add(ns, Ordering::Relaxed);
+        self.latency_max_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    // Counters are read one by one, a snapshot taken while calls finish may be
+    // off by those calls.
+    fn snapshot(&self) -> MethodMetrics {
+        let mut errors = HashMap::new();
+        let mut calls = 0;
+        for (code, count) in self.codes.iter().enumerate() {
+            let count = count.load(Ordering::Relaxed);
+            calls += count;
+            if code != 0 && count > 0 {
+                errors.insert(RpcStatusCode::from(code as i32), count);
+            }
+        }
+        let buckets: Vec<_> = self
+            .latency_buckets
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .collect();
+        MethodMetrics {
+            calls,
+            errors,
+            latency: LatencyHistogram {
+                count: buckets.iter().sum(),
+                sum: Duration::from_nanos(self.latency_sum_ns.load(Ordering::Relaxed)),
+                max: Duration::from_nanos(self.latency_max_ns.load(Ordering::Relaxed)),
+                buckets,
+            },
+        }
+    }
+}
+
+/// Observers notified when calls finish, shared by the calls of a method.
+#[derive(Clone, Default)]
+pub(crate) struct CallObservers {
+    pub metrics: Option<Arc<MethodCounters>>,
+    pub audit: Option<AuditHook>,
+}
+
+impl CallObservers {
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_none() && self.audit.is_none()
+    }
+}
+
+/// Records a call to the observers when dropped.
+pub(crate) struct CallRecorder {
+    metrics: Option<Arc<MethodCounters>>,
+    // The hook and the event to report, whose status and latency are filled on drop.
+    audit: Option<(AuditHook, AuditEvent)>,
+    start: Instant,
+    code: Option<RpcStatusCode>,
+}
+
+impl CallRecorder {
+    pub fn new(
+        metrics: Option<Arc<MethodCounters>>,
+        audit: Option<(AuditHook, AuditEvent)>,
+        start: Instant,
+    ) -> CallRecorder {
+        CallRecorder {
+            metrics,
+            audit,
+            start,
+            code: None,
+        }
+    }
+
+    /// Set the status the call is finished with.
+    pub fn set_status(&mut self, code: RpcStatusCode) {
+        self.code = Some(code);
+    }
+}
+
+impl Drop for CallRecorder {
+    fn drop(&mut self) {
+        let code = self.code.unwrap_or(RpcStatusCode::CANCELLED);
+        let latency = self.start.elapsed();
+        if let Some(m) = &self.metrics {
+            m.record(code, latency);
+        }
+        if let Some((hook, mut event)) = self.audit.take() {
+            event.status = code;
+            event.latency = latency;
+            hook(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram() {
+        let counters = MethodCounters::default();
+        let h = counters.snapshot().latency;
+        assert_eq!(h.mean(), None);
+        assert!(h.buckets().iter().all(|(_, c)| *c == 0));
+        for ms in &[0, 1, 3, 40, 20_000] {
+            counters.record(RpcStatusCode::OK, Duration::from_millis(*ms));
+        }
+        let h = counters.snapshot().latency;
+        assert_eq!(h.count(), 5);
+        assert_eq!(h.max(), Duration::from_millis(20_000));
+        assert_eq!(h.mean(), Some(Duration::from_micros(4_008_800)));
+        let buckets = h.buckets();
+        assert_eq!(buckets.len(), LATENCY_BOUNDS_MS.len() + 1);
+        assert_eq!(buckets[0], (Some(Duration::from_millis(1)), 1));
+        assert_eq!(buckets[1], (Some(Duration::from_millis(2)), 1));
+        assert_eq!(buckets[2], (Some(Duration::from_millis(5)), 1));
+        assert_eq!(buckets[5], (Some(Duration::from_millis(50)), 1));
+        assert_eq!(*buckets.last().unwrap(), (None, 1));
+    }
+
+    #[test]
+    fn test_method_counters() {
+        let counters = MethodCounters::default();
+        let latency = Duration::from_millis(1);
+        counters.record(RpcStatusCode::OK, latency);
+        counters.record(RpcStatusCode::CANCELLED, latency);
+        counters.record(RpcStatusCode::UNKNOWN, latency);
+        counters.record(RpcStatusCode::from(100), latency);
+        let m = counters.snapshot();
+        assert_eq!(m.calls(), 4);
+        assert_eq!(m.errors(), 3);
+        assert_eq!(m.errors_of(RpcStatusCode::CANCELLED), 1);
+        assert_eq!(m.errors_of(RpcStatusCode::UNKNOWN), 2);
+        assert_eq!(m.errors_by_code().len(), 2);
+        assert_eq!(m.latency().count(), 4);
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::future::Future;
+#[cfg(unix)]
+use std::io;
+use std::result;
+use std::sync::Arc;
+
+use futures::stream::{self, StreamExt};
+use futures::SinkExt;
+
+use crate::call::server::{RpcContext, ServerStreamingSink, UnarySink};
+use crate::call::{Method, RpcStatus, WriteFlags};
+use crate::channel::{Channel, ChannelBuilder};
+use crate::env::Environment;
+use crate::error::Result;
+use crate::server::{Server, ServerBuilder, ServiceBuilder};
+
+/// A builder for [`MockServer`].
+///
+/// Every stubbed method is handled by a closure, methods that are not stubbed
+/// return `UNIMPLEMENTED`.
+pub struct MockServerBuilder {
+    env: Arc<Environment>,
+    service: ServiceBuilder,
+}
+
+impl MockServerBuilder {
+    /// Initialize a new [`MockServerBuilder`].
+    pub fn new(env: Arc<Environment>) -> MockServerBuilder {
+        MockServerBuilder {
+            env,
+            service: ServiceBuilder::new(),
+        }
+    }
+
+    /// Stub a unary method.
+    ///
+    /// `f` is called with every request, the returned future resolves to the
+    /// response or the status to fail the call with. Delays can be simulated by
+    /// waiting in the future before resolving it.
+    pub fn unary<Req, Resp, F, Fut>(mut self, method: &Method<Req, Resp>, f: F) -> MockServerBuilder
+    where
+        Req: 'static,
+        Resp: Send + 'static,
+        F: Fn(Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<Resp, RpcStatus>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.service = self.service.add_unary_handler(
+            method,
+            move |ctx: RpcContext<'_>, req: Req, sink: UnarySink<Resp>| {
+                let resp = f(req);
+                ctx.spawn(async move {
+                    let res = match resp.await {
+                        Ok(resp) => sink.success(resp).await,
+                        Err(status) => sink.fail(status).await,
+                    };
+                    if let Err(e) = res {
+                        debug!("mock server failed to reply: {:?}", e);
+                    }
+                });
+            },
+        );
+        self
+    }
+
+    /// Stub a server streaming method.
+    ///
+    /// `f` is called with every request, the returned future resolves to all
+    /// the responses or the status to fail the call with.
+    pub fn server_streaming<Req, Resp, F, Fut>(
+        mut self,
+        method: &Method<Req, Resp>,
+        f: F,
+    ) -> MockServerBuilder
+    where
+        Req: 'static,
+        Resp: Send + 'static,
+        F: Fn(Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<Vec<Resp>, RpcStatus>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.service = self.service.add_server_streaming_handler(
+            method,
+            move |ctx: RpcContext<'_>, req: Req, mut sink: ServerStreamingSink<Resp>| {
+                let resps = f(req);
+                ctx.spawn(async move {
+                    let res = match resps.await {
+                        Ok(resps) => {
+                            let mut resps =
+                                stream::iter(resps).map(|r| Ok((r, WriteFlags::default())));
+                            match sink.send_all(&mut resps).await {
+                                Ok(()) => sink.close().await,
+                                Err(e) => Err(e),
+                            }
+                        }
+                        Err(status) => sink.fail(status).await,
+                    };
+                    if let Err(e) = res {
+                        debug!("mock server failed to reply: {:?}", e);
+                    }
+                });
+            },
+        );
+        self
+    }
+
+    /// Start the mock server.
+    pub fn build(self) -> Result<MockServer> {
+        let mut server =
+            ServerBuilder::new(self.env.clone()).register_service(self.service.build());
+        if !cfg!(unix) {
+            server = server.bind("127.0.0.1", 0);
+        }
+        let mut server = server.build()?;
+        server.start();
+        let channel = connect(&server, ChannelBuilder::new(self.env));
+        Ok(MockServer { server, channel })
+    }
+}
+
+// Connect to the server in process by a socket pair, so no port is taken.
+#[cfg(unix)]
+fn connect(server: &Server, builder: ChannelBuilder) -> Channel {
+    let mut fds = [0; 2];
+    let ret = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(ret, 0, "socketpair: {}", io::Error::last_os_error());
+    unsafe {
+        server.add_insecure_channel_from_fd(fds[0]);
+        builder.connect_from_fd("mock", fds[1])
+    }
+}
+
+// gRPC Core only takes over connections from file descriptors on Unix.
+#[cfg(not(unix))]
+fn connect(server: &Server, builder: ChannelBuilder) -> Channel {
+    let port = server.bind_addrs().next().unwrap().1;
+    builder.connect(&format!("127.0.0.1:{}", port))
+}
+
+/// A server with stubbed methods for testing clients.
+///
+/// On Unix, the server and its channel are connected in process by a socket
+/// pair, so no port is taken. gRPC Core can't take over a connection elsewhere,
+/// so the server listens on a random port of the loopback interface instead.
+/// The server stops when it's dropped.
+///
+/// # Example
+///
+/// ```ignore
+/// let server = MockServerBuilder::new(env)
+///     .unary(&METHOD_GREETER_SAY_HELLO, |_| {
+///         future::err(RpcStatus::new(RpcStatusCode::UNAVAILABLE, None))
+///     })
+///     .build()
+///     .unwrap();
+/// let client = GreeterClient::new(server.channel());
+/// ```
+pub struct MockServer {
+    server: Server,
+    channel: Channel,
+}
+
+impl MockServer {
+    /// Get a channel connected to the server.
+    pub fn channel(&self) -> Channel {
+        self.channel.clone()
+    }
+
+    /// Get the underlying server.
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+}
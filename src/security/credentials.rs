@@ -2,13 +2,15 @@
 
 use std::error::Error as StdError;
 use std::ffi::CString;
+use std::time::Duration;
 use std::{mem, ptr};
 
+use crate::buf::GrpcSlice;
 use crate::error::{Error, Result};
 use crate::grpc_sys::grpc_ssl_certificate_config_reload_status::{self, *};
 use crate::grpc_sys::grpc_ssl_client_certificate_request_type::*;
 use crate::grpc_sys::{
-    self, grpc_channel_credentials, grpc_server_credentials,
+    self, grpc_call_credentials, grpc_channel_credentials, grpc_server_credentials,
     grpc_ssl_client_certificate_request_type, grpc_ssl_server_certificate_config,
 };
 
@@ -120,6 +122,62 @@ pub(crate) unsafe extern "C" fn server_cert_fetcher_wrapper(
     GRPC_SSL_CERTIFICATE_CONFIG_RELOAD_NEW
 }
 
+/// A PEM encoded certificate chain and private key loaded from a PKCS#12
+/// (`.p12`/`.pfx`) bundle.
+///
+/// The private key is zeroed when it's dropped.
+pub struct Pkcs12Identity {
+    cert_chain: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+impl Pkcs12Identity {
+    /// Decode a DER encoded PKCS#12 bundle protected by `passphrase`.
+    ///
+    /// The certificate chain starts with the certificate of the private key, and
+    /// is followed by the other certificates in the bundle.
+    pub fn from_der(der: &[u8], passphrase: &str) -> Result<Pkcs12Identity> {
+        let passphrase = CString::new(passphrase)
+            .map_err(|_| Error::InvalidCredentials("nul byte in passphrase".to_owned()))?;
+        let mut cert_chain = GrpcSlice::default();
+        let mut private_key = GrpcSlice::default();
+        let ok = unsafe {
+            grpc_sys::grpcwrap_pkcs12_to_pem(
+                der.as_ptr() as _,
+                der.len(),
+                passphrase.as_ptr(),
+                cert_chain.as_mut_ptr(),
+                private_key.as_mut_ptr(),
+            )
+        };
+        if ok == 0 {
+            return Err(Error::InvalidCredentials(
+                "malformed PKCS#12 bundle or wrong passphrase".to_owned(),
+            ));
+        }
+        Ok(Pkcs12Identity {
+            cert_chain: cert_chain.as_slice().to_vec(),
+            private_key: private_key.as_slice().to_vec(),
+        })
+    }
+
+    /// Get the PEM encoded certificate chain.
+    pub fn cert_chain(&self) -> &[u8] {
+        &self.cert_chain
+    }
+
+    /// Get the PEM encoded private key.
+    pub fn private_key(&self) -> &[u8] {
+        &self.private_key
+    }
+}
+
+impl Drop for Pkcs12Identity {
+    fn drop(&mut self) {
+        clear_key_securely(&mut self.private_key);
+    }
+}
+
 /// [`ServerCredentials`] factory in order to configure the properties.
 pub struct ServerCredentialsBuilder {
     root: Option<CString>,
@@ -165,6 +223,13 @@ impl ServerCredentialsBuilder {
         self
     }
 
+    /// Add a server side certificate and key loaded from a DER encoded PKCS#12
+    /// bundle, see [`Pkcs12Identity::from_der`].
+    pub fn add_pkcs12(self, der: &[u8], passphrase: &str) -> Result<ServerCredentialsBuilder> {
+        let identity = Pkcs12Identity::from_der(der, passphrase)?;
+        Ok(self.add_cert(identity.cert_chain.clone(), identity.private_key.clone()))
+    }
+
     /// Finalize the [`ServerCredentialsBuilder`] and build the
     /// [`*mut grpcio_sys::bindings::grpc_ssl_server_certificate_config`].
     unsafe fn build_config(mut self) -> *mut grpcio_sys::grpc_ssl_server_certificate_config {
@@ -272,6 +337,13 @@ impl ChannelCredentialsBuilder {
         self
     }
 
+    /// Set the client side certificate and key loaded from a DER encoded PKCS#12
+    /// bundle, see [`Pkcs12Identity::from_der`].
+    pub fn pkcs12(self, der: &[u8], passphrase: &str) -> Result<ChannelCredentialsBuilder> {
+        let identity = Pkcs12Identity::from_der(der, passphrase)?;
+        Ok(self.cert(identity.cert_chain.clone(), identity.private_key.clone()))
+    }
+
     /// Finalize the [`ChannelCredentialsBuilder`] and build the [`ChannelCredentials`].
     pub fn build(mut self) -> ChannelCredentials {
         let root_ptr = self
@@ -334,7 +406,20 @@ impl Drop for ChannelCredentialsBuilder {
 /// Client-side SSL credentials.
 ///
 /// Use [`ChannelCredentialsBuilder`] or [`ChannelCredentials::google_default_credentials`] to
-/// build a [`ChannelCredentials`].
+/// build a [`ChannelCredentials`]. Other constructors like
+/// [`ChannelCredentials::google_compute_engine_credentials`] cover the common credentials of
+/// Google Cloud, for example:
+///
+/// ```no_run
+/// use grpcio::{ChannelBuilder, ChannelCredentials, EnvBuilder};
+/// use std::sync::Arc;
+///
+/// let env = Arc::new(EnvBuilder::new().build());
+/// // Authenticate as the service account of the VM via the GCE metadata server.
+/// let creds = ChannelCredentials::google_compute_engine_credentials().unwrap();
+/// let ch = ChannelBuilder::new(env).secure_connect("pubsub.googleapis.com:443", creds);
+/// // Create a generated client of the Google API with `ch` and make calls as usual.
+/// ```
 pub struct ChannelCredentials {
     creds: *mut grpc_channel_credentials,
 }
@@ -344,6 +429,12 @@ impl ChannelCredentials {
         self.creds
     }
 
+    // gRPC Core never mutates credentials after they are created, so they can be
+    // used by many channels through shared references.
+    pub(crate) fn as_raw(&self) -> *mut grpc_channel_credentials {
+        self.creds
+    }
+
     /// Try to build a [`ChannelCredentials`] to authenticate with Google OAuth credentials.
     pub fn google_default_credentials() -> Result<ChannelCredentials> {
         // Initialize the runtime here. Because this is an associated method
@@ -359,6 +450,92 @@ impl ChannelCredentials {
             Ok(ChannelCredentials { creds })
         }
     }
+
+    /// Build a [`ChannelCredentials`] that authenticates with the access tokens fetched
+    /// from the metadata server of Google Compute Engine.
+    ///
+    /// Tokens are fetched lazily, so it succeeds even outside of GCE, in which case
+    /// calls fail with `UNAVAILABLE`.
+    pub fn google_compute_engine_credentials() -> Result<ChannelCredentials> {
+        unsafe {
+            grpc_sys::grpc_init();
+            let call_creds =
+                grpc_sys::grpc_google_compute_engine_credentials_create(ptr::null_mut());
+            ChannelCredentials::google_composite(call_creds)
+        }
+    }
+
+    /// Build a [`ChannelCredentials`] that authenticates with self-signed JWTs of a service
+    /// account.
+    ///
+    /// `json_key` is the content of the JSON key file of the service account. Every token
+    /// lives for `token_lifetime`, which is capped by gRPC Core to one hour. Returns an error
+    /// if the key is invalid.
+    pub fn service_account_jwt_access_credentials(
+        json_key: &str,
+        token_lifetime: Duration,
+    ) -> Result<ChannelCredentials> {
+        let json_key = CString::new(json_key).map_err(|_| Error::GoogleAuthenticationFailed)?;
+        unsafe {
+            grpc_sys::grpc_init();
+            let call_creds = grpc_sys::grpc_service_account_jwt_access_credentials_create(
+                json_key.as_ptr(),
+                token_lifetime.into(),
+                ptr::null_mut(),
+            );
+            ChannelCredentials::google_composite(call_creds)
+        }
+    }
+
+    /// Build a [`ChannelCredentials`] that authenticates with the OAuth2 refresh token of a
+    /// user, like the one created by `gcloud auth application-default login`.
+    ///
+    /// `json_refresh_token` is the content of the JSON file of the token. Returns an error if
+    /// the token is invalid.
+    pub fn google_refresh_token_credentials(
+        json_refresh_token: &str,
+    ) -> Result<ChannelCredentials> {
+        let token =
+            CString::new(json_refresh_token).map_err(|_| Error::GoogleAuthenticationFailed)?;
+        unsafe {
+            grpc_sys::grpc_init();
+            let call_creds = grpc_sys::grpc_google_refresh_token_credentials_create(
+                token.as_ptr(),
+                ptr::null_mut(),
+            );
+            ChannelCredentials::google_composite(call_creds)
+        }
+    }
+
+    /// Combine the call credentials with SSL credentials using the default root certificates.
+    ///
+    /// Takes the ownership of `call_creds`.
+    unsafe fn google_composite(
+        call_creds: *mut grpc_call_credentials,
+    ) -> Result<ChannelCredentials> {
+        if call_creds.is_null() {
+            return Err(Error::GoogleAuthenticationFailed);
+        }
+        let ssl_creds = grpc_sys::grpc_ssl_credentials_create(
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null_mut(),
+        );
+        let creds = grpc_sys::grpc_composite_channel_credentials_create(
+            ssl_creds,
+            call_creds,
+            ptr::null_mut(),
+        );
+        // The composite credentials hold their own references.
+        grpc_sys::grpc_channel_credentials_release(ssl_creds);
+        grpc_sys::grpc_call_credentials_release(call_creds);
+        if creds.is_null() {
+            Err(Error::GoogleAuthenticationFailed)
+        } else {
+            Ok(ChannelCredentials { creds })
+        }
+    }
 }
 
 impl Drop for ChannelCredentials {
@@ -1,10 +1,14 @@
 // Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
 
 mod credentials;
+#[cfg(feature = "testing")]
+mod testing;
 
 pub use self::credentials::{
-    CertificateRequestType, ChannelCredentials, ChannelCredentialsBuilder, ServerCredentials,
-    ServerCredentialsBuilder, ServerCredentialsFetcher,
+    CertificateRequestType, ChannelCredentials, ChannelCredentialsBuilder, Pkcs12Identity,
+    ServerCredentials, ServerCredentialsBuilder, ServerCredentialsFetcher,
 };
+#[cfg(feature = "testing")]
+pub use self::testing::TestCert;
 
 pub(crate) use self::credentials::server_cert_fetcher_wrapper;
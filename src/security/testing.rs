@@ -0,0 +1,116 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::ffi::CString;
+use std::net::IpAddr;
+use std::os::raw::c_long;
+use std::time::Duration;
+
+use super::credentials::{
+    ChannelCredentials, ChannelCredentialsBuilder, ServerCredentials, ServerCredentialsBuilder,
+};
+use crate::buf::GrpcSlice;
+use crate::error::{Error, Result};
+use crate::grpc_sys;
+
+const DEFAULT_VALIDITY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A generated certificate and private key, and the CA that signed it.
+///
+/// It's meant for tests only: the keys are generated in memory, and the CA is
+/// not trusted by anything but the credentials built from it.
+///
+/// ```ignore
+/// let cert = TestCert::generate(&["localhost", "127.0.0.1"])?;
+/// let server_creds = cert.server_credentials();
+/// let channel_creds = cert.channel_credentials();
+/// ```
+pub struct TestCert {
+    ca_cert: Vec<u8>,
+    cert: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+impl TestCert {
+    /// Generate a CA and a certificate signed by it, valid for a day.
+    ///
+    /// `names` are put into the subject alternative names of the certificate.
+    /// IP addresses are added as IP entries and anything else as DNS entries.
+    /// The first name is also used as the common name.
+    pub fn generate(names: &[&str]) -> Result<TestCert> {
+        TestCert::generate_with_validity(names, DEFAULT_VALIDITY)
+    }
+
+    /// Same as [`generate`](#method.generate), but the certificates are valid for
+    /// `validity`.
+    pub fn generate_with_validity(names: &[&str], validity: Duration) -> Result<TestCert> {
+        if names.is_empty() {
+            return Err(Error::InvalidCredentials(
+                "a certificate needs at least one name".to_owned(),
+            ));
+        }
+        let san = names
+            .iter()
+            .map(|name| match name.parse::<IpAddr>() {
+                Ok(_) => format!("IP:{}", name),
+                Err(_) => format!("DNS:{}", name),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let invalid_name = |_| Error::InvalidCredentials("nul byte in name".to_owned());
+        let common_name = CString::new(names[0]).map_err(invalid_name)?;
+        let san = CString::new(san).map_err(invalid_name)?;
+        let valid_secs = validity.as_secs().min(c_long::max_value() as u64) as c_long;
+        let mut ca_cert = GrpcSlice::default();
+        let mut cert = GrpcSlice::default();
+        let mut private_key = GrpcSlice::default();
+        let ok = unsafe {
+            grpc_sys::grpcwrap_generate_test_cert(
+                common_name.as_ptr(),
+                san.as_ptr(),
+                valid_secs,
+                ca_cert.as_mut_ptr(),
+                cert.as_mut_ptr(),
+                private_key.as_mut_ptr(),
+            )
+        };
+        if ok == 0 {
+            return Err(Error::InvalidCredentials(
+                "failed to generate certificate".to_owned(),
+            ));
+        }
+        Ok(TestCert {
+            ca_cert: ca_cert.as_slice().to_vec(),
+            cert: cert.as_slice().to_vec(),
+            private_key: private_key.as_slice().to_vec(),
+        })
+    }
+
+    /// Get the PEM encoded CA certificate.
+    pub fn ca_cert(&self) -> &[u8] {
+        &self.ca_cert
+    }
+
+    /// Get the PEM encoded certificate.
+    pub fn cert(&self) -> &[u8] {
+        &self.cert
+    }
+
+    /// Get the PEM encoded private key of the certificate.
+    pub fn private_key(&self) -> &[u8] {
+        &self.private_key
+    }
+
+    /// Build server credentials presenting the certificate.
+    pub fn server_credentials(&self) -> ServerCredentials {
+        ServerCredentialsBuilder::new()
+            .add_cert(self.cert.clone(), self.private_key.clone())
+            .build()
+    }
+
+    /// Build channel credentials trusting the CA.
+    pub fn channel_credentials(&self) -> ChannelCredentials {
+        ChannelCredentialsBuilder::new()
+            .root_cert(self.ca_cert.clone())
+            .build()
+    }
+}
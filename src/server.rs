@@ -6,23 +6,30 @@ use std::fmt::{self, Debug, Formatter};
 use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::ptr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use std::{result, str};
 
 use crate::grpc_sys::{self, grpc_call_error, grpc_server};
 use futures::future::Future;
 use futures::task::{Context, Poll};
 
+use crate::audit::{AuditEvent, AuditHook};
 use crate::call::server::*;
-use crate::call::{MessageReader, Method, MethodType};
-use crate::channel::ChannelArgs;
+use crate::call::{MessageReader, Method, MethodType, RpcStatus, RpcStatusCode};
+use crate::channel::{dur_to_ms, ChannelArgs};
+use crate::codec::MessageCompressor;
 use crate::cq::CompletionQueue;
 use crate::env::Environment;
 use crate::error::{Error, Result};
+use crate::metrics::{CallObservers, MethodCounters, ServerMetrics};
 use crate::task::{CallTag, CqFuture};
 use crate::RpcContext;
 
 const DEFAULT_REQUEST_SLOTS_PER_CQ: usize = 1024;
+// The smallest stream window of the HTTP/2 flow control of gRPC Core.
+const MIN_STREAM_WINDOW: i32 = 128;
 
 /// An RPC call holder.
 #[derive(Clone)]
@@ -195,6 +202,46 @@ impl ServiceBuilder {
         self
     }
 
+    /// Add a unary RPC call handler, and mirror a fraction of its requests to a shadow.
+    ///
+    /// It's useful to try out a new implementation with real traffic before rolling
+    /// it out. Responses are always served by `handler`. Out of every request,
+    /// `fraction` of them, which is clamped to `[0, 1]`, are also cloned and passed to
+    /// `shadow`. The future it returns is spawned to the same poll thread and its
+    /// result is discarded, errors are only logged. So the shadow never affects
+    /// the response, but it shouldn't block either. Sampling is deterministic, for
+    /// example with `0.25` every fourth request is mirrored.
+    pub fn add_unary_handler_with_shadow<Req, Resp, F, S, Fut>(
+        self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+        mut shadow: S,
+        fraction: f64,
+    ) -> ServiceBuilder
+    where
+        Req: Clone + 'static,
+        Resp: 'static,
+        F: FnMut(RpcContext<'_>, Req, UnarySink<Resp>) + Send + Clone + 'static,
+        S: FnMut(Req) -> Fut + Send + Clone + 'static,
+        Fut: Future<Output = Result<Resp>> + Send + 'static,
+    {
+        let fraction = fraction.max(0.0).min(1.0);
+        let counter = Arc::new(AtomicUsize::new(0));
+        let name = method.name;
+        self.add_unary_handler(method, move |ctx, req: Req, sink| {
+            let n = counter.fetch_add(1, Ordering::Relaxed);
+            if is_sampled(n, fraction) {
+                let f = shadow(req.clone());
+                ctx.spawn(async move {
+                    if let Err(e) = f.await {
+                        debug!("shadow of {} failed: {:?}", name, e);
+                    }
+                });
+            }
+            handler(ctx, req, sink)
+        })
+    }
+
     /// Add a client streaming RPC call handler.
     pub fn add_client_streaming_handler<Req, Resp, F>(
         mut self,
@@ -266,6 +313,12 @@ impl ServiceBuilder {
     }
 }
 
+// Whether the `n`th request is sampled, so that exactly `fraction` of any
+// `1 / fraction` consecutive requests are sampled.
+fn is_sampled(n: usize, fraction: f64) -> bool {
+    ((n + 1) as f64 * fraction) as usize > (n as f64 * fraction) as usize
+}
+
 /// A gRPC service.
 ///
 /// Use [`ServiceBuilder`] to build a [`Service`].
@@ -280,6 +333,19 @@ pub struct ServerBuilder {
     args: Option<ChannelArgs>,
     slots_per_cq: usize,
     handlers: HashMap<&'static [u8], BoxHandler>,
+    max_receive_message_len: Option<i32>,
+    max_send_message_len: Option<i32>,
+    max_decompressed_message_len: Option<usize>,
+    reuse_port: Option<bool>,
+    max_connection_age: Option<Duration>,
+    max_connection_age_grace: Option<Duration>,
+    connection_receive_buffer: Option<(i32, i32)>,
+    peer_filter: Option<PeerFilter>,
+    compressors: HashMap<String, Arc<dyn MessageCompressor>>,
+    unimplemented_message: Option<UnimplementedMessage>,
+    answer_probes: bool,
+    metrics: Option<Arc<ServerMetrics>>,
+    audit: Option<AuditHook>,
 }
 
 impl ServerBuilder {
@@ -291,6 +357,19 @@ impl ServerBuilder {
             args: None,
             slots_per_cq: DEFAULT_REQUEST_SLOTS_PER_CQ,
             handlers: HashMap::new(),
+            max_receive_message_len: None,
+            max_send_message_len: None,
+            max_decompressed_message_len: None,
+            reuse_port: None,
+            max_connection_age: None,
+            max_connection_age_grace: None,
+            connection_receive_buffer: None,
+            peer_filter: None,
+            compressors: HashMap::new(),
+            unimplemented_message: None,
+            answer_probes: true,
+            metrics: None,
+            audit: None,
         }
     }
 
@@ -308,6 +387,226 @@ impl ServerBuilder {
         self
     }
 
+    /// Set the maximum message length in bytes the server accepts from clients.
+    ///
+    /// Calls carrying a larger request are rejected with `RESOURCE_EXHAUSTED` before
+    /// reaching the handler. `-1` means unlimited. It takes precedence over the same
+    /// option in [`channel_args`].
+    ///
+    /// [`channel_args`]: #method.channel_args
+    pub fn max_receive_message_len(mut self, len: i32) -> ServerBuilder {
+        self.max_receive_message_len = Some(len);
+        self
+    }
+
+    /// Set the maximum message length in bytes the server sends to clients.
+    ///
+    /// Sending a larger response fails the sink with [`Error::RpcFailure`] of
+    /// `RESOURCE_EXHAUSTED`, and a unary or client streaming call is finished with the
+    /// same status. The status tells both the length and the limit. `-1` means
+    /// unlimited. It takes precedence over the same option in [`channel_args`], which
+    /// is checked the same way.
+    ///
+    /// [`Error::RpcFailure`]: ./enum.Error.html#variant.RpcFailure
+    /// [`channel_args`]: #method.channel_args
+    pub fn max_send_message_len(mut self, len: i32) -> ServerBuilder {
+        self.max_send_message_len = Some(len);
+        self
+    }
+
+    /// Set the maximum length in bytes of a request message after decompression.
+    ///
+    /// [`max_receive_message_len`] only limits the length on the wire, so a small
+    /// compressed request can still expand to a huge message. Once the limit is set,
+    /// requests are decompressed by the library instead of gRPC Core, and decompression
+    /// stops as soon as the limit is exceeded, so such a request never takes more
+    /// memory than the limit. It covers both the algorithms built into gRPC and
+    /// [`MessageCompressor`]s. Requests larger than the limit after decompression are
+    /// rejected with `RESOURCE_EXHAUSTED` before being deserialized or handed to the
+    /// handler.
+    ///
+    /// An explicit `GRPC_ARG_ENABLE_PER_MESSAGE_DECOMPRESSION` in the channel args is
+    /// respected. If it enables decompression by gRPC Core, requests are only checked
+    /// against the limit after being decompressed as a whole.
+    ///
+    /// Without the limit, gRPC Core decompresses requests as usual, and the output
+    /// of [`MessageCompressor`]s is limited to the receive limit.
+    ///
+    /// [`max_receive_message_len`]: #method.max_receive_message_len
+    /// [`MessageCompressor`]: ./trait.MessageCompressor.html
+    pub fn max_decompressed_message_len(mut self, len: usize) -> ServerBuilder {
+        self.max_decompressed_message_len = Some(len);
+        self
+    }
+
+    /// Set whether to bind the listening sockets with `SO_REUSEPORT`.
+    ///
+    /// When enabled, multiple servers, usually in different processes, can bind to the
+    /// same port, and the kernel spreads incoming connections among them. All of them
+    /// need to enable it. It's only supported on platforms providing `SO_REUSEPORT`, like
+    /// Linux 3.9+ and the BSDs, and is ignored elsewhere. gRPC Core enables it by default
+    /// where it's supported. It takes precedence over the same option in [`channel_args`].
+    ///
+    /// [`channel_args`]: #method.channel_args
+    pub fn reuse_port(mut self, reuse: bool) -> ServerBuilder {
+        self.reuse_port = Some(reuse);
+        self
+    }
+
+    /// Set the maximum time a connection may exist before it's gracefully closed.
+    ///
+    /// When a connection reaches the age, the server sends a GOAWAY, so the client
+    /// reconnects, re-resolving the target and rebalancing to the new backends.
+    /// gRPC C Core doesn't support re-resolving periodically on the client side, and
+    /// neither does this library, so setting an age on servers is the way to make
+    /// clients adapt to scaling of backends. A random jitter of +/-10% is applied to
+    /// avoid connection storms. Note that clients are still limited by
+    /// [`ChannelBuilder::dns_min_resolution_period`] between two resolutions. It
+    /// takes precedence over the same option in [`channel_args`].
+    ///
+    /// [`ChannelBuilder::dns_min_resolution_period`]: ./struct.ChannelBuilder.html#method.dns_min_resolution_period
+    /// [`channel_args`]: #method.channel_args
+    pub fn max_connection_age(mut self, age: Duration) -> ServerBuilder {
+        self.max_connection_age = Some(age);
+        self
+    }
+
+    /// Set the time in-flight calls are given to finish after a connection reaches
+    /// [`max_connection_age`], before the connection is forcibly closed. It takes
+    /// precedence over the same option in [`channel_args`].
+    ///
+    /// [`max_connection_age`]: #method.max_connection_age
+    /// [`channel_args`]: #method.channel_args
+    pub fn max_connection_age_grace(mut self, grace: Duration) -> ServerBuilder {
+        self.max_connection_age_grace = Some(grace);
+        self
+    }
+
+    /// Limit the total bytes of requests buffered for a connection.
+    ///
+    /// A client can open many streams on one connection and flood them with
+    /// requests that handlers don't read fast enough. By default every stream
+    /// gets its own HTTP/2 flow control window, which may also grow by BDP
+    /// probing, so the memory buffered for a connection is effectively unbounded.
+    ///
+    /// This splits `size` evenly among at most `max_streams` concurrent streams
+    /// of a connection: the per stream window is set to `size / max_streams`
+    /// and BDP probing is disabled. Once a stream has buffered its share, the
+    /// client stops sending on it until the handler reads requests, so the
+    /// buffered bytes of a connection stay under `size` no matter how many
+    /// streams flood it. Streams beyond `max_streams` wait for running ones to
+    /// finish. gRPC Core doesn't offer a connection wide window, hence the
+    /// split. It takes precedence over the same options in [`channel_args`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the per stream window is smaller than 128 bytes, the smallest
+    /// window gRPC Core's HTTP/2 flow control works with.
+    ///
+    /// [`channel_args`]: #method.channel_args
+    pub fn max_connection_receive_buffer(mut self, size: i32, max_streams: i32) -> ServerBuilder {
+        assert!(max_streams > 0, "max_streams should be positive");
+        assert!(
+            size / max_streams >= MIN_STREAM_WINDOW,
+            "size / max_streams should be at least {} bytes",
+            MIN_STREAM_WINDOW
+        );
+        self.connection_receive_buffer = Some((size, max_streams));
+        self
+    }
+
+    /// Set a filter to reject calls from disallowed peers.
+    ///
+    /// `f` is given the peer address of every call, like `ipv4:127.0.0.1:34567`,
+    /// and calls it returns false for are finished with `PERMISSION_DENIED`
+    /// before any handler runs. It can be used to implement IP allow-listing.
+    ///
+    /// Note that the check happens when a call arrives rather than when the
+    /// connection is accepted, as gRPC Core doesn't expose the connections.
+    /// Connections from disallowed peers are still established, including
+    /// TLS handshakes, but none of their calls reach the handlers.
+    pub fn peer_filter<F>(mut self, f: F) -> ServerBuilder
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.peer_filter = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the status message of calls to methods that are not registered.
+    ///
+    /// `f` is given the full path of the called method, like
+    /// `/helloworld.Greeter/SayHello`, and the message it returns is sent along
+    /// with `UNIMPLEMENTED`, for example to point clients of an old API version to
+    /// the new method. By default, no message is sent.
+    pub fn unimplemented_message<F>(mut self, f: F) -> ServerBuilder
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.unimplemented_message = Some(Arc::new(f));
+        self
+    }
+
+    /// Set whether to reply to method probes without calling the handlers.
+    ///
+    /// [`Client::probe_method`] marks its calls with the `x-method-probe` header.
+    /// When enabled, which is the default, probes to registered methods are finished
+    /// with `OK` right away, so they have no side effects. When disabled, the header
+    /// is ignored and probes are handled like any other call, so the handlers of
+    /// client streaming and duplex streaming methods are called with an empty
+    /// request stream.
+    ///
+    /// [`Client::probe_method`]: ./struct.Client.html#method.probe_method
+    pub fn answer_probes(mut self, enable: bool) -> ServerBuilder {
+        self.answer_probes = enable;
+        self
+    }
+
+    /// Collect per-method metrics of the calls to the server.
+    ///
+    /// Keep a clone of `metrics` to take snapshots of them. See [`ServerMetrics`] for
+    /// what are collected.
+    ///
+    /// [`ServerMetrics`]: ./struct.ServerMetrics.html
+    pub fn metrics(mut self, metrics: Arc<ServerMetrics>) -> ServerBuilder {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Report every finished call to `f` as an [`AuditEvent`], which carries the
+    /// method, the peer, the authenticated identity, the status and the timing.
+    ///
+    /// Calls rejected before reaching a handler are reported too, like calls to
+    /// unimplemented methods, calls from denied peers and answered probes.
+    ///
+    /// `f` is called on the completion queue threads when the call is released, so
+    /// it should be quick, like sending the event to a channel. Reporting can be
+    /// switched off and on at runtime by [`Server::set_audit_enabled`].
+    ///
+    /// [`AuditEvent`]: ./struct.AuditEvent.html
+    /// [`Server::set_audit_enabled`]: ./struct.Server.html#method.set_audit_enabled
+    pub fn audit_hook<F>(mut self, f: F) -> ServerBuilder
+    where
+        F: Fn(&AuditEvent) + Send + Sync + 'static,
+    {
+        self.audit = Some(Arc::new(f));
+        self
+    }
+
+    /// Register a compressor that compresses messages in the codec layer.
+    ///
+    /// Calls that ask for the compressor by [`CallOption::message_compressor`]
+    /// get their requests decompressed and responses compressed by it. A
+    /// compressor of the same name replaces the previous one. See
+    /// [`MessageCompressor`] for more details.
+    ///
+    /// [`CallOption::message_compressor`]: ./struct.CallOption.html#method.message_compressor
+    /// [`MessageCompressor`]: ./trait.MessageCompressor.html
+    pub fn register_message_compressor(mut self, c: Arc<dyn MessageCompressor>) -> ServerBuilder {
+        self.compressors.insert(c.name().to_owned(), c);
+        self
+    }
+
     /// Set how many requests a completion queue can handle.
     pub fn requests_slot_per_cq(mut self, slots: usize) -> ServerBuilder {
         self.slots_per_cq = slots;
@@ -322,10 +621,86 @@ impl ServerBuilder {
 
     /// Finalize the [`ServerBuilder`] and build the [`Server`].
     pub fn build(mut self) -> Result<Server> {
-        let args = self
+        let mut overrides: Vec<(&'static [u8], i32)> = Vec::with_capacity(10);
+        if let Some(len) = self.max_receive_message_len {
+            overrides.push((grpc_sys::GRPC_ARG_MAX_RECEIVE_MESSAGE_LENGTH, len));
+        }
+        // The send limit is also checked by the sinks, so handlers get a clear
+        // error instead of a call broken by gRPC C Core.
+        if let Some(len) = self.max_send_message_len {
+            overrides.push((grpc_sys::GRPC_ARG_MAX_SEND_MESSAGE_LENGTH, len));
+        }
+        // gRPC Core decompresses messages as a whole, leave it to the library to stop
+        // at the limit instead when one is set, unless the channel args say otherwise.
+        let core_decompression = self
             .args
             .as_ref()
+            .and_then(|args| args.get_integer(grpc_sys::GRPC_ARG_ENABLE_PER_MESSAGE_DECOMPRESSION));
+        if self.max_decompressed_message_len.is_some() && core_decompression.is_none() {
+            overrides.push((grpc_sys::GRPC_ARG_ENABLE_PER_MESSAGE_DECOMPRESSION, 0));
+        }
+        if let Some(reuse) = self.reuse_port {
+            overrides.push((grpc_sys::GRPC_ARG_ALLOW_REUSEPORT, reuse as i32));
+        }
+        if let Some(age) = self.max_connection_age {
+            overrides.push((grpc_sys::GRPC_ARG_MAX_CONNECTION_AGE_MS, dur_to_ms(age)));
+        }
+        if let Some(grace) = self.max_connection_age_grace {
+            overrides.push((
+                grpc_sys::GRPC_ARG_MAX_CONNECTION_AGE_GRACE_MS,
+                dur_to_ms(grace),
+            ));
+        }
+        if let Some((size, max_streams)) = self.connection_receive_buffer {
+            overrides.push((grpc_sys::GRPC_ARG_MAX_CONCURRENT_STREAMS, max_streams));
+            overrides.push((
+                grpc_sys::GRPC_ARG_HTTP2_STREAM_LOOKAHEAD_BYTES,
+                size / max_streams,
+            ));
+            overrides.push((grpc_sys::GRPC_ARG_HTTP2_BDP_PROBE, 0));
+        }
+        let merged_args = if overrides.is_empty() {
+            None
+        } else {
+            Some(ChannelArgs::with_integers(self.args.as_ref(), &overrides))
+        };
+        let args = merged_args
+            .as_ref()
+            .or_else(|| self.args.as_ref())
             .map_or_else(ptr::null, ChannelArgs::as_ptr);
+        // The limit may also be set by channel args, check it in the sinks all the same.
+        let max_send_len = self.max_send_message_len.or_else(|| {
+            let args = self.args.as_ref()?;
+            args.get_integer(grpc_sys::GRPC_ARG_MAX_SEND_MESSAGE_LENGTH)
+        });
+        let max_receive_len = self
+            .max_receive_message_len
+            .or_else(|| {
+                let args = self.args.as_ref()?;
+                args.get_integer(grpc_sys::GRPC_ARG_MAX_RECEIVE_MESSAGE_LENGTH)
+            })
+            .unwrap_or(grpc_sys::GRPC_DEFAULT_MAX_RECV_MESSAGE_LENGTH as i32);
+        let limits = MessageLimits {
+            max_send_len: max_send_len.filter(|len| *len >= 0).map(|len| len as usize),
+            max_decompressed_receive_len: self.max_decompressed_message_len.or_else(|| {
+                if max_receive_len >= 0 {
+                    Some(max_receive_len as usize)
+                } else {
+                    None
+                }
+            }),
+        };
+        let method_counters = match &self.metrics {
+            Some(metrics) => self
+                .handlers
+                .keys()
+                .map(|path| {
+                    let method = str::from_utf8(path).unwrap();
+                    (*path, metrics.register(method))
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
         unsafe {
             let server = grpc_sys::grpc_server_create(args, ptr::null_mut());
             for binder in self.binders.iter_mut() {
@@ -353,6 +728,14 @@ impl ServerBuilder {
                     shutdown: AtomicBool::new(false),
                     binders: self.binders,
                     slots_per_cq: self.slots_per_cq,
+                    limits,
+                    peer_filter: self.peer_filter,
+                    compressors: self.compressors,
+                    unimplemented_message: self.unimplemented_message,
+                    answer_probes: self.answer_probes,
+                    method_counters,
+                    audit: self.audit,
+                    audit_enabled: AtomicBool::new(true),
                 }),
                 handlers: self.handlers,
             })
@@ -422,6 +805,14 @@ struct ServerCore {
     binders: Vec<Binder>,
     slots_per_cq: usize,
     shutdown: AtomicBool,
+    limits: MessageLimits,
+    peer_filter: Option<PeerFilter>,
+    compressors: HashMap<String, Arc<dyn MessageCompressor>>,
+    unimplemented_message: Option<UnimplementedMessage>,
+    answer_probes: bool,
+    method_counters: HashMap<&'static [u8], Arc<MethodCounters>>,
+    audit: Option<AuditHook>,
+    audit_enabled: AtomicBool,
 }
 
 impl Drop for ServerCore {
@@ -435,6 +826,10 @@ unsafe impl Sync for ServerCore {}
 
 pub type BoxHandler = Box<dyn CloneableHandler>;
 
+type PeerFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+type UnimplementedMessage = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
 #[derive(Clone)]
 pub struct RequestCallContext {
     server: Arc<ServerCore>,
@@ -449,6 +844,77 @@ impl RequestCallContext {
         let registry = &mut *self.registry.get();
         registry.get_mut(path)
     }
+
+    #[inline]
+    pub fn message_limits(&self) -> MessageLimits {
+        self.server.limits
+    }
+
+    /// Get the observers of the calls to the method.
+    #[inline]
+    pub fn observers(&self, method: &[u8]) -> CallObservers {
+        let audit = if self.server.audit_enabled.load(Ordering::Relaxed) {
+            self.server.audit.clone()
+        } else {
+            None
+        };
+        CallObservers {
+            metrics: self.server.method_counters.get(method).cloned(),
+            audit,
+        }
+    }
+
+    /// Get the registered message compressor of the name.
+    pub fn message_compressor(
+        &self,
+        name: &[u8],
+    ) -> result::Result<Arc<dyn MessageCompressor>, RpcStatus> {
+        let c = str::from_utf8(name)
+            .ok()
+            .and_then(|name| self.server.compressors.get(name));
+        match c {
+            Some(c) => Ok(c.clone()),
+            None => {
+                let mut names: Vec<_> =
+                    self.server.compressors.keys().map(String::as_str).collect();
+                names.sort_unstable();
+                Err(RpcStatus::new(
+                    RpcStatusCode::UNIMPLEMENTED,
+                    Some(format!(
+                        "Message encoding {} is not supported, accepted encodings: [{}]",
+                        String::from_utf8_lossy(name),
+                        names.join(", ")
+                    )),
+                ))
+            }
+        }
+    }
+
+    /// Check whether calls from the peer are allowed by the filter of the server.
+    ///
+    /// `peer` is only called when there is a filter.
+    #[inline]
+    pub fn accept_peer<F: FnOnce() -> String>(&self, peer: F) -> bool {
+        self.server
+            .peer_filter
+            .as_ref()
+            .map_or(true, |f| f(&peer()))
+    }
+
+    /// Get the status of calls to the method that is not registered.
+    pub fn unimplemented_status(&self, method: &[u8]) -> RpcStatus {
+        let msg = self
+            .server
+            .unimplemented_message
+            .as_ref()
+            .map(|f| f(&String::from_utf8_lossy(method)));
+        RpcStatus::new(RpcStatusCode::UNIMPLEMENTED, msg)
+    }
+
+    #[inline]
+    pub fn answers_probes(&self) -> bool {
+        self.server.answer_probes
+    }
 }
 
 // Apparently, its life time is guaranteed by the ref count, hence is safe to be sent
@@ -535,6 +1001,13 @@ impl Server {
     }
 
     /// Start the server.
+    ///
+    /// The ports are bound and listening once [`ServerBuilder::build`] returns,
+    /// and the server is ready to accept connections and calls once this method
+    /// returns, so clients can connect right away without retrying or sleeping.
+    /// Connections made before it's called are queued by the OS until then.
+    ///
+    /// [`ServerBuilder::build`]: ./struct.ServerBuilder.html#method.build
     pub fn start(&mut self) {
         unsafe {
             grpc_sys::grpc_server_start(self.core.server);
@@ -558,10 +1031,47 @@ impl Server {
     }
 
     /// Get binded addresses pairs.
+    ///
+    /// Ports are the actual ones bound, so binding to port 0 returns the port
+    /// picked by the OS.
     pub fn bind_addrs(&self) -> impl ExactSizeIterator<Item = (&String, u16)> {
         self.core.binders.iter().map(|b| (&b.host, b.port))
     }
 
+    /// Switch reporting calls to the hook registered by [`ServerBuilder::audit_hook`]
+    /// on or off. It's on by default, and only affects calls started afterwards.
+    ///
+    /// [`ServerBuilder::audit_hook`]: ./struct.ServerBuilder.html#method.audit_hook
+    pub fn set_audit_enabled(&self, enabled: bool) {
+        self.core.audit_enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Get the full names of registered methods in order, like
+    /// `/helloworld.Greeter/SayHello`.
+    pub fn methods(&self) -> impl Iterator<Item = &str> {
+        let mut methods: Vec<_> = self
+            .handlers
+            .keys()
+            // Keys are the names of `Method`, which are str.
+            .map(|k| str::from_utf8(k).unwrap())
+            .collect();
+        methods.sort_unstable();
+        methods.into_iter()
+    }
+
+    /// Get the names of registered services in order, like `helloworld.Greeter`.
+    pub fn services(&self) -> impl Iterator<Item = &str> {
+        let mut services: Vec<_> = self
+            .methods()
+            .map(|m| {
+                let m = m.trim_start_matches('/');
+                m.rfind('/').map_or(m, |pos| &m[..pos])
+            })
+            .collect();
+        services.dedup();
+        services.into_iter()
+    }
+
     /// Add an rpc channel for an established connection represented as a file
     /// descriptor. Takes ownership of the file descriptor, closing it when
     /// channel is closed.
@@ -599,7 +1109,7 @@ impl Debug for Server {
 
 #[cfg(test)]
 mod tests {
-    use super::join_host_port;
+    use super::{is_sampled, join_host_port};
 
     #[test]
     fn test_join_host_port() {
@@ -618,4 +1128,15 @@ mod tests {
             assert_eq!(join_host_port(h, *p), e.to_owned());
         }
     }
+
+    #[test]
+    fn test_is_sampled() {
+        let count = |fraction| (0..100).filter(|n| is_sampled(*n, fraction)).count();
+        assert_eq!(count(0.0), 0);
+        assert_eq!(count(0.25), 25);
+        assert_eq!(count(0.3), 30);
+        assert_eq!(count(1.0), 100);
+        let sampled: Vec<_> = (0..8).filter(|n| is_sampled(*n, 0.25)).collect();
+        assert_eq!(sampled, [3, 7]);
+    }
 }
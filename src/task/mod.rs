@@ -3,6 +3,7 @@
 mod callback;
 mod executor;
 mod promise;
+mod timer;
 
 use std::fmt::{self, Debug, Formatter};
 use std::pin::Pin;
@@ -14,15 +15,16 @@ use parking_lot::Mutex;
 
 use self::callback::{Abort, Request as RequestCallback, UnaryRequest as UnaryRequestCallback};
 use self::executor::SpawnTask;
-use self::promise::{Batch as BatchPromise, Shutdown as ShutdownPromise};
+use self::promise::{Action as ActionPromise, Batch as BatchPromise, Shutdown as ShutdownPromise};
 use crate::call::server::RequestContext;
-use crate::call::{BatchContext, Call, MessageReader};
+use crate::call::{BatchContext, Call};
 use crate::cq::CompletionQueue;
 use crate::error::{Error, Result};
 use crate::server::RequestCallContext;
 
 pub(crate) use self::executor::{Executor, Kicker, UnfinishedWork};
-pub use self::promise::BatchType;
+pub use self::promise::{BatchResult, BatchType};
+pub(crate) use self::timer::Delay;
 
 /// A handle that is used to notify future that the task finishes.
 pub struct NotifyHandle<T> {
@@ -104,7 +106,7 @@ impl<T> Future for CqFuture<T> {
 }
 
 /// Future object for batch jobs.
-pub type BatchFuture = CqFuture<Option<MessageReader>>;
+pub type BatchFuture = CqFuture<BatchResult>;
 
 /// A result holder for asynchronous execution.
 // This enum is going to be passed to FFI, so don't use trait or generic here.
@@ -115,6 +117,7 @@ pub enum CallTag {
     Abort(Abort),
     Shutdown(ShutdownPromise),
     Spawn(Arc<SpawnTask>),
+    Action(ActionPromise),
 }
 
 impl CallTag {
@@ -138,6 +141,14 @@ impl CallTag {
         (CqFuture::new(inner), CallTag::Shutdown(shutdown))
     }
 
+    /// Generate a Future/CallTag pair for an action. The future resolves to whether
+    /// the action succeeds.
+    pub fn action_pair() -> (CqFuture<bool>, CallTag) {
+        let inner = new_inner();
+        let action = ActionPromise::new(inner.clone());
+        (CqFuture::new(inner), CallTag::Action(action))
+    }
+
     /// Generate a CallTag for abort call before handler is called.
     pub fn abort(call: Call) -> CallTag {
         CallTag::Abort(Abort::new(call))
@@ -177,6 +188,7 @@ impl CallTag {
             CallTag::Abort(_) => {}
             CallTag::Shutdown(prom) => prom.resolve(success),
             CallTag::Spawn(notify) => self::executor::resolve(notify, success),
+            CallTag::Action(prom) => prom.resolve(success),
         }
     }
 }
@@ -190,6 +202,7 @@ impl Debug for CallTag {
             CallTag::Abort(_) => write!(f, "CallTag::Abort(..)"),
             CallTag::Shutdown(_) => write!(f, "CallTag::Shutdown"),
             CallTag::Spawn(_) => write!(f, "CallTag::Spawn"),
+            CallTag::Action(_) => write!(f, "CallTag::Action"),
         }
     }
 }
@@ -4,8 +4,32 @@ use std::fmt::{self, Debug, Formatter};
 use std::sync::Arc;
 
 use super::Inner;
-use crate::call::{BatchContext, MessageReader, RpcStatusCode};
+use crate::call::{BatchContext, MessageReader, RpcStatus, RpcStatusCode};
 use crate::error::Error;
+use crate::metadata::Metadata;
+
+/// The result of a batch job.
+#[derive(Default)]
+pub struct BatchResult {
+    /// The received message, if any.
+    pub message_reader: Option<MessageReader>,
+    /// The received initial metadata, if any.
+    pub initial_metadata: Option<Metadata>,
+    /// The received trailing metadata, if any.
+    pub trailing_metadata: Option<Metadata>,
+    /// Whether reading a message failed, which means the call is broken
+    /// rather than finished sending.
+    pub read_failed: bool,
+}
+
+impl BatchResult {
+    fn new(message_reader: Option<MessageReader>) -> BatchResult {
+        BatchResult {
+            message_reader,
+            ..Default::default()
+        }
+    }
+}
 
 /// Batch job type.
 #[derive(PartialEq, Debug)]
@@ -22,11 +46,11 @@ pub enum BatchType {
 pub struct Batch {
     ty: BatchType,
     ctx: BatchContext,
-    inner: Arc<Inner<Option<MessageReader>>>,
+    inner: Arc<Inner<BatchResult>>,
 }
 
 impl Batch {
-    pub fn new(ty: BatchType, inner: Arc<Inner<Option<MessageReader>>>) -> Batch {
+    pub fn new(ty: BatchType, inner: Arc<Inner<BatchResult>>) -> Batch {
         Batch {
             ty,
             ctx: BatchContext::new(),
@@ -42,11 +66,14 @@ impl Batch {
         let task = {
             let mut guard = self.inner.lock();
             if success {
-                guard.set_result(Ok(self.ctx.recv_message()))
+                guard.set_result(Ok(BatchResult::new(self.ctx.recv_message())))
             } else {
                 // rely on C core to handle the failed read (e.g. deliver approriate
                 // statusCode on the clientside).
-                guard.set_result(Ok(None))
+                guard.set_result(Ok(BatchResult {
+                    read_failed: true,
+                    ..Default::default()
+                }))
             }
         };
         task.map(|t| t.wake());
@@ -58,7 +85,12 @@ impl Batch {
             if succeed {
                 let status = self.ctx.rpc_status();
                 if status.status == RpcStatusCode::OK {
-                    guard.set_result(Ok(None))
+                    guard.set_result(Ok(BatchResult {
+                        message_reader: None,
+                        initial_metadata: self.ctx.initial_metadata(),
+                        trailing_metadata: self.ctx.trailing_metadata(),
+                        read_failed: false,
+                    }))
                 } else {
                     guard.set_result(Err(Error::RpcFailure(status)))
                 }
@@ -74,7 +106,20 @@ impl Batch {
             let mut guard = self.inner.lock();
             let status = self.ctx.rpc_status();
             if status.status == RpcStatusCode::OK {
-                guard.set_result(Ok(self.ctx.recv_message()))
+                match self.ctx.recv_message() {
+                    Some(reader) => guard.set_result(Ok(BatchResult {
+                        message_reader: Some(reader),
+                        initial_metadata: self.ctx.initial_metadata(),
+                        trailing_metadata: self.ctx.trailing_metadata(),
+                        read_failed: false,
+                    })),
+                    // A trailers-only response with OK status, the call is
+                    // treated as failed like other gRPC implementations do.
+                    None => guard.set_result(Err(Error::RpcFailure(RpcStatus::new(
+                        RpcStatusCode::INTERNAL,
+                        Some("no message returned for unary request".to_owned()),
+                    )))),
+                }
             } else {
                 guard.set_result(Err(Error::RpcFailure(status)))
             }
@@ -126,3 +171,20 @@ impl Shutdown {
         task.map(|t| t.wake());
     }
 }
+
+/// A promise used to resolve the result of an action, for example watching
+/// connectivity state.
+pub struct Action {
+    inner: Arc<Inner<bool>>,
+}
+
+impl Action {
+    pub fn new(inner: Arc<Inner<bool>>) -> Action {
+        Action { inner }
+    }
+
+    pub fn resolve(self, success: bool) {
+        let task = self.inner.lock().set_result(Ok(success));
+        task.map(|t| t.wake());
+    }
+}
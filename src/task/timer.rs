@@ -0,0 +1,48 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::pin::Pin;
+use std::time::Instant;
+
+use futures::future::Future;
+use futures::task::{Context, Poll};
+
+/// A future that resolves at the given instant.
+///
+/// Dropping it removes the timer from the queue of `futures-timer`, so
+/// cancelled timeouts don't pile up until they would have fired.
+pub struct Delay(futures_timer::Delay);
+
+impl Delay {
+    pub fn new(at: Instant) -> Delay {
+        let timeout = at.saturating_duration_since(Instant::now());
+        Delay(futures_timer::Delay::new(timeout))
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::time::Duration;
+
+    #[test]
+    fn test_delay() {
+        let start = Instant::now();
+        let delays: Vec<_> = [30, 10, 20]
+            .iter()
+            .map(|ms| Delay::new(start + Duration::from_millis(*ms)))
+            .collect();
+        block_on(futures::future::join_all(delays));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        block_on(Delay::new(start));
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::error::Error as StdError;
+use std::task::{Context, Poll};
+
+use futures::future::{self, Either, Ready};
+use tower_service::Service;
+
+use crate::call::client::{CallOption, ClientUnaryReceiver};
+use crate::call::server::{RpcContext, UnarySink};
+use crate::call::{Method, MethodType, RpcStatus, RpcStatusCode};
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::server::ServiceBuilder;
+
+/// A [tower] [`Service`] that makes unary calls to a method.
+///
+/// Every request is sent as a new call with the options given at construction, so
+/// the service can be stacked with tower middlewares like `Buffer`, `RateLimit` or
+/// `Retry`. The service is always ready, it's up to the middlewares to apply
+/// back pressure.
+///
+/// [tower]: https://github.com/tower-rs/tower
+/// [`Service`]: https://docs.rs/tower-service/0.3/tower_service/trait.Service.html
+pub struct UnaryService<Req, Resp> {
+    client: Client,
+    method: Method<Req, Resp>,
+    opt: CallOption,
+}
+
+impl<Req, Resp> UnaryService<Req, Resp> {
+    /// Create a service that calls `method` with `client`.
+    ///
+    /// # Panics
+    ///
+    /// If `method` is not a unary method.
+    pub fn new(client: Client, method: &Method<Req, Resp>) -> UnaryService<Req, Resp> {
+        match method.ty {
+            MethodType::Unary => {}
+            _ => panic!("{} is not a unary method", method.name),
+        }
+        UnaryService {
+            client,
+            method: method.clone(),
+            opt: CallOption::default(),
+        }
+    }
+
+    /// Set the options used by every call.
+    pub fn call_option(mut self, opt: CallOption) -> UnaryService<Req, Resp> {
+        self.opt = opt;
+        self
+    }
+}
+
+impl<Req, Resp> Clone for UnaryService<Req, Resp> {
+    fn clone(&self) -> UnaryService<Req, Resp> {
+        UnaryService {
+            client: self.client.clone(),
+            method: self.method.clone(),
+            opt: self.opt.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Service<Req> for UnaryService<Req, Resp> {
+    type Response = Resp;
+    type Error = Error;
+    type Future = Either<Ready<Result<Resp>>, ClientUnaryReceiver<Resp>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        match self
+            .client
+            .unary_call_async(&self.method, &req, self.opt.clone())
+        {
+            Ok(receiver) => Either::Right(receiver),
+            Err(e) => Either::Left(future::err(e)),
+        }
+    }
+}
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+fn to_status(e: BoxError) -> RpcStatus {
+    match e.downcast::<Error>() {
+        Ok(e) => match *e {
+            Error::RpcFailure(status) => status,
+            e => RpcStatus::new(RpcStatusCode::UNKNOWN, Some(e.to_string())),
+        },
+        Err(e) => RpcStatus::new(RpcStatusCode::UNKNOWN, Some(e.to_string())),
+    }
+}
+
+impl ServiceBuilder {
+    /// Add a [tower] [`Service`] as the handler of a unary method.
+    ///
+    /// Every call is dispatched to a clone of `service` once it's ready, so middlewares
+    /// like `ConcurrencyLimit` that share state between clones apply to all calls of
+    /// the method. If the service fails with [`Error::RpcFailure`], the status is
+    /// returned to the client as is, other errors are returned as `UNKNOWN`.
+    ///
+    /// [tower]: https://github.com/tower-rs/tower
+    /// [`Service`]: https://docs.rs/tower-service/0.3/tower_service/trait.Service.html
+    /// [`Error::RpcFailure`]: ./enum.Error.html#variant.RpcFailure
+    pub fn add_unary_service<Req, Resp, S>(
+        self,
+        method: &Method<Req, Resp>,
+        service: S,
+    ) -> ServiceBuilder
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Service<Req, Response = Resp> + Send + Clone + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+    {
+        self.add_unary_handler(
+            method,
+            move |ctx: RpcContext<'_>, req: Req, sink: UnarySink<Resp>| {
+                let mut service = service.clone();
+                ctx.spawn(async move {
+                    // Convert errors at once, `S::Error` may not be `Send` so it can't
+                    // be kept across awaits.
+                    let ready = future::poll_fn(|cx| service.poll_ready(cx))
+                        .await
+                        .map_err(Into::<BoxError>::into);
+                    let res = match ready {
+                        Ok(()) => service.call(req).await.map_err(Into::into),
+                        Err(e) => Err(e),
+                    };
+                    let f = match res {
+                        Ok(resp) => sink.success(resp),
+                        Err(e) => sink.fail(to_status(e)),
+                    };
+                    if let Err(e) = f.await {
+                        debug!("failed to reply: {:?}", e);
+                    }
+                })
+            },
+        )
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+fn main() {
+    #[cfg(feature = "protobuf-codec")]
+    split_services::generate();
+    // Set by grpcio-sys when it supports socket mutators.
+    if std::env::var("DEP_GRPCIO_SOCKET_MUTATOR").is_ok() {
+        println!("cargo:rustc-cfg=grpcio_socket_mutator");
+    }
+}
+
+// Generates the code of a proto with several services, each into a module of its
+// own, which is compiled by the `split_services` test case.
+#[cfg(feature = "protobuf-codec")]
+mod split_services {
+    use std::path::Path;
+    use std::{env, fs};
+
+    use grpcio_compiler::{codegen, GenOptions};
+
+    const PROTO: &str = "proto/multi_service.proto";
+
+    pub fn generate() {
+        println!("cargo:rerun-if-changed={}", PROTO);
+        let out_dir = Path::new(&env::var("OUT_DIR").unwrap()).join("split_services");
+        fs::create_dir_all(&out_dir).unwrap();
+
+        protobuf_codegen_pure::Codegen::new()
+            .out_dir(&out_dir)
+            .inputs(&[PROTO])
+            .include("proto")
+            .run()
+            .unwrap();
+
+        let parsed =
+            protobuf_codegen_pure::parse_and_typecheck(&[Path::new(PROTO)], &[Path::new("proto")])
+                .unwrap();
+        let opts = GenOptions {
+            split_services: true,
+        };
+        let results =
+            codegen::gen_with_options(&parsed.file_descriptors, &parsed.relative_paths, opts);
+        for r in results {
+            let path = out_dir.join(&r.name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, r.content).unwrap();
+        }
+
+        fs::write(
+            out_dir.join("mod.rs"),
+            "pub mod multi_service;\npub mod multi_service_grpc;\n",
+        )
+        .unwrap();
+    }
+}
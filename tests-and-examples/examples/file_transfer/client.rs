@@ -0,0 +1,49 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+#[macro_use]
+extern crate log;
+
+#[path = "../log_util.rs"]
+mod log_util;
+mod method;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use grpcio::{CallOption, ChannelBuilder, Client, EnvBuilder, GrpcSlice};
+
+use method::METHOD_DOWNLOAD;
+
+fn main() {
+    let _guard = log_util::init_log(None);
+    let mut args = env::args().skip(1);
+    let (src, dest) = match (args.next(), args.next()) {
+        (Some(src), Some(dest)) => (src, dest),
+        _ => {
+            eprintln!("usage: file_transfer_client <remote path> <local path>");
+            return;
+        }
+    };
+    let env = Arc::new(EnvBuilder::new().build());
+    let ch = ChannelBuilder::new(env).connect("localhost:50051");
+    let client = Client::new(ch);
+
+    let mut chunks = client
+        .server_streaming(
+            &METHOD_DOWNLOAD,
+            &GrpcSlice::from(src.as_str()),
+            CallOption::default(),
+        )
+        .expect("rpc");
+    let mut file = File::create(&dest).unwrap();
+    let mut size = 0;
+    while let Some(chunk) = block_on(chunks.try_next()).expect("download") {
+        file.write_all(chunk.as_slice()).unwrap();
+        size += chunk.len();
+    }
+    info!("downloaded {} bytes from {} to {}", size, src, dest);
+}
@@ -0,0 +1,17 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use grpcio::{slice_de, slice_ser, GrpcSlice, Marshaller, Method, MethodType};
+
+/// Download the file at the path given by the request in chunks of raw bytes.
+pub const METHOD_DOWNLOAD: Method<GrpcSlice, GrpcSlice> = Method {
+    ty: MethodType::ServerStreaming,
+    name: "/file.FileTransfer/Download",
+    req_mar: Marshaller {
+        ser: slice_ser,
+        de: slice_de,
+    },
+    resp_mar: Marshaller {
+        ser: slice_ser,
+        de: slice_de,
+    },
+};
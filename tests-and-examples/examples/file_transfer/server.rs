@@ -0,0 +1,74 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+#[macro_use]
+extern crate log;
+
+#[path = "../log_util.rs"]
+mod log_util;
+mod method;
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+use std::{io, thread};
+
+use futures::channel::oneshot;
+use futures::executor::block_on;
+use futures::io::AllowStdIo;
+use grpcio::{
+    Environment, GrpcSlice, RpcContext, RpcStatus, RpcStatusCode, ServerBuilder,
+    ServerStreamingSink, ServiceBuilder,
+};
+
+use method::METHOD_DOWNLOAD;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+fn download(ctx: RpcContext<'_>, path: GrpcSlice, sink: ServerStreamingSink<GrpcSlice>) {
+    let path = String::from_utf8_lossy(path.as_slice()).into_owned();
+    let file = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            let status = RpcStatus::new(RpcStatusCode::NOT_FOUND, Some(e.to_string()));
+            ctx.spawn(async move {
+                let _ = sink.fail(status).await;
+            });
+            return;
+        }
+    };
+    ctx.spawn(async move {
+        match sink
+            .send_reader(AllowStdIo::new(file), CHUNK_SIZE, GrpcSlice::from)
+            .await
+        {
+            Ok(()) => info!("sent {}", path),
+            Err(e) => error!("failed to send {}: {:?}", path, e),
+        }
+    });
+}
+
+fn main() {
+    let _guard = log_util::init_log(None);
+    let env = Arc::new(Environment::new(1));
+    let service = ServiceBuilder::new()
+        .add_server_streaming_handler(&METHOD_DOWNLOAD, download)
+        .build();
+
+    let mut server = ServerBuilder::new(env)
+        .register_service(service)
+        .bind("127.0.0.1", 50_051)
+        .build()
+        .unwrap();
+    server.start();
+    for (host, port) in server.bind_addrs() {
+        info!("listening on {}:{}", host, port);
+    }
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        info!("Press ENTER to exit...");
+        let _ = io::stdin().read(&mut [0]).unwrap();
+        tx.send(())
+    });
+    let _ = block_on(rx);
+    let _ = block_on(server.shutdown());
+}
@@ -3,6 +3,7 @@
 use futures::*;
 use grpcio::*;
 use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::route_guide::{Point, METHOD_ROUTE_GUIDE_GET_FEATURE};
 
 use std::collections::HashMap;
 use std::sync::mpsc::{self, Sender};
@@ -95,6 +96,25 @@ fn test_auth_context() {
     );
     assert_eq!(ctx_map.get("ssl_session_reused").unwrap(), "false");
     assert!(ctx_map.get("x509_subject_alternative_name").is_none());
+
+    // The negotiated security should also be visible on the client side.
+    let mut resp = client.say_hello_async(&req).unwrap();
+    executor::block_on(&mut resp).unwrap();
+    let auth_context = resp.auth_context().unwrap();
+    assert_eq!(auth_context.transport_security_type(), Some("ssl"));
+    // The security level only tells TLS from insecure transports.
+    assert_eq!(
+        auth_context.security_level(),
+        Some("TSI_PRIVACY_AND_INTEGRITY")
+    );
+    assert!(auth_context.ssl_session_reused().is_some());
+    assert_eq!(auth_context.find_properties("x509\0common_name").count(), 0);
+    let cn: Vec<_> = auth_context
+        .find_properties("x509_common_name")
+        .map(|p| p.value_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(cn, vec!["localhost".to_owned()]);
+    rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
 }
 
 #[test]
@@ -124,3 +144,81 @@ fn test_no_crash_on_insecure() {
         .recv_timeout(Duration::from_secs(1))
         .expect_err("Received auth context even though not authenticated");
 }
+
+#[test]
+fn test_audit_hook() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, _rx) = mpsc::channel();
+    let service = create_greeter(GreeterService { tx });
+    let (server_crt, server_key) = read_cert_pair("server1").unwrap();
+    let server_credentials = grpcio::ServerCredentialsBuilder::new()
+        .root_cert(
+            read_single_crt("root").unwrap(),
+            CertificateRequestType::RequestClientCertificateAndVerify,
+        )
+        .add_cert(server_crt.into(), server_key.into())
+        .build();
+    let (event_tx, event_rx) = mpsc::channel();
+    let event_tx = Mutex::new(event_tx);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind_with_cred("127.0.0.1", 0, server_credentials)
+        .audit_hook(move |e: &AuditEvent| event_tx.lock().unwrap().send(e.clone()).unwrap())
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+
+    let (client_crt, client_key) = read_cert_pair("client1").unwrap();
+    let client_credentials = ChannelCredentialsBuilder::new()
+        .root_cert(read_single_crt("root").unwrap().into())
+        .cert(client_crt.into(), client_key.into())
+        .build();
+    let ch =
+        ChannelBuilder::new(env).secure_connect(&format!("localhost:{}", port), client_credentials);
+    let client = GreeterClient::new(ch.clone());
+
+    let mut req = HelloRequest::default();
+    req.set_name("world".to_owned());
+    let before = SystemTime::now();
+    client.say_hello(&req).unwrap();
+    let event = event_rx.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert_eq!(event.method(), "/helloworld.Greeter/SayHello");
+    assert!(event.peer().contains("127.0.0.1"), "{}", event.peer());
+    assert_eq!(event.identity(), ["grpc-client-1".to_owned()]);
+    assert_eq!(event.status(), RpcStatusCode::OK);
+    assert!(event.start() + Duration::from_secs(1) >= before);
+    assert!(event.start() <= SystemTime::now());
+    assert!(event.latency() < Duration::from_secs(3));
+    let json = event.to_json();
+    assert!(
+        json.contains("\"method\":\"/helloworld.Greeter/SayHello\""),
+        "{}",
+        json
+    );
+    assert!(
+        json.contains("\"identity\":[\"grpc-client-1\"]"),
+        "{}",
+        json
+    );
+    assert!(json.contains("\"code\":0"), "{}", json);
+
+    // Calls rejected before reaching a handler are reported too.
+    let res = Client::new(ch).unary_call(
+        &METHOD_ROUTE_GUIDE_GET_FEATURE,
+        &Point::default(),
+        CallOption::default(),
+    );
+    match res {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNIMPLEMENTED),
+        r => panic!("expected unimplemented, got {:?}", r),
+    }
+    let event = event_rx.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert_eq!(event.method(), "/routeguide.RouteGuide/GetFeature");
+    assert_eq!(event.identity(), ["grpc-client-1".to_owned()]);
+    assert_eq!(event.status(), RpcStatusCode::UNIMPLEMENTED);
+
+    server.set_audit_enabled(false);
+    client.say_hello(&req).unwrap();
+    assert!(event_rx.recv_timeout(Duration::from_millis(500)).is_err());
+}
@@ -277,3 +277,137 @@ fn test_early_exit() {
 
     rx.recv_timeout(Duration::from_secs(1)).unwrap();
 }
+
+#[test]
+fn test_channel_cancel_all() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = CancelService::new();
+    // Keep the calls running until they are canceled.
+    *service.route_chat_handler.lock().unwrap() = Some(Box::new(|stream, sink| {
+        let f = stream.try_for_each(|_| future::ready(Ok(()))).then(|_| {
+            let _sink = sink;
+            future::ready(())
+        });
+        Box::pin(f)
+    }));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_route_guide(service))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch.clone());
+
+    let mut calls = vec![];
+    for _ in 0..3 {
+        let (mut tx, rx) = client.route_chat().unwrap();
+        block_on(tx.send((RouteNote::default(), WriteFlags::default()))).unwrap();
+        calls.push((tx, rx));
+    }
+
+    ch.cancel_all();
+    for (_tx, rx) in calls {
+        check_cancel(rx, false);
+    }
+
+    // The channel is still usable after canceling.
+    let (mut tx, _rx) = client.route_chat().unwrap();
+    block_on(tx.send((RouteNote::default(), WriteFlags::default()))).unwrap();
+}
+
+#[test]
+fn test_client_cancel_with_status() {
+    let (service, client, _server) = prepare_suite();
+    let (tx, rx) = std_mpsc::channel();
+    let tx = Mutex::new(tx);
+    *service.route_chat_handler.lock().unwrap() = Some(Box::new(move |stream, sink| {
+        let tx = tx.lock().unwrap().clone();
+        let f = stream
+            .try_for_each(|_| future::ready(Ok(())))
+            .then(move |res| {
+                let _sink = sink;
+                tx.send(res).unwrap();
+                future::ready(())
+            });
+        Box::pin(f)
+    }));
+
+    let (mut sink, mut receiver) = client.route_chat().unwrap();
+    block_on(sink.send((RouteNote::default(), WriteFlags::default()))).unwrap();
+    let status = RpcStatus::new(
+        RpcStatusCode::ABORTED,
+        Some("lost the leadership".to_owned()),
+    );
+    receiver.cancel_with_status(&status);
+    match block_on(receiver.try_next()) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::ABORTED);
+            assert_eq!(s.details.as_deref(), Some("lost the leadership"));
+        }
+        r => panic!("expected aborted, got {:?}", r),
+    }
+    // The server sees the call is broken instead of half closed.
+    let res = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert!(res.is_err(), "{:?}", res);
+}
+
+#[test]
+fn test_request_stream_termination() {
+    let (service, client, _server) = prepare_suite();
+    let (tx, rx) = std_mpsc::channel();
+    let tx = Mutex::new(tx);
+    *service.route_chat_handler.lock().unwrap() = Some(Box::new(move |mut stream, mut sink| {
+        let tx = tx.lock().unwrap().clone();
+        let f = async move {
+            let mut count = 0;
+            let end = loop {
+                match stream.next().await {
+                    Some(Ok(_)) => count += 1,
+                    Some(Err(e)) => break Err(e),
+                    None => break Ok(()),
+                }
+            };
+            // The stream is fused after the terminal item.
+            assert!(stream.next().await.is_none());
+            if end.is_ok() {
+                // Finish normally after a clean half-close.
+                let mut note = RouteNote::default();
+                note.set_message(count.to_string());
+                sink.send((note, WriteFlags::default())).await.unwrap();
+                sink.close().await.unwrap();
+            }
+            tx.send((count, end)).unwrap();
+        };
+        Box::pin(f)
+    }));
+
+    // The client finishes sending cleanly.
+    let (mut sink, receiver) = client.route_chat().unwrap();
+    let notes = block_on(async move {
+        for _ in 0..2 {
+            sink.send((RouteNote::default(), WriteFlags::default()))
+                .await
+                .unwrap();
+        }
+        sink.close().await.unwrap();
+        receiver.try_collect::<Vec<_>>().await.unwrap()
+    });
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].get_message(), "2");
+    let (count, end) = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert_eq!(count, 2);
+    end.unwrap();
+
+    // The client goes away in the middle of the stream.
+    let (mut sink, mut receiver) = client.route_chat().unwrap();
+    block_on(sink.send((RouteNote::default(), WriteFlags::default()))).unwrap();
+    receiver.cancel();
+    let (count, end) = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert!(count <= 1);
+    match end {
+        Err(Error::RemoteStopped) => {}
+        r => panic!("expected remote stopped, got {:?}", r),
+    }
+}
@@ -0,0 +1,970 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use futures::prelude::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::sync::*;
+use std::thread;
+use std::time::*;
+
+#[derive(Clone)]
+struct GreeterService;
+
+impl Greeter for GreeterService {
+    fn say_hello(
+        &mut self,
+        ctx: RpcContext<'_>,
+        mut req: HelloRequest,
+        sink: UnarySink<HelloReply>,
+    ) {
+        let mut resp = HelloReply::default();
+        resp.set_message(format!("hello {}", req.take_name()));
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e))
+                .map(|_| ()),
+        );
+    }
+}
+
+fn start_server(env: Arc<Environment>) -> (Server, u16) {
+    let service = create_greeter(GreeterService);
+    let mut server = ServerBuilder::new(env)
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    (server, port)
+}
+
+fn say_hello(client: &GreeterClient, name: &str) -> String {
+    let mut req = HelloRequest::default();
+    req.set_name(name.to_owned());
+    let resp = client.say_hello(&req).unwrap();
+    resp.get_message().to_owned()
+}
+
+#[test]
+fn test_max_channel_idle() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (_server, port) = start_server(env.clone());
+    let ch = ChannelBuilder::new(env)
+        .max_channel_idle(Duration::from_secs(1))
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch.clone());
+
+    assert_eq!(say_hello(&client, "world"), "hello world");
+    assert_eq!(
+        ch.check_connectivity_state(false),
+        ConnectivityState::GRPC_CHANNEL_READY
+    );
+
+    // The connection should be released after the channel is idle for a while.
+    let mut idle = false;
+    for _ in 0..50 {
+        thread::sleep(Duration::from_millis(100));
+        if ch.check_connectivity_state(false) == ConnectivityState::GRPC_CHANNEL_IDLE {
+            idle = true;
+            break;
+        }
+    }
+    assert!(idle, "channel should enter idle state");
+
+    // Next RPC should re-establish the connection.
+    assert_eq!(say_hello(&client, "again"), "hello again");
+    assert_eq!(
+        ch.check_connectivity_state(false),
+        ConnectivityState::GRPC_CHANNEL_READY
+    );
+}
+
+#[test]
+fn test_http2_settings() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let args = ChannelBuilder::new(env.clone())
+        .http2_hpack_table_size_decoder(8192)
+        .http2_hpack_table_size_encoder(0)
+        .http2_max_frame_size(32768)
+        .build_args();
+    let service = create_greeter(GreeterService);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .channel_args(args)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+
+    let ch = ChannelBuilder::new(env)
+        .http2_hpack_table_size_decoder(0)
+        .http2_hpack_table_size_encoder(65536)
+        .http2_max_frame_size(16777215)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    // Send several calls so that the dynamic table is actually exercised.
+    for i in 0..3 {
+        let name = format!("world {}", i);
+        assert_eq!(say_hello(&client, &name), format!("hello {}", name));
+    }
+}
+
+#[test]
+fn test_connection_event() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (mut server, port) = start_server(env.clone());
+    let ch = ChannelBuilder::new(env.clone())
+        .initial_reconnect_backoff(Duration::from_millis(100))
+        .max_reconnect_backoff(Duration::from_millis(100))
+        .connect(&format!("127.0.0.1:{}", port));
+    let events = ch.connection_events();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for e in futures::executor::block_on_stream(events) {
+            if tx.send(e).is_err() {
+                return;
+            }
+        }
+    });
+    let client = GreeterClient::new(ch.clone());
+
+    assert_eq!(say_hello(&client, "world"), "hello world");
+    let timeout = Duration::from_secs(5);
+    let addr = format!("ipv4:127.0.0.1:{}", port);
+    let connected = ConnectionEvent::Connected(addr.clone());
+    assert_eq!(rx.recv_timeout(timeout), Ok(connected.clone()));
+
+    // Restart the backend on the same port.
+    futures::executor::block_on(server.shutdown()).unwrap();
+    drop(server);
+    assert_eq!(
+        rx.recv_timeout(timeout),
+        Ok(ConnectionEvent::Disconnected(addr))
+    );
+    let service = create_greeter(GreeterService);
+    let mut server = ServerBuilder::new(env)
+        .register_service(service)
+        .bind("127.0.0.1", port)
+        .build()
+        .unwrap();
+    server.start();
+
+    let opt = CallOption::default().wait_for_ready(true).timeout(timeout);
+    client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+    assert_eq!(rx.recv_timeout(timeout), Ok(connected));
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn test_resolved_addresses() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (_server, port) = start_server(env.clone());
+
+    // The target can't be resolved by DNS, so the call only succeeds if the
+    // addresses are used directly.
+    let addr = format!("127.0.0.1:{}", port).parse().unwrap();
+    let ch = ChannelBuilder::new(env)
+        .resolved_addresses(&[addr])
+        .connect("grpc-rs.invalid:443");
+    let client = GreeterClient::new(ch);
+    assert_eq!(say_hello(&client, "direct"), "hello direct");
+}
+
+#[test]
+fn test_static_dns() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (_server, port) = start_server(env.clone());
+
+    let addr = format!("127.0.0.1:{}", port).parse().unwrap();
+    let builder = || ChannelBuilder::new(env.clone()).add_static_dns("myservice.internal", &[addr]);
+    for target in &["myservice.internal:443", "dns:///MyService.internal"] {
+        let client = GreeterClient::new(builder().connect(target));
+        assert_eq!(say_hello(&client, "static"), "hello static");
+    }
+
+    // Other names are still resolved by DNS.
+    let client = GreeterClient::new(builder().connect("other.invalid:443"));
+    let opt = CallOption::default().timeout(Duration::from_secs(1));
+    assert!(client.say_hello_opt(&HelloRequest::default(), opt).is_err());
+}
+
+#[test]
+fn test_channel_label() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (_server, port) = start_server(env.clone());
+    let addr = format!("127.0.0.1:{}", port);
+
+    let ch = ChannelBuilder::new(env.clone())
+        .channel_label("tenant-a")
+        .connect(&addr);
+    assert_eq!(ch.label(), Some("tenant-a"));
+    assert_eq!(ch.clone().label(), Some("tenant-a"));
+    assert!(ch.target().contains(&addr), "{}", ch.target());
+    let client = GreeterClient::new(ch);
+    assert_eq!(say_hello(&client, "label"), "hello label");
+
+    let ch = ChannelBuilder::new(env).connect(&addr);
+    assert_eq!(ch.label(), None);
+}
+
+#[derive(Clone)]
+struct UnavailableService(Arc<atomic::AtomicUsize>);
+
+impl Greeter for UnavailableService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.0.fetch_add(1, atomic::Ordering::SeqCst);
+        let status = RpcStatus::new(RpcStatusCode::UNAVAILABLE, None);
+        ctx.spawn(sink.fail(status).map(|_| ()));
+    }
+}
+
+const THROTTLED_RETRY_CONFIG: &str = r#"{
+    "methodConfig": [{
+        "name": [{"service": "helloworld.Greeter"}],
+        "retryPolicy": {
+            "maxAttempts": 5,
+            "initialBackoff": "0.01s",
+            "maxBackoff": "0.1s",
+            "backoffMultiplier": 2,
+            "retryableStatusCodes": ["UNAVAILABLE"]
+        }
+    }],
+    "retryThrottling": {"maxTokens": 10, "tokenRatio": 0.1}
+}"#;
+
+#[test]
+fn test_retry_throttling() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let attempts = Arc::new(atomic::AtomicUsize::new(0));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(UnavailableService(attempts.clone())))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env)
+        .service_config(THROTTLED_RETRY_CONFIG)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let calls = 4;
+    for _ in 0..calls {
+        match client.say_hello(&HelloRequest::default()) {
+            Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNAVAILABLE),
+            r => panic!("expected unavailable, got {:?}", r),
+        }
+    }
+    // The first call is retried until it runs out of attempts, leaving 5 tokens, after
+    // which retries are throttled. Without throttling it would be 5 attempts per call.
+    let total = attempts.load(atomic::Ordering::SeqCst);
+    assert!(total > calls, "{}", total);
+    assert!(total <= 5 + (calls - 1), "{}", total);
+}
+
+#[test]
+fn test_disable_retries() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let attempts = Arc::new(atomic::AtomicUsize::new(0));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(UnavailableService(attempts.clone())))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let addr = format!("127.0.0.1:{}", port);
+
+    for (enable, expected) in vec![(true, 5), (false, 1)] {
+        attempts.store(0, atomic::Ordering::SeqCst);
+        let ch = ChannelBuilder::new(env.clone())
+            .service_config(THROTTLED_RETRY_CONFIG)
+            .enable_retries(enable)
+            .channel_label(format!("retries-{}", enable))
+            .connect(&addr);
+        let client = GreeterClient::new(ch);
+        match client.say_hello(&HelloRequest::default()) {
+            Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNAVAILABLE),
+            r => panic!("expected unavailable, got {:?}", r),
+        }
+        assert_eq!(attempts.load(atomic::Ordering::SeqCst), expected);
+    }
+}
+
+#[test]
+fn test_debug_error_string() {
+    let env = Arc::new(EnvBuilder::new().build());
+    // Nothing listens on the port after the listener is dropped.
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let e = client.say_hello(&HelloRequest::default()).unwrap_err();
+    let s = match &e {
+        Error::RpcFailure(s) => s,
+        e => panic!("expected unavailable, got {:?}", e),
+    };
+    assert_eq!(s.status, RpcStatusCode::UNAVAILABLE);
+    assert_eq!(s.code(), 14);
+    let details = s.details.as_deref().unwrap();
+    let debug = e.debug_error_string().unwrap();
+    assert_eq!(s.debug_error_string(), Some(debug));
+    assert!(debug.contains(details), "{}", debug);
+    assert!(debug.contains("\"grpc_status\":14"), "{}", debug);
+    // The debug error string doesn't change the format of failures.
+    assert!(!format!("{:?}", e).contains("grpc_status"), "{:?}", e);
+}
+
+// A minimal HTTP proxy that only supports the CONNECT method, the request line
+// of every tunnel is sent to the returned receiver.
+fn start_connect_proxy() -> (u16, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for client in listener.incoming() {
+            let client = client.unwrap();
+            let mut reader = BufReader::new(client.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let target = request_line.split_whitespace().nth(1).unwrap().to_owned();
+            tx.send(request_line.trim_end().to_owned()).unwrap();
+            let server = TcpStream::connect(target).unwrap();
+            (&client).write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            let mut server_writer = server.try_clone().unwrap();
+            thread::spawn(move || io::copy(&mut reader, &mut server_writer));
+            thread::spawn(move || io::copy(&mut &server, &mut &client));
+        }
+    });
+    (port, rx)
+}
+
+#[test]
+fn test_http_proxy() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (_server, port) = start_server(env.clone());
+    let (proxy_port, rx) = start_connect_proxy();
+    let proxy = format!("http://127.0.0.1:{}", proxy_port);
+
+    let ch = ChannelBuilder::new(env.clone())
+        .http_proxy(&proxy)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    assert_eq!(say_hello(&client, "proxy"), "hello proxy");
+    assert_eq!(
+        rx.try_recv().unwrap(),
+        format!("CONNECT 127.0.0.1:{} HTTP/1.0", port)
+    );
+
+    let ch = ChannelBuilder::new(env)
+        .http_proxy(&proxy)
+        .no_proxy(vec!["localhost"])
+        .channel_label("direct")
+        .connect(&format!("localhost:{}", port));
+    let client = GreeterClient::new(ch);
+    assert_eq!(say_hello(&client, "direct"), "hello direct");
+    assert!(rx.try_recv().is_err());
+}
+
+// A minimal DNS server that resolves every name to 127.0.0.1, the number of
+// received A queries is counted in the returned counter.
+fn start_dns_server() -> (u16, Arc<atomic::AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let queries = Arc::new(atomic::AtomicUsize::new(0));
+    let counter = queries.clone();
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        loop {
+            let (_, peer) = socket.recv_from(&mut buf).unwrap();
+            // Skip the header and the labels of the question name.
+            let mut pos = 12;
+            while buf[pos] != 0 {
+                pos += buf[pos] as usize + 1;
+            }
+            let qtype = u16::from_be_bytes([buf[pos + 1], buf[pos + 2]]);
+            let question_end = pos + 5;
+            let mut resp = buf[..question_end].to_vec();
+            resp[2] = 0x81;
+            resp[3] = 0x80;
+            // No answers, authorities or additional records by default.
+            resp[6..12].copy_from_slice(&[0; 6]);
+            if qtype == 1 {
+                counter.fetch_add(1, atomic::Ordering::SeqCst);
+                resp[7] = 1;
+                // Name pointer to the question, type A, class IN, TTL 0, 127.0.0.1.
+                resp.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 127, 0, 0, 1]);
+            }
+            socket.send_to(&resp, peer).unwrap();
+        }
+    });
+    (port, queries)
+}
+
+#[test]
+fn test_dns_min_resolution_period() {
+    let env = Arc::new(EnvBuilder::new().build());
+    // Nothing listens on the port, so connections keep failing and the channel
+    // keeps asking for re-resolution.
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    for (period, frequent) in vec![(10000, false), (100, true)] {
+        let (dns_port, queries) = start_dns_server();
+        let ch = ChannelBuilder::new(env.clone())
+            .dns_min_resolution_period(Duration::from_millis(period))
+            .initial_reconnect_backoff(Duration::from_millis(100))
+            .max_reconnect_backoff(Duration::from_millis(100))
+            .channel_label(format!("dns-{}", period))
+            .connect(&format!(
+                "dns://127.0.0.1:{}/grpc-rs.test:{}",
+                dns_port, port
+            ));
+        let client = GreeterClient::new(ch);
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(3) {
+            let opt = CallOption::default().timeout(Duration::from_millis(100));
+            assert!(client.say_hello_opt(&HelloRequest::default(), opt).is_err());
+        }
+        let count = queries.load(atomic::Ordering::SeqCst);
+        if frequent {
+            assert!(count > 1, "{}", count);
+        } else {
+            assert_eq!(count, 1);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PeerService(Arc<Mutex<HashSet<String>>>);
+
+impl Greeter for PeerService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.0.lock().unwrap().insert(ctx.peer());
+        ctx.spawn(sink.success(HelloReply::default()).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_max_connection_age() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let peers = Arc::new(Mutex::new(HashSet::new()));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(PeerService(peers.clone())))
+        .max_connection_age(Duration::from_millis(500))
+        .max_connection_age_grace(Duration::from_millis(100))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+
+    let (dns_port, queries) = start_dns_server();
+    let ch = ChannelBuilder::new(env)
+        .dns_min_resolution_period(Duration::from_millis(100))
+        .connect(&format!(
+            "dns://127.0.0.1:{}/grpc-rs.test:{}",
+            dns_port, port
+        ));
+    let client = GreeterClient::new(ch);
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(3) {
+        // Calls keep succeeding while connections are aged out.
+        let opt = CallOption::default().wait_for_ready(true);
+        client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+        thread::sleep(Duration::from_millis(50));
+    }
+    // A connection lives at most 0.5s +/-10%, so 3 seconds should see the client
+    // reconnect from several ports, re-resolving the target every time.
+    let reconnects = peers.lock().unwrap().len();
+    assert!(reconnects > 2, "{}", reconnects);
+    let count = queries.load(atomic::Ordering::SeqCst);
+    assert!(count > 2, "{}", count);
+}
+
+#[test]
+fn test_max_subchannels() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let listeners: Vec<_> = (0..10)
+        .map(|_| {
+            let l = TcpListener::bind("127.0.0.1:0").unwrap();
+            l.set_nonblocking(true).unwrap();
+            l
+        })
+        .collect();
+    let addrs: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+    let count_connected = |ch: Channel| {
+        ch.check_connectivity_state(true);
+        let mut connected = vec![false; listeners.len()];
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(2) {
+            for (l, c) in listeners.iter().zip(&mut connected) {
+                if l.accept().is_ok() {
+                    *c = true;
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        connected.iter().filter(|c| **c).count()
+    };
+
+    let ch = ChannelBuilder::new(env.clone())
+        .resolved_addresses(&addrs)
+        .load_balancing_policy(LbPolicy::RoundRobin)
+        .max_subchannels(3)
+        .connect("grpc-rs.test");
+    // Round robin connects to all the addresses it knows.
+    assert_eq!(count_connected(ch), 3);
+
+    // Address lists in the target are capped too.
+    let list: Vec<_> = addrs.iter().map(ToString::to_string).collect();
+    let ch = ChannelBuilder::new(env)
+        .load_balancing_policy(LbPolicy::RoundRobin)
+        .max_subchannels(2)
+        .connect(&format!("ipv4:{}", list.join(",")));
+    assert_eq!(count_connected(ch), 2);
+}
+
+#[test]
+fn test_max_subchannels_dns() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // Addresses resolved by gRPC Core can't be capped, they are connected as usual.
+    let ch = ChannelBuilder::new(env)
+        .max_subchannels(3)
+        .connect(&format!("dns:127.0.0.1:{}", port));
+    ch.check_connectivity_state(true);
+    listener.accept().unwrap();
+}
+
+#[test]
+fn test_channel_pool() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let pool = ChannelBuilder::new(env.clone()).connect_pool(&addr.to_string(), 3);
+    assert_eq!(pool.channels().len(), 3);
+    for ch in pool.channels() {
+        ch.check_connectivity_state(true);
+    }
+    let mut accepted = 0;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(2) {
+        if listener.accept().is_ok() {
+            accepted += 1;
+        } else {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    // Channels of a pool don't share connections.
+    assert_eq!(accepted, 3);
+
+    let (_server, port) = start_server(env.clone());
+    let pool = ChannelBuilder::new(env).connect_pool(&format!("127.0.0.1:{}", port), 2);
+    let client = GreeterClient::with_client(Client::with_pool(pool.clone()));
+    for _ in 0..4 {
+        assert_eq!(say_hello(&client, "pool"), "hello pool");
+    }
+    // Calls are spread over all the channels.
+    for ch in pool.channels() {
+        assert_eq!(
+            ch.check_connectivity_state(false),
+            ConnectivityState::GRPC_CHANNEL_READY
+        );
+    }
+}
+
+#[test]
+fn test_keepalive_stats() {
+    let env = Arc::new(EnvBuilder::new().build());
+    // Allow pings without calls, or the server closes the connection.
+    let args = ChannelBuilder::new(env.clone())
+        .http2_min_recv_ping_interval_without_data(Duration::from_millis(10))
+        .http2_max_ping_strikes(0)
+        .build_args();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .channel_args(args)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env)
+        .keepalive_time(Duration::from_millis(100))
+        .keepalive_permit_without_calls(true)
+        .http2_max_pings_without_data(0)
+        .http2_min_sent_ping_interval_without_data(Duration::from_millis(100))
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch.clone());
+    assert_eq!(say_hello(&client, "ping"), "hello ping");
+    let stats = ch.keepalive_stats();
+    assert_eq!(stats.len(), 1, "{:?}", stats);
+    assert_eq!(stats[0].remote, format!("ipv4:127.0.0.1:{}", port));
+    let last_received = stats[0].last_message_received.unwrap();
+    assert!(last_received <= SystemTime::now());
+    let sent_before = stats[0].keepalives_sent;
+
+    // Keep the connection idle, pings are still sent.
+    thread::sleep(Duration::from_secs(1));
+    let stats = ch.keepalive_stats();
+    assert_eq!(stats.len(), 1, "{:?}", stats);
+    assert!(
+        stats[0].keepalives_sent > sent_before,
+        "{} vs {}",
+        stats[0].keepalives_sent,
+        sent_before
+    );
+    assert_eq!(stats[0].last_message_received, Some(last_received));
+}
+
+#[test]
+fn test_ping_strikes() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let server_args = ChannelBuilder::new(env.clone())
+        .keepalive_permit_without_calls(true)
+        .http2_min_recv_ping_interval_without_data(Duration::from_millis(300))
+        .http2_max_ping_strikes(2)
+        .build_args();
+    assert_eq!(server_args.http2_max_ping_strikes(), Some(2));
+    assert_eq!(server_args.http2_max_pings_without_data(), None);
+    let min_recv = server_args
+        .http2_min_recv_ping_interval_without_data()
+        .unwrap();
+    assert_eq!(min_recv, Duration::from_millis(300));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .channel_args(server_args)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let addr = format!("127.0.0.1:{}", server.bind_addrs().next().unwrap().1);
+
+    let connect = |ping_interval| {
+        let builder = ChannelBuilder::new(env.clone())
+            .keepalive_time(Duration::from_millis(50))
+            .keepalive_permit_without_calls(true)
+            .http2_max_pings_without_data(0)
+            .http2_min_sent_ping_interval_without_data(ping_interval);
+        let args = builder.build_args();
+        assert_eq!(args.http2_max_pings_without_data(), Some(0));
+        assert_eq!(
+            args.http2_min_sent_ping_interval_without_data(),
+            Some(ping_interval)
+        );
+        let ch = builder.connect(&addr);
+        assert_eq!(
+            say_hello(&GreeterClient::new(ch.clone()), "ping"),
+            "hello ping"
+        );
+        ch
+    };
+    // Matches the server's policy with some margin for timer jitter.
+    let polite = connect(min_recv + Duration::from_millis(100));
+    let eager = connect(Duration::from_millis(50));
+
+    thread::sleep(Duration::from_secs(2));
+    assert!(polite.keepalive_stats()[0].keepalives_sent >= 3);
+    assert_eq!(
+        polite.check_connectivity_state(false),
+        ConnectivityState::GRPC_CHANNEL_READY
+    );
+    // The server sends GOAWAY after too many pings, and nothing reconnects.
+    assert_ne!(
+        eager.check_connectivity_state(false),
+        ConnectivityState::GRPC_CHANNEL_READY
+    );
+}
+
+#[test]
+fn test_http2_interop_options() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (_server, port) = start_server(env.clone());
+    let addr = format!("127.0.0.1:{}", port);
+
+    for &compat in &[false, true] {
+        let ch = ChannelBuilder::new(env.clone())
+            .http2_scheme(if compat { "https" } else { "http" })
+            .http2_true_binary(!compat)
+            .http2_bdp_probe(!compat)
+            .connect(&addr);
+        let client = GreeterClient::new(ch);
+        assert_eq!(say_hello(&client, "h2"), "hello h2");
+    }
+}
+
+#[test]
+fn test_min_reconnect_backoff() {
+    let env = Arc::new(EnvBuilder::new().build());
+    // Packets to the address are usually dropped, so connecting hangs until the
+    // attempt is given up.
+    let ch = ChannelBuilder::new(env)
+        .min_reconnect_backoff(Duration::from_millis(300))
+        .initial_reconnect_backoff(Duration::from_millis(300))
+        .connect("10.255.255.1:80");
+    let start = Instant::now();
+    let mut state = ch.check_connectivity_state(true);
+    while state != ConnectivityState::GRPC_CHANNEL_TRANSIENT_FAILURE {
+        let changed =
+            futures::executor::block_on(ch.wait_for_state_change(state, Duration::from_secs(3)));
+        assert!(changed, "stuck in {:?}", state);
+        state = ch.check_connectivity_state(false);
+    }
+    // It would take 20 seconds by default.
+    let elapsed = start.elapsed();
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
+
+#[cfg(all(target_os = "linux", grpcio_socket_mutator))]
+#[test]
+fn test_socket_mutator() {
+    use std::os::unix::io::RawFd;
+
+    fn get_priority(fd: RawFd) -> io::Result<libc::c_int> {
+        let mut val: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PRIORITY,
+                &mut val as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(val)
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let (_server, port) = start_server(env.clone());
+    let priorities = Arc::new(Mutex::new(vec![]));
+    let p = priorities.clone();
+    let ch = ChannelBuilder::new(env)
+        .socket_mutator(move |fd| {
+            let val: libc::c_int = 5;
+            let ret = unsafe {
+                libc::setsockopt(
+                    fd,
+                    libc::SOL_SOCKET,
+                    libc::SO_PRIORITY,
+                    &val as *const _ as *const libc::c_void,
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Verify the option is applied.
+            p.lock().unwrap().push(get_priority(fd)?);
+            Ok(())
+        })
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    assert_eq!(say_hello(&client, "world"), "hello world");
+    let priorities = priorities.lock().unwrap();
+    assert!(!priorities.is_empty());
+    assert!(priorities.iter().all(|p| *p == 5), "{:?}", priorities);
+
+    // A failing mutator fails the connection.
+    let env = Arc::new(EnvBuilder::new().build());
+    let ch = ChannelBuilder::new(env)
+        .socket_mutator(|_| Err(io::Error::new(io::ErrorKind::Other, "rejected")))
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let opt = CallOption::default().timeout(Duration::from_secs(1));
+    assert!(client.say_hello_opt(&HelloRequest::default(), opt).is_err());
+}
+
+#[derive(Clone)]
+struct UserAgentService;
+
+impl Greeter for UserAgentService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let agent = ctx
+            .request_headers()
+            .iter()
+            .find(|(k, _)| *k == "user-agent")
+            .map(|(_, v)| String::from_utf8(v.to_vec()).unwrap())
+            .unwrap_or_default();
+        let mut resp = HelloReply::default();
+        resp.set_message(agent);
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e))
+                .map(|_| ()),
+        );
+    }
+}
+
+#[test]
+fn test_primary_user_agent_without_version() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(UserAgentService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let addr = format!("127.0.0.1:{}", port);
+    let user_agent = |builder: ChannelBuilder| {
+        let client = GreeterClient::new(builder.connect(&addr));
+        client
+            .say_hello(&HelloRequest::default())
+            .unwrap()
+            .take_message()
+    };
+
+    let agent =
+        user_agent(ChannelBuilder::new(env.clone()).primary_user_agent("strict-client/1.0"));
+    assert!(
+        agent.starts_with("strict-client/1.0 grpc-rust/"),
+        "{}",
+        agent
+    );
+
+    let agent = user_agent(
+        ChannelBuilder::new(env).primary_user_agent_without_version("strict-client/1.0"),
+    );
+    // gRPC Core's own token is the only thing following the value.
+    let core_token = agent.strip_prefix("strict-client/1.0 ").unwrap();
+    assert!(core_token.starts_with("grpc-c/"), "{}", agent);
+    assert!(!agent.contains("grpc-rust"), "{}", agent);
+}
+
+#[test]
+fn test_register_methods() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (_server, port) = start_server(env.clone());
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    assert!(!ch.is_registered(METHOD_GREETER_SAY_HELLO.name));
+
+    // Generated clients register all the methods of the service.
+    let client = GreeterClient::new(ch.clone());
+    assert!(ch.is_registered(METHOD_GREETER_SAY_HELLO.name));
+    for i in 0..3 {
+        assert_eq!(say_hello(&client, &i.to_string()), format!("hello {}", i));
+    }
+    // Registering again is a no-op.
+    ch.register_methods(&[METHOD_GREETER_SAY_HELLO.name]);
+    let client = GreeterClient::new(ch.clone());
+    assert_eq!(say_hello(&client, "again"), "hello again");
+
+    // Methods unknown to the server fail the same way whether registered or not.
+    let method = Method {
+        name: "/helloworld.Greeter/Missing",
+        ..METHOD_GREETER_SAY_HELLO
+    };
+    let client = Client::new(ch.clone());
+    for _ in 0..2 {
+        match client.unary_call(&method, &HelloRequest::default(), CallOption::default()) {
+            Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNIMPLEMENTED),
+            r => panic!("expected unimplemented, got {:?}", r),
+        }
+        ch.register_methods(&[method.name]);
+    }
+}
+
+// Replies to the first call after `delay`, and to the others at once.
+#[derive(Clone)]
+struct SlowFirstService {
+    attempts: Arc<atomic::AtomicUsize>,
+    delay: Duration,
+}
+
+impl Greeter for SlowFirstService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let delay = if self.attempts.fetch_add(1, atomic::Ordering::SeqCst) == 0 {
+            self.delay
+        } else {
+            Duration::from_millis(0)
+        };
+        ctx.spawn(async move {
+            futures_timer::Delay::new(delay).await;
+            // The connection may be closed already.
+            let _ = sink.success(HelloReply::default()).await;
+        });
+    }
+}
+
+// Retries every method of the service but `SayHello`, which is sent at most once.
+const AT_MOST_ONCE_CONFIG: &str = r#"{
+    "methodConfig": [{
+        "name": [{"service": "helloworld.Greeter"}],
+        "retryPolicy": {
+            "maxAttempts": 5,
+            "initialBackoff": "0.01s",
+            "maxBackoff": "0.1s",
+            "backoffMultiplier": 2,
+            "retryableStatusCodes": ["UNAVAILABLE"]
+        }
+    }, {
+        "name": [{"service": "helloworld.Greeter", "method": "SayHello"}]
+    }]
+}"#;
+
+#[test]
+fn test_at_most_once() {
+    let env = Arc::new(EnvBuilder::new().build());
+    for (config, at_most_once) in vec![(THROTTLED_RETRY_CONFIG, false), (AT_MOST_ONCE_CONFIG, true)]
+    {
+        // The connection is closed after a GOAWAY while the first call is in flight.
+        let attempts = Arc::new(atomic::AtomicUsize::new(0));
+        let service = SlowFirstService {
+            attempts: attempts.clone(),
+            delay: Duration::from_secs(3),
+        };
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_greeter(service))
+            .max_connection_age(Duration::from_millis(300))
+            .max_connection_age_grace(Duration::from_millis(200))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+        let ch = ChannelBuilder::new(env.clone())
+            .service_config(config)
+            .connect(&format!("127.0.0.1:{}", port));
+        let client = GreeterClient::new(ch);
+
+        let opt = CallOption::default().timeout(Duration::from_secs(10));
+        let res = client.say_hello_opt(&HelloRequest::default(), opt);
+        let attempts = attempts.load(atomic::Ordering::SeqCst);
+        if at_most_once {
+            match res {
+                Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNAVAILABLE),
+                r => panic!("expected unavailable, got {:?}", r),
+            }
+            assert_eq!(attempts, 1);
+        } else {
+            // Retried on a new connection, so the server handles it twice.
+            res.unwrap();
+            assert!(attempts >= 2, "{}", attempts);
+        }
+    }
+}
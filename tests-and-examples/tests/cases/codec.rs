@@ -0,0 +1,297 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use std::io::{self, Read, Write};
+use std::sync::*;
+
+// Messages containing "bad" can't be serialized.
+fn str_ser(s: &String, buf: &mut GrpcSlice) -> Result<()> {
+    if s.contains("bad") {
+        return Err(Error::Codec(Box::new(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad message",
+        ))));
+    }
+    *buf = GrpcSlice::from(s.as_str());
+    Ok(())
+}
+
+fn str_de(mut reader: MessageReader) -> Result<String> {
+    let mut s = String::new();
+    reader
+        .read_to_string(&mut s)
+        .map_err(|e| Error::Codec(Box::new(e)))?;
+    Ok(s)
+}
+
+const METHOD_ECHO: Method<String, String> = Method {
+    ty: MethodType::Unary,
+    name: "/codec.Echo/Echo",
+    req_mar: Marshaller {
+        ser: str_ser,
+        de: str_de,
+    },
+    resp_mar: Marshaller {
+        ser: str_ser,
+        de: str_de,
+    },
+};
+
+#[test]
+fn test_serialize_error() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_unary_handler(&METHOD_ECHO, |ctx, req: String, sink| {
+            // Make the response fail to serialize when asked to.
+            let resp = if req == "fail" { "bad".to_owned() } else { req };
+            ctx.spawn(sink.success(resp).map(|_| ()));
+        })
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let resp = client
+        .unary_call(&METHOD_ECHO, &"hello".to_owned(), CallOption::default())
+        .unwrap();
+    assert_eq!(resp, "hello");
+
+    // Request serialization failure should be reported to the caller.
+    match client.unary_call(&METHOD_ECHO, &"bad".to_owned(), CallOption::default()) {
+        Err(Error::Codec(_)) => {}
+        res => panic!("expected codec error, got {:?}", res),
+    }
+
+    // Response serialization failure should be reported to the client as INTERNAL.
+    match client.unary_call(&METHOD_ECHO, &"fail".to_owned(), CallOption::default()) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::INTERNAL);
+            assert!(s.details.as_ref().unwrap().contains("serialize"), "{:?}", s);
+        }
+        res => panic!("expected internal error, got {:?}", res),
+    }
+}
+
+const METHOD_SLICE_ECHO: Method<GrpcSlice, GrpcSlice> = Method {
+    ty: MethodType::Duplex,
+    name: "/codec.Echo/SliceEcho",
+    req_mar: Marshaller {
+        ser: slice_ser,
+        de: slice_de,
+    },
+    resp_mar: Marshaller {
+        ser: slice_ser,
+        de: slice_de,
+    },
+};
+
+#[test]
+fn test_slice_codec() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_duplex_streaming_handler(&METHOD_SLICE_ECHO, |ctx, stream, sink| {
+            let f = stream
+                .map_ok(|m| (m, WriteFlags::default()))
+                .forward(sink)
+                .map(|r| r.unwrap());
+            ctx.spawn(f);
+        })
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    // Large messages are split into many frames and received in multiple slices.
+    let msgs: Vec<GrpcSlice> = [3 * 1024 * 1024, 1, 0, 100 * 1024]
+        .iter()
+        .enumerate()
+        .map(|(i, len)| GrpcSlice::from((0..*len).map(|j| (i + j) as u8).collect::<Vec<_>>()))
+        .collect();
+    let (mut sink, receiver) = client
+        .duplex_streaming(&METHOD_SLICE_ECHO, CallOption::default())
+        .unwrap();
+    let to_send = msgs.clone();
+    let resps = block_on(async move {
+        for m in to_send {
+            sink.send((m, WriteFlags::default())).await.unwrap();
+        }
+        sink.close().await.unwrap();
+        receiver.try_collect::<Vec<_>>().await.unwrap()
+    });
+    assert_eq!(resps, msgs);
+}
+
+struct Zstd;
+
+impl MessageCompressor for Zstd {
+    fn name(&self) -> &str {
+        "zstd"
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::encode_all(data, 0)
+    }
+
+    fn decompress(&self, data: &[u8], out: &mut dyn Write) -> io::Result<()> {
+        zstd::stream::copy_decode(data, out)
+    }
+}
+
+#[test]
+fn test_message_compressor() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_unary_handler(&METHOD_ECHO, |ctx, req: String, sink| {
+            ctx.spawn(sink.success(req).map(|_| ()));
+        })
+        .add_duplex_streaming_handler(&METHOD_SLICE_ECHO, |ctx, stream, sink| {
+            let f = stream
+                .map_ok(|m| (m, WriteFlags::default()))
+                .forward(sink)
+                .map(|r| r.unwrap());
+            ctx.spawn(f);
+        })
+        .build();
+    // Requests are far larger than the limit, so they can only pass when
+    // they are compressed.
+    let limit = 64 * 1024;
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .register_message_compressor(Arc::new(Zstd))
+        .max_receive_message_len(limit)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+    let opt = CallOption::default().message_compressor(Arc::new(Zstd));
+
+    let req = "hello ".repeat(128 * 1024);
+    let resp = client.unary_call(&METHOD_ECHO, &req, opt.clone()).unwrap();
+    assert_eq!(resp, req);
+    match client.unary_call(&METHOD_ECHO, &req, CallOption::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+
+    let msgs: Vec<GrpcSlice> = (0..3)
+        .map(|i| GrpcSlice::from(vec![i as u8; 512 * 1024]))
+        .collect();
+    let (mut sink, receiver) = client.duplex_streaming(&METHOD_SLICE_ECHO, opt).unwrap();
+    let to_send = msgs.clone();
+    let resps = block_on(async move {
+        for m in to_send {
+            sink.send((m, WriteFlags::default())).await.unwrap();
+        }
+        sink.close().await.unwrap();
+        receiver.try_collect::<Vec<_>>().await.unwrap()
+    });
+    assert_eq!(resps, msgs);
+}
+
+#[test]
+fn test_message_compressor_limit() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_unary_handler(&METHOD_ECHO, |ctx, req: String, sink| {
+            ctx.spawn(sink.success(req.repeat(1024)).map(|_| ()));
+        })
+        .build();
+    let limit = 64 * 1024;
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .register_message_compressor(Arc::new(Zstd))
+        .max_decompressed_message_len(limit)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env)
+        .max_receive_message_len(limit as i32)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+    let opt = CallOption::default().message_compressor(Arc::new(Zstd));
+
+    // A few hundred bytes on the wire, but 1 MiB after decompression.
+    let bomb = "a".repeat(1024 * 1024);
+    match client.unary_call(&METHOD_ECHO, &bomb, opt.clone()) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED);
+            assert!(s.details.unwrap().contains("after decompression"));
+        }
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+
+    // The response is 1 MiB after decompression, over the limit of the client.
+    let req = "a".repeat(1024);
+    match client.unary_call(&METHOD_ECHO, &req, opt) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED);
+            assert!(s.details.unwrap().contains("after decompression"));
+        }
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+}
+
+struct Unknown;
+
+impl MessageCompressor for Unknown {
+    fn name(&self) -> &str {
+        "unknown"
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn decompress(&self, data: &[u8], out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(data)
+    }
+}
+
+#[test]
+fn test_unknown_message_compressor() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_unary_handler(&METHOD_ECHO, |ctx, req: String, sink| {
+            ctx.spawn(sink.success(req).map(|_| ()));
+        })
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .register_message_compressor(Arc::new(Zstd))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let opt = CallOption::default().message_compressor(Arc::new(Unknown));
+    match client.unary_call(&METHOD_ECHO, &"hello".to_owned(), opt) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::UNIMPLEMENTED);
+            assert!(s.details.unwrap().contains("[zstd]"));
+        }
+        r => panic!("expected unimplemented, got {:?}", r),
+    }
+}
@@ -2,12 +2,14 @@
 
 use futures::prelude::*;
 use grpcio::{
-    CertificateRequestType, ChannelBuilder, ChannelCredentialsBuilder, EnvBuilder, RpcContext,
-    ServerBuilder, ServerCredentialsBuilder, ServerCredentialsFetcher, UnarySink,
+    CallOption, CertificateRequestType, ChannelBuilder, ChannelCredentials,
+    ChannelCredentialsBuilder, EnvBuilder, Error, Pkcs12Identity, RpcContext, RpcStatusCode,
+    ServerBuilder, ServerCredentialsBuilder, ServerCredentialsFetcher, TestCert, UnarySink,
 };
 use grpcio_proto::example::helloworld::*;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tests_and_examples::util::{read_cert_pair, read_single_crt};
 
 #[derive(Clone)]
@@ -158,3 +160,189 @@ fn test_reload_fail() {
         assert_eq!(reply.get_message(), "Hello world");
     }
 }
+
+fn assert_security_mismatch(client: &GreeterClient, hint: &str) {
+    match client.say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::UNAVAILABLE);
+            let details = s.details.unwrap();
+            assert!(details.contains(hint), "{}", details);
+        }
+        r => panic!("expected security mismatch, got {:?}", r),
+    }
+}
+
+fn assert_no_security_mismatch(client: &GreeterClient) {
+    match client.say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::UNAVAILABLE);
+            let details = s.details.unwrap_or_default();
+            assert!(!details.contains("security mismatch"), "{}", details);
+        }
+        r => panic!("expected failure, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_security_mismatch() {
+    let env = Arc::new(EnvBuilder::new().build());
+
+    // Secure channel to an insecure server.
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let client_credentials = ChannelCredentialsBuilder::new()
+        .root_cert(read_single_crt("root").unwrap().into())
+        .build();
+    let ch = ChannelBuilder::new(env.clone())
+        .secure_connect(&format!("localhost:{}", port), client_credentials);
+    let client = GreeterClient::new(ch);
+    assert_security_mismatch(
+        &client,
+        "a secure channel can't connect to a plaintext server",
+    );
+
+    // Insecure channel to a secure server.
+    let (server_crt, server_key) = read_cert_pair("server1").unwrap();
+    let server_credentials = ServerCredentialsBuilder::new()
+        .add_cert(server_crt.into(), server_key.into())
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind_with_cred("127.0.0.1", 0, server_credentials)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    // The server closes the connection during the handshake.
+    assert_security_mismatch(&client, "an insecure channel can't connect to a TLS server");
+
+    // A refused connection is not a security mismatch.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    assert_no_security_mismatch(&client);
+    let client_credentials = ChannelCredentialsBuilder::new()
+        .root_cert(read_single_crt("root").unwrap().into())
+        .build();
+    let ch =
+        ChannelBuilder::new(env).secure_connect(&format!("localhost:{}", port), client_credentials);
+    let client = GreeterClient::new(ch);
+    assert_no_security_mismatch(&client);
+}
+
+#[test]
+fn test_google_credentials() {
+    ChannelCredentials::google_compute_engine_credentials().unwrap();
+
+    // A fake service account, only the private key is required to be valid.
+    let (_, key) = read_cert_pair("server1").unwrap();
+    let json_key = format!(
+        r#"{{"type": "service_account", "project_id": "test", "private_key_id": "1234", "private_key": "{}", "client_email": "test@test.iam.gserviceaccount.com", "client_id": "1234"}}"#,
+        key.replace('\n', "\\n")
+    );
+    ChannelCredentials::service_account_jwt_access_credentials(&json_key, Duration::from_secs(600))
+        .unwrap();
+    match ChannelCredentials::service_account_jwt_access_credentials("{}", Duration::from_secs(600))
+    {
+        Err(Error::GoogleAuthenticationFailed) => {}
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("invalid key should be rejected"),
+    }
+
+    let token = r#"{"type": "authorized_user", "client_id": "1234", "client_secret": "secret", "refresh_token": "token"}"#;
+    ChannelCredentials::google_refresh_token_credentials(token).unwrap();
+    assert!(ChannelCredentials::google_refresh_token_credentials("{}").is_err());
+}
+
+#[test]
+fn test_pkcs12() {
+    let der = std::fs::read("certs/server1.p12").unwrap();
+    match Pkcs12Identity::from_der(&der, "wrong") {
+        Err(Error::InvalidCredentials(_)) => {}
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("wrong passphrase should be rejected"),
+    }
+    assert!(Pkcs12Identity::from_der(b"not a bundle", "grpc-rs").is_err());
+
+    let identity = Pkcs12Identity::from_der(&der, "grpc-rs").unwrap();
+    let chain = String::from_utf8(identity.cert_chain().to_vec()).unwrap();
+    let (server_crt, _) = read_cert_pair("server1").unwrap();
+    // The leaf certificate comes first, followed by the root.
+    assert_eq!(chain.matches("-----BEGIN CERTIFICATE-----").count(), 2);
+    let leaf_end = chain.find("-----END CERTIFICATE-----").unwrap();
+    assert!(server_crt
+        .replace("\r\n", "\n")
+        .contains(chain[..leaf_end].trim_end()));
+    assert!(String::from_utf8_lossy(identity.private_key()).contains("PRIVATE KEY-----"));
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let server_credentials = ServerCredentialsBuilder::new()
+        .add_pkcs12(&der, "grpc-rs")
+        .unwrap()
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind_with_cred("127.0.0.1", 0, server_credentials)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let client_credentials = ChannelCredentialsBuilder::new()
+        .root_cert(read_single_crt("root").unwrap().into())
+        .build();
+    let ch =
+        ChannelBuilder::new(env).secure_connect(&format!("localhost:{}", port), client_credentials);
+    let client = GreeterClient::new(ch);
+    let mut req = HelloRequest::default();
+    req.set_name("world".to_owned());
+    let reply = client.say_hello(&req).unwrap();
+    assert_eq!(reply.get_message(), "Hello world");
+}
+
+#[test]
+fn test_generated_cert() {
+    assert!(TestCert::generate(&[]).is_err());
+    let cert = TestCert::generate(&["localhost", "127.0.0.1"]).unwrap();
+    assert!(String::from_utf8_lossy(cert.ca_cert()).starts_with("-----BEGIN CERTIFICATE-----"));
+    assert!(String::from_utf8_lossy(cert.cert()).starts_with("-----BEGIN CERTIFICATE-----"));
+    assert!(String::from_utf8_lossy(cert.private_key()).contains("PRIVATE KEY-----"));
+    assert_ne!(cert.ca_cert(), cert.cert());
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind_with_cred("127.0.0.1", 0, cert.server_credentials())
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let mut req = HelloRequest::default();
+    req.set_name("world".to_owned());
+    for host in &["localhost", "127.0.0.1"] {
+        let ch = ChannelBuilder::new(env.clone())
+            .secure_connect(&format!("{}:{}", host, port), cert.channel_credentials());
+        let client = GreeterClient::new(ch);
+        let reply = client.say_hello(&req).unwrap();
+        assert_eq!(reply.get_message(), "Hello world");
+    }
+
+    // A freshly generated CA doesn't trust the server's certificate.
+    let other = TestCert::generate(&["localhost"]).unwrap();
+    let ch = ChannelBuilder::new(env)
+        .secure_connect(&format!("localhost:{}", port), other.channel_credentials());
+    let client = GreeterClient::new(ch);
+    let opt = CallOption::default().timeout(Duration::from_secs(3));
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(s)) => assert_ne!(s.status, RpcStatusCode::OK),
+        other => panic!("expected failure, got {:?}", other),
+    }
+}
@@ -0,0 +1,283 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use futures_timer::Delay;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::route_guide::{
+    create_route_guide, Feature, Point, Rectangle, RouteGuide, RouteGuideClient, RouteNote,
+    RouteSummary,
+};
+use std::sync::mpsc::{self, Sender};
+use std::sync::*;
+use std::time::*;
+
+const TRACE_KEY: &str = "x-trace-id";
+
+#[derive(Clone)]
+struct HopService {
+    next: Option<Channel>,
+    tx: Sender<(Option<Duration>, Option<String>)>,
+}
+
+impl Greeter for HopService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, req: HelloRequest, sink: UnarySink<HelloReply>) {
+        let trace = ctx
+            .request_headers()
+            .iter()
+            .find(|(k, _)| *k == TRACE_KEY)
+            .map(|(_, v)| String::from_utf8(v.to_vec()).unwrap());
+        self.tx.send((ctx.deadline().remaining(), trace)).unwrap();
+        let next = self.next.as_ref().map(|ch| {
+            let client = Client::new(ch.clone()).propagate_from(&ctx, &[TRACE_KEY]);
+            GreeterClient::with_client(client)
+        });
+        ctx.spawn(async move {
+            let resp = match next {
+                Some(client) => {
+                    Delay::new(Duration::from_millis(50)).await;
+                    client.say_hello_async(&req).unwrap().await.unwrap()
+                }
+                None => HelloReply::default(),
+            };
+            let _ = sink.success(resp).await;
+        });
+    }
+}
+
+fn start_hop(
+    env: Arc<Environment>,
+    next: Option<Channel>,
+    tx: Sender<(Option<Duration>, Option<String>)>,
+) -> (Server, Channel) {
+    let service = create_greeter(HopService { next, tx });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    (server, ch)
+}
+
+#[test]
+fn test_propagate_deadline() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let (_s3, ch3) = start_hop(env.clone(), None, tx.clone());
+    let (_s2, ch2) = start_hop(env.clone(), Some(ch3), tx.clone());
+    let (_s1, ch1) = start_hop(env, Some(ch2), tx);
+
+    let mut headers = MetadataBuilder::new();
+    headers.add_str(TRACE_KEY, "trace-1").unwrap();
+    let opt = CallOption::default()
+        .timeout(Duration::from_millis(300))
+        .headers(headers.build());
+    let client = GreeterClient::new(ch1);
+    block_on(
+        client
+            .say_hello_async_opt(&HelloRequest::default(), opt)
+            .unwrap(),
+    )
+    .unwrap();
+
+    let hops: Vec<_> = rx.try_iter().collect();
+    assert_eq!(hops.len(), 3);
+    let mut last = Duration::from_millis(300);
+    for (remaining, trace) in &hops {
+        let remaining = remaining.unwrap();
+        assert!(remaining <= last, "{:?} > {:?}", remaining, last);
+        assert_eq!(trace.as_deref(), Some("trace-1"));
+        last = remaining;
+    }
+    // Every hop waits 50ms before calling the next one.
+    let first = hops[0].0.unwrap();
+    assert!(first - last >= Duration::from_millis(100), "{:?}", hops);
+}
+
+struct DropNotifier(Sender<()>);
+
+impl Drop for DropNotifier {
+    fn drop(&mut self) {
+        let _ = self.0.send(());
+    }
+}
+
+#[derive(Clone)]
+struct SlowService {
+    tx: Arc<Mutex<Sender<()>>>,
+}
+
+impl Greeter for SlowService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let notifier = DropNotifier(self.tx.lock().unwrap().clone());
+        ctx.spawn_with_deadline(async move {
+            let _notifier = notifier;
+            // Never checks whether the call is canceled.
+            Delay::new(Duration::from_secs(10)).await;
+            let _ = sink.success(HelloReply::default()).await;
+        });
+    }
+}
+
+#[test]
+fn test_handler_deadline() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_greeter(SlowService {
+        tx: Arc::new(Mutex::new(tx)),
+    });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let start = Instant::now();
+    let opt = CallOption::default().timeout(Duration::from_millis(200));
+    match client.say_hello_opt(&HelloRequest::default(), opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+        r => panic!("expected deadline exceeded, got {:?}", r),
+    }
+    assert!(start.elapsed() < Duration::from_secs(2));
+    // The handler is dropped instead of running for 10 seconds.
+    rx.recv_timeout(Duration::from_secs(2)).unwrap();
+}
+
+const PRODUCE_THRESHOLD: Duration = Duration::from_millis(300);
+
+#[derive(Clone)]
+struct ProducerService {
+    tx: Arc<Mutex<Sender<usize>>>,
+}
+
+impl RouteGuide for ProducerService {
+    fn get_feature(&mut self, _: RpcContext<'_>, _: Point, _: UnarySink<Feature>) {
+        unimplemented!()
+    }
+    fn list_features(
+        &mut self,
+        ctx: RpcContext<'_>,
+        _: Rectangle,
+        mut sink: ServerStreamingSink<Feature>,
+    ) {
+        assert!(ctx.time_remaining().unwrap() <= Duration::from_secs(1));
+        let deadline = *ctx.deadline();
+        let tx = self.tx.lock().unwrap().clone();
+        ctx.spawn(async move {
+            let mut produced = 0;
+            while produced < 100 {
+                if deadline.remaining().unwrap() < PRODUCE_THRESHOLD {
+                    break;
+                }
+                Delay::new(Duration::from_millis(50)).await;
+                sink.send((Feature::default(), WriteFlags::default()))
+                    .await
+                    .unwrap();
+                produced += 1;
+            }
+            sink.close().await.unwrap();
+            tx.send(produced).unwrap();
+        });
+    }
+    fn record_route(
+        &mut self,
+        _: RpcContext<'_>,
+        _: RequestStream<Point>,
+        _: ClientStreamingSink<RouteSummary>,
+    ) {
+        unimplemented!()
+    }
+    fn route_chat(
+        &mut self,
+        _: RpcContext<'_>,
+        _: RequestStream<RouteNote>,
+        _: DuplexSink<RouteNote>,
+    ) {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn test_time_remaining() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_route_guide(ProducerService {
+        tx: Arc::new(Mutex::new(tx)),
+    });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    let opt = CallOption::default().timeout(Duration::from_secs(1));
+    let features = client
+        .list_features_opt(&Rectangle::default(), opt)
+        .unwrap();
+    let received = block_on(features.try_collect::<Vec<_>>()).unwrap();
+    // The producer stops early instead of running into the deadline.
+    let produced = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert!(produced > 0 && produced < 100, "{}", produced);
+    assert_eq!(received.len(), produced);
+}
+
+#[derive(Clone)]
+struct DeadlineService(Arc<Mutex<Sender<Option<SystemTime>>>>);
+
+impl Greeter for DeadlineService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.0.lock().unwrap().send(ctx.deadline_instant()).unwrap();
+        ctx.spawn(sink.success(HelloReply::default()).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_deadline_instant() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_greeter(DeadlineService(Arc::new(Mutex::new(tx))));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let within = |deadline: SystemTime, expected: SystemTime| {
+        let diff = match deadline.duration_since(expected) {
+            Ok(d) => d,
+            Err(e) => e.duration(),
+        };
+        assert!(diff < Duration::from_millis(100), "{:?}", diff);
+    };
+
+    let timeout = Duration::from_millis(500);
+    let now = SystemTime::now();
+    let opt = CallOption::default().timeout(timeout);
+    let receiver = client
+        .say_hello_async_opt(&HelloRequest::default(), opt)
+        .unwrap();
+    within(receiver.deadline_instant().unwrap(), now + timeout);
+    block_on(receiver).unwrap();
+    let deadline = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+    within(deadline, now + timeout);
+
+    let receiver = client.say_hello_async(&HelloRequest::default()).unwrap();
+    assert_eq!(receiver.deadline_instant(), None);
+    block_on(receiver).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), None);
+}
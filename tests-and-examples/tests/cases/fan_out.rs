@@ -0,0 +1,160 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use futures_timer::Delay;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use std::sync::*;
+use std::time::*;
+
+const METHOD_SAY_HELLO: Method<HelloRequest, HelloReply> = Method {
+    ty: MethodType::Unary,
+    name: "/helloworld.Greeter/SayHello",
+    req_mar: Marshaller {
+        ser: pb_ser,
+        de: pb_de,
+    },
+    resp_mar: Marshaller {
+        ser: pb_ser,
+        de: pb_de,
+    },
+};
+
+#[derive(Clone)]
+struct BackendService {
+    id: usize,
+    fail: bool,
+}
+
+impl Greeter for BackendService {
+    fn say_hello(
+        &mut self,
+        ctx: RpcContext<'_>,
+        mut req: HelloRequest,
+        sink: UnarySink<HelloReply>,
+    ) {
+        let f = if self.fail {
+            sink.fail(RpcStatus::new(RpcStatusCode::UNAVAILABLE, None))
+        } else {
+            let mut resp = HelloReply::default();
+            resp.set_message(format!("{} from {}", req.take_name(), self.id));
+            sink.success(resp)
+        };
+        ctx.spawn(f.map(|_| ()));
+    }
+}
+
+#[test]
+fn test_unary_fan_out() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut servers = vec![];
+    let mut calls = vec![];
+    for id in 0..3 {
+        let service = create_greeter(BackendService { id, fail: id == 1 });
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(service)
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+        let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+        let mut req = HelloRequest::default();
+        req.set_name(format!("hello {}", id));
+        calls.push((Client::new(ch), req));
+        servers.push(server);
+    }
+
+    let opt = CallOption::default().timeout(Duration::from_secs(5));
+    let mut results: Vec<_> =
+        block_on(unary_fan_out(&METHOD_SAY_HELLO, calls.clone(), opt, 2).collect());
+    results.sort_by_key(|(i, _)| *i);
+    assert_eq!(results.len(), 3);
+    assert_eq!(
+        results[0].1.as_ref().unwrap().get_message(),
+        "hello 0 from 0"
+    );
+    match &results[1].1 {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNAVAILABLE),
+        r => panic!("expected failure, got {:?}", r),
+    }
+    assert_eq!(
+        results[2].1.as_ref().unwrap().get_message(),
+        "hello 2 from 2"
+    );
+
+    // Calls that can't be issued before the deadline fail immediately.
+    let opt = CallOption::default().timeout(Duration::from_secs(0));
+    let results: Vec<_> = block_on(unary_fan_out(&METHOD_SAY_HELLO, calls, opt, 1).collect());
+    assert_eq!(results.len(), 3);
+    for (_, r) in results {
+        match r {
+            Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+            r => panic!("expected deadline exceeded, got {:?}", r),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct DelayedService {
+    id: usize,
+    delay: Duration,
+    // Receives the result of sending the response.
+    tx: Arc<Mutex<mpsc::Sender<(usize, Result<()>)>>>,
+}
+
+impl Greeter for DelayedService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let (id, delay) = (self.id, self.delay);
+        let tx = self.tx.lock().unwrap().clone();
+        ctx.spawn(async move {
+            Delay::new(delay).await;
+            let mut resp = HelloReply::default();
+            resp.set_message(format!("from {}", id));
+            let res = sink.success(resp).await;
+            tx.send((id, res)).unwrap();
+        });
+    }
+}
+
+#[test]
+fn test_unary_hedge() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let tx = Arc::new(Mutex::new(tx));
+    let mut servers = vec![];
+    let mut clients = vec![];
+    for (id, delay) in [(0, 3000), (1, 10)].iter() {
+        let service = create_greeter(DelayedService {
+            id: *id,
+            delay: Duration::from_millis(*delay),
+            tx: tx.clone(),
+        });
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(service)
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+        let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+        clients.push(Client::new(ch));
+        servers.push(server);
+    }
+
+    let start = Instant::now();
+    let opt = CallOption::default().timeout(Duration::from_secs(10));
+    let req = HelloRequest::default();
+    let resp = block_on(unary_hedge(&METHOD_SAY_HELLO, clients, &req, opt)).unwrap();
+    assert_eq!(resp.get_message(), "from 1");
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    let (id, res) = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert_eq!(id, 1);
+    res.unwrap();
+    // The slow backend is cancelled, so it can't send its response.
+    let (id, res) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(id, 0);
+    assert!(res.is_err(), "{:?}", res);
+}
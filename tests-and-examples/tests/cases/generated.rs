@@ -0,0 +1,150 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use grpcio::*;
+use grpcio_proto::example::route_guide::*;
+
+#[derive(Clone)]
+struct EchoService;
+
+impl RouteGuide for EchoService {
+    fn get_feature(&mut self, ctx: RpcContext<'_>, point: Point, sink: UnarySink<Feature>) {
+        let mut f = Feature::default();
+        f.set_location(point);
+        ctx.spawn(sink.success(f).map(|_| ()));
+    }
+
+    fn list_features(
+        &mut self,
+        ctx: RpcContext<'_>,
+        rect: Rectangle,
+        mut sink: ServerStreamingSink<Feature>,
+    ) {
+        let mut f = Feature::default();
+        f.set_location(rect.get_lo().clone());
+        ctx.spawn(
+            async move {
+                sink.send((f, WriteFlags::default())).await?;
+                sink.close().await
+            }
+            .map(|_: Result<()>| ()),
+        );
+    }
+
+    fn record_route(
+        &mut self,
+        ctx: RpcContext<'_>,
+        points: RequestStream<Point>,
+        sink: ClientStreamingSink<RouteSummary>,
+    ) {
+        ctx.spawn(
+            async move {
+                let count = points.try_fold(0, |c, _| future::ok(c + 1)).await?;
+                let mut summary = RouteSummary::default();
+                summary.set_point_count(count);
+                sink.success(summary).await
+            }
+            .map(|_: Result<()>| ()),
+        );
+    }
+
+    fn route_chat(
+        &mut self,
+        ctx: RpcContext<'_>,
+        notes: RequestStream<RouteNote>,
+        mut sink: DuplexSink<RouteNote>,
+    ) {
+        ctx.spawn(
+            async move {
+                sink.send_all(&mut notes.map_ok(|n| (n, WriteFlags::default())))
+                    .await?;
+                sink.close().await
+            }
+            .map(|_: Result<()>| ()),
+        );
+    }
+}
+
+fn point(lat: i32) -> Point {
+    let mut p = Point::default();
+    p.set_latitude(lat);
+    p
+}
+
+// Both `method` and `method_opt` should be generated for every kind of method.
+#[test]
+fn test_default_and_opt_variants() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_route_guide(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+    let opt = || CallOption::default().timeout(Duration::from_secs(10));
+
+    // Unary.
+    let f = client.get_feature(&point(1)).unwrap();
+    assert_eq!(f.get_location().get_latitude(), 1);
+    let f = client.get_feature_opt(&point(2), opt()).unwrap();
+    assert_eq!(f.get_location().get_latitude(), 2);
+    let f = block_on(client.get_feature_async(&point(3)).unwrap()).unwrap();
+    assert_eq!(f.get_location().get_latitude(), 3);
+    let f = block_on(client.get_feature_async_opt(&point(4), opt()).unwrap()).unwrap();
+    assert_eq!(f.get_location().get_latitude(), 4);
+
+    // Server streaming.
+    let mut rect = Rectangle::default();
+    rect.set_lo(point(5));
+    let fs: Vec<_> = block_on(client.list_features(&rect).unwrap().try_collect()).unwrap();
+    assert_eq!(fs.len(), 1);
+    let fs: Vec<_> = block_on(
+        client
+            .list_features_opt(&rect, opt())
+            .unwrap()
+            .try_collect(),
+    )
+    .unwrap();
+    assert_eq!(fs.len(), 1);
+
+    // Client streaming.
+    let record = |(mut tx, rx): (
+        ClientCStreamSender<Point>,
+        ClientCStreamReceiver<RouteSummary>,
+    )| {
+        block_on(async move {
+            for i in 0..3 {
+                tx.send((point(i), WriteFlags::default())).await.unwrap();
+            }
+            tx.close().await.unwrap();
+            rx.await.unwrap().get_point_count()
+        })
+    };
+    assert_eq!(record(client.record_route().unwrap()), 3);
+    assert_eq!(record(client.record_route_opt(opt()).unwrap()), 3);
+
+    // Duplex streaming.
+    let chat = |(mut tx, rx): (
+        ClientDuplexSender<RouteNote>,
+        ClientDuplexReceiver<RouteNote>,
+    )| {
+        block_on(async move {
+            for _ in 0..3 {
+                tx.send((RouteNote::default(), WriteFlags::default()))
+                    .await
+                    .unwrap();
+            }
+            tx.close().await.unwrap();
+            rx.try_collect::<Vec<_>>().await.unwrap().len()
+        })
+    };
+    assert_eq!(chat(client.route_chat().unwrap()), 3);
+    assert_eq!(chat(client.route_chat_opt(opt()).unwrap()), 3);
+}
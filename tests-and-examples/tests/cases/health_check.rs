@@ -3,8 +3,10 @@
 use futures::prelude::*;
 use grpcio::*;
 use grpcio_proto::health::v1::health::*;
+use grpcio_proto::util::health_check;
 use std::collections::*;
 use std::sync::*;
+use std::time::*;
 
 type StatusRegistry = HashMap<String, HealthCheckResponse_ServingStatus>;
 
@@ -94,3 +96,38 @@ fn test_health_check() {
         e => panic!("unexpected error: {:?}", e),
     }
 }
+
+#[test]
+fn test_health_probe() {
+    let env = Arc::new(Environment::new(1));
+    let status: Arc<RwLock<StatusRegistry>> = Arc::default();
+    status
+        .write()
+        .unwrap()
+        .insert("".to_owned(), HealthCheckResponse_ServingStatus::SERVING);
+    status.write().unwrap().insert(
+        "down".to_owned(),
+        HealthCheckResponse_ServingStatus::NOT_SERVING,
+    );
+    let service = create_health(HealthService { status });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let (_, port) = server.bind_addrs().next().unwrap();
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+
+    let timeout = Duration::from_secs(3);
+    let check = |service| health_check(&ch, service, timeout).unwrap();
+    assert_eq!(check(""), HealthCheckResponse_ServingStatus::SERVING);
+    assert_eq!(
+        check("down"),
+        HealthCheckResponse_ServingStatus::NOT_SERVING
+    );
+    assert_eq!(
+        check("not-exist"),
+        HealthCheckResponse_ServingStatus::SERVICE_UNKNOWN
+    );
+}
@@ -1,8 +1,10 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use futures::executor::block_on;
 use futures::*;
 use grpcio::*;
 use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::route_guide::*;
 use std::sync::mpsc::{self, Sender};
 use std::sync::*;
 use std::time::*;
@@ -68,3 +70,473 @@ fn test_metadata() {
     let metadata = rx.recv_timeout(Duration::from_secs(1)).unwrap();
     assert_eq!(metadata, ("k1-bin".to_owned(), vec![0x00, 0x01, 0x02]));
 }
+
+#[derive(Clone)]
+struct StreamMetadataService;
+
+impl RouteGuide for StreamMetadataService {
+    fn get_feature(&mut self, _: RpcContext<'_>, _: Point, _: UnarySink<Feature>) {
+        unimplemented!()
+    }
+
+    fn list_features(
+        &mut self,
+        ctx: RpcContext<'_>,
+        rect: Rectangle,
+        mut sink: ServerStreamingSink<Feature>,
+    ) {
+        let count = rect.get_hi().get_latitude();
+        ctx.spawn(
+            async move {
+                let mut headers = MetadataBuilder::new();
+                headers
+                    .add_str("x-content-type", "application/x-feature-stream")
+                    .unwrap();
+                sink.send_headers(headers.build())?;
+                for _ in 0..count {
+                    sink.send((Feature::default(), WriteFlags::default()))
+                        .await?;
+                }
+                let mut trailers = MetadataBuilder::new();
+                trailers
+                    .add_str("x-total-count", &count.to_string())
+                    .unwrap()
+                    .add_bytes("x-checksum-bin", &[0xde, 0xad])
+                    .unwrap();
+                sink.set_trailers(trailers.build());
+                sink.close().await
+            }
+            .map(|_: Result<()>| ()),
+        );
+    }
+
+    fn record_route(
+        &mut self,
+        _: RpcContext<'_>,
+        _: RequestStream<Point>,
+        _: ClientStreamingSink<RouteSummary>,
+    ) {
+        unimplemented!()
+    }
+
+    fn route_chat(
+        &mut self,
+        ctx: RpcContext<'_>,
+        notes: RequestStream<RouteNote>,
+        mut sink: DuplexSink<RouteNote>,
+    ) {
+        ctx.spawn(
+            async move {
+                let mut notes = notes.map(|n| n.map(|n| (n, WriteFlags::default())));
+                sink.send_all(&mut notes).await?;
+                let mut trailers = MetadataBuilder::new();
+                trailers.add_str("x-done", "true").unwrap();
+                sink.set_trailers(trailers.build());
+                sink.close().await
+            }
+            .map(|_: Result<()>| ()),
+        );
+    }
+}
+
+#[test]
+fn test_streaming_trailers() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = create_route_guide(StreamMetadataService);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    let mut rect = Rectangle::default();
+    rect.mut_hi().set_latitude(3);
+    let mut receiver = client.list_features(&rect).unwrap();
+    let features: Vec<_> = block_on(receiver.by_ref().try_collect()).unwrap();
+    assert_eq!(features.len(), 3);
+    let trailers = receiver.trailers().unwrap();
+    let trailers: Vec<_> = trailers.iter().collect();
+    assert_eq!(
+        trailers,
+        vec![
+            ("x-total-count", &b"3"[..]),
+            ("x-checksum-bin", &[0xde, 0xad][..])
+        ]
+    );
+
+    let (mut sink, mut receiver) = client.route_chat().unwrap();
+    block_on(async {
+        sink.send((RouteNote::default(), WriteFlags::default()))
+            .await
+            .unwrap();
+        sink.close().await.unwrap();
+        let notes: Vec<_> = receiver.by_ref().try_collect().await.unwrap();
+        assert_eq!(notes.len(), 1);
+    });
+    let trailers = receiver.trailers().unwrap();
+    assert_eq!(trailers.get(0), Some(("x-done", &b"true"[..])));
+}
+
+#[test]
+fn test_streaming_headers() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = create_route_guide(StreamMetadataService);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    // Headers can be read before any message, even if there is none.
+    let mut receiver = client.list_features(&Rectangle::default()).unwrap();
+    let headers = block_on(receiver.headers()).unwrap();
+    assert_eq!(
+        headers.get(0),
+        Some(("x-content-type", &b"application/x-feature-stream"[..]))
+    );
+    let features: Vec<_> = block_on(receiver.by_ref().try_collect()).unwrap();
+    assert!(features.is_empty());
+
+    let mut rect = Rectangle::default();
+    rect.mut_hi().set_latitude(2);
+    let mut receiver = client.list_features(&rect).unwrap();
+    let headers = block_on(receiver.headers()).unwrap();
+    assert_eq!(headers.len(), 1);
+    let features: Vec<_> = block_on(receiver.by_ref().try_collect()).unwrap();
+    assert_eq!(features.len(), 2);
+
+    // Headers are sent along with the first message if not sent explicitly.
+    let (mut sink, mut receiver) = client.route_chat().unwrap();
+    block_on(async {
+        sink.send((RouteNote::default(), WriteFlags::default()))
+            .await
+            .unwrap();
+        let headers = receiver.headers().await.unwrap();
+        assert!(headers.is_empty());
+        sink.close().await.unwrap();
+        let notes: Vec<_> = receiver.try_collect().await.unwrap();
+        assert_eq!(notes.len(), 1);
+    });
+}
+
+#[derive(Clone)]
+struct CensusService {
+    tx: Sender<Option<Vec<u8>>>,
+}
+
+impl Greeter for CensusService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.tx
+            .send(ctx.census_context().map(<[u8]>::to_vec))
+            .unwrap();
+        ctx.spawn(sink.success(HelloReply::default()).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_census_context() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_greeter(CensusService { tx });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), None);
+
+    let mut builder = MetadataBuilder::new();
+    builder.add_str("k1", "v1").unwrap();
+    let opt = CallOption::default()
+        .census_context(&[0x00, 0x01, 0x02])
+        .headers(builder.build());
+    client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+    let census = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(census, Some(vec![0x00, 0x01, 0x02]));
+}
+
+#[derive(Clone)]
+struct BinaryContextService {
+    tx: Sender<Option<Vec<u8>>>,
+}
+
+impl Greeter for BinaryContextService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.tx
+            .send(ctx.binary_context("x-mesh-ctx").map(<[u8]>::to_vec))
+            .unwrap();
+        ctx.spawn(sink.success(HelloReply::default()).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_binary_context() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_greeter(BinaryContextService { tx });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), None);
+
+    // Invalid or reserved names are rejected.
+    assert!(CallOption::default()
+        .binary_context("x-mesh-ctx-bin", b"")
+        .is_err());
+    assert!(CallOption::default()
+        .binary_context("grpc-ctx", b"")
+        .is_err());
+    assert!(CallOption::default().binary_context("x mesh", b"").is_err());
+
+    let blob: Vec<u8> = (0..=255).collect();
+    let mut builder = MetadataBuilder::new();
+    builder.add_str("k1", "v1").unwrap();
+    let opt = CallOption::default()
+        .binary_context("X-Mesh-Ctx", b"stale")
+        .unwrap()
+        .headers(builder.build())
+        .binary_context("x-mesh-ctx", &blob)
+        .unwrap();
+    assert_eq!(opt.get_binary_context("x-mesh-ctx"), Some(blob.as_slice()));
+    client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+    let ctx = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(ctx, Some(blob));
+}
+
+#[derive(Clone)]
+struct FullResponseService;
+
+impl Greeter for FullResponseService {
+    fn say_hello(
+        &mut self,
+        ctx: RpcContext<'_>,
+        mut req: HelloRequest,
+        mut sink: UnarySink<HelloReply>,
+    ) {
+        let mut headers = MetadataBuilder::new();
+        headers.add_str("x-request-id", "42").unwrap();
+        sink.set_headers(headers.build());
+        let mut trailers = MetadataBuilder::new();
+        trailers.add_bytes("x-cost-bin", &[0x01, 0x02]).unwrap();
+        sink.set_trailers(trailers.build());
+
+        let mut resp = HelloReply::default();
+        resp.set_message(format!("hello {}", req.take_name()));
+        ctx.spawn(sink.success(resp).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_unary_full_response() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = create_greeter(FullResponseService);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("world".to_owned());
+    let resp = client.say_hello_full(&req, CallOption::default()).unwrap();
+    assert_eq!(resp.headers().get(0), Some(("x-request-id", &b"42"[..])));
+    assert_eq!(
+        resp.trailers().get(0),
+        Some(("x-cost-bin", &[0x01, 0x02][..]))
+    );
+    assert_eq!(resp.message().get_message(), "hello world");
+
+    let resp = block_on(client.say_hello_async(&req).unwrap().full()).unwrap();
+    let (headers, message, trailers) = resp.into_parts();
+    assert_eq!(headers.len(), 1);
+    assert_eq!(message.get_message(), "hello world");
+    assert_eq!(trailers.len(), 1);
+}
+
+struct TraceRequest(&'static str);
+
+#[test]
+fn test_call_option_extension() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_greeter(GreeterService { tx });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch).with_interceptor(|method, opt| {
+        assert_eq!(method, "/helloworld.Greeter/SayHello");
+        let id = match opt.extension::<TraceRequest>() {
+            Some(TraceRequest(id)) => *id,
+            None => return opt,
+        };
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("trace-id", id).unwrap();
+        opt.headers(builder.build())
+    });
+    let client = GreeterClient::with_client(client);
+
+    let trace_ids = |rx: &mpsc::Receiver<(String, Vec<u8>)>| {
+        rx.try_iter()
+            .filter(|(k, _)| k == "trace-id")
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>()
+    };
+    client.say_hello(&HelloRequest::default()).unwrap();
+    assert!(trace_ids(&rx).is_empty());
+
+    let opt = CallOption::default().with_extension(TraceRequest("42"));
+    assert_eq!(opt.extension::<TraceRequest>().unwrap().0, "42");
+    assert!(opt.extension::<String>().is_none());
+    client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+    assert_eq!(trace_ids(&rx), vec![b"42".to_vec()]);
+}
+
+#[derive(Clone)]
+struct CallIdService {
+    tx: Sender<Option<String>>,
+}
+
+impl Greeter for CallIdService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.tx.send(ctx.call_id().map(str::to_owned)).unwrap();
+        ctx.spawn(sink.success(HelloReply::default()).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_call_id() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_greeter(CallIdService { tx });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+
+    let client = GreeterClient::new(ch.clone());
+    client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), None);
+
+    let client = GreeterClient::with_client(Client::new(ch).with_call_ids());
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let receiver = client.say_hello_async(&HelloRequest::default()).unwrap();
+        let id = receiver.call_id().unwrap();
+        assert_eq!(id.len(), 32);
+        block_on(receiver).unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(1)).unwrap(),
+            Some(id.clone())
+        );
+        ids.push(id);
+    }
+    assert_ne!(ids[0], ids[1]);
+
+    let opt = CallOption::default().call_id("my-call");
+    client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+    let id = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(id.as_deref(), Some("my-call"));
+}
+
+#[test]
+fn test_client_context() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_greeter(GreeterService { tx });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    // Long values are not inlined, so sharing them can be observed.
+    let token = format!("Bearer {}", "t".repeat(64));
+    let mut builder = MetadataBuilder::new();
+    builder
+        .add_str("authorization", &token)
+        .unwrap()
+        .add_str("x-tenant", "tenant-with-a-long-name-0001")
+        .unwrap();
+    let ctx = ClientContext::with_headers(builder.build());
+    let shared = ctx.headers().unwrap().get(0).unwrap().1.as_ptr();
+
+    for i in 0..10 {
+        let opt = if i % 2 == 0 {
+            ctx.call_option()
+        } else {
+            ctx.apply(CallOption::default().timeout(Duration::from_secs(3)))
+        };
+        // Entries are shared with the context instead of rebuilt.
+        let (key, value) = opt.get_headers().unwrap().get(0).unwrap();
+        assert_eq!(key, "authorization");
+        assert_eq!(value.as_ptr(), shared);
+
+        let mut req = HelloRequest::default();
+        req.set_name(i.to_string());
+        let resp = client.say_hello_opt(&req, opt).unwrap();
+        assert_eq!(resp.get_message(), format!("hello {}", i));
+        let headers: Vec<_> = rx.try_iter().collect();
+        assert_eq!(
+            headers,
+            [
+                ("authorization".to_owned(), token.clone().into_bytes()),
+                (
+                    "x-tenant".to_owned(),
+                    b"tenant-with-a-long-name-0001".to_vec()
+                ),
+            ]
+        );
+    }
+
+    // Headers set explicitly on a call are merged with the context.
+    let mut builder = MetadataBuilder::new();
+    builder.add_str("x-tenant", "other").unwrap();
+    let opt = ctx.apply(CallOption::default().headers(builder.build()));
+    let headers: Vec<_> = opt.get_headers().unwrap().iter().collect();
+    assert_eq!(
+        headers,
+        [
+            ("authorization", token.as_bytes()),
+            ("x-tenant", &b"other"[..])
+        ]
+    );
+}
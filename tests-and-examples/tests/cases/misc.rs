@@ -221,3 +221,1112 @@ fn test_shutdown_when_exists_grpc_call() {
         "Send should get error because server is shutdown, so the grpc is cancelled."
     );
 }
+
+// Uses about 16 MiB of stack, which overflows the default 2 MiB stack.
+fn recurse(depth: usize) -> usize {
+    let buf = [depth as u8; 4096];
+    if depth == 0 {
+        return 0;
+    }
+    let v = unsafe { std::ptr::read_volatile(&buf[depth % buf.len()]) };
+    v as usize + recurse(depth - 1)
+}
+
+#[derive(Clone)]
+struct DeepService;
+
+impl Greeter for DeepService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        // Handlers are executed on polling threads.
+        let mut resp = HelloReply::default();
+        resp.set_message(recurse(4096).to_string());
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e))
+                .map(|_| ()),
+        );
+    }
+}
+
+#[test]
+fn test_stack_size() {
+    let env = Arc::new(
+        EnvBuilder::new()
+            .cq_count(1)
+            .stack_size(64 * 1024 * 1024)
+            .build(),
+    );
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(DeepService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let resp = client.say_hello(&HelloRequest::default()).unwrap();
+    let expected: usize = (1..=4096).map(|d| d % 256).sum();
+    assert_eq!(resp.get_message(), expected.to_string());
+}
+
+#[derive(Clone)]
+struct StatusService(i32);
+
+impl Greeter for StatusService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let status = RpcStatus::new(self.0, Some("custom".to_owned()));
+        ctx.spawn(sink.fail(status).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_unknown_status_code() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(StatusService(42)))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    match client.say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => {
+            // Out-of-range codes should be preserved instead of being mapped to
+            // other codes.
+            assert_eq!(s.code(), 42);
+            assert!(!s.status.is_known());
+            assert_ne!(s.status, RpcStatusCode::OK);
+            assert_ne!(s.status, RpcStatusCode::UNKNOWN);
+            assert_eq!(s.details.as_deref(), Some("custom"));
+        }
+        r => panic!("expected failure, got {:?}", r),
+    }
+    assert!(RpcStatusCode::UNAUTHENTICATED.is_known());
+    assert!(!RpcStatusCode::DO_NOT_USE.is_known());
+}
+
+#[test]
+fn test_call_flags() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = create_greeter(PeerService);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let opts = vec![
+        CallOption::default().idempotent(true),
+        CallOption::default().cacheable(true),
+        CallOption::default().idempotent(true).cacheable(true),
+        CallOption::default()
+            .idempotent(true)
+            .cacheable(true)
+            .idempotent(false)
+            .cacheable(false),
+    ];
+    for opt in opts {
+        let resp = client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+        assert!(resp.get_message().contains("127.0.0.1"), "{:?}", resp);
+    }
+}
+
+#[test]
+fn test_poller_index() {
+    let indexes = Arc::new(Mutex::new(vec![]));
+    let indexes_add = indexes.clone();
+    let env = EnvBuilder::new()
+        .cq_count(4)
+        .after_start_with_index(move |i| {
+            let name = thread::current().name().map(ToOwned::to_owned);
+            indexes_add.lock().unwrap().push((i, name));
+        })
+        .name_prefix("poller")
+        .build();
+    assert_eq!(env.completion_queues().len(), 4);
+
+    let mut indexes = indexes.lock().unwrap().clone();
+    indexes.sort();
+    let expected: Vec<_> = (0..4).map(|i| (i, Some(format!("poller-{}", i)))).collect();
+    assert_eq!(indexes, expected);
+}
+
+#[derive(Clone)]
+struct HoldService(Arc<Mutex<Vec<UnarySink<HelloReply>>>>);
+
+impl Greeter for HoldService {
+    fn say_hello(&mut self, _: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.0.lock().unwrap().push(sink);
+    }
+}
+
+fn cq_call_counts(policy: CqPickPolicy) -> Vec<usize> {
+    let sinks = Arc::new(Mutex::new(vec![]));
+    let server_env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let mut server = ServerBuilder::new(server_env)
+        .register_service(create_greeter(HoldService(sinks.clone())))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+
+    let env = Arc::new(EnvBuilder::new().cq_count(4).cq_pick_policy(policy).build());
+    // All calls arrive on a single channel.
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let receivers: Vec<_> = (0..8)
+        .map(|_| client.say_hello_async(&HelloRequest::default()).unwrap())
+        .collect();
+    let counts = env
+        .completion_queues()
+        .iter()
+        .map(|cq| cq.call_count())
+        .collect();
+
+    for _ in 0..100 {
+        if sinks.lock().unwrap().len() == 8 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    for sink in sinks.lock().unwrap().drain(..) {
+        block_on(sink.success(HelloReply::default())).unwrap();
+    }
+    block_on(futures::future::try_join_all(receivers)).unwrap();
+    for _ in 0..100 {
+        if env
+            .completion_queues()
+            .iter()
+            .all(|cq| cq.call_count() == 0)
+        {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    for cq in env.completion_queues() {
+        assert_eq!(cq.call_count(), 0);
+    }
+    counts
+}
+
+#[test]
+fn test_cq_pick_policy() {
+    let mut counts = cq_call_counts(CqPickPolicy::RoundRobin);
+    counts.sort();
+    assert_eq!(counts, vec![0, 0, 0, 8]);
+
+    let counts = cq_call_counts(CqPickPolicy::LeastLoaded);
+    assert_eq!(counts, vec![2, 2, 2, 2]);
+}
+
+#[derive(Clone)]
+struct SizeService(mpsc::Sender<Result<()>>);
+
+impl Greeter for SizeService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, req: HelloRequest, sink: UnarySink<HelloReply>) {
+        let mut resp = HelloReply::default();
+        resp.set_message("x".repeat(req.get_name().parse().unwrap()));
+        let tx = self.0.clone();
+        ctx.spawn(sink.success(resp).map(move |res| tx.send(res).unwrap()));
+    }
+}
+
+#[test]
+fn test_server_message_len_limits() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(SizeService(tx)))
+        .max_receive_message_len(64)
+        .max_send_message_len(1024)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("512".to_owned());
+    let resp = client.say_hello(&req).unwrap();
+    assert_eq!(resp.get_message().len(), 512);
+    rx.recv_timeout(Duration::from_secs(3)).unwrap().unwrap();
+
+    // Over-limit inbound, rejected before reaching the handler.
+    req.set_name(format!("{:0>128}", 512));
+    match client.say_hello(&req) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+    // Over-limit outbound, reported to the handler and the client.
+    req.set_name("2048".to_owned());
+    match client.say_hello(&req) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED);
+            assert!(s
+                .details
+                .unwrap()
+                .starts_with("Sent message larger than max"));
+        }
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+    match rx.recv_timeout(Duration::from_secs(3)).unwrap() {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_call_message_len_limits() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, _rx) = mpsc::channel();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(SizeService(tx)))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env.clone())
+        .max_receive_message_len(1024)
+        .receive_message_len_ceiling(8192)
+        .max_send_message_len(64)
+        .send_message_len_ceiling(-1)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let check_exhausted = |res: Result<HelloReply>, details: &str| match res {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED);
+            assert!(s.details.as_ref().unwrap().starts_with(details), "{:?}", s);
+        }
+        r => panic!("expected resource exhausted, got {:?}", r),
+    };
+
+    // A large response is only accepted by the call that raises the limit.
+    let mut req = HelloRequest::default();
+    req.set_name("4096".to_owned());
+    check_exhausted(client.say_hello(&req), "Received message larger than max");
+    let opt = CallOption::default().max_receive_message_len(8192);
+    let resp = client.say_hello_opt(&req, opt).unwrap();
+    assert_eq!(resp.get_message().len(), 4096);
+    check_exhausted(client.say_hello(&req), "Received message larger than max");
+    // But not beyond the ceiling, which gRPC Core checks before buffering it.
+    req.set_name("16384".to_owned());
+    let opt = CallOption::default().max_receive_message_len(-1);
+    check_exhausted(
+        client.say_hello_opt(&req, opt),
+        "Received message larger than max",
+    );
+
+    // So is a large request.
+    req.set_name(format!("{:0>128}", 512));
+    check_exhausted(client.say_hello(&req), "Sent message larger than max");
+    let opt = CallOption::default().max_send_message_len(-1);
+    let resp = client.say_hello_opt(&req, opt).unwrap();
+    assert_eq!(resp.get_message().len(), 512);
+    check_exhausted(client.say_hello(&req), "Sent message larger than max");
+
+    // Without a ceiling, gRPC Core still enforces the limit of the channel.
+    let ch = ChannelBuilder::new(env)
+        .max_send_message_len(64)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let opt = CallOption::default().max_send_message_len(-1);
+    check_exhausted(
+        client.say_hello_opt(&req, opt),
+        "Sent message larger than max",
+    );
+}
+
+#[test]
+fn test_send_message_too_large() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    // The limit set by channel args is checked before hitting the wire as well.
+    let args = ChannelBuilder::new(env.clone())
+        .max_send_message_len(100)
+        .build_args();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(SizeService(tx)))
+        .channel_args(args)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env)
+        .max_send_message_len(64)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let check_too_large = |res: Result<HelloReply>, len: usize, max: usize| match res {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED);
+            assert_eq!(
+                s.details.unwrap(),
+                format!("Sent message larger than max ({} vs. {})", len, max)
+            );
+        }
+        r => panic!("expected resource exhausted, got {:?}", r),
+    };
+
+    // The reply has 200 bytes of message and 3 bytes of field header.
+    let mut req = HelloRequest::default();
+    req.set_name("200".to_owned());
+    check_too_large(client.say_hello(&req), 203, 100);
+    match rx.recv_timeout(Duration::from_secs(3)).unwrap() {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+    req.set_name("90".to_owned());
+    assert_eq!(client.say_hello(&req).unwrap().get_message().len(), 90);
+    rx.recv_timeout(Duration::from_secs(3)).unwrap().unwrap();
+
+    // The request is rejected by the client without being sent.
+    req.set_name(format!("{:0>100}", 1));
+    check_too_large(client.say_hello(&req), 102, 64);
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+}
+
+#[test]
+fn test_user_driven_pollers() {
+    let (env, mut pollers) = EnvBuilder::new().cq_count(2).build_with_pollers();
+    let env = Arc::new(env);
+    let p0 = pollers.remove(0);
+    let p1 = pollers.remove(0);
+    let mut handles = vec![thread::spawn(move || p0.run())];
+    handles.push(thread::spawn(move || {
+        while p1.poll_once(Duration::from_millis(10)) {}
+    }));
+
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(PeerService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    for _ in 0..4 {
+        let resp = client.say_hello(&HelloRequest::default()).unwrap();
+        assert!(resp.get_message().contains("127.0.0.1"), "{:?}", resp);
+    }
+
+    drop(client);
+    block_on(server.shutdown()).unwrap();
+    drop(server);
+    drop(env);
+    // Pollers return once the queues are shut down.
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn test_max_decompressed_message_len() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(PeerService))
+        .max_decompressed_message_len(64 * 1024)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env)
+        .default_compression_algorithm(CompressionAlgorithms::GRPC_COMPRESS_GZIP)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("a".repeat(1024));
+    client.say_hello(&req).unwrap();
+
+    // Tens of KiB on the wire, but 64 MiB after decompression, which is never held
+    // by the server as decompression stops at the limit.
+    req.set_name("a".repeat(64 * 1024 * 1024));
+    match client.say_hello(&req) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED);
+            assert!(s.details.unwrap().contains("after decompression"));
+        }
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+}
+
+#[derive(Clone)]
+struct IdService(usize);
+
+impl Greeter for IdService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let mut resp = HelloReply::default();
+        resp.set_message(self.0.to_string());
+        ctx.spawn(sink.success(resp).map(|_| ()));
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_reuse_port() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let build_server = |id, port| {
+        ServerBuilder::new(env.clone())
+            .register_service(create_greeter(IdService(id)))
+            .reuse_port(true)
+            .bind("127.0.0.1", port)
+            .build()
+    };
+    let mut server0 = build_server(0, 0).unwrap();
+    server0.start();
+    let port = server0.bind_addrs().next().unwrap().1;
+    let mut server1 = build_server(1, port).unwrap();
+    server1.start();
+
+    // All servers have to enable the option to share the port.
+    let res = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(IdService(2)))
+        .reuse_port(false)
+        .bind("127.0.0.1", port)
+        .build();
+    assert!(matches!(res, Err(Error::BindFail(..))));
+
+    // The kernel spreads connections among the servers.
+    let mut seen = [false; 2];
+    for i in 0..32 {
+        // Different labels prevent channels from sharing the connection.
+        let ch = ChannelBuilder::new(env.clone())
+            .channel_label(format!("conn-{}", i))
+            .connect(&format!("127.0.0.1:{}", port));
+        let client = GreeterClient::new(ch);
+        let resp = client.say_hello(&HelloRequest::default()).unwrap();
+        seen[resp.get_message().parse::<usize>().unwrap()] = true;
+        if seen.iter().all(|s| *s) {
+            break;
+        }
+    }
+    assert_eq!(seen, [true, true]);
+}
+
+struct CaptureLogger(Mutex<Vec<String>>);
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn test_trace_http2_frames() {
+    let logger: &'static CaptureLogger = Box::leak(Box::new(CaptureLogger(Mutex::default())));
+    log::set_logger(logger).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(PeerService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    trace_http2_frames(true);
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    client.say_hello(&HelloRequest::default()).unwrap();
+    trace_http2_frames(false);
+
+    // Headers of the call are traced.
+    let lines = logger.0.lock().unwrap();
+    assert!(
+        lines
+            .iter()
+            .any(|l| l.contains("/helloworld.Greeter/SayHello")),
+        "{:?}",
+        lines
+    );
+    assert!(!set_tracer_enabled("no-such-tracer", true));
+}
+
+#[derive(Clone)]
+struct RemainingService;
+
+impl Greeter for RemainingService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let remaining = ctx.time_remaining().map_or(0, |d| d.as_millis());
+        let mut resp = HelloReply::default();
+        resp.set_message(remaining.to_string());
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e))
+                .map(|_| ()),
+        );
+    }
+}
+
+#[test]
+fn test_default_call_option() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(RemainingService))
+        .max_receive_message_len(16 * 1024)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let base = CallOption::default()
+        .timeout(Duration::from_secs(60))
+        .compression_algorithm(CompressionAlgorithms::GRPC_COMPRESS_GZIP);
+    let client = GreeterClient::new(ch).with_default_call_option(base);
+
+    // Only fits in the limit when compressed.
+    let mut req = HelloRequest::default();
+    req.set_name("a".repeat(64 * 1024));
+    let remaining: u128 = client
+        .say_hello(&req)
+        .unwrap()
+        .get_message()
+        .parse()
+        .unwrap();
+    assert!(remaining > 30_000 && remaining <= 60_000, "{}", remaining);
+
+    let opt = CallOption::default().timeout(Duration::from_secs(5));
+    let remaining: u128 = client
+        .say_hello_opt(&req, opt)
+        .unwrap()
+        .get_message()
+        .parse()
+        .unwrap();
+    assert!(remaining <= 5_000, "{}", remaining);
+
+    let opt =
+        CallOption::default().compression_algorithm(CompressionAlgorithms::GRPC_COMPRESS_NONE);
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_peer_filter() {
+    struct Defer(&'static str);
+
+    impl Drop for Defer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(self.0);
+        }
+    }
+    let socket_path = Defer("test_peer_filter_socket");
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(PeerService))
+        .bind("127.0.0.1", 0)
+        .bind(format!("unix:{}", socket_path.0), 0)
+        .peer_filter(|peer| !peer.starts_with("ipv4:127.0.0.1:"))
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    match client.say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::PERMISSION_DENIED),
+        r => panic!("expected permission denied, got {:?}", r),
+    }
+
+    let ch = ChannelBuilder::new(env).connect(&format!("unix:{}", socket_path.0));
+    let client = GreeterClient::new(ch);
+    let resp = client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(resp.get_message(), format!("unix:{}", socket_path.0));
+}
+
+#[test]
+fn test_connect_after_start() {
+    let env = Arc::new(EnvBuilder::new().build());
+    for _ in 0..10 {
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_greeter(PeerService))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+        // Connect without waiting for ready, so any failure to connect fails
+        // the call immediately.
+        let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+        let client = GreeterClient::new(ch);
+        let opt = CallOption::default().wait_for_ready(false);
+        client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+    }
+}
+
+#[derive(Clone)]
+struct FileService;
+
+fn read_greeting(name: &str) -> std::io::Result<String> {
+    match name {
+        "" => Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "disk failure",
+        )),
+        name => Ok(format!("hello {}", name)),
+    }
+}
+
+impl FileService {
+    fn greet(req: &HelloRequest) -> std::result::Result<HelloReply, RpcStatus> {
+        if req.get_name() == "admin" {
+            return Err(RpcStatus::permission_denied("admin is reserved"));
+        }
+        let greeting = read_greeting(req.get_name())?;
+        let len: u8 = greeting
+            .len()
+            .to_string()
+            .parse()
+            .status(RpcStatusCode::OUT_OF_RANGE)?;
+        let mut resp = HelloReply::default();
+        resp.set_message(format!("{} ({})", greeting, len));
+        Ok(resp)
+    }
+}
+
+impl Greeter for FileService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, req: HelloRequest, sink: UnarySink<HelloReply>) {
+        let f = match FileService::greet(&req) {
+            Ok(resp) => sink.success(resp),
+            Err(status) => sink.fail(status),
+        };
+        ctx.spawn(f.map(|_| ()));
+    }
+}
+
+#[test]
+fn test_status_helpers() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(FileService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let say_hello = |name: &str| {
+        let mut req = HelloRequest::default();
+        req.set_name(name.to_owned());
+        match client.say_hello(&req) {
+            Err(Error::RpcFailure(s)) => Err((s.status, s.details.unwrap())),
+            r => Ok(r.unwrap().take_message()),
+        }
+    };
+    assert_eq!(say_hello("world").unwrap(), "hello world (11)");
+    assert_eq!(
+        say_hello("").unwrap_err(),
+        (RpcStatusCode::INTERNAL, "disk failure".to_owned())
+    );
+    assert_eq!(
+        say_hello("admin").unwrap_err(),
+        (
+            RpcStatusCode::PERMISSION_DENIED,
+            "admin is reserved".to_owned()
+        )
+    );
+    assert_eq!(
+        say_hello(&"x".repeat(300)).unwrap_err().0,
+        RpcStatusCode::OUT_OF_RANGE
+    );
+}
+
+#[derive(Clone)]
+struct CountService(Arc<AtomicUsize>);
+
+impl Greeter for CountService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ctx.spawn(sink.success(HelloReply::default()).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_probe_method() {
+    use grpcio_proto::example::route_guide::METHOD_ROUTE_GUIDE_GET_FEATURE;
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let service = create_greeter(CountService(calls.clone()));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let present = client
+        .probe_method(&METHOD_GREETER_SAY_HELLO, CallOption::default())
+        .unwrap();
+    assert!(present);
+    let present = client
+        .probe_method(&METHOD_ROUTE_GUIDE_GET_FEATURE, CallOption::default())
+        .unwrap();
+    assert!(!present);
+    // Probes never reach the handler.
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    // Without answering probes, the unary handler isn't called for lack of a
+    // request, which still tells the method exists.
+    let service = create_greeter(CountService(calls.clone()));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .answer_probes(false)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+    let present = client
+        .probe_method(&METHOD_GREETER_SAY_HELLO, CallOption::default())
+        .unwrap();
+    assert!(present);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    // Denied probes don't tell whether the method exists.
+    let service = create_greeter(CountService(calls.clone()));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .peer_filter(|_| false)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+    match client.probe_method(&METHOD_GREETER_SAY_HELLO, CallOption::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::PERMISSION_DENIED),
+        r => panic!("expected permission denied, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_unimplemented_message() {
+    use grpcio_proto::example::route_guide::{Point, METHOD_ROUTE_GUIDE_GET_FEATURE};
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = create_greeter(CountService(Arc::default()));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .unimplemented_message(|method| format!("{} is removed in v2", method))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    match client.unary_call(
+        &METHOD_ROUTE_GUIDE_GET_FEATURE,
+        &Point::default(),
+        CallOption::default(),
+    ) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::UNIMPLEMENTED);
+            assert_eq!(
+                s.details.as_deref(),
+                Some("/routeguide.RouteGuide/GetFeature is removed in v2")
+            );
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // Registered methods are not affected.
+    let client = GreeterClient::with_client(client);
+    client.say_hello(&HelloRequest::default()).unwrap();
+}
+
+#[derive(Clone)]
+struct AuthorityService(&'static str);
+
+impl Greeter for AuthorityService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let host = String::from_utf8_lossy(ctx.host()).into_owned();
+        if host == self.0 {
+            ctx.spawn(sink.success(HelloReply::default()).map(|_| ()));
+        } else {
+            ctx.spawn(sink.fail(RpcStatus::invalid_argument(host)).map(|_| ()));
+        }
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_unix_domain_socket_authority() {
+    let dir = std::env::temp_dir().join(format!("grpc-rs-authority-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = format!("unix:{}", dir.join("socket").display());
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = create_greeter(AuthorityService("greeter.example.com"));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind(socket.as_str(), 0)
+        .build()
+        .unwrap();
+    server.start();
+
+    let ch = ChannelBuilder::new(env.clone()).connect(&socket);
+    match GreeterClient::new(ch).say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::INVALID_ARGUMENT);
+            assert_eq!(s.details.as_deref(), Some("localhost"));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    let ch = ChannelBuilder::new(env)
+        .default_authority("greeter.example.com")
+        .connect(&socket);
+    GreeterClient::new(ch)
+        .say_hello(&HelloRequest::default())
+        .unwrap();
+    drop(server);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_server_services() {
+    use grpcio_proto::example::route_guide::{Feature, Point, METHOD_ROUTE_GUIDE_GET_FEATURE};
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let route_guide = ServiceBuilder::new()
+        .add_unary_handler(
+            &METHOD_ROUTE_GUIDE_GET_FEATURE,
+            |ctx, _: Point, sink: UnarySink<Feature>| {
+                ctx.spawn(sink.success(Feature::default()).map(|_| ()));
+            },
+        )
+        .build();
+    let server = ServerBuilder::new(env)
+        .register_service(create_greeter(CountService(Arc::default())))
+        .register_service(route_guide)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+
+    let services: Vec<_> = server.services().collect();
+    assert_eq!(services, ["helloworld.Greeter", "routeguide.RouteGuide"]);
+    let methods: Vec<_> = server.methods().collect();
+    assert_eq!(
+        methods,
+        [
+            "/helloworld.Greeter/SayHello",
+            "/routeguide.RouteGuide/GetFeature"
+        ]
+    );
+}
+
+#[derive(Clone)]
+struct CompressionService(mpsc::Sender<Option<CompressionAlgorithms>>);
+
+impl Greeter for CompressionService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        self.0.send(ctx.incoming_compression()).unwrap();
+        ctx.spawn(sink.success(HelloReply::default()).map(|_| ()));
+    }
+}
+
+#[test]
+fn test_incoming_compression() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let mut req = HelloRequest::default();
+    req.set_name("a".repeat(1024));
+    let gzip =
+        || CallOption::default().compression_algorithm(CompressionAlgorithms::GRPC_COMPRESS_GZIP);
+
+    // The library decompresses requests once a decompressed limit is set.
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(CompressionService(tx.clone())))
+        .max_decompressed_message_len(4 * 1024 * 1024)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    client.say_hello_opt(&req, gzip()).unwrap();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(3)).unwrap(),
+        Some(CompressionAlgorithms::GRPC_COMPRESS_GZIP)
+    );
+    client.say_hello(&req).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(3)).unwrap(), None);
+
+    // By default gRPC Core decompresses requests and forgets the algorithm.
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(CompressionService(tx)))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    client.say_hello_opt(&req, gzip()).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(3)).unwrap(), None);
+}
+
+#[test]
+fn test_shadow_handler() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let tx = Arc::new(Mutex::new(tx));
+    let service = ServiceBuilder::new()
+        .add_unary_handler_with_shadow(
+            &METHOD_GREETER_SAY_HELLO,
+            |ctx, req: HelloRequest, sink: UnarySink<HelloReply>| {
+                let mut resp = HelloReply::default();
+                resp.set_message(format!("hello {}", req.get_name()));
+                ctx.spawn(sink.success(resp).map(|_| ()));
+            },
+            move |req: HelloRequest| {
+                tx.lock().unwrap().send(req.get_name().to_owned()).unwrap();
+                // Neither a different reply nor an error reaches the client.
+                let res = if req.get_name() == "3" {
+                    Err(Error::RpcFinished(None))
+                } else {
+                    Ok(HelloReply::default())
+                };
+                future::ready(res)
+            },
+            0.5,
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    for i in 0..10 {
+        let mut req = HelloRequest::default();
+        req.set_name(i.to_string());
+        let resp = client.say_hello(&req).unwrap();
+        assert_eq!(resp.get_message(), format!("hello {}", i));
+    }
+    let shadowed: Vec<_> = rx.try_iter().collect();
+    assert_eq!(shadowed, ["1", "3", "5", "7", "9"]);
+}
+
+#[derive(Clone)]
+struct MetricsService;
+
+impl Greeter for MetricsService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, req: HelloRequest, sink: UnarySink<HelloReply>) {
+        let f = async move {
+            Delay::new(Duration::from_millis(10)).await;
+            if req.get_name() == "fail" {
+                let status = RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, None);
+                sink.fail(status).await
+            } else {
+                sink.success(HelloReply::default()).await
+            }
+        };
+        ctx.spawn(f.map(|_| ()));
+    }
+}
+
+#[test]
+fn test_server_metrics() {
+    use grpcio_proto::example::route_guide::{Feature, Point, METHOD_ROUTE_GUIDE_GET_FEATURE};
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let route_guide = ServiceBuilder::new()
+        .add_unary_handler(
+            &METHOD_ROUTE_GUIDE_GET_FEATURE,
+            |_, _: Point, sink: UnarySink<Feature>| {
+                // Dropping the sink cancels the call.
+                drop(sink);
+            },
+        )
+        .build();
+    let metrics = Arc::new(ServerMetrics::new());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(MetricsService))
+        .register_service(route_guide)
+        .metrics(metrics.clone())
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch.clone());
+    for name in &["a", "fail", "b", "fail", "c"] {
+        let mut req = HelloRequest::default();
+        req.set_name(name.to_string());
+        let res = client.say_hello(&req);
+        assert_eq!(res.is_err(), *name == "fail");
+    }
+    let res = Client::new(ch).unary_call(
+        &METHOD_ROUTE_GUIDE_GET_FEATURE,
+        &Point::default(),
+        CallOption::default(),
+    );
+    assert!(res.is_err());
+
+    // Calls are recorded after they are released, which may be a little later than
+    // the client gets the responses.
+    let start = Instant::now();
+    let mut snapshot = metrics.snapshot();
+    while snapshot.values().map(|m| m.calls()).sum::<u64>() < 6 {
+        assert!(start.elapsed() < Duration::from_secs(3), "{:?}", snapshot);
+        thread::sleep(Duration::from_millis(10));
+        snapshot = metrics.snapshot();
+    }
+    assert_eq!(snapshot.len(), 2);
+    let hello = &snapshot["/helloworld.Greeter/SayHello"];
+    assert_eq!(hello.calls(), 5);
+    assert_eq!(hello.errors(), 2);
+    assert_eq!(hello.errors_of(RpcStatusCode::INVALID_ARGUMENT), 2);
+    assert_eq!(hello.latency().count(), 5);
+    assert!(hello.latency().mean().unwrap() >= Duration::from_millis(10));
+    let buckets: u64 = hello.latency().buckets().iter().map(|(_, c)| c).sum();
+    assert_eq!(buckets, 5);
+    let feature = &snapshot["/routeguide.RouteGuide/GetFeature"];
+    assert_eq!(feature.calls(), 1);
+    assert_eq!(feature.errors_of(RpcStatusCode::CANCELLED), 1);
+    assert!(feature.latency().max() > Duration::from_secs(0));
+}
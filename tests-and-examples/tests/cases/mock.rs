@@ -0,0 +1,109 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use futures_timer::Delay;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::route_guide::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_mock_unary_error() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let server = MockServerBuilder::new(env)
+        .unary(&METHOD_GREETER_SAY_HELLO, |req: HelloRequest| {
+            future::err(RpcStatus::new(
+                RpcStatusCode::UNAVAILABLE,
+                Some(format!("{} is down", req.get_name())),
+            ))
+        })
+        .build()
+        .unwrap();
+    // The client is connected in process, without listening on a port.
+    #[cfg(unix)]
+    assert_eq!(server.server().bind_addrs().count(), 0);
+    let client = GreeterClient::new(server.channel());
+
+    let mut req = HelloRequest::default();
+    req.set_name("backend".to_owned());
+    match client.say_hello(&req) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::UNAVAILABLE);
+            assert_eq!(s.details.as_deref(), Some("backend is down"));
+        }
+        r => panic!("expected unavailable, got {:?}", r),
+    }
+
+    // Methods that are not stubbed are unimplemented.
+    let client = RouteGuideClient::new(server.channel());
+    match client.get_feature(&Point::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNIMPLEMENTED),
+        r => panic!("expected unimplemented, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_mock_delay() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let server = MockServerBuilder::new(env)
+        .unary(&METHOD_GREETER_SAY_HELLO, |_| async {
+            Delay::new(Duration::from_secs(1)).await;
+            Ok(HelloReply::default())
+        })
+        .server_streaming(&METHOD_ROUTE_GUIDE_LIST_FEATURES, |_| {
+            future::ok(vec![Feature::default(); 3])
+        })
+        .build()
+        .unwrap();
+
+    let client = GreeterClient::new(server.channel());
+    let opt = CallOption::default().timeout(Duration::from_millis(100));
+    match client.say_hello_opt(&HelloRequest::default(), opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+        r => panic!("expected deadline exceeded, got {:?}", r),
+    }
+    client.say_hello(&HelloRequest::default()).unwrap();
+
+    let client = RouteGuideClient::new(server.channel());
+    let features = client.list_features(&Rectangle::default()).unwrap();
+    let features: Vec<_> = block_on(features.try_collect()).unwrap();
+    assert_eq!(features.len(), 3);
+}
+
+#[test]
+fn test_trailers_only() {
+    let env = Arc::new(EnvBuilder::new().build());
+    // Replying with a status only makes the server send a trailers-only response.
+    let server = MockServerBuilder::new(env.clone())
+        .unary(&METHOD_GREETER_SAY_HELLO, |_| {
+            future::err(RpcStatus::new(RpcStatusCode::NOT_FOUND, None))
+        })
+        .build()
+        .unwrap();
+    let client = GreeterClient::new(server.channel());
+    let opt = CallOption::default().timeout(Duration::from_secs(5));
+    let start = Instant::now();
+    match client.say_hello_opt(&HelloRequest::default(), opt.clone()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::NOT_FOUND),
+        r => panic!("expected not found, got {:?}", r),
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // A streaming handler that closes the call without sending any message
+    // makes a trailers-only response with OK status.
+    let method = Method {
+        ty: MethodType::ServerStreaming,
+        ..METHOD_GREETER_SAY_HELLO.clone()
+    };
+    let server = MockServerBuilder::new(env)
+        .server_streaming(&method, |_| future::ok(Vec::<HelloReply>::new()))
+        .build()
+        .unwrap();
+    let client = GreeterClient::new(server.channel());
+    match client.say_hello_opt(&HelloRequest::default(), opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::INTERNAL),
+        r => panic!("expected internal, got {:?}", r),
+    }
+}
@@ -2,9 +2,18 @@
 
 mod auth_context;
 mod cancel;
+mod channel;
+mod codec;
 mod credential;
+mod deadline;
+mod fan_out;
+mod generated;
 mod health_check;
 mod kick;
 mod metadata;
 mod misc;
+mod mock;
+#[cfg(feature = "protobuf-codec")]
+mod split_services;
 mod stream;
+mod tower_adapter;
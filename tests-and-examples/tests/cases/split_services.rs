@@ -0,0 +1,91 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::sync::Arc;
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use grpcio::*;
+
+// Generated by the build script with every service split into a module of its own.
+mod split {
+    include!(concat!(env!("OUT_DIR"), "/split_services/mod.rs"));
+}
+
+use split::multi_service::{Req, Resp};
+use split::multi_service_grpc::{
+    create_bar_service, create_foo_service, BarService, BarServiceClient, FooService,
+    FooServiceClient,
+};
+
+#[derive(Clone)]
+struct MultiService;
+
+impl FooService for MultiService {
+    fn call(&mut self, ctx: RpcContext<'_>, req: Req, sink: UnarySink<Resp>) {
+        let mut resp = Resp::default();
+        resp.set_message(format!("foo {}", req.get_name()));
+        ctx.spawn(sink.success(resp).map(|_| ()));
+    }
+}
+
+impl BarService for MultiService {
+    fn call(&mut self, ctx: RpcContext<'_>, req: Resp, sink: UnarySink<Req>) {
+        let mut resp = Req::default();
+        resp.set_name(format!("bar {}", req.get_message()));
+        ctx.spawn(sink.success(resp).map(|_| ()));
+    }
+
+    fn collect(
+        &mut self,
+        ctx: RpcContext<'_>,
+        stream: RequestStream<Req>,
+        sink: ClientStreamingSink<Resp>,
+    ) {
+        let f = async move {
+            let names: Vec<_> = stream
+                .map_ok(|r| r.get_name().to_owned())
+                .try_collect()
+                .await?;
+            let mut resp = Resp::default();
+            resp.set_message(names.join(","));
+            sink.success(resp).await
+        };
+        ctx.spawn(f.map(|_| ()));
+    }
+}
+
+#[test]
+fn test_split_services() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_foo_service(MultiService))
+        .register_service(create_bar_service(MultiService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+
+    let foo = FooServiceClient::new(ch.clone());
+    let mut req = Req::default();
+    req.set_name("a".to_owned());
+    assert_eq!(foo.call(&req).unwrap().get_message(), "foo a");
+
+    let bar = BarServiceClient::new(ch);
+    let mut req = Resp::default();
+    req.set_message("b".to_owned());
+    assert_eq!(bar.call(&req).unwrap().get_name(), "bar b");
+
+    let (mut sink, receiver) = bar.collect().unwrap();
+    let resp = block_on(async move {
+        for name in &["c", "d"] {
+            let mut req = Req::default();
+            req.set_name((*name).to_owned());
+            sink.send((req, WriteFlags::default())).await.unwrap();
+        }
+        sink.close().await.unwrap();
+        receiver.await.unwrap()
+    });
+    assert_eq!(resp.get_message(), "c,d");
+}
@@ -1,16 +1,23 @@
 // Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
 
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
 
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::executor::block_on;
+use futures::io::AllowStdIo;
 use futures::join;
 use futures::prelude::*;
 use futures::sink::SinkExt;
 use futures_timer::Delay;
 use grpcio::{
-    ChannelBuilder, ClientStreamingSink, DuplexSink, EnvBuilder, RequestStream, RpcContext,
-    ServerBuilder, ServerStreamingSink, UnarySink, WriteFlags,
+    slice_de, slice_ser, CallOption, ChannelBuilder, Client, ClientSStreamReceiver,
+    ClientStreamingSink, DrainResult, DuplexSink, EnvBuilder, Error, GrpcSlice, Marshaller, Method,
+    MethodType, RequestStream, RpcContext, RpcStatusCode, ServerBuilder, ServerStreamingSink,
+    ServiceBuilder, UnarySink, WriteFlags,
 };
 use grpcio_proto::example::route_guide::*;
 
@@ -150,3 +157,703 @@ fn test_client_send_all() {
     };
     block_on(exec_test_f);
 }
+
+const NOTE_NUM: usize = 64;
+const NOTE_SIZE: usize = 16 * 1024;
+const WINDOW_SIZE: i32 = 64 * 1024;
+
+#[derive(Clone)]
+struct BackpressureService {
+    resume: Arc<Mutex<Option<oneshot::Receiver<()>>>>,
+}
+
+impl RouteGuide for BackpressureService {
+    fn get_feature(&mut self, _: RpcContext<'_>, _: Point, _: UnarySink<Feature>) {
+        unimplemented!()
+    }
+    fn list_features(&mut self, _: RpcContext<'_>, _: Rectangle, _: ServerStreamingSink<Feature>) {
+        unimplemented!()
+    }
+    fn record_route(
+        &mut self,
+        _: RpcContext<'_>,
+        _: RequestStream<Point>,
+        _: ClientStreamingSink<RouteSummary>,
+    ) {
+        unimplemented!()
+    }
+    fn route_chat(
+        &mut self,
+        ctx: RpcContext<'_>,
+        mut notes: RequestStream<RouteNote>,
+        mut sink: DuplexSink<RouteNote>,
+    ) {
+        notes.read_ahead(false);
+        let resume = self.resume.lock().unwrap().take().unwrap();
+        let f = async move {
+            let mut count = 0;
+            notes.try_next().await?.unwrap();
+            count += 1;
+            // Stop reading, so the client can only send as much as the
+            // stream window allows.
+            resume.await.unwrap();
+            while notes.try_next().await?.is_some() {
+                count += 1;
+            }
+            let mut reply = RouteNote::default();
+            reply.set_message(count.to_string());
+            sink.send((reply, WriteFlags::default())).await?;
+            sink.close().await?;
+            Ok(())
+        }
+        .map_err(|e: grpcio::Error| panic!("server got error: {:?}", e))
+        .map(|_| ());
+        ctx.spawn(f)
+    }
+}
+
+#[test]
+fn test_request_stream_backpressure() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (resume_tx, resume_rx) = oneshot::channel();
+    let service = create_route_guide(BackpressureService {
+        resume: Arc::new(Mutex::new(Some(resume_rx))),
+    });
+    let args = ChannelBuilder::new(env.clone())
+        .stream_initial_window_size(WINDOW_SIZE)
+        .http2_bdp_probe(false)
+        .build_args();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .channel_args(args)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    let (mut sink, mut receiver) = client.route_chat().unwrap();
+    let sent = Arc::new(AtomicUsize::new(0));
+    let sent_count = sent.clone();
+    let sender = thread::spawn(move || {
+        block_on(async move {
+            for _ in 0..NOTE_NUM {
+                let mut note = RouteNote::default();
+                note.set_message("x".repeat(NOTE_SIZE));
+                sink.send((note, WriteFlags::default())).await.unwrap();
+                sent_count.fetch_add(1, Ordering::SeqCst);
+            }
+            sink.close().await.unwrap();
+        })
+    });
+
+    thread::sleep(Duration::from_secs(1));
+    // Only about a stream window of messages can be sent before the handler
+    // reads again.
+    let sent_before_resume = sent.load(Ordering::SeqCst);
+    assert!(
+        sent_before_resume < NOTE_NUM / 2,
+        "{} messages are sent before the handler reads",
+        sent_before_resume
+    );
+
+    resume_tx.send(()).unwrap();
+    sender.join().unwrap();
+    assert_eq!(sent.load(Ordering::SeqCst), NOTE_NUM);
+    let reply = block_on(receiver.try_next()).unwrap().unwrap();
+    assert_eq!(reply.get_message(), NOTE_NUM.to_string());
+    assert!(block_on(receiver.try_next()).unwrap().is_none());
+}
+
+#[test]
+fn test_sink_buffered_len() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (resume_tx, resume_rx) = oneshot::channel();
+    let service = create_route_guide(BackpressureService {
+        resume: Arc::new(Mutex::new(Some(resume_rx))),
+    });
+    let args = ChannelBuilder::new(env.clone())
+        .stream_initial_window_size(WINDOW_SIZE)
+        .http2_bdp_probe(false)
+        .build_args();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .channel_args(args)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    let (mut sink, mut receiver) = client.route_chat().unwrap();
+    assert_eq!(sink.buffered_len(), 0);
+    // The handler reads the first note.
+    block_on(sink.send((RouteNote::default(), WriteFlags::default()))).unwrap();
+    assert_eq!(sink.buffered_len(), 0);
+
+    let mut note = RouteNote::default();
+    note.set_message("x".repeat(4 * WINDOW_SIZE as usize));
+    block_on(sink.feed((note, WriteFlags::default()))).unwrap();
+    assert!(sink.buffered_len() > 4 * WINDOW_SIZE as usize);
+    // The handler stops reading, so the note can't be sent out.
+    let timeout = Delay::new(Duration::from_millis(500));
+    match block_on(future::select(sink.flush(), timeout)) {
+        future::Either::Left(_) => panic!("note should be blocked by flow control"),
+        future::Either::Right(_) => {}
+    }
+    assert!(sink.buffered_len() > 4 * WINDOW_SIZE as usize);
+
+    resume_tx.send(()).unwrap();
+    block_on(sink.flush()).unwrap();
+    assert_eq!(sink.buffered_len(), 0);
+    block_on(sink.close()).unwrap();
+    let reply = block_on(receiver.try_next()).unwrap().unwrap();
+    assert_eq!(reply.get_message(), "2");
+}
+
+#[derive(Clone)]
+struct SlowListService;
+
+impl RouteGuide for SlowListService {
+    fn get_feature(&mut self, _: RpcContext<'_>, _: Point, _: UnarySink<Feature>) {
+        unimplemented!()
+    }
+    fn list_features(
+        &mut self,
+        ctx: RpcContext<'_>,
+        _: Rectangle,
+        mut sink: ServerStreamingSink<Feature>,
+    ) {
+        ctx.spawn(async move {
+            for _ in 0..5 {
+                Delay::new(Duration::from_millis(100)).await;
+                sink.send((Feature::default(), WriteFlags::default()))
+                    .await
+                    .unwrap();
+            }
+            sink.close().await.unwrap();
+        });
+    }
+    fn record_route(
+        &mut self,
+        _: RpcContext<'_>,
+        _: RequestStream<Point>,
+        _: ClientStreamingSink<RouteSummary>,
+    ) {
+        unimplemented!()
+    }
+    fn route_chat(
+        &mut self,
+        _: RpcContext<'_>,
+        _: RequestStream<RouteNote>,
+        _: DuplexSink<RouteNote>,
+    ) {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn test_channel_drain() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_route_guide(SlowListService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let addr = format!("127.0.0.1:{}", port);
+
+    let ch = ChannelBuilder::new(env.clone()).connect(&addr);
+    let client = RouteGuideClient::new(ch.clone());
+    let mut features = client.list_features(&Rectangle::default()).unwrap();
+    let reader = thread::spawn(move || {
+        let mut count = 0;
+        while block_on(features.try_next()).unwrap().is_some() {
+            count += 1;
+        }
+        count
+    });
+    let res = block_on(ch.drain(Instant::now() + Duration::from_secs(5)));
+    assert_eq!(res, DrainResult::Drained);
+    assert_eq!(reader.join().unwrap(), 5);
+    match client.list_features(&Rectangle::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNAVAILABLE),
+        Err(e) => panic!("expected unavailable, got {:?}", e),
+        Ok(_) => panic!("expected unavailable, got a call"),
+    }
+
+    // Calls that are still alive when the deadline is hit are reported.
+    let ch = ChannelBuilder::new(env).connect(&addr);
+    let client = RouteGuideClient::new(ch.clone());
+    let _features = client.list_features(&Rectangle::default()).unwrap();
+    let res = block_on(ch.drain(Instant::now() + Duration::from_millis(100)));
+    assert_eq!(res, DrainResult::DeadlineExceeded(1));
+}
+
+const METHOD_DOWNLOAD: Method<GrpcSlice, GrpcSlice> = Method {
+    ty: MethodType::ServerStreaming,
+    name: "/file.FileTransfer/Download",
+    req_mar: Marshaller {
+        ser: slice_ser,
+        de: slice_de,
+    },
+    resp_mar: Marshaller {
+        ser: slice_ser,
+        de: slice_de,
+    },
+};
+
+#[test]
+fn test_send_reader() {
+    let path = env::temp_dir().join(format!("grpc-rs-send-reader-{}", process::id()));
+    let data: Vec<u8> = (0..1024 * 1024 + 123)
+        .map(|i| (i * 7 % 251) as u8)
+        .collect();
+    fs::write(&path, &data).unwrap();
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_server_streaming_handler(
+            &METHOD_DOWNLOAD,
+            |ctx, path: GrpcSlice, sink: ServerStreamingSink<GrpcSlice>| {
+                let path = String::from_utf8(path.as_slice().to_vec()).unwrap();
+                let file = AllowStdIo::new(fs::File::open(path).unwrap());
+                ctx.spawn(
+                    sink.send_reader(file, 64 * 1024, GrpcSlice::from)
+                        .map(|r| r.unwrap()),
+                );
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let req = GrpcSlice::from(path.to_str().unwrap());
+    let chunks = client
+        .server_streaming(&METHOD_DOWNLOAD, &req, CallOption::default())
+        .unwrap();
+    let chunks: Vec<GrpcSlice> = block_on(chunks.try_collect()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(chunks.len(), 17);
+    assert!(chunks[..16].iter().all(|c| c.len() == 64 * 1024));
+    let received: Vec<u8> = chunks.iter().flat_map(|c| c.as_slice().to_vec()).collect();
+    assert!(received == data, "content mismatch");
+}
+
+#[test]
+fn test_send_now() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_server_streaming_handler(
+            &METHOD_ROUTE_GUIDE_LIST_FEATURES,
+            |ctx, _: Rectangle, mut sink: ServerStreamingSink<Feature>| {
+                sink.enhance_batch(true);
+                ctx.spawn(async move {
+                    let mut ack = Feature::default();
+                    ack.set_name("ack".to_owned());
+                    // Even a message with buffer hint is sent out immediately.
+                    let flags = WriteFlags::default().buffer_hint(true);
+                    sink.send_now(ack, flags).await.unwrap();
+                    // Slow processing.
+                    Delay::new(Duration::from_secs(1)).await;
+                    let mut result = Feature::default();
+                    result.set_name("result".to_owned());
+                    sink.send((result, WriteFlags::default())).await.unwrap();
+                    sink.close().await.unwrap();
+                });
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    let start = Instant::now();
+    let mut features = client.list_features(&Rectangle::default()).unwrap();
+    let ack = block_on(features.try_next()).unwrap().unwrap();
+    let ack_elapsed = start.elapsed();
+    assert_eq!(ack.get_name(), "ack");
+    let result = block_on(features.try_next()).unwrap().unwrap();
+    assert_eq!(result.get_name(), "result");
+    assert!(block_on(features.try_next()).unwrap().is_none());
+    assert!(
+        ack_elapsed < Duration::from_millis(500),
+        "{:?}",
+        ack_elapsed
+    );
+    assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[test]
+fn test_request_stream_half_close() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = std::sync::mpsc::channel();
+    let service = ServiceBuilder::new()
+        .add_client_streaming_handler(
+            &METHOD_ROUTE_GUIDE_RECORD_ROUTE,
+            move |ctx,
+                  mut points: RequestStream<Point>,
+                  sink: ClientStreamingSink<RouteSummary>| {
+                let tx = tx.clone();
+                ctx.spawn(async move {
+                    let mut count = 0;
+                    let res = loop {
+                        match points.try_next().await {
+                            Ok(Some(_)) => count += 1,
+                            Ok(None) => break Ok(count),
+                            Err(e) => break Err(e),
+                        }
+                    };
+                    let half_closed = points.is_half_closed();
+                    tx.send((res.is_ok(), half_closed, count)).unwrap();
+                    if res.is_ok() {
+                        let mut summary = RouteSummary::default();
+                        summary.set_point_count(count);
+                        sink.success(summary).await.unwrap();
+                    }
+                });
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    // The server gets a clean end of stream after the client half-closes.
+    let (mut sink, receiver) = client.record_route().unwrap();
+    let summary = block_on(async move {
+        for _ in 0..3 {
+            sink.send((Point::default(), WriteFlags::default()))
+                .await
+                .unwrap();
+        }
+        sink.close().await.unwrap();
+        receiver.await.unwrap()
+    });
+    assert_eq!(summary.get_point_count(), 3);
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(3)).unwrap(),
+        (true, true, 3)
+    );
+
+    // The server gets an error if the client cancels before half-closing.
+    let (mut sink, mut receiver) = client.record_route().unwrap();
+    block_on(async {
+        sink.send((Point::default(), WriteFlags::default()))
+            .await
+            .unwrap();
+        Delay::new(Duration::from_millis(200)).await;
+        receiver.cancel();
+    });
+    let (ok, half_closed, count) = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert!(!ok);
+    assert!(!half_closed);
+    assert_eq!(count, 1);
+}
+
+// Reads features like a proxy that gives up on streams idle for longer than `idle_timeout`.
+fn read_with_idle_timeout(
+    mut features: ClientSStreamReceiver<Feature>,
+    idle_timeout: Duration,
+) -> std::result::Result<Vec<String>, Vec<String>> {
+    block_on(async move {
+        let mut names = vec![];
+        loop {
+            let next = features.try_next();
+            match future::select(next, Delay::new(idle_timeout)).await {
+                future::Either::Left((Ok(Some(f)), _)) => names.push(f.get_name().to_owned()),
+                future::Either::Left((Ok(None), _)) => return Ok(names),
+                future::Either::Left((Err(e), _)) => panic!("unexpected error: {:?}", e),
+                future::Either::Right(_) => return Err(names),
+            }
+        }
+    })
+}
+
+#[test]
+fn test_sink_heartbeat() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_server_streaming_handler(
+            &METHOD_ROUTE_GUIDE_LIST_FEATURES,
+            |ctx, rect: Rectangle, mut sink: ServerStreamingSink<Feature>| {
+                if rect.has_lo() {
+                    sink.set_heartbeat(Duration::from_millis(100), Feature::default());
+                }
+                let feature = |name: &str| {
+                    let mut f = Feature::default();
+                    f.set_name(name.to_owned());
+                    Ok((f, WriteFlags::default()))
+                };
+                let last = async move {
+                    // The stream is idle for a while.
+                    Delay::new(Duration::from_secs(1)).await;
+                    feature("last")
+                };
+                let mut features =
+                    stream::iter(vec![feature("first")]).chain(stream::once(Box::pin(last)));
+                ctx.spawn(async move {
+                    sink.send_all(&mut features).await.unwrap();
+                    sink.close().await.unwrap();
+                });
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+    let idle_timeout = Duration::from_millis(400);
+
+    // Without heartbeats the stream looks dead.
+    let features = client.list_features(&Rectangle::default()).unwrap();
+    let names = read_with_idle_timeout(features, idle_timeout).unwrap_err();
+    assert_eq!(names, ["first"]);
+
+    let mut rect = Rectangle::default();
+    rect.set_lo(Point::default());
+    let features = client.list_features(&rect).unwrap();
+    let names = read_with_idle_timeout(features, idle_timeout).unwrap();
+    assert_eq!(names.first().unwrap(), "first");
+    assert_eq!(names.last().unwrap(), "last");
+    let heartbeats = names.iter().filter(|n| n.is_empty()).count();
+    assert!(heartbeats >= 5, "{:?}", names);
+}
+
+const METHOD_UPLOAD: Method<GrpcSlice, GrpcSlice> = Method {
+    ty: MethodType::ClientStreaming,
+    name: "/file.FileTransfer/Upload",
+    req_mar: Marshaller {
+        ser: slice_ser,
+        de: slice_de,
+    },
+    resp_mar: Marshaller {
+        ser: slice_ser,
+        de: slice_de,
+    },
+};
+
+#[test]
+fn test_connection_receive_buffer() {
+    const BUFFER_SIZE: usize = 64 * 1024;
+    const CHUNK_SIZE: usize = 1024;
+    const STREAM_NUM: usize = 16;
+
+    let env = Arc::new(EnvBuilder::new().build());
+    // Handlers never read, so the uploads are buffered by the server.
+    let held = Arc::new(Mutex::new(vec![]));
+    let held_uploads = held.clone();
+    let service = ServiceBuilder::new()
+        .add_client_streaming_handler(
+            &METHOD_UPLOAD,
+            move |_, chunks: RequestStream<GrpcSlice>, sink: ClientStreamingSink<GrpcSlice>| {
+                held_uploads.lock().unwrap().push((chunks, sink));
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .max_connection_receive_buffer(BUFFER_SIZE as i32, 8)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    // Let the client learn the settings of the server before flooding.
+    ch.check_connectivity_state(true);
+    thread::sleep(Duration::from_millis(500));
+    let client = Client::new(ch);
+
+    let uploads = (0..STREAM_NUM).map(|_| {
+        let (mut sink, receiver) = client
+            .client_streaming(&METHOD_UPLOAD, CallOption::default())
+            .unwrap();
+        async move {
+            let mut sent = 0;
+            loop {
+                let chunk = GrpcSlice::from(vec![0; CHUNK_SIZE]);
+                let send = sink.send((chunk, WriteFlags::default()));
+                match future::select(send, Delay::new(Duration::from_millis(500))).await {
+                    future::Either::Left((res, _)) => res.unwrap(),
+                    // Blocked by flow control.
+                    future::Either::Right(_) => return (sent, sink, receiver),
+                }
+                sent += CHUNK_SIZE;
+            }
+        }
+    });
+    let results = block_on(future::join_all(uploads));
+    let sent: usize = results.iter().map(|(sent, _, _)| sent).sum();
+    // Allow a pending chunk and framing overhead per stream.
+    assert!(sent > 0);
+    assert!(
+        sent <= BUFFER_SIZE + STREAM_NUM * 2 * CHUNK_SIZE,
+        "{} bytes are buffered for the connection",
+        sent
+    );
+    // Only the allowed number of streams reach the handlers.
+    assert_eq!(held.lock().unwrap().len(), 8);
+}
+
+#[test]
+#[should_panic(expected = "size / max_streams")]
+fn test_connection_receive_buffer_too_small() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let _ = ServerBuilder::new(env).max_connection_receive_buffer(1024, 16);
+}
+
+#[test]
+fn test_idle_timeout() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx = Mutex::new(tx);
+    let service = ServiceBuilder::new()
+        .add_duplex_streaming_handler(
+            &METHOD_ROUTE_GUIDE_ROUTE_CHAT,
+            move |ctx, mut notes: RequestStream<RouteNote>, mut sink: DuplexSink<RouteNote>| {
+                let tx = tx.lock().unwrap().clone();
+                ctx.spawn(async move {
+                    // Only replies to the first two notes, and then goes quiet.
+                    let mut replied = 0;
+                    let res = loop {
+                        match notes.try_next().await {
+                            Ok(Some(note)) if replied < 2 => {
+                                sink.send((note, WriteFlags::default())).await.unwrap();
+                                replied += 1;
+                            }
+                            Ok(Some(_)) => {}
+                            Ok(None) => break Ok(()),
+                            Err(e) => break Err(e),
+                        }
+                    };
+                    tx.send(res).unwrap();
+                });
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    let opt = CallOption::default()
+        .timeout(Duration::from_secs(30))
+        .idle_timeout(Duration::from_secs(1));
+    let (mut sink, mut receiver) = client.route_chat_opt(opt).unwrap();
+    block_on(async {
+        // The stream stays alive beyond the idle timeout as long as it's active.
+        for _ in 0..2 {
+            sink.send((RouteNote::default(), WriteFlags::default()))
+                .await
+                .unwrap();
+            receiver.try_next().await.unwrap().unwrap();
+            Delay::new(Duration::from_millis(700)).await;
+        }
+        sink.send((RouteNote::default(), WriteFlags::default()))
+            .await
+            .unwrap();
+        let quiet = Instant::now();
+        match receiver.try_next().await {
+            Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+            r => panic!("expected idle timeout, got {:?}", r),
+        }
+        let idle = quiet.elapsed();
+        assert!(idle >= Duration::from_millis(900), "{:?}", idle);
+        assert!(idle < Duration::from_secs(5), "{:?}", idle);
+    });
+    // The server sees the call is broken instead of half closed.
+    let res = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert!(res.is_err(), "{:?}", res);
+}
+
+#[test]
+fn test_total_deadline() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = ServiceBuilder::new()
+        .add_server_streaming_handler(
+            &METHOD_ROUTE_GUIDE_LIST_FEATURES,
+            |ctx, _: Rectangle, mut sink: ServerStreamingSink<Feature>| {
+                ctx.spawn(async move {
+                    // A slow stream that takes about 5 seconds.
+                    for _ in 0..10 {
+                        let res = sink.send((Feature::default(), WriteFlags::default())).await;
+                        if res.is_err() {
+                            return;
+                        }
+                        Delay::new(Duration::from_millis(500)).await;
+                    }
+                    let _ = sink.close().await;
+                });
+            },
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = RouteGuideClient::new(ch);
+
+    let start = Instant::now();
+    let mut features = client
+        .list_features(&Rectangle::default())
+        .unwrap()
+        .total_deadline(Duration::from_millis(1200));
+    block_on(async {
+        let mut received = 0;
+        let err = loop {
+            match features.next().await {
+                Some(Ok(_)) => received += 1,
+                Some(Err(e)) => break e,
+                None => panic!("stream should not finish in time"),
+            }
+        };
+        match err {
+            Error::RpcFailure(s) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+            e => panic!("expected deadline exceeded, got {:?}", e),
+        }
+        assert!(received >= 2 && received < 10, "{}", received);
+    });
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(1100), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(4), "{:?}", elapsed);
+}
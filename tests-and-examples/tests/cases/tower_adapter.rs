@@ -0,0 +1,167 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use futures::executor::block_on;
+use futures::prelude::*;
+use futures_timer::Delay;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use std::error::Error as StdError;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tower::{Service as _, ServiceBuilder, ServiceExt};
+
+#[derive(Clone)]
+struct GreeterService;
+
+impl Greeter for GreeterService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, req: HelloRequest, sink: UnarySink<HelloReply>) {
+        let mut resp = HelloReply::default();
+        resp.set_message(format!("hello {}", req.get_name()));
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e))
+                .map(|_| ()),
+        );
+    }
+}
+
+#[test]
+fn test_unary_service() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let service = UnaryService::new(Client::new(ch), &METHOD_GREETER_SAY_HELLO)
+        .call_option(CallOption::default().timeout(Duration::from_secs(5)));
+
+    let mut rt = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_time()
+        .build()
+        .unwrap();
+    rt.block_on(async move {
+        let mut service = ServiceBuilder::new()
+            .buffer(4)
+            .rate_limit(2, Duration::from_millis(200))
+            .service(service);
+        let start = Instant::now();
+        for i in 0..5 {
+            let mut req = HelloRequest::default();
+            req.set_name(i.to_string());
+            let resp = service.ready_and().await.unwrap().call(req).await.unwrap();
+            assert_eq!(resp.get_message(), format!("hello {}", i));
+        }
+        // 5 requests with at most 2 per period have to wait for 2 periods.
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    });
+}
+
+#[test]
+fn test_unary_service_handler() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let max_in_flight = Arc::new(AtomicUsize::new(0));
+    let (f1, f2) = (in_flight.clone(), max_in_flight.clone());
+    let handler = tower::ServiceBuilder::new()
+        .concurrency_limit(1)
+        .service(tower::service_fn(move |req: HelloRequest| {
+            let (in_flight, max_in_flight) = (f1.clone(), f2.clone());
+            async move {
+                let count = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(count, Ordering::SeqCst);
+                Delay::new(Duration::from_millis(100)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                if req.get_name().is_empty() {
+                    return Err(Error::RpcFailure(RpcStatus::new(
+                        RpcStatusCode::INVALID_ARGUMENT,
+                        Some("empty name".to_owned()),
+                    )));
+                }
+                let mut resp = HelloReply::default();
+                resp.set_message(format!("hello {}", req.get_name()));
+                Ok(resp)
+            }
+        }));
+    let service = grpcio::ServiceBuilder::new()
+        .add_unary_service(&METHOD_GREETER_SAY_HELLO, handler)
+        .build();
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let receivers: Vec<_> = (0..4)
+        .map(|i| {
+            let mut req = HelloRequest::default();
+            req.set_name(i.to_string());
+            client.say_hello_async(&req).unwrap()
+        })
+        .collect();
+    for (i, resp) in block_on(future::try_join_all(receivers))
+        .unwrap()
+        .into_iter()
+        .enumerate()
+    {
+        assert_eq!(resp.get_message(), format!("hello {}", i));
+    }
+    assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
+
+    match client.say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::INVALID_ARGUMENT);
+            assert_eq!(s.details.as_deref(), Some("empty name"));
+        }
+        r => panic!("expected invalid argument, got {:?}", r),
+    }
+}
+
+// An error that can't be sent to other threads.
+struct LocalError(Rc<String>);
+
+impl From<LocalError> for Box<dyn StdError + Send + Sync> {
+    fn from(e: LocalError) -> Self {
+        e.0.as_str().into()
+    }
+}
+
+#[test]
+fn test_unary_service_local_error() {
+    let handler = tower::service_fn(|_: HelloRequest| async {
+        Err::<HelloReply, _>(LocalError(Rc::new("not here".to_owned())))
+    });
+    let service = grpcio::ServiceBuilder::new()
+        .add_unary_service(&METHOD_GREETER_SAY_HELLO, handler)
+        .build();
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    match client.say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::UNKNOWN);
+            assert_eq!(s.details.as_deref(), Some("not here"));
+        }
+        r => panic!("expected unknown, got {:?}", r),
+    }
+}
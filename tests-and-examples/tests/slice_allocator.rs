@@ -0,0 +1,72 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+// The slice allocator is global, so the test runs in its own process to not
+// count the slices of other tests.
+
+use futures::prelude::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::*;
+
+#[derive(Default)]
+struct CountingAllocator {
+    allocs: AtomicUsize,
+    deallocs: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocs.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocs.fetch_add(1, Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[derive(Clone)]
+struct EchoGreeter;
+
+impl Greeter for EchoGreeter {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, req: HelloRequest, sink: UnarySink<HelloReply>) {
+        let mut resp = HelloReply::default();
+        resp.set_message(req.get_name().to_owned());
+        ctx.spawn(sink.success(resp).map(|r| r.unwrap()));
+    }
+}
+
+#[test]
+fn test_slice_allocator() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoGreeter))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs().next().unwrap().1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let allocator: &'static CountingAllocator = Box::leak(Box::default());
+    set_slice_allocator(Some(allocator));
+    // Long enough to not be inlined.
+    let name = "x".repeat(1024);
+    let mut req = HelloRequest::default();
+    req.set_name(name.clone());
+    let resp = client.say_hello(&req).unwrap();
+    set_slice_allocator(None);
+    assert_eq!(resp.get_message(), name);
+    // Both the request and the response are serialized into allocated slices.
+    assert!(allocator.allocs.load(Ordering::SeqCst) >= 2);
+
+    // Slices allocated before are still freed by the allocator.
+    let allocs = allocator.allocs.load(Ordering::SeqCst);
+    client.say_hello(&req).unwrap();
+    assert_eq!(allocator.allocs.load(Ordering::SeqCst), allocs);
+    assert!(allocator.deallocs.load(Ordering::SeqCst) <= allocs);
+}